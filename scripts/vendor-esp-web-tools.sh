@@ -0,0 +1,16 @@
+#!/bin/sh
+# Refreshes vendor/esp-web-tools from the npm registry. The files are
+# embedded into the binary when it's built, so run this before building.
+# Keep the version in sync with ESP_WEB_TOOLS_VERSION in src/vendor.rs.
+set -eu
+
+VERSION="${1:-9.4.3}"
+DIR="$(cd "$(dirname "$0")/.." && pwd)/vendor/esp-web-tools"
+TMP="$(mktemp -d)"
+trap 'rm -rf "$TMP"' EXIT
+
+curl -fsSL "https://registry.npmjs.org/esp-web-tools/-/esp-web-tools-$VERSION.tgz" |
+    tar -xz -C "$TMP"
+find "$DIR" -type f -name '*.js' -delete
+cp -R "$TMP/package/dist/web/." "$DIR/"
+echo "Vendored esp-web-tools $VERSION into $DIR"
@@ -0,0 +1,73 @@
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+};
+
+use crate::auth::{token_matches, AuthSettings, Refused};
+
+/// Token protecting the administrative endpoints
+#[derive(Clone)]
+pub struct AdminSettings {
+    pub token: Option<String>,
+    /// whether `POST /shutdown` is mounted, off with `--no-admin`
+    pub shutdown: bool,
+}
+
+/// Request guard admitting only requests carrying `Authorization: Bearer <admin token>`
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = match req
+            .rocket()
+            .state::<AdminSettings>()
+            .and_then(|settings| settings.token.as_deref())
+        {
+            Some(token) => token,
+            None => return Outcome::Failure((Status::Forbidden, "admin API is disabled")),
+        };
+
+        let given = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match given {
+            Some(given) if token_matches(given, token) => Outcome::Success(Admin),
+            _ => {
+                req.local_cache(|| Refused("missing or invalid admin token"));
+                Outcome::Failure((Status::Unauthorized, "missing or invalid admin token"))
+            }
+        }
+    }
+}
+
+/// Request guard for `POST /shutdown`: loopback clients, requests that
+/// passed `--auth-token` (checked by the `Access` guard before this one)
+/// and requests carrying the admin token
+pub struct Operator;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Operator {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if req.client_ip().map_or(false, |ip| ip.is_loopback())
+            || req
+                .rocket()
+                .state::<AuthSettings>()
+                .map_or(false, |settings| settings.token.is_some())
+        {
+            return Outcome::Success(Operator);
+        }
+        match Admin::from_request(req).await {
+            Outcome::Success(_) => Outcome::Success(Operator),
+            _ => Outcome::Failure((
+                Status::Forbidden,
+                "only accepted from loopback, with the --auth-token or with the admin token",
+            )),
+        }
+    }
+}
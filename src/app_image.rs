@@ -0,0 +1,57 @@
+use espflash::Chip;
+use serde::Serialize;
+
+use crate::bootloader_image;
+
+pub const IMAGE_MAGIC: u8 = 0xe9;
+/// Offset of the entry point in the image header
+const ENTRY_OFFSET: usize = 4;
+const CHIP_ID_OFFSET: usize = 12;
+const HASH_APPENDED_OFFSET: usize = 23;
+
+/// Header of an app image given instead of an ELF, as reported in /info
+#[derive(Serialize, Debug, Clone)]
+pub struct AppHeader {
+    pub segments: u8,
+    pub entry_point: String,
+    pub flash_mode: String,
+    pub flash_freq: String,
+    /// `None` for a size code the chip has no flash size for
+    pub flash_size: Option<String>,
+    /// absent in the ESP8266 format
+    pub chip_id: Option<u16>,
+    pub hash_appended: bool,
+}
+
+/// Whether `data` is an app image (as written by `esptool.py elf2image`)
+/// rather than an ELF
+pub fn is_app_image(data: &[u8]) -> bool {
+    data.first() == Some(&IMAGE_MAGIC)
+}
+
+/// Checks the magic and chip id of an app image and reads its header
+pub fn inspect(image: &[u8], chip: Chip) -> Result<AppHeader, String> {
+    bootloader_image::validate(image, chip)?;
+    let (flash_mode, flash_freq) = bootloader_image::flash_params(image)
+        .ok_or_else(|| String::from("image header is truncated"))?;
+    let entry = image
+        .get(ENTRY_OFFSET..ENTRY_OFFSET + 4)
+        .ok_or_else(|| String::from("image header is truncated"))?;
+    let extended = chip != Chip::Esp8266;
+
+    Ok(AppHeader {
+        segments: image[1],
+        entry_point: format!(
+            "0x{:08x}",
+            u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]])
+        ),
+        flash_mode,
+        flash_freq,
+        flash_size: bootloader_image::header_flash_size(image, chip),
+        chip_id: image
+            .get(CHIP_ID_OFFSET..CHIP_ID_OFFSET + 2)
+            .filter(|_| extended)
+            .map(|id| u16::from_le_bytes([id[0], id[1]])),
+        hash_appended: extended && image.get(HASH_APPENDED_OFFSET) == Some(&1),
+    })
+}
@@ -0,0 +1,79 @@
+use std::io::{Cursor, Write};
+
+use rocket::{http::Header, serde::json};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    prepared_build::PreparedBuild,
+    web_manifest::{self, ErasePolicy},
+};
+
+/// `/artifacts.zip`, offered for download as `<name>.zip`
+#[derive(Responder)]
+#[response(content_type = "zip")]
+pub struct ArtifactsZip {
+    pub zip: Vec<u8>,
+    pub disposition: Header<'static>,
+}
+
+impl ArtifactsZip {
+    pub fn new(zip: Vec<u8>, base: &str) -> Self {
+        ArtifactsZip {
+            zip,
+            disposition: Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}.zip\"", base),
+            ),
+        }
+    }
+}
+
+/// `<name>-<version>` with everything but letters, digits, `.`, `-` and
+/// `_` replaced, for the zip and the folder in it
+pub fn base_name(name: &str, version: Option<&str>) -> String {
+    let name = match version {
+        Some(version) => format!("{}-{}", name, version),
+        None => name.to_string(),
+    };
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The parts, a manifest referring to them and their checksums in a folder
+/// named `base`, like `export` writes them. Built per request, in memory.
+pub fn build(
+    build: &PreparedBuild,
+    name: &str,
+    base: &str,
+    erase: ErasePolicy,
+) -> zip::result::ZipResult<Vec<u8>> {
+    let manifest = web_manifest::flat(&build.parts, name, erase);
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut checksums = String::new();
+    for part in manifest.builds.iter().flat_map(|build| &build.parts) {
+        let data = build.part(&part.path).unwrap_or_default();
+        zip.start_file(format!("{}/{}", base, part.path), options)?;
+        zip.write_all(data)?;
+        if let Some(sha256) = build.sha256(&part.path) {
+            checksums.push_str(&format!("{}  {}\n", sha256, part.path));
+        }
+    }
+
+    // serializing the manifest can't fail, it holds only strings and numbers
+    let manifest = json::to_pretty_string(&manifest).unwrap_or_default();
+    zip.start_file(format!("{}/manifest.json", base), options)?;
+    zip.write_all(manifest.as_bytes())?;
+    zip.start_file(format!("{}/checksums.txt", base), options)?;
+    zip.write_all(checksums.as_bytes())?;
+
+    Ok(zip.finish()?.into_inner())
+}
@@ -0,0 +1,169 @@
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    serde::json::Json,
+};
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+
+use crate::admin::AdminSettings;
+
+/// `--auth-token`, required by every route when set
+#[derive(Clone)]
+pub struct AuthSettings {
+    pub token: Option<String>,
+}
+
+/// Checks a `--auth-token` value: it's put into URLs as is
+pub fn token_arg(s: &str) -> Result<String, String> {
+    if !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'))
+    {
+        Ok(s.to_string())
+    } else {
+        Err(String::from(
+            "the token may only contain letters, digits, '-', '.', '_' and '~'",
+        ))
+    }
+}
+
+/// Compares a token taken from a request in constant time, so how long
+/// the comparison takes tells nothing about how much of it was right
+pub fn token_matches(given: &str, token: &str) -> bool {
+    given.as_bytes().ct_eq(token.as_bytes()).into()
+}
+
+/// A random token for `--auth-token` given without a value
+pub fn generate() -> Result<String, getrandom::Error> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Replaces the value of every `token=` query parameter in `text`, a URI
+/// or a body with URIs in it, for logs and recordings that may be shared
+pub fn redact(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find("token=") {
+        let (before, after) = rest.split_at(i + "token=".len());
+        redacted.push_str(before);
+        rest = after;
+        if matches!(before[..i].chars().last(), Some('?' | '&')) {
+            let end = rest
+                .find(|c: char| matches!(c, '&' | '#' | '"' | '\'') || c.is_whitespace())
+                .unwrap_or(rest.len());
+            redacted.push_str("[redacted]");
+            rest = &rest[end..];
+        }
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Request guard every route takes: with `--auth-token`, admits only
+/// requests carrying it as `Authorization: Bearer <token>` or `?token=`
+/// (or the admin token, which may do more)
+pub struct Access {
+    token: Option<String>,
+}
+
+impl Access {
+    /// `token=<token>` for URLs handed to the browser, so the requests it
+    /// makes from them pass as well
+    pub fn query(&self) -> Option<String> {
+        self.token().map(|token| format!("token={}", token))
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+}
+
+/// Why the request was refused, for the 401 catcher
+pub struct Refused(pub &'static str);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Access {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = match req
+            .rocket()
+            .state::<AuthSettings>()
+            .and_then(|settings| settings.token.clone())
+        {
+            Some(token) => token,
+            None => return Outcome::Success(Access { token: None }),
+        };
+        let admin = req
+            .rocket()
+            .state::<AdminSettings>()
+            .and_then(|settings| settings.token.as_deref());
+
+        let bearer = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let query = req.query_value::<&str>("token").and_then(Result::ok);
+        // both compared, whichever matches
+        let valid = |given: &str| {
+            let user = token_matches(given, &token);
+            let admin = admin.map_or(false, |admin| token_matches(given, admin));
+            user | admin
+        };
+        if bearer.map_or(false, valid) || query.map_or(false, valid) {
+            return Outcome::Success(Access { token: Some(token) });
+        }
+
+        let reason = if bearer.is_none() && query.is_none() {
+            "this server requires a token: open the URL it printed, or send Authorization: Bearer <token>"
+        } else {
+            "invalid token"
+        };
+        req.local_cache(|| Refused(reason));
+        Outcome::Failure((Status::Unauthorized, reason))
+    }
+}
+
+#[derive(Serialize)]
+pub struct Unauthorized {
+    error: &'static str,
+}
+
+/// JSON body of the 401 answers
+#[rocket::catch(401)]
+pub fn unauthorized(req: &Request) -> Json<Unauthorized> {
+    Json(Unauthorized {
+        error: req.local_cache(|| Refused("unauthorized")).0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_token_query_values() {
+        assert_eq!(
+            redact("/b/0123/firmware.bin?session=abc&token=s3cr3t"),
+            "/b/0123/firmware.bin?session=abc&token=[redacted]"
+        );
+        assert_eq!(
+            redact(r#"{"path":"/bootloader.bin?token=s3cr3t","x":"?token=a&b=1"}"#),
+            r#"{"path":"/bootloader.bin?token=[redacted]","x":"?token=[redacted]&b=1"}"#
+        );
+        // only query parameters named token
+        assert_eq!(redact("/info?csrf_token=1"), "/info?csrf_token=1");
+        assert_eq!(redact("/manifest.json"), "/manifest.json");
+    }
+
+    #[test]
+    fn tokens_match_only_exactly() {
+        assert!(token_matches("0123abcd", "0123abcd"));
+        assert!(!token_matches("0123abce", "0123abcd"));
+        assert!(!token_matches("0123abc", "0123abcd"));
+        assert!(!token_matches("", "0123abcd"));
+    }
+}
@@ -0,0 +1,225 @@
+use std::{collections::BTreeMap, ffi::OsString, path::PathBuf};
+
+use serde::Deserialize;
+
+/// Defaults for a dev board, applied for every flag not given explicitly
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Board {
+    /// value for --chip
+    pub chip: String,
+    /// value for --flash-size
+    pub flash_size: Option<String>,
+    /// value for --partition-table, a file or preset:<name>
+    pub partition_table: Option<String>,
+    /// value for --bootloader
+    pub bootloader: Option<PathBuf>,
+    /// quirks worth knowing when flashing this board
+    #[serde(default)]
+    pub notes: String,
+}
+
+/// (name, chip, flash size, partition table, notes)
+const BUILT_IN: &[(&str, &str, &str, &str, &str)] = &[
+    (
+        "esp32-devkitc",
+        "esp32",
+        "4MB",
+        "preset:singleapp",
+        "hold BOOT while resetting if the auto-reset circuit doesn't kick in",
+    ),
+    (
+        "esp32-devkitc-16mb",
+        "esp32",
+        "16MB",
+        "preset:two_ota_large",
+        "WROVER/WROOM modules with 16MB flash",
+    ),
+    ("esp32-s2-saola-1", "esp32s2", "4MB", "preset:singleapp", ""),
+    (
+        "esp32-s3-devkitc-1",
+        "esp32s3",
+        "8MB",
+        "preset:two_ota_large",
+        "flash through the UART port; the USB port is the native USB Serial/JTAG",
+    ),
+    (
+        "esp32-c3-devkitm-1",
+        "esp32c3",
+        "4MB",
+        "preset:singleapp",
+        "",
+    ),
+    (
+        "esp32-c3-devkit-rust-1",
+        "esp32c3",
+        "4MB",
+        "preset:singleapp",
+        "USB Serial/JTAG only, no USB-UART bridge",
+    ),
+    ("esp8266-nodemcu", "esp8266", "4MB", "preset:singleapp", ""),
+];
+
+/// Where users add their own boards, as `[<name>]` tables
+fn user_registry_path() -> Option<PathBuf> {
+    Some(crate::config_dir()?.join("boards.toml"))
+}
+
+/// Built-in boards plus the user's, which take precedence on equal names.
+/// The second value tells where each board came from.
+pub fn registry() -> Result<BTreeMap<String, (Board, String)>, String> {
+    let mut boards: BTreeMap<String, (Board, String)> = BUILT_IN
+        .iter()
+        .map(|(name, chip, flash_size, partition_table, notes)| {
+            let board = Board {
+                chip: chip.to_string(),
+                flash_size: Some(flash_size.to_string()),
+                partition_table: Some(partition_table.to_string()),
+                bootloader: None,
+                notes: notes.to_string(),
+            };
+            (name.to_string(), (board, String::from("built-in")))
+        })
+        .collect();
+
+    if let Some(path) = user_registry_path().filter(|path| path.exists()) {
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let user: BTreeMap<String, Board> =
+            toml::from_str(&text).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+        let source = path.display().to_string();
+        boards.extend(
+            user.into_iter()
+                .map(|(name, board)| (name, (board, source.clone()))),
+        );
+    }
+
+    Ok(boards)
+}
+
+/// Prints the registry for `--list-boards`
+pub fn print_list() -> Result<(), String> {
+    println!("Available boards (use --board <name>):");
+    for (name, (board, source)) in registry()? {
+        println!(
+            "  {:<24} {:<8} {:<5} {}{}",
+            name,
+            board.chip,
+            board.flash_size.as_deref().unwrap_or("-"),
+            board.partition_table.as_deref().unwrap_or("default table"),
+            if source == "built-in" {
+                String::new()
+            } else {
+                format!(" ({})", source)
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Whether `flag` (long, optionally with `=value`, or short) is among `args`
+/// or set through its `ESP_WEB_FLASH_*` variable
+pub fn given(args: &[OsString], long: &str, short: Option<char>) -> bool {
+    let env = format!(
+        "ESP_WEB_FLASH_{}",
+        long.trim_start_matches('-')
+            .replace('-', "_")
+            .to_uppercase()
+    );
+    std::env::var_os(env).is_some()
+        || args.iter().any(|arg| {
+            let arg = arg.to_string_lossy();
+            arg == long
+                || arg.starts_with(&format!("{}=", long))
+                || short.map_or(false, |short| {
+                    arg.starts_with(&format!("-{}", short)) && !arg.starts_with("--")
+                })
+        })
+}
+
+/// The value of the option `long` in `args`, if any
+pub fn value_of(args: &[OsString], long: &str) -> Option<String> {
+    let mut iter = args.iter().map(|arg| arg.to_string_lossy());
+    while let Some(arg) = iter.next() {
+        if arg == long {
+            return iter.next().map(|value| value.to_string());
+        }
+        if let Some(value) = arg.strip_prefix(&format!("{}=", long)) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// Resolves `--board` and inserts its defaults into `args` (after the
+/// subcommand at index 1) for every flag that isn't given explicitly,
+/// printing what was applied
+pub fn apply(args: &mut Vec<OsString>) -> Result<(), String> {
+    let name = match value_of(args, "--board") {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    let mut boards = registry()?;
+    let (board, source) = boards.remove(&name).ok_or_else(|| {
+        format!(
+            "unknown board '{}' (available: {})",
+            name,
+            registry()
+                .map(|boards| boards.into_keys().collect::<Vec<_>>().join(", "))
+                .unwrap_or_default()
+        )
+    })?;
+
+    // a table only applies if nothing else decides the layout
+    let layout_given = [
+        "--gen-partition-table",
+        "--serve-dir",
+        "--idf-build-dir",
+        "--pio",
+        "--merged-bin",
+    ]
+    .iter()
+    .any(|flag| given(args, flag, None));
+
+    let candidates = [
+        ("--chip", Some('c'), Some(board.chip.clone())),
+        ("--flash-size", Some('f'), board.flash_size.clone()),
+        (
+            "--partition-table",
+            Some('p'),
+            board.partition_table.clone().filter(|_| !layout_given),
+        ),
+        (
+            "--bootloader",
+            Some('b'),
+            board
+                .bootloader
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .filter(|_| !layout_given),
+        ),
+    ];
+
+    status!("Board: {} ({})", name, source);
+    let mut defaults = Vec::new();
+    for (flag, short, value) in candidates {
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        if given(args, flag, short) {
+            status!("  {} {} (overridden)", flag, value);
+        } else {
+            status!("  {} {}", flag, value);
+            defaults.push(OsString::from(flag));
+            defaults.push(OsString::from(value));
+        }
+    }
+    if !board.notes.is_empty() {
+        status!("  Note: {}", board.notes);
+    }
+
+    let at = 2.min(args.len());
+    args.splice(at..at, defaults);
+    Ok(())
+}
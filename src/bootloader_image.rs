@@ -0,0 +1,454 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use espflash::{elf::FirmwareImageBuilder, Chip, FlashSize};
+use sha2::{Digest, Sha256};
+
+use crate::{checksums::Checksums, prepare_error::PrepareError};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const IMAGE_MAGIC: u8 = 0xe9;
+/// Offset of `chip_id` in the image header (after the 8 byte common header
+/// and 4 bytes of SPI pin settings in the extended header)
+const CHIP_ID_OFFSET: usize = 12;
+/// Offset of the flag telling whether a SHA-256 digest follows the image
+const HASH_APPENDED_OFFSET: usize = 23;
+
+/// The `chip_id` the ROM expects in the extended image header
+fn chip_id(chip: Chip) -> Option<u16> {
+    crate::chips::info(chip).image_id
+}
+
+/// Reads the bootloader, converting it to a flashable image first if it's
+/// an ELF (as found next to the .bin in ESP-IDF build trees)
+pub fn load(
+    path: &Path,
+    chip: Chip,
+    flash_size: FlashSize,
+    checksums: &Checksums,
+) -> Result<Vec<u8>, PrepareError> {
+    let invalid = |reason: String| PrepareError::InvalidBootloader {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let raw = checksums.read(path)?;
+
+    let image = if raw.starts_with(ELF_MAGIC) {
+        let firmware = FirmwareImageBuilder::new(&raw)
+            .flash_size(Some(flash_size))
+            .build()
+            .map_err(|e| invalid(format!("failed to parse the ELF: {}", e)))?;
+        let flash_image = chip
+            .get_flash_image(&firmware, None, None, None, None)
+            .map_err(|e| invalid(e.to_string()))?;
+        let converted = flash_image
+            .flash_segments()
+            .last()
+            .ok_or_else(|| invalid(String::from("espflash produced no image for the ELF")))?
+            .data
+            .to_vec();
+
+        status!(
+            "Bootloader {} is an ELF, converted it to a {} byte {} image",
+            path.display(),
+            converted.len(),
+            chip
+        );
+        converted
+    } else {
+        raw
+    };
+
+    validate(&image, chip).map_err(invalid)?;
+    Ok(image)
+}
+
+/// Checks the image magic and the chip id of an app or bootloader image
+pub fn validate(image: &[u8], chip: Chip) -> Result<(), String> {
+    if image.first() != Some(&IMAGE_MAGIC) {
+        return Err(format!(
+            "expected the image magic 0x{:02x}, found {}",
+            IMAGE_MAGIC,
+            image
+                .first()
+                .map_or(String::from("an empty file"), |b| format!("0x{:02x}", b))
+        ));
+    }
+
+    if let Some(expected) = chip_id(chip) {
+        let found = image
+            .get(CHIP_ID_OFFSET..CHIP_ID_OFFSET + 2)
+            .map(|id| u16::from_le_bytes([id[0], id[1]]))
+            .ok_or_else(|| String::from("image header is truncated"))?;
+        if found != expected {
+            return Err(format!(
+                "image is built for chip id {}, but {} has chip id {}",
+                found, chip, expected
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// SPI mode of the flash, byte 2 of the image header
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashMode {
+    Qio,
+    Qout,
+    Dio,
+    Dout,
+}
+
+/// Name of the flash mode byte of an image header
+fn mode_name(b: u8) -> String {
+    match FlashMode::from_repr(b) {
+        Some(mode) => format!("{:?}", mode).to_uppercase(),
+        None => format!("0x{:02x}", b),
+    }
+}
+
+impl FlashMode {
+    fn from_repr(b: u8) -> Option<Self> {
+        [Self::Qio, Self::Qout, Self::Dio, Self::Dout]
+            .get(b as usize)
+            .copied()
+    }
+
+    /// The same mode for espflash's image builder
+    pub fn to_espflash(self) -> espflash::FlashMode {
+        match self {
+            FlashMode::Qio => espflash::FlashMode::Qio,
+            FlashMode::Qout => espflash::FlashMode::Qout,
+            FlashMode::Dio => espflash::FlashMode::Dio,
+            FlashMode::Dout => espflash::FlashMode::Dout,
+        }
+    }
+}
+
+/// SPI clock of the flash, low nibble of byte 3 of the image header
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashFreq {
+    #[value(name = "80m")]
+    Freq80M,
+    #[value(name = "40m")]
+    Freq40M,
+    #[value(name = "26m")]
+    Freq26M,
+    #[value(name = "20m")]
+    Freq20M,
+}
+
+impl FlashFreq {
+    const ALL: [FlashFreq; 4] = [
+        FlashFreq::Freq80M,
+        FlashFreq::Freq40M,
+        FlashFreq::Freq26M,
+        FlashFreq::Freq20M,
+    ];
+
+    fn nibble(self) -> u8 {
+        match self {
+            FlashFreq::Freq40M => 0x0,
+            FlashFreq::Freq26M => 0x1,
+            FlashFreq::Freq20M => 0x2,
+            FlashFreq::Freq80M => 0xf,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FlashFreq::Freq80M => "80m",
+            FlashFreq::Freq40M => "40m",
+            FlashFreq::Freq26M => "26m",
+            FlashFreq::Freq20M => "20m",
+        }
+    }
+
+    /// The same frequency for espflash's image builder
+    pub fn to_espflash(self) -> espflash::FlashFrequency {
+        match self {
+            FlashFreq::Freq80M => espflash::FlashFrequency::Flash80M,
+            FlashFreq::Freq40M => espflash::FlashFrequency::Flash40M,
+            FlashFreq::Freq26M => espflash::FlashFrequency::Flash26M,
+            FlashFreq::Freq20M => espflash::FlashFrequency::Flash20M,
+        }
+    }
+}
+
+/// Flash modes and frequencies the ROM bootloader of `chip` can be told
+/// to use in the image header
+fn supported(chip: Chip) -> (&'static [FlashMode], &'static [FlashFreq]) {
+    const MODES: &[FlashMode] = &[
+        FlashMode::Qio,
+        FlashMode::Qout,
+        FlashMode::Dio,
+        FlashMode::Dout,
+    ];
+    match chip {
+        // the ESP8266 ROM reads everything but QOUT
+        Chip::Esp8266 => (
+            &[FlashMode::Qio, FlashMode::Dio, FlashMode::Dout],
+            &FlashFreq::ALL,
+        ),
+        _ => (MODES, &FlashFreq::ALL),
+    }
+}
+
+/// Rejects a `--flash-mode`/`--flash-freq` the chip can't boot with,
+/// listing the ones it can
+pub fn check_flash_params(
+    chip: Chip,
+    mode: Option<FlashMode>,
+    freq: Option<FlashFreq>,
+) -> Result<(), String> {
+    let (modes, freqs) = supported(chip);
+    let names = |names: Vec<String>| names.join(", ");
+    if let Some(mode) = mode.filter(|mode| !modes.contains(mode)) {
+        return Err(format!(
+            "{} doesn't support flash mode {} (supported: {})",
+            chip,
+            mode_name(mode as u8),
+            names(modes.iter().map(|&m| mode_name(m as u8)).collect())
+        ));
+    }
+    if let Some(freq) = freq.filter(|freq| !freqs.contains(freq)) {
+        return Err(format!(
+            "{} doesn't support flash frequency {} (supported: {})",
+            chip,
+            freq.name(),
+            names(freqs.iter().map(|f| f.name().to_string()).collect())
+        ));
+    }
+    Ok(())
+}
+
+/// Flash mode and frequency in the header of an image, as `DIO` and `40m`
+pub fn flash_params(image: &[u8]) -> Option<(String, String)> {
+    if image.len() < 4 || image[0] != IMAGE_MAGIC {
+        return None;
+    }
+    let freq = FlashFreq::ALL
+        .iter()
+        .find(|freq| freq.nibble() == image[3] & 0x0f)
+        .map_or_else(
+            || format!("0x{:x}", image[3] & 0x0f),
+            |freq| freq.name().to_string(),
+        );
+    Some((mode_name(image[2]), freq))
+}
+
+/// High nibble of byte 3 of the image header for a flash of `bytes`
+fn size_nibble(chip: Chip, bytes: u32) -> Option<u8> {
+    let mb = bytes / 0x10_0000;
+    match chip {
+        // 512KB, 256KB, then 1MB and up
+        Chip::Esp8266 => match mb {
+            1 => Some(2),
+            2 => Some(3),
+            4 => Some(4),
+            8 => Some(8),
+            16 => Some(9),
+            _ => None,
+        },
+        _ => match mb {
+            1 => Some(0),
+            2 => Some(1),
+            4 => Some(2),
+            8 => Some(3),
+            16 => Some(4),
+            32 => Some(5),
+            64 => Some(6),
+            128 => Some(7),
+            _ => None,
+        },
+    }
+}
+
+/// The flash size an image header declares, as `4MB`
+pub fn header_flash_size(image: &[u8], chip: Chip) -> Option<String> {
+    let nibble = image.get(3)? >> 4;
+    [1, 2, 4, 8, 16, 32, 64, 128]
+        .into_iter()
+        .find(|&mb| size_nibble(chip, mb * 0x10_0000) == Some(nibble))
+        .map(|mb| format!("{}MB", mb))
+}
+
+/// Offset of the appended SHA-256 digest: after the header, the segments
+/// and the checksum byte, which ends on a 16 byte boundary
+fn digest_offset(image: &[u8], header_len: usize) -> Option<usize> {
+    let segments = *image.get(1)? as usize;
+    let mut pos = header_len;
+    for _ in 0..segments {
+        let len = image.get(pos + 4..pos + 8)?;
+        pos += 8 + u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    }
+    Some((pos + 16) & !15)
+}
+
+/// Rewrites the flash mode, frequency and size in the bootloader header to
+/// match the flash the image is prepared for, as `esptool.py write_flash`
+/// does, updating the appended digest. Returns a description of each change.
+pub fn patch_flash_params(
+    image: &mut [u8],
+    chip: Chip,
+    flash_size_bytes: u32,
+    mode: Option<FlashMode>,
+    freq: Option<FlashFreq>,
+) -> Result<Vec<String>, String> {
+    if image.len() < 24 {
+        return Err(String::from("image header is truncated"));
+    }
+    let size = size_nibble(chip, flash_size_bytes).ok_or_else(|| {
+        format!(
+            "no header encoding for a {} byte flash on {}",
+            flash_size_bytes, chip
+        )
+    })?;
+
+    let (old_mode, old_params) = (image[2], image[3]);
+    if let Some(mode) = mode {
+        image[2] = mode as u8;
+    }
+    let freq = freq.map_or(old_params & 0x0f, FlashFreq::nibble);
+    image[3] = (size << 4) | freq;
+
+    let mut changes = Vec::new();
+    if image[2] != old_mode {
+        changes.push(format!(
+            "flash mode {} -> {}",
+            mode_name(old_mode),
+            mode_name(image[2])
+        ));
+    }
+    if image[3] & 0x0f != old_params & 0x0f {
+        changes.push(format!(
+            "flash frequency code 0x{:x} -> 0x{:x}",
+            old_params & 0x0f,
+            freq
+        ));
+    }
+    if image[3] >> 4 != old_params >> 4 {
+        changes.push(format!(
+            "flash size code 0x{:x} -> 0x{:x} ({} MB)",
+            old_params >> 4,
+            size,
+            flash_size_bytes / 0x10_0000
+        ));
+    }
+
+    if !changes.is_empty() && update_digest(image, chip)? {
+        changes.push(String::from("appended SHA-256 digest updated"));
+    }
+
+    Ok(changes)
+}
+
+/// Recomputes the SHA-256 digest appended to an image after its header was
+/// changed; false if the image has none
+pub fn update_digest(image: &mut [u8], chip: Chip) -> Result<bool, String> {
+    // the ESP8266 format has neither the extended header nor a digest
+    if chip == Chip::Esp8266 || image.get(HASH_APPENDED_OFFSET) != Some(&1) {
+        return Ok(false);
+    }
+    let offset = digest_offset(image, 24)
+        .filter(|&offset| offset + 32 <= image.len())
+        .ok_or_else(|| String::from("image is shorter than its segments and digest"))?;
+    let digest = Sha256::digest(&image[..offset]);
+    image[offset..offset + 32].copy_from_slice(&digest);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// See tests/fixtures/gen_bootloader.py for how esptool's output was
+    /// obtained
+    const QIO_4MB_40M: &[u8] = include_bytes!("../tests/fixtures/bootloader-esp32-4MB-qio-40m.bin");
+    const DIO_8MB_80M: &[u8] = include_bytes!("../tests/fixtures/bootloader-esp32-8MB-dio-80m.bin");
+    const QIO_16MB_40M: &[u8] =
+        include_bytes!("../tests/fixtures/bootloader-esp32-16MB-qio-40m.bin");
+    const MB: u32 = 0x10_0000;
+
+    #[test]
+    fn matches_esptool_for_new_mode_frequency_and_size() {
+        let mut image = QIO_4MB_40M.to_vec();
+        let changes = patch_flash_params(
+            &mut image,
+            Chip::Esp32,
+            8 * MB,
+            Some(FlashMode::Dio),
+            Some(FlashFreq::Freq80M),
+        )
+        .unwrap();
+        assert_eq!(image, DIO_8MB_80M);
+        assert_eq!(
+            changes,
+            [
+                "flash mode QIO -> DIO",
+                "flash frequency code 0x0 -> 0xf",
+                "flash size code 0x2 -> 0x3 (8 MB)",
+                "appended SHA-256 digest updated",
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_esptool_for_a_new_size_only() {
+        let mut image = QIO_4MB_40M.to_vec();
+        let changes = patch_flash_params(&mut image, Chip::Esp32, 16 * MB, None, None).unwrap();
+        assert_eq!(image, QIO_16MB_40M);
+        assert_eq!(changes.len(), 2);
+    }
+
+    #[test]
+    fn leaves_matching_images_alone() {
+        let mut image = QIO_4MB_40M.to_vec();
+        let changes = patch_flash_params(
+            &mut image,
+            Chip::Esp32,
+            4 * MB,
+            Some(FlashMode::Qio),
+            Some(FlashFreq::Freq40M),
+        )
+        .unwrap();
+        assert!(changes.is_empty());
+        assert_eq!(image, QIO_4MB_40M);
+    }
+
+    #[test]
+    fn digest_covers_the_image_up_to_the_checksum() {
+        let offset = digest_offset(QIO_4MB_40M, 24).unwrap();
+        assert_eq!(offset + 32, QIO_4MB_40M.len());
+        assert_eq!(
+            Sha256::digest(&QIO_4MB_40M[..offset])[..],
+            QIO_4MB_40M[offset..]
+        );
+
+        let mut image = QIO_4MB_40M.to_vec();
+        image[HASH_APPENDED_OFFSET] = 0;
+        assert_eq!(update_digest(&mut image, Chip::Esp32), Ok(false));
+    }
+
+    #[test]
+    fn reads_header_params() {
+        assert_eq!(
+            flash_params(DIO_8MB_80M),
+            Some((String::from("DIO"), String::from("80m")))
+        );
+        assert_eq!(
+            header_flash_size(DIO_8MB_80M, Chip::Esp32).as_deref(),
+            Some("8MB")
+        );
+        assert_eq!(validate(QIO_4MB_40M, Chip::Esp32), Ok(()));
+        assert!(validate(QIO_4MB_40M, Chip::Esp32c3).is_err());
+    }
+
+    #[test]
+    fn rejects_unencodable_sizes() {
+        let mut image = QIO_4MB_40M.to_vec();
+        assert!(patch_flash_params(&mut image, Chip::Esp32, 3 * MB, None, None).is_err());
+        assert_eq!(image, QIO_4MB_40M);
+    }
+}
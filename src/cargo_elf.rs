@@ -0,0 +1,278 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+    time::SystemTime,
+};
+
+use rocket::serde::json;
+use serde::Deserialize;
+
+use crate::{elf_arg, prepare_error::PrepareError};
+
+/// The parts of `cargo metadata` used to find the binaries
+#[derive(Deserialize)]
+struct CargoMetadata {
+    packages: Vec<Package>,
+    target_directory: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+fn metadata() -> Result<CargoMetadata, PrepareError> {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+    let output = Command::new(&cargo)
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .output()
+        .map_err(|e| {
+            PrepareError::CargoDiscovery(format!(
+                "failed to run {} metadata: {}",
+                cargo.to_string_lossy(),
+                e
+            ))
+        })?;
+    if !output.status.success() {
+        return Err(PrepareError::CargoDiscovery(format!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    json::from_slice(&output.stdout).map_err(|e| {
+        PrepareError::CargoDiscovery(format!("unexpected cargo metadata output: {}", e))
+    })
+}
+
+/// The binary to look for: the only one in the workspace, or in `package`
+fn binary(metadata: &CargoMetadata, package: Option<&str>) -> Result<String, PrepareError> {
+    let packages: Vec<&Package> = match package {
+        Some(name) => {
+            let found: Vec<&Package> = metadata
+                .packages
+                .iter()
+                .filter(|p| p.name == name)
+                .collect();
+            if found.is_empty() {
+                return Err(PrepareError::CargoDiscovery(format!(
+                    "no package '{}' in the workspace (packages: {})",
+                    name,
+                    metadata
+                        .packages
+                        .iter()
+                        .map(|p| p.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )));
+            }
+            found
+        }
+        None => metadata.packages.iter().collect(),
+    };
+
+    let bins: Vec<(&str, &str)> = packages
+        .iter()
+        .flat_map(|p| {
+            p.targets
+                .iter()
+                .filter(|t| t.kind.iter().any(|kind| kind == "bin"))
+                .map(move |t| (p.name.as_str(), t.name.as_str()))
+        })
+        .collect();
+    match bins.as_slice() {
+        [] => Err(PrepareError::CargoDiscovery(String::from(
+            "the workspace has no binary target",
+        ))),
+        [(_, bin)] => Ok(bin.to_string()),
+        _ => Err(PrepareError::CargoDiscovery(format!(
+            "several binaries, pick a package with --package:\n{}",
+            bins.iter()
+                .map(|(package, bin)| format!("  {} (package {})", bin, package))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))),
+    }
+}
+
+/// `<target>/<profile>/<bin>` and `<target>/<triple>/<profile>/<bin>`
+fn candidates(target_dir: &Path, bin: &str) -> Vec<(PathBuf, SystemTime)> {
+    let subdirs = |dir: &Path| -> Vec<PathBuf> {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_dir())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut dirs = Vec::new();
+    for dir in subdirs(target_dir) {
+        dirs.extend(subdirs(&dir));
+        dirs.push(dir);
+    }
+    dirs.into_iter()
+        .map(|dir| dir.join(bin))
+        .filter(|path| elf_arg::is_elf(path))
+        .filter_map(|path| {
+            let modified = std::fs::metadata(&path)
+                .and_then(|meta| meta.modified())
+                .ok()?;
+            Some((path, modified))
+        })
+        .collect()
+}
+
+/// Finds the most recently built ELF of the package in the current
+/// directory, `--cargo`
+pub fn discover(package: Option<&str>) -> Result<PathBuf, PrepareError> {
+    newest_build(&metadata()?, package)
+}
+
+fn newest_build(metadata: &CargoMetadata, package: Option<&str>) -> Result<PathBuf, PrepareError> {
+    let bin = binary(metadata, package)?;
+    let found = candidates(&metadata.target_directory, &bin);
+
+    let newest = found.iter().map(|(_, time)| *time).max().ok_or_else(|| {
+        PrepareError::CargoDiscovery(format!(
+            "no built '{}' below {}, run cargo build first",
+            bin,
+            metadata.target_directory.display()
+        ))
+    })?;
+    let mut chosen: Vec<&PathBuf> = found
+        .iter()
+        .filter(|(_, time)| *time == newest)
+        .map(|(path, _)| path)
+        .collect();
+    if chosen.len() > 1 {
+        chosen.sort();
+        return Err(PrepareError::CargoDiscovery(format!(
+            "{} builds of '{}' are equally new:\n{}",
+            chosen.len(),
+            bin,
+            chosen
+                .iter()
+                .map(|path| format!("  {}", path.display()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )));
+    }
+
+    let path = chosen[0].clone();
+    status!(
+        "Cargo: {} is the newest of {} build(s) of '{}'",
+        path.display(),
+        found.len(),
+        bin
+    );
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::*;
+
+    /// `cargo metadata` of a workspace with a firmware and a tools package
+    const METADATA: &str = include_str!("../tests/fixtures/cargo-metadata.json");
+
+    /// A target directory holding `files` (path, contents, modification
+    /// time in seconds since the epoch), and the metadata pointing at it
+    fn workspace(name: &str, files: &[(&str, &[u8], u64)]) -> CargoMetadata {
+        let dir = std::env::temp_dir().join(format!("cargo-elf-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        for &(path, contents, secs) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        }
+        let mut metadata: CargoMetadata = json::from_str(METADATA).unwrap();
+        metadata.target_directory = dir;
+        metadata
+    }
+
+    const ELF: &[u8] = b"\x7fELF\x01\x01\x01";
+
+    fn message(e: PrepareError) -> String {
+        match e {
+            PrepareError::CargoDiscovery(message) => message,
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn picks_the_binary_of_the_package() {
+        let metadata = workspace("binary", &[]);
+        assert_eq!(binary(&metadata, Some("blink")).unwrap(), "blink");
+        assert_eq!(binary(&metadata, Some("tools")).unwrap(), "provision");
+
+        let e = message(binary(&metadata, None).unwrap_err());
+        assert!(e.contains("blink (package blink)"), "{}", e);
+        assert!(e.contains("provision (package tools)"), "{}", e);
+        let e = message(binary(&metadata, Some("nope")).unwrap_err());
+        assert!(e.contains("packages: blink, blink-hal, tools"), "{}", e);
+        assert!(binary(&metadata, Some("blink-hal")).is_err());
+    }
+
+    #[test]
+    fn finds_the_newest_build() {
+        let metadata = workspace(
+            "newest",
+            &[
+                ("debug/blink", ELF, 100),
+                ("xtensa-esp32-espidf/debug/blink", ELF, 200),
+                ("xtensa-esp32-espidf/release/blink", ELF, 300),
+                // not an ELF, and too deep
+                ("release/blink", b"#!/bin/sh", 400),
+                ("xtensa-esp32-espidf/release/deps/blink", ELF, 500),
+            ],
+        );
+        assert_eq!(
+            newest_build(&metadata, Some("blink")).unwrap(),
+            metadata
+                .target_directory
+                .join("xtensa-esp32-espidf/release/blink")
+        );
+    }
+
+    #[test]
+    fn equally_new_builds_are_ambiguous() {
+        let metadata = workspace(
+            "ambiguous",
+            &[
+                ("riscv32imc-esp-espidf/release/blink", ELF, 100),
+                ("xtensa-esp32-espidf/release/blink", ELF, 100),
+            ],
+        );
+        let e = message(newest_build(&metadata, Some("blink")).unwrap_err());
+        assert!(e.contains("2 builds of 'blink' are equally new"), "{}", e);
+    }
+
+    #[test]
+    fn nothing_built_yet() {
+        let metadata = workspace("unbuilt", &[("debug/provision", ELF, 100)]);
+        let e = message(newest_build(&metadata, Some("blink")).unwrap_err());
+        assert!(e.contains("run cargo build first"), "{}", e);
+    }
+}
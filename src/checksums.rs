@@ -0,0 +1,198 @@
+use std::{
+    cell::RefCell,
+    fs::File,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use memmap2::{MmapMut, MmapOptions};
+use rocket::request::FromParam;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::prepare_error::PrepareError;
+
+/// `--expect-sha256 <file>=<hex>`
+#[derive(Debug, Clone)]
+pub struct ExpectSha256 {
+    pub file: String,
+    pub sha256: String,
+}
+
+fn parse_digest(hex: &str) -> Option<String> {
+    (hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit())).then(|| hex.to_lowercase())
+}
+
+impl FromStr for ExpectSha256 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (file, hex) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected <file>=<sha256>, got '{}'", s))?;
+        let sha256 = parse_digest(hex)
+            .ok_or_else(|| format!("'{}' is not a SHA-256 digest (64 hex digits)", hex))?;
+        Ok(ExpectSha256 {
+            file: file.to_string(),
+            sha256,
+        })
+    }
+}
+
+/// Lowercase hex SHA-256 of `data`, as printed by sha256sum
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// SHA-256 of a served file, for `/checksums.txt` and `/info`
+#[derive(Serialize, Debug, Clone)]
+pub struct FileDigest {
+    pub file: String,
+    /// lowercase hex
+    pub sha256: String,
+}
+
+impl FileDigest {
+    /// `<hex>  <file>`, a line of sha256sum output
+    pub fn line(&self) -> String {
+        format!("{}  {}\n", self.sha256, self.file)
+    }
+}
+
+/// `<file>.sha256`; any other name forwards to the next route
+pub struct Sha256File<'a>(pub &'a str);
+
+impl<'a> FromParam<'a> for Sha256File<'a> {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        match param.strip_suffix(".sha256") {
+            Some(file) if !file.is_empty() => Ok(Sha256File(file)),
+            _ => Err(param),
+        }
+    }
+}
+
+/// Expected digests of the inputs, checked as each input is read
+pub struct Checksums {
+    expected: Vec<ExpectSha256>,
+    /// inputs whose digest matched, for `/info`
+    verified: RefCell<Vec<String>>,
+    used: RefCell<Vec<bool>>,
+}
+
+impl Checksums {
+    pub fn new(
+        expect: &[ExpectSha256],
+        checksums_file: Option<&Path>,
+    ) -> Result<Self, PrepareError> {
+        let mut expected = expect.to_vec();
+        if let Some(path) = checksums_file {
+            let text = std::fs::read_to_string(path).map_err(|source| PrepareError::Read {
+                path: path.to_path_buf(),
+                source,
+            })?;
+            expected.extend(parse_sha256sum(&text).map_err(|reason| {
+                PrepareError::InvalidChecksums {
+                    path: path.to_path_buf(),
+                    reason,
+                }
+            })?);
+        }
+
+        let used = RefCell::new(vec![false; expected.len()]);
+        Ok(Checksums {
+            expected,
+            verified: RefCell::new(Vec::new()),
+            used,
+        })
+    }
+
+    /// Reads an input, refusing it if its digest doesn't match the expected one
+    pub fn read(&self, path: &Path) -> Result<Vec<u8>, PrepareError> {
+        let data = std::fs::read(path).map_err(|source| PrepareError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        self.check(path, &data)?;
+        Ok(data)
+    }
+
+    /// Maps an input copy-on-write instead of reading it, so a large ELF
+    /// only occupies RAM for the pages that are touched or patched
+    pub fn map(&self, path: &Path) -> Result<MmapMut, PrepareError> {
+        let read_error = |source| PrepareError::Read {
+            path: path.to_path_buf(),
+            source,
+        };
+        let file = File::open(path).map_err(read_error)?;
+        // SAFETY: the private mapping is never written through to the file,
+        // but the file must not be truncated while it's mapped; inputs are
+        // build outputs nothing else writes to while we prepare
+        let data = unsafe { MmapOptions::new().map_copy(&file) }.map_err(read_error)?;
+        self.check(path, &data)?;
+        Ok(data)
+    }
+
+    fn check(&self, path: &Path, data: &[u8]) -> Result<(), PrepareError> {
+        if let Some(i) = self.expected.iter().position(|e| matches(&e.file, path)) {
+            let actual = sha256_hex(data);
+            if actual != self.expected[i].sha256 {
+                return Err(PrepareError::ChecksumMismatch {
+                    path: path.to_path_buf(),
+                    expected: self.expected[i].sha256.clone(),
+                    actual,
+                });
+            }
+            self.used.borrow_mut()[i] = true;
+            self.verified.borrow_mut().push(path.display().to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn verified(&self) -> Vec<String> {
+        self.verified.borrow().clone()
+    }
+
+    /// Expectations that matched none of the inputs, likely a typo
+    pub fn unused(&self) -> Vec<String> {
+        self.expected
+            .iter()
+            .zip(self.used.borrow().iter())
+            .filter(|(_, used)| !**used)
+            .map(|(e, _)| e.file.clone())
+            .collect()
+    }
+}
+
+/// An expectation names either the path as given or, without a directory,
+/// just the file name, as in sha256sum output
+fn matches(file: &str, path: &Path) -> bool {
+    let expected = PathBuf::from(file);
+    expected == path
+        || (expected.parent() == Some(Path::new("")) && path.file_name() == expected.file_name())
+}
+
+/// Parses `sha256sum` output: `<hex>  <file>` or `<hex> *<file>` per line
+fn parse_sha256sum(text: &str) -> Result<Vec<ExpectSha256>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            let (hex, file) = line
+                .split_once(' ')
+                .ok_or_else(|| format!("line {} is not '<sha256>  <file>'", i + 1))?;
+            let sha256 = parse_digest(hex)
+                .ok_or_else(|| format!("line {}: '{}' is not a SHA-256 digest", i + 1, hex))?;
+            let file = file.trim_start_matches([' ', '*']);
+            Ok(ExpectSha256 {
+                file: file.to_string(),
+                sha256,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,185 @@
+use std::ops::Range;
+
+use espflash::Chip;
+use xmas_elf::{program::Type, ElfFile};
+
+use crate::{app_image, chip_name, chips};
+
+/// Offset of e_machine in the ELF header
+const E_MACHINE: usize = 18;
+const EM_XTENSA: u16 = 94;
+const EM_RISCV: u16 = 243;
+
+/// Where the Xtensa chips map flash data (.rodata), which tells them apart
+const XTENSA_DROM: &[(Chip, Range<u32>)] = &[
+    (Chip::Esp32, 0x3f40_0000..0x3f80_0000),
+    (Chip::Esp32s2, 0x3f00_0000..0x3f40_0000),
+    (Chip::Esp32s3, 0x3c00_0000..0x3e00_0000),
+];
+/// The ESP8266 maps flash code only, no data
+const ESP8266_IROM: Range<u32> = 0x4020_0000..0x4030_0000;
+
+/// Set in the extended header of ESP32-family app images, after which the
+/// chip id follows
+const WP_PIN_DISABLED: u8 = 0xee;
+
+fn is_riscv(chip: Chip) -> bool {
+    chips::info(chip).riscv
+}
+
+fn architecture(riscv: bool) -> &'static str {
+    if riscv {
+        "RISC-V"
+    } else {
+        "Xtensa"
+    }
+}
+
+/// The chip an app image's extended header names
+fn from_app_image(image: &[u8]) -> Result<Chip, String> {
+    let id = image
+        .get(12..14)
+        .filter(|_| image.get(8) == Some(&WP_PIN_DISABLED))
+        .map(|id| u16::from_le_bytes([id[0], id[1]]))
+        .ok_or_else(|| String::from("the app image names no chip, pass --chip"))?;
+    chips::by_image_id(id)
+        .ok_or_else(|| format!("the app image is for unknown chip id {}, pass --chip", id))
+}
+
+/// Whether the ELF is RISC-V, and the chips its loadable segments fit
+fn inspect(elf: &[u8]) -> Result<(bool, Vec<Chip>), String> {
+    let file = ElfFile::new(elf).map_err(|e| format!("failed to parse the ELF: {}", e))?;
+    let machine = elf
+        .get(E_MACHINE..E_MACHINE + 2)
+        .map_or(0, |m| u16::from_le_bytes([m[0], m[1]]));
+    let riscv = match machine {
+        EM_RISCV => true,
+        EM_XTENSA => false,
+        machine => {
+            return Err(format!(
+                "the ELF is for machine {}, neither Xtensa nor RISC-V",
+                machine
+            ))
+        }
+    };
+    if riscv {
+        // their address maps overlap, so any RISC-V chip may be the one
+        let riscv_chips = chips::CHIPS
+            .iter()
+            .filter(|info| info.riscv)
+            .map(|info| info.chip)
+            .collect();
+        return Ok((true, riscv_chips));
+    }
+
+    let addrs: Vec<u32> = file
+        .program_iter()
+        .filter(|ph| matches!(ph.get_type(), Ok(Type::Load)) && ph.mem_size() > 0)
+        .map(|ph| ph.virtual_addr() as u32)
+        .collect();
+    let mut chips: Vec<Chip> = XTENSA_DROM
+        .iter()
+        .filter(|(_, range)| addrs.iter().any(|addr| range.contains(addr)))
+        .map(|(chip, _)| *chip)
+        .collect();
+    if chips.is_empty() && addrs.iter().any(|addr| ESP8266_IROM.contains(addr)) {
+        chips.push(Chip::Esp8266);
+    }
+    Ok((false, chips))
+}
+
+/// Picks the chip from the ELF's machine and where its segments are
+/// mapped, or from the header of an app image
+pub fn detect(data: &[u8]) -> Result<Chip, String> {
+    if app_image::is_app_image(data) {
+        return from_app_image(data);
+    }
+    match inspect(data)? {
+        (_, chips) if chips.len() == 1 => Ok(chips[0]),
+        (riscv, chips) if chips.is_empty() => Err(format!(
+            "can't tell which {} chip the ELF is for, pass --chip",
+            architecture(riscv)
+        )),
+        (_, chips) => Err(format!(
+            "the ELF could be for {}, pass --chip",
+            chips
+                .iter()
+                .map(|chip| chip_name(*chip))
+                .collect::<Vec<_>>()
+                .join(" or ")
+        )),
+    }
+}
+
+/// Checks `--chip` against the ELF: a different architecture can never
+/// boot, a different chip of the same one is only warned about
+pub fn check(data: &[u8], chip: Chip) -> Result<(), String> {
+    if app_image::is_app_image(data) {
+        // the app image header is checked against the chip when preparing
+        return Ok(());
+    }
+    let (riscv, chips) = inspect(data)?;
+    if riscv != is_riscv(chip) {
+        return Err(format!(
+            "--chip {} is {}, but the ELF is built for {}; the image would never boot",
+            chip_name(chip),
+            architecture(is_riscv(chip)),
+            architecture(riscv)
+        ));
+    }
+    if let [detected] = chips.as_slice() {
+        if *detected != chip {
+            warning!(
+                "the ELF's memory map looks like {}, not {}",
+                chip_name(*detected),
+                chip_name(chip)
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// See tests/fixtures/gen_elf.py
+    const ESP32_ELF: &[u8] = include_bytes!("../tests/fixtures/esp32-app.elf");
+    const ESP32C3_ELF: &[u8] = include_bytes!("../tests/fixtures/esp32c3-app.elf");
+    const ESP32S3_ELF: &[u8] = include_bytes!("../tests/fixtures/esp32s3-app.elf");
+    /// An app image header naming chip id 0
+    const ESP32_IMAGE: &[u8] = include_bytes!("../tests/fixtures/bootloader-esp32-4MB-qio-40m.bin");
+
+    #[test]
+    fn detects_the_chip_of_an_elf() {
+        assert_eq!(detect(ESP32_ELF), Ok(Chip::Esp32));
+        assert_eq!(detect(ESP32C3_ELF), Ok(Chip::Esp32c3));
+        assert_eq!(detect(ESP32S3_ELF), Ok(Chip::Esp32s3));
+    }
+
+    #[test]
+    fn detects_the_chip_of_an_app_image() {
+        assert_eq!(detect(ESP32_IMAGE), Ok(Chip::Esp32));
+    }
+
+    #[test]
+    fn rejects_other_machines() {
+        let mut elf = ESP32_ELF.to_vec();
+        // EM_X86_64
+        elf[E_MACHINE..E_MACHINE + 2].copy_from_slice(&62u16.to_le_bytes());
+        assert!(detect(&elf).unwrap_err().contains("machine 62"));
+        assert!(detect(b"not an elf").is_err());
+    }
+
+    #[test]
+    fn chip_of_another_architecture_never_boots() {
+        let e = check(ESP32_ELF, Chip::Esp32c3).unwrap_err();
+        assert!(e.contains("the ELF is built for Xtensa"), "{}", e);
+        let e = check(ESP32C3_ELF, Chip::Esp32).unwrap_err();
+        assert!(e.contains("the ELF is built for RISC-V"), "{}", e);
+
+        assert_eq!(check(ESP32_ELF, Chip::Esp32), Ok(()));
+        // same architecture, only warned about
+        assert_eq!(check(ESP32_ELF, Chip::Esp32s3), Ok(()));
+    }
+}
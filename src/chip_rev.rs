@@ -0,0 +1,156 @@
+use std::fmt;
+
+use espflash::Chip;
+
+use crate::{bootloader_image, chip_name};
+
+/// `min_chip_rev`, the revision as the ROMs that predate the full field
+/// read it
+const LEGACY_OFFSET: usize = 14;
+/// `min_chip_rev_full`, `major * 100 + minor`
+const FULL_OFFSET: usize = 15;
+
+/// A chip revision, `v<major>.<minor>` as esptool prints it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChipRev {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl fmt::Display for ChipRev {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}.{}", self.major, self.minor)
+    }
+}
+
+/// Parses a `--min-chip-rev` value, e.g. `v3.0` or `0.3`
+pub fn parse(s: &str) -> Result<ChipRev, String> {
+    let invalid = || format!("expected <major>.<minor>, e.g. v3.0 or 0.3, got '{}'", s);
+    let (major, minor) = s
+        .strip_prefix('v')
+        .unwrap_or(s)
+        .split_once('.')
+        .ok_or_else(invalid)?;
+    let rev = ChipRev {
+        major: major.parse().map_err(|_| invalid())?,
+        minor: minor.parse().map_err(|_| invalid())?,
+    };
+    if rev.minor > 99 || rev.major > 255 {
+        return Err(format!(
+            "{} doesn't fit the image header (major up to 255, minor up to 99)",
+            rev
+        ));
+    }
+    Ok(rev)
+}
+
+/// `min_chip_rev` for `rev` on `chip`: the ESP32 counts it in majors, the
+/// later chips like the full field, which has to fit its byte then
+fn legacy(rev: ChipRev, chip: Chip) -> Result<u8, String> {
+    let value = match chip {
+        Chip::Esp8266 => {
+            return Err(String::from(
+                "the ESP8266 image header has no chip revision, drop --min-chip-rev",
+            ))
+        }
+        Chip::Esp32 => rev.major,
+        _ => rev.major * 100 + rev.minor,
+    };
+    u8::try_from(value).map_err(|_| format!("{} can't be expressed for {}", rev, chip_name(chip)))
+}
+
+/// Whether `chip`'s image header can carry `rev`
+pub fn check(rev: ChipRev, chip: Chip) -> Result<(), String> {
+    legacy(rev, chip).map(|_| ())
+}
+
+/// The minimum revision an app image's header asks for, `None` if any
+/// revision boots it
+pub fn read(image: &[u8], chip: Chip) -> Option<ChipRev> {
+    if chip == Chip::Esp8266 || !crate::app_image::is_app_image(image) {
+        return None;
+    }
+    let full = image.get(FULL_OFFSET..FULL_OFFSET + 2)?;
+    let full = u16::from_le_bytes([full[0], full[1]]);
+    (full > 0).then(|| ChipRev {
+        major: full / 100,
+        minor: full % 100,
+    })
+}
+
+/// Writes `rev` into both revision fields of an app image header, updating
+/// the appended digest
+pub fn patch(image: &mut [u8], chip: Chip, rev: ChipRev) -> Result<(), String> {
+    let legacy = legacy(rev, chip)?;
+    if image.len() < 24 {
+        return Err(String::from("image header is truncated"));
+    }
+    image[LEGACY_OFFSET] = legacy;
+    image[FULL_OFFSET..FULL_OFFSET + 2]
+        .copy_from_slice(&(rev.major * 100 + rev.minor).to_le_bytes());
+    bootloader_image::update_digest(image, chip)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use espflash::elf::FirmwareImageBuilder;
+
+    use super::*;
+
+    /// The app image espflash builds from a fixture ELF, see
+    /// tests/fixtures/gen_elf.py
+    fn app_image(elf: &[u8], chip: Chip) -> Vec<u8> {
+        let firmware = FirmwareImageBuilder::new(elf).build().unwrap();
+        let image = chip
+            .get_flash_image(&firmware, None, None, None, None)
+            .unwrap();
+        let app = image.flash_segments().last().unwrap();
+        app.data.to_vec()
+    }
+
+    #[test]
+    fn revision_round_trips_through_the_app_image() {
+        let elf = include_bytes!("../tests/fixtures/esp32c3-app.elf");
+        let mut image = app_image(elf, Chip::Esp32c3);
+        let rev = parse("v0.3").unwrap();
+        patch(&mut image, Chip::Esp32c3, rev).unwrap();
+
+        assert_eq!(read(&image, Chip::Esp32c3), Some(rev));
+        assert_eq!(image[LEGACY_OFFSET], 3);
+        assert_eq!(image[FULL_OFFSET..FULL_OFFSET + 2], 3u16.to_le_bytes());
+        // the appended digest already covers the patched header
+        let mut redigested = image.clone();
+        bootloader_image::update_digest(&mut redigested, Chip::Esp32c3).unwrap();
+        assert_eq!(redigested, image);
+    }
+
+    #[test]
+    fn esp32_counts_majors_in_the_legacy_field() {
+        let elf = include_bytes!("../tests/fixtures/esp32-app.elf");
+        let mut image = app_image(elf, Chip::Esp32);
+        let rev = parse("3.1").unwrap();
+        patch(&mut image, Chip::Esp32, rev).unwrap();
+        assert_eq!(read(&image, Chip::Esp32), Some(rev));
+        assert_eq!(image[LEGACY_OFFSET], 3);
+        assert_eq!(image[FULL_OFFSET..FULL_OFFSET + 2], 301u16.to_le_bytes());
+    }
+
+    #[test]
+    fn rejects_revisions_the_chip_cant_express() {
+        let rev = parse("v3.0").unwrap();
+        assert_eq!(check(rev, Chip::Esp32), Ok(()));
+        assert!(check(rev, Chip::Esp32c3).is_err());
+        assert!(check(parse("0.3").unwrap(), Chip::Esp8266).is_err());
+    }
+
+    #[test]
+    fn parses_revisions() {
+        assert_eq!(parse("v3.0"), Ok(ChipRev { major: 3, minor: 0 }));
+        assert_eq!(parse("0.3"), Ok(ChipRev { major: 0, minor: 3 }));
+        assert_eq!(parse("v1.2").unwrap().to_string(), "v1.2");
+        assert!(parse("3").is_err());
+        assert!(parse("v0.100").is_err());
+        assert!(parse("x.y").is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use espflash::Chip;
+
+/// What this crate needs to know about a chip, one row per chip
+pub struct ChipInfo {
+    pub chip: Chip,
+    /// value `--chip` takes
+    pub arg: &'static str,
+    /// esp-web-tools' `chipFamily`, also shown on the page and in /info
+    pub name: &'static str,
+    /// where the ROM loads the second stage bootloader from
+    pub bootloader_offset: u32,
+    /// `chip_id` in the extended image header; the ESP8266 format has none
+    pub image_id: Option<u16>,
+    pub riscv: bool,
+}
+
+pub const CHIPS: &[ChipInfo] = &[
+    ChipInfo {
+        chip: Chip::Esp32,
+        arg: "esp32",
+        name: "ESP32",
+        bootloader_offset: 0x1000,
+        image_id: Some(0),
+        riscv: false,
+    },
+    ChipInfo {
+        chip: Chip::Esp32s2,
+        arg: "esp32s2",
+        name: "ESP32-S2",
+        bootloader_offset: 0x1000,
+        image_id: Some(2),
+        riscv: false,
+    },
+    ChipInfo {
+        chip: Chip::Esp32c3,
+        arg: "esp32c3",
+        name: "ESP32-C3",
+        bootloader_offset: 0x0,
+        image_id: Some(5),
+        riscv: true,
+    },
+    ChipInfo {
+        chip: Chip::Esp32s3,
+        arg: "esp32s3",
+        name: "ESP32-S3",
+        bootloader_offset: 0x0,
+        image_id: Some(9),
+        riscv: false,
+    },
+    ChipInfo {
+        chip: Chip::Esp8266,
+        arg: "esp8266",
+        name: "ESP8266",
+        bootloader_offset: 0x0,
+        image_id: None,
+        riscv: false,
+    },
+];
+
+pub fn info(chip: Chip) -> &'static ChipInfo {
+    CHIPS
+        .iter()
+        .find(|info| info.chip == chip)
+        .expect("every chip espflash supports has a row in CHIPS")
+}
+
+/// The chip an image header's `chip_id` is for
+pub fn by_image_id(id: u16) -> Option<Chip> {
+    CHIPS
+        .iter()
+        .find(|info| info.image_id == Some(id))
+        .map(|info| info.chip)
+}
+
+/// Parser for `--chip`, listing the values so shell completions offer them
+pub fn arg_parser() -> impl TypedValueParser<Value = Chip> {
+    PossibleValuesParser::new(CHIPS.iter().map(|info| info.arg)).map(|arg| {
+        CHIPS
+            .iter()
+            .find(|info| info.arg == arg)
+            .expect("only the listed values are accepted")
+            .chip
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bootloader_offsets() {
+        // the same offsets espflash writes the bootloader to
+        assert_eq!(info(Chip::Esp32).bootloader_offset, 0x1000);
+        assert_eq!(info(Chip::Esp32s2).bootloader_offset, 0x1000);
+        assert_eq!(info(Chip::Esp32c3).bootloader_offset, 0x0);
+        assert_eq!(info(Chip::Esp32s3).bootloader_offset, 0x0);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::telemetry;
+
+/// Largest log a page may send, in bytes
+pub const MAX_LOG_SIZE: u64 = 1024 * 1024;
+
+/// One line of the page's console
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LogEntry {
+    pub timestamp: String,
+    /// `info`, `success`, `warning`, `error` or `progress`
+    pub level: String,
+    pub message: String,
+}
+
+/// How the flash the log belongs to ended
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum FinalState {
+    Finished,
+    Error,
+}
+
+/// The body of `POST /logs`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClientLog {
+    /// the flash session, as in the manifest's part URLs
+    pub session: Option<String>,
+    pub state: FinalState,
+    pub entries: Vec<LogEntry>,
+}
+
+/// What `POST /logs` answers with
+#[derive(Serialize, Debug)]
+pub struct Saved {
+    pub file: String,
+}
+
+/// `--log-dir`, where the logs the pages send are kept
+#[derive(Clone)]
+pub struct ClientLogs {
+    dir: PathBuf,
+}
+
+impl ClientLogs {
+    pub fn create(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        status!("Client logs: {}", dir.display());
+        Ok(ClientLogs {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Writes `log` to a new file named after the time and the session,
+    /// never replacing an existing one. The session is only used in the
+    /// name if it's one the page could have generated.
+    pub fn save(&self, log: &ClientLog) -> std::io::Result<String> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let session = telemetry::session_id(log.session.as_deref()).unwrap_or("no-session");
+        let mut n = 0;
+        loop {
+            let file = match n {
+                0 => format!("{}-{}.json", millis, session),
+                n => format!("{}-{}-{}.json", millis, session, n),
+            };
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.dir.join(&file))
+            {
+                Ok(mut out) => {
+                    let json = rocket::serde::json::to_string(log)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                    out.write_all(json.as_bytes())?;
+                    return Ok(file);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && n < 100 => n += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
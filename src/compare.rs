@@ -0,0 +1,295 @@
+use std::{collections::BTreeMap, fs::File, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use memmap2::Mmap;
+use rocket::serde::json;
+use serde::Serialize;
+use xmas_elf::{
+    sections::{ShType, SHF_ALLOC},
+    symbol_table::Type,
+    ElfFile,
+};
+
+use crate::{
+    fota::{self, AppDesc},
+    symbols, OutputFormat,
+};
+
+const IMAGE_MAGIC: u8 = 0xe9;
+/// Size of the app image header, followed by the segments
+const IMAGE_HEADER_LEN: usize = 24;
+/// Sections the app descriptor is linked into, by ESP-IDF 5 and 4
+const APP_DESC_SECTIONS: &[&str] = &[".flash.appdesc", ".rodata_desc"];
+
+/// What `compare` looks at in one build
+struct Build {
+    /// loaded bytes of an ELF, or the size of an app image
+    total: u64,
+    /// allocated sections of an ELF, or segments of an app image
+    sections: BTreeMap<String, u64>,
+    /// demangled name to size; `None` for app images, which have no symbols
+    symbols: Option<BTreeMap<String, u64>>,
+    desc: Option<AppDesc>,
+}
+
+impl Build {
+    fn load(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        // SAFETY: read-only mapping of a build output, dropped before returning
+        let data = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Failed to map {}", path.display()))?;
+
+        if data.starts_with(b"\x7fELF") {
+            Self::from_elf(&data).with_context(|| format!("Invalid ELF {}", path.display()))
+        } else if data.first() == Some(&IMAGE_MAGIC) {
+            Self::from_image(&data).with_context(|| format!("Invalid app image {}", path.display()))
+        } else {
+            bail!("{} is neither an ELF nor an app image", path.display())
+        }
+    }
+
+    fn from_elf(elf: &[u8]) -> Result<Self> {
+        let file = ElfFile::new(elf).map_err(|e| anyhow!("{}", e))?;
+
+        let mut total = 0;
+        let mut sections = BTreeMap::new();
+        let mut desc = None;
+        for section in file.section_iter() {
+            let name = section.get_name(&file).unwrap_or("?");
+            if APP_DESC_SECTIONS.contains(&name) {
+                desc = AppDesc::parse(section.raw_data(&file));
+            }
+            if section.flags() & SHF_ALLOC == 0 || section.size() == 0 {
+                continue;
+            }
+            if section.get_type() != Ok(ShType::NoBits) {
+                total += section.size();
+            }
+            *sections.entry(name.to_string()).or_default() += section.size();
+        }
+
+        let mut symbols = BTreeMap::new();
+        for (_, size, name) in symbols::sized(&file, &[Type::Func, Type::Object]) {
+            *symbols.entry(name).or_default() += u64::from(size);
+        }
+
+        Ok(Build {
+            total,
+            sections,
+            symbols: Some(symbols),
+            desc,
+        })
+    }
+
+    fn from_image(image: &[u8]) -> Result<Self> {
+        let count = image
+            .get(1)
+            .copied()
+            .ok_or_else(|| anyhow!("truncated header"))?;
+        let mut sections = BTreeMap::new();
+        let mut offset = IMAGE_HEADER_LEN;
+        for i in 0..count {
+            let header = image
+                .get(offset..offset + 8)
+                .ok_or_else(|| anyhow!("segment {} is truncated", i))?;
+            let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            // by index, as load addresses can move between builds
+            sections.insert(format!("segment {}", i), u64::from(len));
+            offset += 8 + len as usize;
+        }
+
+        Ok(Build {
+            total: image.len() as u64,
+            sections,
+            symbols: None,
+            desc: fota::app_desc(image),
+        })
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SizeDelta {
+    pub name: String,
+    /// `None` if it didn't exist in that build
+    pub old: Option<u64>,
+    pub new: Option<u64>,
+    pub delta: i64,
+}
+
+impl SizeDelta {
+    fn new(name: &str, old: Option<u64>, new: Option<u64>) -> Self {
+        SizeDelta {
+            name: name.to_string(),
+            old,
+            new,
+            delta: new.unwrap_or(0) as i64 - old.unwrap_or(0) as i64,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SymbolChanges {
+    pub grew: Vec<SizeDelta>,
+    pub shrank: Vec<SizeDelta>,
+    pub appeared: Vec<SizeDelta>,
+    pub disappeared: Vec<SizeDelta>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DescChange {
+    pub field: &'static str,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Comparison {
+    pub old: String,
+    pub new: String,
+    pub total: SizeDelta,
+    /// every section or segment whose size changed
+    pub sections: Vec<SizeDelta>,
+    /// `None` unless both builds are ELF files
+    pub symbols: Option<SymbolChanges>,
+    pub app_desc: Vec<DescChange>,
+}
+
+fn diff(old: &BTreeMap<String, u64>, new: &BTreeMap<String, u64>) -> Vec<SizeDelta> {
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| SizeDelta::new(name, old.get(name).copied(), new.get(name).copied()))
+        .filter(|delta| delta.delta != 0 || delta.old.is_none() != delta.new.is_none())
+        .collect()
+}
+
+fn symbol_changes(
+    old: &BTreeMap<String, u64>,
+    new: &BTreeMap<String, u64>,
+    top: usize,
+) -> SymbolChanges {
+    let mut changes = SymbolChanges {
+        grew: Vec::new(),
+        shrank: Vec::new(),
+        appeared: Vec::new(),
+        disappeared: Vec::new(),
+    };
+    for delta in diff(old, new) {
+        match (delta.old, delta.new) {
+            (None, _) => changes.appeared.push(delta),
+            (_, None) => changes.disappeared.push(delta),
+            _ if delta.delta > 0 => changes.grew.push(delta),
+            _ => changes.shrank.push(delta),
+        }
+    }
+    for list in [
+        &mut changes.grew,
+        &mut changes.shrank,
+        &mut changes.appeared,
+        &mut changes.disappeared,
+    ] {
+        list.sort_by_key(|delta| std::cmp::Reverse(delta.delta.abs()));
+        list.truncate(top);
+    }
+    changes
+}
+
+fn desc_changes(old: Option<&AppDesc>, new: Option<&AppDesc>) -> Vec<DescChange> {
+    let fields: [(&'static str, fn(&AppDesc) -> &String); 6] = [
+        ("version", |desc| &desc.version),
+        ("project_name", |desc| &desc.project_name),
+        ("idf_version", |desc| &desc.idf_version),
+        ("compile_date", |desc| &desc.date),
+        ("compile_time", |desc| &desc.time),
+        ("app_elf_sha256", |desc| &desc.app_elf_sha256),
+    ];
+    fields
+        .into_iter()
+        .filter_map(|(field, get)| {
+            let (old, new) = (old.map(get).cloned(), new.map(get).cloned());
+            (old != new).then_some(DescChange { field, old, new })
+        })
+        .collect()
+}
+
+/// Compares two builds, each an ELF or an app image, keeping the `top`
+/// symbol changes of each kind
+pub fn compare(old_path: &Path, new_path: &Path, top: usize) -> Result<Comparison> {
+    let (old, new) = (Build::load(old_path)?, Build::load(new_path)?);
+    Ok(Comparison {
+        old: old_path.display().to_string(),
+        new: new_path.display().to_string(),
+        total: SizeDelta::new("total", Some(old.total), Some(new.total)),
+        sections: diff(&old.sections, &new.sections),
+        symbols: match (&old.symbols, &new.symbols) {
+            (Some(old), Some(new)) => Some(symbol_changes(old, new, top)),
+            _ => None,
+        },
+        app_desc: desc_changes(old.desc.as_ref(), new.desc.as_ref()),
+    })
+}
+
+fn size(size: Option<u64>) -> String {
+    size.map_or_else(|| String::from("-"), |size| size.to_string())
+}
+
+fn print_deltas(title: &str, deltas: &[SizeDelta]) {
+    if deltas.is_empty() {
+        return;
+    }
+    println!("\n{}:", title);
+    for delta in deltas {
+        println!(
+            "  {:>+9}  {:>9} -> {:<9}  {}",
+            delta.delta,
+            size(delta.old),
+            size(delta.new),
+            delta.name
+        );
+    }
+}
+
+pub fn print_report(comparison: &Comparison, output: OutputFormat) {
+    if output == OutputFormat::Json {
+        println!("{}", json::to_pretty_string(comparison).unwrap_or_default());
+        return;
+    }
+
+    println!("{} -> {}", comparison.old, comparison.new);
+    println!(
+        "Total: {} -> {} bytes ({:+})",
+        size(comparison.total.old),
+        size(comparison.total.new),
+        comparison.total.delta
+    );
+
+    if comparison.sections.is_empty() {
+        println!("\nNo section changed size");
+    }
+    print_deltas("Sections", &comparison.sections);
+
+    match &comparison.symbols {
+        Some(symbols) => {
+            print_deltas("Symbols grown", &symbols.grew);
+            print_deltas("Symbols shrunk", &symbols.shrank);
+            print_deltas("Symbols added", &symbols.appeared);
+            print_deltas("Symbols removed", &symbols.disappeared);
+        }
+        None => println!("\nSymbols: not compared, as an app image has none"),
+    }
+
+    if !comparison.app_desc.is_empty() {
+        println!("\nApp descriptor:");
+        for change in &comparison.app_desc {
+            println!(
+                "  {:<13} {} -> {}",
+                change.field,
+                change.old.as_deref().unwrap_or("-"),
+                change.new.as_deref().unwrap_or("-")
+            );
+        }
+    }
+}
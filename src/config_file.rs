@@ -0,0 +1,213 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+use clap::{parser::ValueSource, Arg, ArgAction, ArgMatches, Command};
+use toml::{Spanned, Value};
+
+use crate::boards;
+
+/// Loaded from the current directory when there's no `--config`
+pub const DEFAULT_FILE: &str = "esp-web-flash.toml";
+/// Subcommands taking options from the file; keys only one of them knows
+/// are skipped by the others
+pub const SUBCOMMANDS: &[&str] = &[
+    "serve",
+    "verify",
+    "flash-all",
+    "export",
+    "manifest",
+    "check",
+];
+
+/// 1-based line of byte offset `at` in `text`
+fn line_of(text: &str, at: usize) -> usize {
+    text[..at.min(text.len())].matches('\n').count() + 1
+}
+
+/// The strings a value stands for on the command line
+fn values(value: &Value) -> Result<Vec<String>, String> {
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(String::from("expected a string, number or boolean")),
+    };
+    match value {
+        Value::Array(items) => items.iter().map(scalar).collect(),
+        value => Ok(vec![scalar(value)?]),
+    }
+}
+
+/// The option a key names, by its id (`flash_size`) or long name
+/// (`flash-size`)
+fn find<'a>(command: &'a Command, key: &str) -> Option<&'a Arg> {
+    let (id, long) = (key.replace('-', "_"), key.replace('_', "-"));
+    command.get_arguments().find(|arg| {
+        (arg.get_id() == id.as_str() || arg.get_long() == Some(long.as_str()))
+            && !["help", "version", "config"].contains(&arg.get_id().as_str())
+    })
+}
+
+/// Whether the command line or an `ESP_WEB_FLASH_*` variable sets `id`,
+/// both of which take precedence over the file
+fn given(matches: Option<&ArgMatches>, id: &str) -> bool {
+    matches.map_or(false, |matches| {
+        matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    })
+}
+
+/// What `args` sets, even while required arguments are missing
+fn lenient_matches(cli: &Command, args: &[OsString]) -> Option<ArgMatches> {
+    cli.clone()
+        .ignore_errors(true)
+        .try_get_matches_from(args.iter())
+        .ok()
+}
+
+/// Loads `--config`, or `esp-web-flash.toml` if it exists, and inserts its
+/// values into `args` (after the subcommand at index 1) for every option
+/// that isn't given explicitly, printing what was applied. Keys are option
+/// names, with `-` or `_`; `elf` is the positional ELF argument.
+pub fn apply(cli: &Command, args: &mut Vec<OsString>) -> Result<(), String> {
+    let path = match boards::value_of(args, "--config") {
+        Some(path) => PathBuf::from(path),
+        None if Path::new(DEFAULT_FILE).is_file() => PathBuf::from(DEFAULT_FILE),
+        None => return Ok(()),
+    };
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let table: BTreeMap<Spanned<String>, Value> =
+        toml::from_str(&text).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+
+    let name = args.get(1).map(|arg| arg.to_string_lossy().into_owned());
+    let subcommand = match name.as_deref().and_then(|name| cli.find_subcommand(name)) {
+        Some(subcommand) => subcommand,
+        None => return Ok(()),
+    };
+    let matches = lenient_matches(cli, args);
+    let sub_matches = matches
+        .as_ref()
+        .and_then(|matches| matches.subcommand_matches(subcommand.get_name()));
+
+    status!("Config: {}", path.display());
+    let (mut options, mut positional) = (Vec::new(), Vec::new());
+    for (key, value) in table {
+        let line = line_of(&text, key.span().start);
+        let at = |message: String| format!("{}:{}: {}", path.display(), line, message);
+        let key = key.into_inner();
+
+        let (command, arg, matches) = match find(subcommand, &key) {
+            Some(arg) => (subcommand, arg, sub_matches),
+            None => match find(cli, &key) {
+                Some(arg) => (cli, arg, matches.as_ref()),
+                None => {
+                    let elsewhere = SUBCOMMANDS.iter().any(|other| {
+                        cli.find_subcommand(other)
+                            .map_or(false, |other| find(other, &key).is_some())
+                    });
+                    if elsewhere {
+                        continue;
+                    }
+                    return Err(at(format!("unknown option '{}'", key)));
+                }
+            },
+        };
+
+        let id = arg.get_id().as_str();
+        let values = values(&value).map_err(|e| at(format!("'{}': {}", key, e)))?;
+        let flag = arg.get_long().map(|long| format!("--{}", long));
+        let display = flag
+            .clone()
+            .unwrap_or_else(|| format!("<{}>", id.to_uppercase()));
+        if given(matches, id) {
+            status!("  {} {} (overridden)", display, values.join(" "));
+            continue;
+        }
+
+        match arg.get_action() {
+            ArgAction::SetTrue => {
+                if value.as_bool().is_none() {
+                    return Err(at(format!("'{}' must be true or false", key)));
+                }
+                if value.as_bool() == Some(true) {
+                    options.extend(flag.map(OsString::from));
+                }
+            }
+            action => {
+                if values.len() != 1 && !matches!(action, ArgAction::Append) {
+                    return Err(at(format!("'{}' takes a single value", key)));
+                }
+                for value in &values {
+                    arg.get_value_parser()
+                        .parse_ref(command, Some(arg), OsStr::new(value))
+                        .map_err(|e| {
+                            let e = e.to_string();
+                            let e = e.lines().next().unwrap_or_default();
+                            at(e.trim_start_matches("error: ").to_string())
+                        })?;
+                    match &flag {
+                        Some(flag) => {
+                            options.push(OsString::from(flag));
+                            options.push(OsString::from(value));
+                        }
+                        None => positional.push(OsString::from(value)),
+                    }
+                }
+            }
+        }
+        status!("  {} {}", display, values.join(" "));
+    }
+
+    let at = 2.min(args.len());
+    args.splice(at..at, options);
+    args.extend(positional);
+    Ok(())
+}
+
+/// Prints the options of the subcommand in `args` whose value comes from
+/// the environment, hiding secrets such as the admin token
+pub fn report_env(cli: &Command, args: &[OsString]) {
+    let name = args.get(1).map(|arg| arg.to_string_lossy().into_owned());
+    let subcommand = match name.as_deref().and_then(|name| cli.find_subcommand(name)) {
+        Some(subcommand) => subcommand,
+        None => return,
+    };
+    let matches = lenient_matches(cli, args);
+    let sub_matches = match matches
+        .as_ref()
+        .and_then(|matches| matches.subcommand_matches(subcommand.get_name()))
+    {
+        Some(sub_matches) => sub_matches,
+        None => return,
+    };
+
+    let mut header = false;
+    for arg in subcommand.get_arguments() {
+        let var = match arg.get_env() {
+            Some(var) => var,
+            None => continue,
+        };
+        if sub_matches.value_source(arg.get_id().as_str()) != Some(ValueSource::EnvVariable) {
+            continue;
+        }
+        if !std::mem::replace(&mut header, true) {
+            status!("Environment:");
+        }
+        let value = if arg.is_hide_env_values_set() {
+            String::from("(hidden)")
+        } else {
+            std::env::var_os(var)
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        };
+        status!("  {}={}", var.to_string_lossy(), value);
+    }
+}
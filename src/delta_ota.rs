@@ -0,0 +1,150 @@
+use std::{path::Path, time::Instant};
+
+use anyhow::{bail, Context, Result};
+use rocket::http::Header;
+use serde::Serialize;
+
+use crate::checksums::sha256_hex;
+
+pub const ALGORITHM: &str = "bsdiff";
+
+/// What `/ota/patch.json` reports about the patch
+#[derive(Serialize, Debug, Clone)]
+pub struct PatchInfo {
+    pub algorithm: &'static str,
+    pub base_sha256: String,
+    pub target_sha256: String,
+    pub base_size: usize,
+    pub target_size: usize,
+    pub patch_size: usize,
+    /// where devices not running the base image get the whole app
+    pub full_image: &'static str,
+}
+
+/// A patch from `--ota-base` to the app image prepared at startup
+#[derive(Clone)]
+pub struct DeltaOta {
+    pub info: PatchInfo,
+    pub patch: Vec<u8>,
+}
+
+impl DeltaOta {
+    /// Diffs the app image in `base` against `target`, checking that the
+    /// patch reproduces `target` before it's ever served
+    pub fn generate(base: &Path, target: &[u8]) -> Result<Self> {
+        let base_data =
+            std::fs::read(base).with_context(|| format!("Failed to read {}", base.display()))?;
+
+        let start = Instant::now();
+        let mut patch = Vec::new();
+        bsdiff::diff(&base_data, target, &mut patch).context("Failed to diff the app images")?;
+        let elapsed = start.elapsed();
+
+        let mut applied = Vec::with_capacity(target.len());
+        bsdiff::patch(&base_data, &mut patch.as_slice(), &mut applied)
+            .context("Failed to apply the generated patch")?;
+        if applied != target {
+            bail!(
+                "The patch from {} doesn't reproduce the current app image",
+                base.display()
+            );
+        }
+
+        status!(
+            "Delta OTA patch from {}: {} bytes ({:.0}% of the {} byte app), generated in {:.1} s",
+            base.display(),
+            patch.len(),
+            patch.len() as f64 * 100.0 / target.len().max(1) as f64,
+            target.len(),
+            elapsed.as_secs_f32()
+        );
+
+        Ok(DeltaOta {
+            info: PatchInfo {
+                algorithm: ALGORITHM,
+                base_sha256: sha256_hex(&base_data),
+                target_sha256: sha256_hex(target),
+                base_size: base_data.len(),
+                target_size: target.len(),
+                patch_size: patch.len(),
+                full_image: "/firmware.bin",
+            },
+            patch,
+        })
+    }
+}
+
+/// The patch with the hashes it goes from and to, so a device can check
+/// them before applying it
+#[derive(Responder)]
+#[response(content_type = "binary")]
+pub struct PatchResponse {
+    pub patch: Vec<u8>,
+    pub base: Header<'static>,
+    pub target: Header<'static>,
+}
+
+impl PatchResponse {
+    pub fn new(delta: &DeltaOta) -> Self {
+        PatchResponse {
+            patch: delta.patch.clone(),
+            base: Header::new("X-Delta-Base-SHA256", delta.info.base_sha256.clone()),
+            target: Header::new("X-Delta-Target-SHA256", delta.info.target_sha256.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An app-like image: repetitive, but not trivially so
+    fn image(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                if i % 64 < 48 {
+                    (i / 64) as u8
+                } else {
+                    (state >> 16) as u8
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn patch_reproduces_the_target() {
+        let base = image(64 * 1024, 1);
+        let mut target = base.clone();
+        target[0x1000..0x1010].copy_from_slice(b"version 1.4.3\0\0\0");
+        target.splice(0x8000..0x8000, image(3000, 2));
+        target.truncate(target.len() - 100);
+
+        let path = std::env::temp_dir().join(format!("delta-ota-base-{}.bin", std::process::id()));
+        std::fs::write(&path, &base).unwrap();
+        let delta = DeltaOta::generate(&path, &target);
+        std::fs::remove_file(&path).unwrap();
+        let delta = delta.unwrap();
+
+        let mut applied = Vec::new();
+        bsdiff::patch(&base, &mut delta.patch.as_slice(), &mut applied).unwrap();
+        assert_eq!(applied, target);
+
+        assert_eq!(delta.info.base_sha256, sha256_hex(&base));
+        assert_eq!(delta.info.target_sha256, sha256_hex(&target));
+        assert_eq!(
+            (
+                delta.info.base_size,
+                delta.info.target_size,
+                delta.info.patch_size
+            ),
+            (base.len(), target.len(), delta.patch.len())
+        );
+    }
+
+    #[test]
+    fn missing_base_is_an_error() {
+        assert!(DeltaOta::generate(Path::new("/nonexistent/base.bin"), b"app").is_err());
+    }
+}
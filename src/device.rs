@@ -0,0 +1,161 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use anyhow::{bail, Context, Result};
+use espflash::{Chip, Flasher};
+use serialport::{SerialPortType, UsbPortInfo};
+
+/// Exclusive claim on a serial port, backed by a lock file so that other
+/// web-flash processes (local flashing, monitor, erase) see it too
+pub struct PortClaim {
+    path: PathBuf,
+}
+
+impl Drop for PortClaim {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+fn lock_path(port: &str) -> PathBuf {
+    let name: String = port
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    std::env::temp_dir().join(format!("web-flash-{}.lock", name))
+}
+
+/// Claims `port` for `owner`, failing with a message naming the current
+/// owner if someone else holds it
+pub fn claim(port: &str, owner: &str) -> Result<PortClaim> {
+    let path = lock_path(port);
+    match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(mut file) => {
+            writeln!(file, "{} (pid {})", owner, std::process::id())?;
+            Ok(PortClaim { path })
+        }
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            let holder = fs::read_to_string(&path).unwrap_or_default();
+            bail!(
+                "Port {} is in use by {}; wait for it to finish (or remove {} if that process is gone)",
+                port,
+                holder.trim(),
+                path.display()
+            )
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to create {}", path.display())),
+    }
+}
+
+/// Opens `port` and connects to the ROM loader, checking the chip matches
+/// the one the image was prepared for
+pub fn connect(port: &str, chip: Option<Chip>) -> Result<Flasher> {
+    let port_info = serialport::available_ports()?
+        .into_iter()
+        .find(|info| info.port_name == port)
+        .and_then(|info| match info.port_type {
+            SerialPortType::UsbPort(usb) => Some(usb),
+            _ => None,
+        })
+        .unwrap_or(UsbPortInfo {
+            vid: 0,
+            pid: 0,
+            serial_number: None,
+            manufacturer: None,
+            product: None,
+        });
+
+    let serial = serialport::new(port, 115_200)
+        .open()
+        .with_context(|| format!("Failed to open serial port {}", port))?;
+    let flasher = Flasher::connect(serial, port_info, None)
+        .with_context(|| format!("Failed to connect to the device on {}", port))?;
+
+    if let Some(chip) = chip {
+        if flasher.chip() != chip {
+            bail!(
+                "Device on {} is an {}, but the image was prepared for {}",
+                port,
+                flasher.chip(),
+                chip
+            );
+        }
+    }
+
+    Ok(flasher)
+}
+
+/// Erases the whole flash of the device on `port`, reporting each step
+/// through `progress`. The caller is expected to hold a [`PortClaim`].
+pub fn erase(port: &str, chip: Option<Chip>, progress: impl Fn(&str)) -> Result<()> {
+    progress(&format!("Connecting to {}", port));
+    let mut flasher = connect(port, chip)?;
+
+    progress(&format!(
+        "Erasing the entire flash of the {}...",
+        flasher.chip()
+    ));
+    let start = Instant::now();
+    flasher.erase_flash()?;
+    progress(&format!(
+        "Chip erase finished in {:.1} s",
+        start.elapsed().as_secs_f32()
+    ));
+
+    Ok(())
+}
+
+/// USB IDs of Espressif's built-in USB and of the USB-serial bridges
+/// found on common dev boards (CP210x, CH340/CH9102, FTDI)
+const ESP_USB_IDS: &[(u16, u16)] = &[
+    (0x303a, 0x1001),
+    (0x303a, 0x0002),
+    (0x10c4, 0xea60),
+    (0x1a86, 0x7523),
+    (0x1a86, 0x55d4),
+    (0x0403, 0x6001),
+    (0x0403, 0x6010),
+    (0x0403, 0x6015),
+];
+
+/// Serial ports that look like an attached ESP board
+pub fn candidate_ports() -> Result<Vec<String>> {
+    Ok(serialport::available_ports()?
+        .into_iter()
+        .filter(|info| match &info.port_type {
+            SerialPortType::UsbPort(usb) => ESP_USB_IDS.contains(&(usb.vid, usb.pid)),
+            _ => false,
+        })
+        .map(|info| info.port_name)
+        .collect())
+}
+
+/// Writes `regions` (offset, data) to the device on `port`, returning its
+/// MAC address. The caller is expected to hold a [`PortClaim`].
+pub fn flash(
+    port: &str,
+    chip: Option<Chip>,
+    regions: &[(u32, &[u8])],
+    progress: impl Fn(&str),
+) -> Result<String> {
+    progress(&format!("Connecting to {}", port));
+    let mut flasher = connect(port, chip)?;
+    let mac = flasher
+        .chip()
+        .into_target()
+        .mac_address(flasher.connection())
+        .with_context(|| format!("Failed to read the MAC address of the device on {}", port))?;
+
+    for (offset, data) in regions {
+        progress(&format!("Writing {} bytes at 0x{:06x}", data.len(), offset));
+        flasher
+            .write_bin_to_flash(*offset, data, None)
+            .with_context(|| format!("Failed to write at 0x{:06x}", offset))?;
+    }
+
+    Ok(mac)
+}
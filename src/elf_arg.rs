@@ -0,0 +1,139 @@
+use std::{
+    fs::Metadata,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::prepare_error::PrepareError;
+
+/// How many of the files considered are listed when nothing matches
+const MAX_LISTED: usize = 20;
+
+/// Resolves the ELF argument: a glob or a directory stands for the newest
+/// regular file it matches (in a directory, the newest ELF file), anything
+/// else is taken as is
+pub fn resolve(arg: &Path) -> Result<PathBuf, PrepareError> {
+    let pattern = arg.to_string_lossy();
+    let is_glob = pattern.contains(['*', '?', '[']);
+    let candidates = if is_glob {
+        let paths = glob::glob(&pattern).map_err(|e| PrepareError::ElfPattern {
+            pattern: pattern.to_string(),
+            reason: e.to_string(),
+        })?;
+        paths
+            .flatten()
+            .filter_map(|path| regular_file(&path).map(|meta| (path, meta)))
+            .collect::<Vec<_>>()
+    } else if arg.is_dir() {
+        std::fs::read_dir(arg)
+            .map_err(|source| PrepareError::Read {
+                path: arg.to_path_buf(),
+                source,
+            })?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| is_elf(path))
+            .filter_map(|path| regular_file(&path).map(|meta| (path, meta)))
+            .collect()
+    } else {
+        return Ok(arg.to_path_buf());
+    };
+
+    let newest = candidates
+        .iter()
+        .map(|(_, meta)| modified(meta))
+        .max()
+        .ok_or_else(|| PrepareError::ElfPattern {
+            pattern: pattern.to_string(),
+            reason: no_match(arg, is_glob),
+        })?;
+    let mut chosen: Vec<&PathBuf> = candidates
+        .iter()
+        .filter(|(_, meta)| modified(meta) == newest)
+        .map(|(path, _)| path)
+        .collect();
+    if chosen.len() > 1 {
+        chosen.sort();
+        return Err(PrepareError::ElfPattern {
+            pattern: pattern.to_string(),
+            reason: format!(
+                "{} files are equally new:\n{}",
+                chosen.len(),
+                list(chosen.iter().map(|path| path.display().to_string()))
+            ),
+        });
+    }
+
+    let path = chosen[0].clone();
+    status!("ELF argument '{}' resolved to {}", pattern, path.display());
+    Ok(path)
+}
+
+fn regular_file(path: &Path) -> Option<Metadata> {
+    std::fs::metadata(path).ok().filter(Metadata::is_file)
+}
+
+fn modified(meta: &Metadata) -> SystemTime {
+    meta.modified().unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+pub fn is_elf(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    std::fs::File::open(path)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut magic))
+        .is_ok()
+        && magic == *b"\x7fELF"
+}
+
+/// Explains a pattern without matches by listing the directory it searched:
+/// for a glob, the part of the path before the first wildcard
+fn no_match(arg: &Path, is_glob: bool) -> String {
+    let dir = if is_glob {
+        let literal: PathBuf = arg
+            .components()
+            .take_while(|c| match c {
+                Component::Normal(name) => !name.to_string_lossy().contains(['*', '?', '[']),
+                _ => true,
+            })
+            .collect();
+        if literal.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            literal
+        }
+    } else {
+        arg.to_path_buf()
+    };
+
+    let mut entries: Vec<String> = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path().display().to_string())
+            .collect(),
+        Err(e) => return format!("no match, and {} can't be read: {}", dir.display(), e),
+    };
+    if entries.is_empty() {
+        return format!("no match, {} is empty", dir.display());
+    }
+    entries.sort();
+    let what = if is_glob { "regular file" } else { "ELF file" };
+    let more = entries.len().saturating_sub(MAX_LISTED);
+    entries.truncate(MAX_LISTED);
+    let mut reason = format!(
+        "no {} matches; {} contains:\n{}",
+        what,
+        dir.display(),
+        list(entries.into_iter())
+    );
+    if more > 0 {
+        reason.push_str(&format!("\n  ... and {} more", more));
+    }
+    reason
+}
+
+fn list(items: impl Iterator<Item = String>) -> String {
+    items
+        .map(|item| format!("  {}", item))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
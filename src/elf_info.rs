@@ -0,0 +1,166 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use espflash::{elf::FirmwareImage, Chip};
+use rocket::http::Header;
+use serde::Serialize;
+use xmas_elf::{program::Type, ElfFile};
+
+use crate::patch_symbol::AppliedPatch;
+
+/// `/firmware.elf`, downloaded under the ELF's own file name
+#[derive(Responder)]
+#[response(content_type = "application/x-elf")]
+pub struct ElfDownload {
+    pub elf: Vec<u8>,
+    pub disposition: Header<'static>,
+}
+
+impl ElfDownload {
+    pub fn new(elf: Vec<u8>, path: Option<&str>) -> Self {
+        let name = path
+            .and_then(|path| Path::new(path).file_name())
+            .and_then(|name| name.to_str())
+            .filter(|name| !name.contains('"'))
+            .unwrap_or("firmware.elf");
+        ElfDownload {
+            elf,
+            disposition: Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", name),
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    Flash,
+    Ram,
+    Unknown,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ProgramHeader {
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub vaddr: u32,
+    pub paddr: u32,
+    pub filesz: u32,
+    pub memsz: u32,
+    pub region: Region,
+    /// whether any part of this segment ended up in the app image
+    pub in_image: bool,
+    /// hints such as `ram-only` or `zero-length`
+    pub labels: Vec<&'static str>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ImageSegment {
+    pub addr: u32,
+    pub size: u32,
+    pub region: Region,
+}
+
+/// What espflash saw when turning the ELF into an app image, empty when
+/// serving prebuilt binaries
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ElfInfo {
+    pub entry: u32,
+    pub program_headers: Vec<ProgramHeader>,
+    pub image_segments: Vec<ImageSegment>,
+    /// `--patch-symbol` stamps written into the ELF before building
+    pub patched_symbols: Vec<AppliedPatch>,
+}
+
+pub fn inspect(elf: &[u8], firmware: &FirmwareImage, chip: Chip) -> Result<ElfInfo> {
+    let file = ElfFile::new(elf).map_err(|e| anyhow!("Failed to parse ELF: {}", e))?;
+
+    let mut image_segments: Vec<ImageSegment> = firmware
+        .rom_segments(chip)
+        .map(|s| ImageSegment {
+            addr: s.addr,
+            size: s.size(),
+            region: Region::Flash,
+        })
+        .collect();
+    image_segments.extend(firmware.ram_segments(chip).map(|s| ImageSegment {
+        addr: s.addr,
+        size: s.size(),
+        region: Region::Ram,
+    }));
+    image_segments.sort_by_key(|s| s.addr);
+
+    let program_headers = file
+        .program_iter()
+        .map(|ph| {
+            let vaddr = ph.virtual_addr() as u32;
+            let memsz = ph.mem_size() as u32;
+            let filesz = ph.file_size() as u32;
+            let end = vaddr.saturating_add(memsz);
+
+            let overlapping: Vec<&ImageSegment> = image_segments
+                .iter()
+                .filter(|s| s.addr < end && vaddr < s.addr.saturating_add(s.size))
+                .collect();
+            let region = match overlapping.first() {
+                Some(segment) => segment.region,
+                None => Region::Unknown,
+            };
+
+            let mut labels = Vec::new();
+            if region == Region::Ram {
+                labels.push("ram-only");
+            }
+            if filesz == 0 {
+                labels.push("zero-length");
+            }
+
+            ProgramHeader {
+                ty: match ph.get_type() {
+                    Ok(Type::Load) => String::from("LOAD"),
+                    Ok(other) => format!("{:?}", other).to_uppercase(),
+                    Err(_) => String::from("UNKNOWN"),
+                },
+                vaddr,
+                paddr: ph.physical_addr() as u32,
+                filesz,
+                memsz,
+                region,
+                in_image: !overlapping.is_empty(),
+                labels,
+            }
+        })
+        .collect();
+
+    Ok(ElfInfo {
+        entry: file.header.pt2.entry_point() as u32,
+        program_headers,
+        image_segments,
+        patched_symbols: Vec::new(),
+    })
+}
+
+/// Lists the program headers with their address ranges, one per line
+pub fn describe_segments(info: &ElfInfo) -> String {
+    info.program_headers
+        .iter()
+        .map(|ph| {
+            let region = match ph.region {
+                Region::Flash => "flash",
+                Region::Ram => "RAM",
+                Region::Unknown => "not in the image",
+            };
+            format!(
+                "  {:<8} 0x{:08x}..0x{:08x} ({} bytes in file) {}",
+                ph.ty,
+                ph.vaddr,
+                ph.vaddr.saturating_add(ph.memsz),
+                ph.filesz,
+                region
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,182 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::Ordering,
+    time::Duration,
+};
+
+use crate::{checksums::sha256_hex, prepare_error::PrepareError};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest pause between two reads of the download
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// `--http-header "Name: value"`, sent with the ELF download
+#[derive(Debug, Clone)]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for HttpHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() => Ok(HttpHeader {
+                name: name.trim().to_string(),
+                value: value.trim().to_string(),
+            }),
+            _ => Err(format!(
+                "expected <name>: <value>, e.g. 'Authorization: Bearer <token>', got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether the ELF argument is an http(s) URL rather than a path
+pub fn is_url(arg: &Path) -> bool {
+    arg.to_str().map_or(false, |arg| {
+        arg.starts_with("http://") || arg.starts_with("https://")
+    })
+}
+
+/// `$XDG_CACHE_HOME/esp-web-flash/elf`, `~/.cache/...` or the temp dir
+fn cache_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map_or_else(
+            || std::env::temp_dir().join("web-flash-elf-cache"),
+            |cache| cache.join("esp-web-flash").join("elf"),
+        )
+}
+
+fn key(text: &str) -> String {
+    sha256_hex(text.as_bytes())[..16].to_string()
+}
+
+/// Copies the body into `file`, showing the progress on stderr
+fn download(body: &mut impl Read, file: &mut File, length: Option<u64>) -> std::io::Result<u64> {
+    let show = !crate::QUIET.load(Ordering::Relaxed);
+    let mut buf = vec![0; 64 * 1024];
+    let mut done = 0u64;
+    loop {
+        let read = body.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buf[..read])?;
+        done += read as u64;
+        if show {
+            match length {
+                Some(length) => eprint!(
+                    "\r  {} / {} bytes ({}%)",
+                    done,
+                    length,
+                    done * 100 / length.max(1)
+                ),
+                None => eprint!("\r  {} bytes", done),
+            }
+        }
+    }
+    if show {
+        eprintln!();
+    }
+    Ok(done)
+}
+
+/// Downloads the ELF at `url` into the cache and returns its path. The
+/// cached copy of the URL's last ETag is revalidated and reused when
+/// unchanged, or when the server can't be reached.
+pub fn fetch(url: &str, headers: &[HttpHeader]) -> Result<PathBuf, PrepareError> {
+    let dir = cache_dir();
+    let etag_file = dir.join(format!("{}.etag", key(url)));
+    let cached = std::fs::read_to_string(&etag_file)
+        .ok()
+        .map(|etag| {
+            (
+                dir.join(format!("{}.elf", key(&format!("{}\n{}", url, etag)))),
+                etag,
+            )
+        })
+        .filter(|(path, _)| path.is_file());
+    let error = |reason: String| PrepareError::Download {
+        url: url.to_string(),
+        reason,
+    };
+
+    // proxies from http_proxy, https_proxy, all_proxy and no_proxy
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .try_proxy_from_env(true)
+        .build();
+    let mut request = agent.get(url);
+    for header in headers {
+        request = request.set(&header.name, &header.value);
+    }
+    if let Some((_, etag)) = &cached {
+        request = request.set("If-None-Match", etag);
+    }
+
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(code, response)) => {
+            return Err(error(format!("HTTP {} {}", code, response.status_text())))
+        }
+        Err(ureq::Error::Transport(e)) => {
+            return match cached {
+                Some((path, _)) => {
+                    warning!("{}, using the cached download of {}", e, url);
+                    Ok(path)
+                }
+                None => Err(error(e.to_string())),
+            }
+        }
+    };
+    if let (304, Some((path, _))) = (response.status(), &cached) {
+        status!("ELF {} not modified, using {}", url, path.display());
+        return Ok(path.clone());
+    }
+
+    let etag = response.header("ETag").map(str::to_string);
+    let length = response
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok());
+    let path = dir.join(format!(
+        "{}.elf",
+        key(&format!("{}\n{}", url, etag.as_deref().unwrap_or_default()))
+    ));
+    let partial = path.with_extension("part");
+    let io_error = |source| PrepareError::Read {
+        path: partial.clone(),
+        source,
+    };
+    std::fs::create_dir_all(&dir).map_err(|source| PrepareError::Read {
+        path: dir.clone(),
+        source,
+    })?;
+
+    status!("Downloading {}", url);
+    let mut file = File::create(&partial).map_err(io_error)?;
+    let size = download(&mut response.into_reader(), &mut file, length)
+        .map_err(|e| error(e.to_string()))?;
+    drop(file);
+    std::fs::rename(&partial, &path).map_err(io_error)?;
+    match &etag {
+        Some(etag) => std::fs::write(&etag_file, etag).map_err(|source| PrepareError::Read {
+            path: etag_file.clone(),
+            source,
+        })?,
+        // without an ETag there's nothing to revalidate against
+        None => {
+            let _ = std::fs::remove_file(&etag_file);
+        }
+    }
+    status!("Downloaded {} bytes to {}", size, path.display());
+    Ok(path)
+}
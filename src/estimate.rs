@@ -0,0 +1,26 @@
+/// Baud rate esp-web-tools flashes at unless told otherwise
+pub const DEFAULT_BAUD: u32 = 115_200;
+
+/// Connecting, syncing with the ROM loader and resetting, independent of size
+const OVERHEAD_SECONDS: f64 = 5.0;
+/// Erasing before writing, per MiB written
+const ERASE_SECONDS_PER_MIB: f64 = 1.5;
+/// Share of the raw size actually sent, as esptool-js writes deflated data;
+/// app images typically compress to a bit over half
+const COMPRESSION_RATIO: f64 = 0.6;
+/// 8 data bits plus start and stop bit per byte, plus SLIP framing
+const BITS_PER_BYTE: f64 = 10.5;
+
+/// Settings for the flash time estimate
+#[derive(Clone)]
+pub struct EstimateSettings {
+    pub assumed_baud: u32,
+}
+
+/// Rough number of seconds esp-web-tools needs to write `total_bytes`
+pub fn flash_seconds(total_bytes: usize, baud: u32) -> u64 {
+    let bytes = total_bytes as f64;
+    let transfer = bytes * COMPRESSION_RATIO * BITS_PER_BYTE / baud.max(1) as f64;
+    let erase = bytes / (1024.0 * 1024.0) * ERASE_SECONDS_PER_MIB;
+    (OVERHEAD_SECONDS + transfer + erase).round() as u64
+}
@@ -0,0 +1,161 @@
+use std::{ffi::OsString, path::Path, process::Command};
+
+use clap::ValueEnum;
+
+use crate::{partition_table::PartitionEntry, prepare_error::PrepareError, ExtraPart};
+
+const TYPE_DATA: u8 = 0x01;
+const SUBTYPE_SPIFFS: u8 = 0x82;
+const SUBTYPE_LITTLEFS: u8 = 0x83;
+/// Block and page size of the ESP-IDF and Arduino filesystem drivers
+const BLOCK_SIZE: &str = "4096";
+const PAGE_SIZE: &str = "256";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filesystem {
+    Littlefs,
+    Spiffs,
+}
+
+impl Filesystem {
+    fn name(self) -> &'static str {
+        match self {
+            Filesystem::Littlefs => "littlefs",
+            Filesystem::Spiffs => "spiffs",
+        }
+    }
+
+    /// Environment variable naming the image tool, like `CARGO` does for
+    /// --cargo
+    fn tool_var(self) -> &'static str {
+        match self {
+            Filesystem::Littlefs => "MKLITTLEFS",
+            Filesystem::Spiffs => "MKSPIFFS",
+        }
+    }
+
+    fn tool(self) -> OsString {
+        std::env::var_os(self.tool_var())
+            .unwrap_or_else(|| OsString::from(self.tool_var().to_lowercase()))
+    }
+}
+
+/// The partition named after the filesystem, or else the only data
+/// partition of the spiffs or littlefs subtype
+fn partition(fs: Filesystem, table: &[PartitionEntry]) -> Result<&PartitionEntry, PrepareError> {
+    let data: Vec<&PartitionEntry> = table
+        .iter()
+        .filter(|e| e.ty == TYPE_DATA && matches!(e.subtype, SUBTYPE_SPIFFS | SUBTYPE_LITTLEFS))
+        .collect();
+    table
+        .iter()
+        .find(|e| e.name == fs.name())
+        .or(match data.as_slice() {
+            [only] => Some(*only),
+            _ => None,
+        })
+        .ok_or_else(|| PrepareError::PartitionNotFound {
+            name: fs.name().to_string(),
+            available: table
+                .iter()
+                .map(|e| e.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        })
+}
+
+/// Bytes of the files below `dir`
+fn content_size(dir: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        size += if meta.is_dir() {
+            content_size(&entry.path())?
+        } else {
+            meta.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Packs `dir` into an image of the filesystem partition with
+/// `mklittlefs` or `mkspiffs`, `--data-dir`
+pub fn prepare(
+    dir: &Path,
+    fs: Filesystem,
+    table: &[PartitionEntry],
+) -> Result<ExtraPart, PrepareError> {
+    let entry = partition(fs, table)?;
+    let error = |reason: String| PrepareError::Filesystem {
+        path: dir.to_path_buf(),
+        reason,
+    };
+    let content = content_size(dir).map_err(|e| PrepareError::Read {
+        path: dir.to_path_buf(),
+        source: e,
+    })?;
+    if content > entry.size as u64 {
+        return Err(error(format!(
+            "its files are {} bytes, {} more than partition '{}' holds ({} bytes)",
+            content,
+            content - entry.size as u64,
+            entry.name,
+            entry.size
+        )));
+    }
+
+    let image = std::env::temp_dir().join(format!(
+        "web-flash-{}-{}.bin",
+        fs.name(),
+        std::process::id()
+    ));
+    let tool = fs.tool();
+    let output = Command::new(&tool)
+        .arg("-c")
+        .arg(dir)
+        .args(["-b", BLOCK_SIZE, "-p", PAGE_SIZE, "-s"])
+        .arg(entry.size.to_string())
+        .arg(&image)
+        .output()
+        .map_err(|e| {
+            error(format!(
+                "failed to run {}: {} (install it or point {} at it)",
+                tool.to_string_lossy(),
+                e,
+                fs.tool_var()
+            ))
+        })?;
+    let data = std::fs::read(&image);
+    let _ = std::fs::remove_file(&image);
+    if !output.status.success() {
+        // the files fit, but not with the filesystem's own overhead
+        return Err(error(format!(
+            "{} failed with {} bytes of files for the {} bytes of partition '{}': {}",
+            tool.to_string_lossy(),
+            content,
+            entry.size,
+            entry.name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let data = data.map_err(|e| PrepareError::Read {
+        path: image,
+        source: e,
+    })?;
+
+    status!(
+        "Filesystem: {} image of {} ({} bytes of files) for '{}' at 0x{:x}",
+        fs.name(),
+        dir.display(),
+        content,
+        entry.name,
+        entry.offset
+    );
+    Ok(ExtraPart {
+        file: String::from("filesystem.bin"),
+        label: format!("Filesystem ({})", entry.name),
+        offset: entry.offset,
+        data,
+    })
+}
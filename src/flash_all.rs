@@ -0,0 +1,182 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use espflash::Chip;
+use rocket::serde::json;
+use serde::Serialize;
+
+use crate::{device, prepared_build::PreparedBuild, OutputFormat};
+
+/// Which ports to flash and how, shared by `flash-all` and `POST /flash-all`
+#[derive(Clone)]
+pub struct BatchSettings {
+    /// explicit ports; empty means every port with a known ESP USB ID
+    pub ports: Vec<String>,
+    /// how many devices are flashed at the same time
+    pub parallel: usize,
+    /// file every result is appended to as a line of JSON
+    pub history: Option<PathBuf>,
+}
+
+/// Outcome of flashing one port
+#[derive(Serialize, Debug, Clone)]
+pub struct PortResult {
+    pub port: String,
+    pub mac: Option<String>,
+    pub duration_secs: f32,
+    pub error: Option<String>,
+}
+
+impl PortResult {
+    pub fn ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// A line of the flash history
+#[derive(Serialize)]
+struct HistoryEntry<'a> {
+    /// seconds since the Unix epoch
+    time: u64,
+    build: &'a str,
+    #[serde(flatten)]
+    result: &'a PortResult,
+}
+
+/// Every non-empty part of the build with its offset, in flash order
+fn regions(build: &PreparedBuild) -> Vec<(u32, &[u8])> {
+    let parts = &build.parts;
+    let mut regions = vec![
+        (parts.bootloader_offset, parts.bootloader.as_slice()),
+        (parts.partitions_offset, parts.partitions.as_slice()),
+        (parts.firmware_offset, parts.firmware.as_slice()),
+    ];
+    regions.extend(
+        parts
+            .extra_parts
+            .iter()
+            .map(|part| (part.offset, part.data.as_slice())),
+    );
+    regions.retain(|(_, data)| !data.is_empty());
+    regions.sort_by_key(|(offset, _)| *offset);
+    regions
+}
+
+/// Flashes `build` to every selected port, at most `settings.parallel` at a
+/// time. A failing port doesn't stop the others; only finding no port at
+/// all is an error.
+pub fn run(settings: &BatchSettings, build: &PreparedBuild, chip: Chip) -> Result<Vec<PortResult>> {
+    let ports = if settings.ports.is_empty() {
+        device::candidate_ports()?
+    } else {
+        settings.ports.clone()
+    };
+    if ports.is_empty() {
+        bail!("No serial port with a known ESP USB ID found; pass them with --ports");
+    }
+
+    let regions = regions(build);
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; ports.len()]);
+    let workers = settings.parallel.clamp(1, ports.len());
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(port) = ports.get(i) else { break };
+
+                let result = flash_port(port, chip, &regions);
+                if let Some(path) = &settings.history {
+                    if let Err(e) = append_history(path, &build.hash, &result) {
+                        warning!("{:#}", e);
+                    }
+                }
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    Ok(results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+fn flash_port(port: &str, chip: Chip, regions: &[(u32, &[u8])]) -> PortResult {
+    let start = Instant::now();
+    let result = device::claim(port, "web-flash flash-all").and_then(|_claim| {
+        device::flash(port, Some(chip), regions, |msg| {
+            println!("[{}] {}", port, msg)
+        })
+    });
+
+    let (mac, error) = match result {
+        Ok(mac) => (Some(mac), None),
+        Err(e) => (None, Some(format!("{:#}", e))),
+    };
+    PortResult {
+        port: port.to_string(),
+        mac,
+        duration_secs: start.elapsed().as_secs_f32(),
+        error,
+    }
+}
+
+fn append_history(path: &Path, build: &str, result: &PortResult) -> Result<()> {
+    let entry = HistoryEntry {
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        build,
+        result,
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open the flash history {}", path.display()))?;
+    writeln!(file, "{}", json::to_string(&entry)?)
+        .with_context(|| format!("Failed to append to {}", path.display()))
+}
+
+pub fn print_report(results: &[PortResult], output: OutputFormat) {
+    if output == OutputFormat::Json {
+        println!("{}", json::to_pretty_string(&results).unwrap_or_default());
+        return;
+    }
+
+    println!("\nFlash report:");
+    for result in results {
+        match &result.error {
+            None => println!(
+                "  {:<16} {:<17}  ok      {:>6.1} s",
+                result.port,
+                result.mac.as_deref().unwrap_or("-"),
+                result.duration_secs
+            ),
+            Some(e) => println!(
+                "  {:<16} {:<17}  FAILED  {:>6.1} s  {}",
+                result.port, "-", result.duration_secs, e
+            ),
+        }
+    }
+
+    let failed = results.iter().filter(|result| !result.ok()).count();
+    if failed == 0 {
+        println!("All {} devices flashed", results.len());
+    } else {
+        println!("{} of {} devices failed", failed, results.len());
+    }
+}
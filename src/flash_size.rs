@@ -0,0 +1,109 @@
+use std::fmt;
+
+use espflash::FlashSize;
+
+/// Sizes `--flash-size` accepts, in MB
+const SIZES: &[u32] = &[1, 2, 4, 8, 16, 32, 64, 128];
+
+/// A `--flash-size` value, displayed normalized as `4MB`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashSizeArg {
+    mb: u32,
+}
+
+impl FlashSizeArg {
+    /// The size for espflash's image builder
+    pub fn espflash(self) -> FlashSize {
+        match self.mb {
+            1 => FlashSize::Flash1Mb,
+            2 => FlashSize::Flash2Mb,
+            4 => FlashSize::Flash4Mb,
+            8 => FlashSize::Flash8Mb,
+            16 => FlashSize::Flash16Mb,
+            32 => FlashSize::Flash32Mb,
+            64 => FlashSize::Flash64Mb,
+            _ => FlashSize::Flash128Mb,
+        }
+    }
+
+    pub fn bytes(self) -> u32 {
+        self.mb * 0x10_0000
+    }
+}
+
+impl fmt::Display for FlashSizeArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}MB", self.mb)
+    }
+}
+
+/// Parses `4MB`, `4mb` or `4M`, rejecting sizes flash chips don't come in
+pub fn parse(s: &str) -> Result<FlashSizeArg, String> {
+    let upper = s.trim().to_uppercase();
+    let mb = upper
+        .strip_suffix("MB")
+        .or_else(|| upper.strip_suffix('M'))
+        .and_then(|number| number.parse::<u32>().ok());
+    match mb {
+        Some(mb) if SIZES.contains(&mb) => Ok(FlashSizeArg { mb }),
+        _ => Err(format!(
+            "unknown flash size '{}' (valid: {})",
+            s,
+            SIZES
+                .iter()
+                .map(|mb| format!("{}MB", mb))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_every_size_with_or_without_b() {
+        for &mb in SIZES {
+            for s in [
+                format!("{}MB", mb),
+                format!("{}mb", mb),
+                format!("{}M", mb),
+                format!("{}m", mb),
+            ] {
+                let size = parse(&s).unwrap();
+                assert_eq!(size.to_string(), format!("{}MB", mb));
+                assert_eq!(size.bytes(), mb * 0x10_0000);
+            }
+        }
+        assert_eq!(parse(" 8Mb ").unwrap().to_string(), "8MB");
+    }
+
+    #[test]
+    fn rejects_other_values_listing_the_valid_ones() {
+        for s in ["", "4", "4G", "4KB", "3MB", "256MB", "MB", "-4MB", "4 MB"] {
+            let e = parse(s).unwrap_err();
+            assert!(
+                e.contains("1MB, 2MB, 4MB, 8MB, 16MB, 32MB, 64MB, 128MB"),
+                "{}",
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn maps_to_espflash() {
+        assert!(matches!(
+            parse("1MB").unwrap().espflash(),
+            FlashSize::Flash1Mb
+        ));
+        assert!(matches!(
+            parse("16MB").unwrap().espflash(),
+            FlashSize::Flash16Mb
+        ));
+        assert!(matches!(
+            parse("128MB").unwrap().espflash(),
+            FlashSize::Flash128Mb
+        ));
+    }
+}
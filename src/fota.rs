@@ -0,0 +1,309 @@
+use std::cmp::Ordering;
+
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Serialize;
+
+/// Offset of `esp_app_desc_t` in an app image: the 24 byte image header plus
+/// the 8 byte header of the first segment
+const APP_DESC_OFFSET: usize = 32;
+const APP_DESC_MAGIC: u32 = 0xabcd_5432;
+const SECURE_VERSION: usize = 4;
+/// Offsets and lengths of the strings in `esp_app_desc_t`
+const VERSION: (usize, usize) = (16, 32);
+const PROJECT_NAME: (usize, usize) = (48, 32);
+const TIME: (usize, usize) = (80, 16);
+const DATE: (usize, usize) = (96, 16);
+const IDF_VERSION: (usize, usize) = (112, 32);
+/// SHA-256 of the ELF the app was built from
+const APP_ELF_SHA256: (usize, usize) = (144, 32);
+
+/// Settings of the esp32FOTA descriptor at `/fota.json`
+#[derive(Clone)]
+pub struct FotaSettings {
+    /// device type the library matches against its own
+    pub ty: String,
+    /// `--firmware-version`, taking precedence over the app descriptor
+    pub version: Option<String>,
+    /// `--public-url`, the base of absolute links handed to devices
+    pub public_url: Option<String>,
+}
+
+impl FotaSettings {
+    /// `--firmware-version`, or else the app descriptor's
+    pub fn version<'a>(&'a self, app_version: Option<&'a str>) -> Option<&'a str> {
+        self.version.as_deref().or(app_version)
+    }
+}
+
+/// The JSON esp32FOTA polls to decide whether to update
+#[derive(Serialize, Debug, Clone)]
+pub struct Descriptor {
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub version: String,
+    pub url: String,
+}
+
+/// The strings ESP-IDF embeds in the app descriptor
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppDesc {
+    pub version: String,
+    pub project_name: String,
+    /// compile time and date, as `__TIME__` and `__DATE__`
+    pub time: String,
+    pub date: String,
+    pub idf_version: String,
+    /// anti-rollback counter of secure boot
+    pub secure_version: u32,
+    /// lowercase hex
+    pub app_elf_sha256: String,
+}
+
+/// `/app-info`: the app descriptor, every field `null` when the image has
+/// none (as bare-metal esp-hal apps)
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct AppInfo {
+    pub project_name: Option<String>,
+    pub version: Option<String>,
+    pub idf_version: Option<String>,
+    pub compile_date: Option<String>,
+    pub compile_time: Option<String>,
+    pub secure_version: Option<u32>,
+    pub app_elf_sha256: Option<String>,
+}
+
+impl Descriptor {
+    /// Offers `version` as the firmware served below `base`
+    pub fn new(settings: &FotaSettings, version: [u64; 3], base: &str) -> Self {
+        Descriptor {
+            ty: settings.ty.clone(),
+            version: format_version(version),
+            url: format!("{}/firmware.bin", base),
+        }
+    }
+}
+
+impl From<Option<&AppDesc>> for AppInfo {
+    fn from(desc: Option<&AppDesc>) -> Self {
+        match desc {
+            Some(desc) => AppInfo {
+                project_name: Some(desc.project_name.clone()),
+                version: Some(desc.version.clone()),
+                idf_version: Some(desc.idf_version.clone()),
+                compile_date: Some(desc.date.clone()),
+                compile_time: Some(desc.time.clone()),
+                secure_version: Some(desc.secure_version),
+                app_elf_sha256: Some(desc.app_elf_sha256.clone()),
+            },
+            None => AppInfo::default(),
+        }
+    }
+}
+
+impl AppDesc {
+    /// Parses an `esp_app_desc_t` starting at `desc`, as found in the
+    /// `.flash.appdesc` section of an ELF
+    pub fn parse(desc: &[u8]) -> Option<Self> {
+        let desc = desc.get(..APP_ELF_SHA256.0 + APP_ELF_SHA256.1)?;
+        if u32::from_le_bytes([desc[0], desc[1], desc[2], desc[3]]) != APP_DESC_MAGIC {
+            return None;
+        }
+
+        let string = |(offset, len): (usize, usize)| {
+            let field = &desc[offset..offset + len];
+            let end = field.iter().position(|&b| b == 0).unwrap_or(len);
+            String::from_utf8_lossy(&field[..end]).trim().to_string()
+        };
+        Some(AppDesc {
+            version: string(VERSION),
+            project_name: string(PROJECT_NAME),
+            time: string(TIME),
+            date: string(DATE),
+            idf_version: string(IDF_VERSION),
+            secure_version: u32::from_le_bytes(
+                desc[SECURE_VERSION..SECURE_VERSION + 4].try_into().ok()?,
+            ),
+            app_elf_sha256: desc[APP_ELF_SHA256.0..APP_ELF_SHA256.0 + APP_ELF_SHA256.1]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        })
+    }
+}
+
+/// Reads the app descriptor of an app image, if it has one
+pub fn app_desc(firmware: &[u8]) -> Option<AppDesc> {
+    AppDesc::parse(firmware.get(APP_DESC_OFFSET..)?)
+}
+
+/// Reads the version string ESP-IDF embeds in the app descriptor, if the
+/// image has one
+pub fn app_version(firmware: &[u8]) -> Option<String> {
+    let version = app_desc(firmware)?.version;
+    (!version.is_empty()).then_some(version)
+}
+
+/// Parses `1.2.3`, `v1.2` or `2` into the major, minor and patch numbers
+/// esp32FOTA compares, ignoring pre-release and build suffixes
+pub fn parse_version(version: &str) -> Option<[u64; 3]> {
+    let version = version.trim().trim_start_matches(['v', 'V']);
+    let core = version.split(['-', '+']).next()?;
+
+    let mut numbers = [0; 3];
+    let mut parts = core.split('.');
+    for number in numbers.iter_mut() {
+        match parts.next() {
+            Some(part) => *number = part.parse().ok()?,
+            None => break,
+        }
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(numbers)
+}
+
+/// Validates `--firmware-version`
+pub fn version_arg(s: &str) -> Result<String, String> {
+    parse_version(s)
+        .map(|_| s.to_string())
+        .ok_or_else(|| format!("'{}' is not a version like 1.2.3", s))
+}
+
+/// Whether a device running `current` would take an update to `offered`;
+/// like esp32FOTA, only strictly newer versions are offered
+pub fn is_newer(offered: [u64; 3], current: [u64; 3]) -> bool {
+    offered.cmp(&current) == Ordering::Greater
+}
+
+pub fn format_version(version: [u64; 3]) -> String {
+    format!("{}.{}.{}", version[0], version[1], version[2])
+}
+
+/// Request guard resolving the absolute base URL devices should use:
+/// `--public-url` if given, otherwise the host the request was sent to
+pub struct BaseUrl(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BaseUrl {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let public_url = req
+            .rocket()
+            .state::<FotaSettings>()
+            .and_then(|settings| settings.public_url.clone());
+        let base = match (public_url, req.host()) {
+            (Some(url), _) => url.trim_end_matches('/').to_string(),
+            (None, Some(host)) => {
+                let scheme = if req.rocket().config().tls_enabled() {
+                    "https"
+                } else {
+                    "http"
+                };
+                format!("{}://{}", scheme, host)
+            }
+            (None, None) => {
+                let config = req.rocket().config();
+                format!("http://{}:{}", config.address, config.port)
+            }
+        };
+        Outcome::Success(BaseUrl(base))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::serde::json;
+
+    use super::*;
+
+    fn settings(version: Option<&str>) -> FotaSettings {
+        FotaSettings {
+            ty: String::from("esp32-fota-http"),
+            version: version.map(str::to_string),
+            public_url: Some(String::from("https://flash.example.com")),
+        }
+    }
+
+    #[test]
+    fn descriptor_has_the_esp32fota_schema() {
+        let descriptor = Descriptor::new(&settings(None), [1, 2, 0], "https://flash.example.com");
+        assert_eq!(
+            json::to_string(&descriptor).unwrap(),
+            r#"{"type":"esp32-fota-http","version":"1.2.0","url":"https://flash.example.com/firmware.bin"}"#
+        );
+    }
+
+    #[test]
+    fn firmware_version_takes_precedence() {
+        assert_eq!(
+            settings(Some("2.0.0")).version(Some("1.0.0")),
+            Some("2.0.0")
+        );
+        assert_eq!(settings(None).version(Some("1.0.0")), Some("1.0.0"));
+        assert_eq!(settings(None).version(None), None);
+    }
+
+    #[test]
+    fn parses_versions_like_esp32fota() {
+        assert_eq!(parse_version("1.2.3"), Some([1, 2, 3]));
+        assert_eq!(parse_version("v1.2"), Some([1, 2, 0]));
+        assert_eq!(parse_version("2"), Some([2, 0, 0]));
+        assert_eq!(parse_version("1.2.3-rc1+abc"), Some([1, 2, 3]));
+        assert_eq!(parse_version("1.2.3.4"), None);
+        assert_eq!(parse_version("one"), None);
+        assert!(version_arg("1.x").is_err());
+    }
+
+    #[test]
+    fn offers_only_newer_versions() {
+        assert!(is_newer([1, 2, 4], [1, 2, 3]));
+        assert!(is_newer([2, 0, 0], [1, 9, 9]));
+        assert!(!is_newer([1, 2, 3], [1, 2, 3]));
+        assert!(!is_newer([1, 2, 3], [1, 10, 0]));
+    }
+
+    #[test]
+    fn reads_the_app_descriptor() {
+        let mut firmware = vec![0; APP_DESC_OFFSET + 256];
+        let desc = &mut firmware[APP_DESC_OFFSET..];
+        desc[..4].copy_from_slice(&APP_DESC_MAGIC.to_le_bytes());
+        desc[SECURE_VERSION..SECURE_VERSION + 4].copy_from_slice(&3u32.to_le_bytes());
+        desc[VERSION.0..VERSION.0 + 6].copy_from_slice(b"v1.4.2");
+        desc[PROJECT_NAME.0..PROJECT_NAME.0 + 5].copy_from_slice(b"blink");
+        assert_eq!(app_version(&firmware).as_deref(), Some("v1.4.2"));
+        let desc = app_desc(&firmware).unwrap();
+        assert_eq!(desc.project_name, "blink");
+        assert_eq!(desc.secure_version, 3);
+
+        firmware[APP_DESC_OFFSET] = 0;
+        assert_eq!(app_version(&firmware), None);
+    }
+
+    #[test]
+    fn extracts_the_version_from_app_images() {
+        // see tests/fixtures/gen_elf.py
+        let image = include_bytes!("../tests/fixtures/esp32-app.bin");
+        assert_eq!(app_version(image).as_deref(), Some("v2.0.1"));
+
+        let elf = include_bytes!("../tests/fixtures/esp32-app.elf");
+        let firmware = espflash::elf::FirmwareImageBuilder::new(elf)
+            .build()
+            .unwrap();
+        let flash_image = espflash::Chip::Esp32
+            .get_flash_image(&firmware, None, None, None, None)
+            .unwrap();
+        let app = flash_image.flash_segments().last().unwrap();
+        assert_eq!(app_version(&app.data).as_deref(), Some("v1.4.2"));
+        let desc = app_desc(&app.data).unwrap();
+        assert_eq!(
+            (desc.project_name.as_str(), desc.idf_version.as_str()),
+            ("blink", "v5.1")
+        );
+        assert_eq!(
+            (desc.date.as_str(), desc.time.as_str()),
+            ("Jan  1 2024", "12:00:00")
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use rocket::http::Status;
+use serde::Serialize;
+
+use crate::{
+    prepared_build::{BuildState, Builds},
+    stats::ServeStats,
+};
+
+/// What `/health` reports
+#[derive(Serialize, Debug)]
+pub struct Health {
+    /// `ok`, `building` or `error`, as `/status` reports the build state
+    pub status: &'static str,
+    pub uptime_seconds: u64,
+    /// whether an image is prepared and served; false with `--monitor-only`
+    pub prepared: bool,
+    /// the ELF the served build was prepared from
+    pub elf_path: Option<String>,
+    /// its modification time when it was read, in seconds since the Unix epoch
+    pub elf_mtime: Option<u64>,
+}
+
+/// The report and its status code: 503 while a rebuild runs or after one
+/// failed, so a probe waits for a build that matches the sources
+pub fn check(builds: Option<&Builds>, stats: &ServeStats) -> (Status, Health) {
+    let mut health = Health {
+        status: "ok",
+        uptime_seconds: stats.uptime_seconds(),
+        prepared: false,
+        elf_path: None,
+        elf_mtime: None,
+    };
+    let builds = match builds {
+        Some(builds) => builds,
+        None => return (Status::Ok, health),
+    };
+
+    let build = builds.current();
+    health.prepared = true;
+    health.elf_path = build.parts.elf_path.clone();
+    health.elf_mtime = build.parts.elf_mtime;
+    match builds.status().state {
+        BuildState::Ready => (Status::Ok, health),
+        BuildState::Building => {
+            health.status = "building";
+            (Status::ServiceUnavailable, health)
+        }
+        BuildState::Error => {
+            health.status = "error";
+            (Status::ServiceUnavailable, health)
+        }
+    }
+}
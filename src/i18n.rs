@@ -0,0 +1,389 @@
+/// User-visible strings of the built-in page for one language.
+///
+/// Every field is a placeholder in `index.html`, so adding a language only
+/// requires a new entry in [`TRANSLATIONS`].
+pub struct Translation {
+    pub code: &'static str,
+    pub title: &'static str,
+    pub firmware_information: &'static str,
+    pub firmware_version: &'static str,
+    pub chip: &'static str,
+    pub flash_size: &'static str,
+    pub flash_mode: &'static str,
+    pub flash_freq: &'static str,
+    pub min_chip_rev: &'static str,
+    pub served_since: &'static str,
+    pub bootloader: &'static str,
+    pub partitions: &'static str,
+    pub firmware: &'static str,
+    pub total_size: &'static str,
+    pub estimated_time: &'static str,
+    pub share_link: &'static str,
+    pub install_mode: &'static str,
+    pub erase_prompt: &'static str,
+    pub erase_keep: &'static str,
+    pub erase_full: &'static str,
+    pub erase_locked_note: &'static str,
+    pub build_label: &'static str,
+    pub current_build: &'static str,
+    pub variant_label: &'static str,
+    pub default_variant: &'static str,
+    pub flash_layout: &'static str,
+    pub note: &'static str,
+    pub close_port_note: &'static str,
+    pub progress: &'static str,
+    pub uploaded: &'static str,
+    pub bytes: &'static str,
+    pub console_output: &'static str,
+    pub download_logs: &'static str,
+    pub download_all: &'static str,
+    pub reload_firmware: &'static str,
+    pub admin_token_prompt: &'static str,
+    pub clear_logs: &'static str,
+    pub diagnostics: &'static str,
+    pub building: &'static str,
+    pub build_failed: &'static str,
+    pub browser_not_supported: &'static str,
+    pub no_web_serial: &'static str,
+    pub use_supported_browser: &'static str,
+}
+
+impl Translation {
+    /// Placeholder name and value pairs used to render the page
+    pub fn vars(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("lang", self.code),
+            ("title", self.title),
+            ("firmware_information", self.firmware_information),
+            ("firmware_version", self.firmware_version),
+            ("chip", self.chip),
+            ("flash_size", self.flash_size),
+            ("flash_mode", self.flash_mode),
+            ("flash_freq", self.flash_freq),
+            ("min_chip_rev", self.min_chip_rev),
+            ("served_since", self.served_since),
+            ("bootloader", self.bootloader),
+            ("partitions", self.partitions),
+            ("firmware", self.firmware),
+            ("total_size", self.total_size),
+            ("estimated_time", self.estimated_time),
+            ("share_link", self.share_link),
+            ("install_mode", self.install_mode),
+            ("erase_prompt", self.erase_prompt),
+            ("erase_keep", self.erase_keep),
+            ("erase_full", self.erase_full),
+            ("erase_locked_note", self.erase_locked_note),
+            ("build_label", self.build_label),
+            ("current_build", self.current_build),
+            ("variant_label", self.variant_label),
+            ("default_variant", self.default_variant),
+            ("flash_layout", self.flash_layout),
+            ("note", self.note),
+            ("close_port_note", self.close_port_note),
+            ("progress", self.progress),
+            ("uploaded", self.uploaded),
+            ("bytes", self.bytes),
+            ("console_output", self.console_output),
+            ("download_logs", self.download_logs),
+            ("download_all", self.download_all),
+            ("reload_firmware", self.reload_firmware),
+            ("admin_token_prompt", self.admin_token_prompt),
+            ("clear_logs", self.clear_logs),
+            ("diagnostics", self.diagnostics),
+            ("building", self.building),
+            ("build_failed", self.build_failed),
+            ("browser_not_supported", self.browser_not_supported),
+            ("no_web_serial", self.no_web_serial),
+            ("use_supported_browser", self.use_supported_browser),
+        ]
+    }
+}
+
+pub const ENGLISH: Translation = Translation {
+    code: "en",
+    title: "ESP Web Flasher",
+    firmware_information: "Firmware Information",
+    firmware_version: "Version",
+    chip: "Chip",
+    flash_size: "Flash Size",
+    flash_mode: "Flash Mode",
+    flash_freq: "Flash Frequency",
+    min_chip_rev: "Min. Chip Revision",
+    served_since: "Served Since",
+    bootloader: "Bootloader",
+    partitions: "Partitions",
+    firmware: "Firmware",
+    total_size: "Total Size",
+    estimated_time: "Estimated flash time",
+    share_link: "Share this link",
+    install_mode: "Install mode",
+    erase_prompt: "Ask before erasing",
+    erase_keep: "Update (keep settings)",
+    erase_full: "Factory install (full erase)",
+    erase_locked_note:
+        "Full erase is disabled on this server: installs keep the device's settings (NVS).",
+    build_label: "Build",
+    current_build: "current",
+    variant_label: "Variant",
+    default_variant: "default",
+    flash_layout: "Flash Layout",
+    note: "Note",
+    close_port_note:
+        "Make sure to close any applications using your device's COM port (e.g., Serial Monitor)",
+    progress: "Progress",
+    uploaded: "Uploaded",
+    bytes: "bytes",
+    console_output: "Console Output",
+    download_logs: "Download Logs",
+    download_all: "Download all files",
+    reload_firmware: "Reload firmware",
+    admin_token_prompt: "Admin token",
+    clear_logs: "Clear Logs",
+    diagnostics: "Diagnostics",
+    building: "Rebuilding firmware, installing is disabled until it is done…",
+    build_failed: "Build failed, still serving the previous build:",
+    browser_not_supported: "Browser Not Supported",
+    no_web_serial: "Your browser does not support the Web Serial API.",
+    use_supported_browser: "Please use Chrome or Microsoft Edge to flash your ESP device.",
+};
+
+pub const TRANSLATIONS: &[Translation] = &[
+    ENGLISH,
+    Translation {
+        code: "de",
+        title: "ESP Web Flasher",
+        firmware_information: "Firmware-Informationen",
+        firmware_version: "Version",
+        chip: "Chip",
+        flash_size: "Flash-Größe",
+        flash_mode: "Flash-Modus",
+        flash_freq: "Flash-Frequenz",
+        min_chip_rev: "Min. Chip-Revision",
+        served_since: "Bereitgestellt seit",
+        bootloader: "Bootloader",
+        partitions: "Partitionen",
+        firmware: "Firmware",
+        total_size: "Gesamtgröße",
+        estimated_time: "Geschätzte Flash-Dauer",
+        share_link: "Diesen Link teilen",
+        install_mode: "Installationsart",
+        erase_prompt: "Vor dem Löschen fragen",
+        erase_keep: "Aktualisieren (Einstellungen behalten)",
+        erase_full: "Werksinstallation (alles löschen)",
+        erase_locked_note: "Vollständiges Löschen ist auf diesem Server deaktiviert: Installationen behalten die Einstellungen des Geräts (NVS).",
+        build_label: "Build",
+        current_build: "aktuell",
+        variant_label: "Variante",
+        default_variant: "Standard",
+        flash_layout: "Flash-Belegung",
+        note: "Hinweis",
+        close_port_note: "Schließen Sie alle Anwendungen, die den COM-Port Ihres Geräts verwenden (z. B. den seriellen Monitor)",
+        progress: "Fortschritt",
+        uploaded: "Übertragen",
+        bytes: "Bytes",
+        console_output: "Konsolenausgabe",
+        download_logs: "Logs herunterladen",
+        download_all: "Alle Dateien herunterladen",
+        reload_firmware: "Firmware neu laden",
+        admin_token_prompt: "Admin-Token",
+        clear_logs: "Logs löschen",
+        diagnostics: "Diagnose",
+        building: "Firmware wird neu gebaut, Installieren ist bis zum Abschluss deaktiviert…",
+        build_failed: "Build fehlgeschlagen, der vorherige Build wird weiter ausgeliefert:",
+        browser_not_supported: "Browser nicht unterstützt",
+        no_web_serial: "Ihr Browser unterstützt die Web Serial API nicht.",
+        use_supported_browser: "Bitte verwenden Sie Chrome oder Microsoft Edge, um Ihr ESP-Gerät zu flashen.",
+    },
+    Translation {
+        code: "ja",
+        title: "ESP Web フラッシャー",
+        firmware_information: "ファームウェア情報",
+        firmware_version: "バージョン",
+        chip: "チップ",
+        flash_size: "フラッシュサイズ",
+        flash_mode: "フラッシュモード",
+        flash_freq: "フラッシュ周波数",
+        min_chip_rev: "最小チップリビジョン",
+        served_since: "配信開始",
+        bootloader: "ブートローダー",
+        partitions: "パーティション",
+        firmware: "ファームウェア",
+        total_size: "合計サイズ",
+        estimated_time: "推定書き込み時間",
+        share_link: "このリンクを共有",
+        install_mode: "インストール方法",
+        erase_prompt: "消去前に確認",
+        erase_keep: "更新（設定を保持）",
+        erase_full: "初期インストール（全消去）",
+        erase_locked_note: "このサーバーでは全消去が無効です。インストール時にデバイスの設定（NVS）は保持されます。",
+        build_label: "ビルド",
+        current_build: "現在",
+        variant_label: "バリアント",
+        default_variant: "デフォルト",
+        flash_layout: "フラッシュレイアウト",
+        note: "注意",
+        close_port_note: "デバイスの COM ポートを使用しているアプリケーション（シリアルモニターなど）をすべて閉じてください",
+        progress: "進行状況",
+        uploaded: "書き込み済み",
+        bytes: "バイト",
+        console_output: "コンソール出力",
+        download_logs: "ログをダウンロード",
+        download_all: "すべてのファイルをダウンロード",
+        reload_firmware: "ファームウェアを再読み込み",
+        admin_token_prompt: "管理トークン",
+        clear_logs: "ログを消去",
+        diagnostics: "診断情報",
+        building: "ファームウェアを再ビルド中です。完了するまでインストールできません…",
+        build_failed: "ビルドに失敗しました。以前のビルドを引き続き提供しています：",
+        browser_not_supported: "対応していないブラウザです",
+        no_web_serial: "お使いのブラウザは Web Serial API に対応していません。",
+        use_supported_browser: "ESP デバイスに書き込むには Chrome または Microsoft Edge を使用してください。",
+    },
+    Translation {
+        code: "fr",
+        title: "ESP Web Flasher",
+        firmware_information: "Informations sur le firmware",
+        firmware_version: "Version",
+        chip: "Puce",
+        flash_size: "Taille de la flash",
+        flash_mode: "Mode flash",
+        flash_freq: "Fréquence flash",
+        min_chip_rev: "Révision min. de la puce",
+        served_since: "Servi depuis",
+        bootloader: "Bootloader",
+        partitions: "Partitions",
+        firmware: "Firmware",
+        total_size: "Taille totale",
+        estimated_time: "Durée de flash estimée",
+        share_link: "Partager ce lien",
+        install_mode: "Mode d’installation",
+        erase_prompt: "Demander avant d’effacer",
+        erase_keep: "Mise à jour (conserver les réglages)",
+        erase_full: "Installation d’usine (effacement complet)",
+        erase_locked_note: "L’effacement complet est désactivé sur ce serveur : les installations conservent les réglages de l’appareil (NVS).",
+        build_label: "Build",
+        current_build: "actuel",
+        variant_label: "Variante",
+        default_variant: "par défaut",
+        flash_layout: "Plan de la flash",
+        note: "Remarque",
+        close_port_note: "Fermez toutes les applications utilisant le port COM de votre appareil (par ex. le moniteur série)",
+        progress: "Progression",
+        uploaded: "Envoyé",
+        bytes: "octets",
+        console_output: "Sortie de la console",
+        download_logs: "Télécharger les journaux",
+        download_all: "Télécharger tous les fichiers",
+        reload_firmware: "Recharger le firmware",
+        admin_token_prompt: "Jeton d’administration",
+        clear_logs: "Effacer les journaux",
+        diagnostics: "Diagnostic",
+        building: "Recompilation du firmware, l'installation est désactivée jusqu'à la fin…",
+        build_failed: "Échec de la compilation, la version précédente reste servie :",
+        browser_not_supported: "Navigateur non pris en charge",
+        no_web_serial: "Votre navigateur ne prend pas en charge l'API Web Serial.",
+        use_supported_browser: "Veuillez utiliser Chrome ou Microsoft Edge pour flasher votre appareil ESP.",
+    },
+    Translation {
+        code: "es",
+        title: "ESP Web Flasher",
+        firmware_information: "Información del firmware",
+        firmware_version: "Versión",
+        chip: "Chip",
+        flash_size: "Tamaño de flash",
+        flash_mode: "Modo de flash",
+        flash_freq: "Frecuencia de flash",
+        min_chip_rev: "Revisión mín. del chip",
+        served_since: "Servido desde",
+        bootloader: "Bootloader",
+        partitions: "Particiones",
+        firmware: "Firmware",
+        total_size: "Tamaño total",
+        estimated_time: "Tiempo de flasheo estimado",
+        share_link: "Compartir este enlace",
+        install_mode: "Modo de instalación",
+        erase_prompt: "Preguntar antes de borrar",
+        erase_keep: "Actualizar (conservar ajustes)",
+        erase_full: "Instalación de fábrica (borrado completo)",
+        erase_locked_note: "El borrado completo está desactivado en este servidor: las instalaciones conservan los ajustes del dispositivo (NVS).",
+        build_label: "Compilación",
+        current_build: "actual",
+        variant_label: "Variante",
+        default_variant: "predeterminada",
+        flash_layout: "Mapa de la flash",
+        note: "Nota",
+        close_port_note: "Cierra cualquier aplicación que esté usando el puerto COM de tu dispositivo (p. ej., el monitor serie)",
+        progress: "Progreso",
+        uploaded: "Enviado",
+        bytes: "bytes",
+        console_output: "Salida de consola",
+        download_logs: "Descargar registros",
+        download_all: "Descargar todos los archivos",
+        reload_firmware: "Recargar firmware",
+        admin_token_prompt: "Token de administrador",
+        clear_logs: "Borrar registros",
+        diagnostics: "Diagnóstico",
+        building: "Recompilando el firmware, la instalación está desactivada hasta que termine…",
+        build_failed: "La compilación falló, se sigue sirviendo la versión anterior:",
+        browser_not_supported: "Navegador no compatible",
+        no_web_serial: "Tu navegador no es compatible con la API Web Serial.",
+        use_supported_browser: "Usa Chrome o Microsoft Edge para flashear tu dispositivo ESP.",
+    },
+];
+
+/// Looks up a translation by language code, e.g. `de` or `ja-JP`
+pub fn find(code: &str) -> Option<&'static Translation> {
+    let primary = code.split(['-', '_']).next().unwrap_or(code);
+    TRANSLATIONS
+        .iter()
+        .find(|t| t.code.eq_ignore_ascii_case(primary))
+}
+
+/// Names of all available languages, for warnings and help output
+pub fn available() -> Vec<&'static str> {
+    TRANSLATIONS.iter().map(|t| t.code).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_translation_is_complete() {
+        let pages = [include_str!("index.html"), include_str!("monitor.html")].concat();
+        let names: Vec<&str> = ENGLISH.vars().iter().map(|(name, _)| *name).collect();
+        for translation in TRANSLATIONS {
+            for (name, value) in translation.vars() {
+                assert!(
+                    !value.trim().is_empty(),
+                    "'{}' has no text for {}",
+                    translation.code,
+                    name
+                );
+            }
+        }
+        for name in names {
+            assert!(
+                pages.contains(&format!("{{{{{}}}}}", name)),
+                "{{{{{}}}}} is used by neither page",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn codes_are_unique() {
+        let mut codes = available();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), TRANSLATIONS.len());
+    }
+
+    #[test]
+    fn find_ignores_region_and_case() {
+        assert_eq!(find("ja-JP").map(|t| t.code), Some("ja"));
+        assert_eq!(find("DE").map(|t| t.code), Some("de"));
+        assert_eq!(find("es_MX").map(|t| t.code), Some("es"));
+        assert!(find("xx").is_none());
+    }
+}
@@ -0,0 +1,102 @@
+use std::{collections::BTreeMap, path::Path};
+
+use espflash::Chip;
+use rocket::serde::json;
+use serde::Deserialize;
+
+use crate::{
+    checksums::Checksums, partition_gen, prebuilt, prepare_error::PrepareError, PartsData,
+};
+
+/// Written by `idf.py build` next to the binaries
+pub const FLASHER_ARGS: &str = "flasher_args.json";
+
+/// The parts of `flasher_args.json` used to serve the build
+#[derive(Deserialize)]
+struct FlasherArgs {
+    /// offset -> file, every binary `idf.py flash` writes
+    flash_files: BTreeMap<String, String>,
+    bootloader: Option<Entry>,
+    #[serde(rename = "partition-table")]
+    partition_table: Option<Entry>,
+    #[serde(default)]
+    flash_settings: FlashSettings,
+    #[serde(default)]
+    extra_esptool_args: EsptoolArgs,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    offset: String,
+}
+
+#[derive(Deserialize, Default)]
+struct FlashSettings {
+    flash_size: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct EsptoolArgs {
+    chip: Option<String>,
+}
+
+/// Serves the binaries an ESP-IDF build directory's `flasher_args.json`
+/// lists, each at its declared offset, `--idf-build-dir`
+pub fn prepare(
+    dir: &Path,
+    chip: Chip,
+    flash_size: &str,
+    flash_size_bytes: u32,
+    checksums: &Checksums,
+) -> Result<PartsData, PrepareError> {
+    let path = dir.join(FLASHER_ARGS);
+    let invalid = |reason: String| PrepareError::InvalidPart {
+        path: path.clone(),
+        reason,
+    };
+    let text = checksums.read(&path)?;
+    let args: FlasherArgs = json::from_slice(&text).map_err(|e| invalid(e.to_string()))?;
+
+    if let Some(built_for) = &args.extra_esptool_args.chip {
+        let expected = crate::chip_name(chip).replace('-', "").to_lowercase();
+        if *built_for != expected {
+            return Err(invalid(format!(
+                "the build is for {}, not {}",
+                built_for, expected
+            )));
+        }
+    }
+    if let Some(size) = &args.flash_settings.flash_size {
+        if !["detect", "keep"].contains(&size.as_str()) && !size.eq_ignore_ascii_case(flash_size) {
+            warning!(
+                "the build is configured for {} flash, serving it for {}",
+                size,
+                flash_size
+            );
+        }
+    }
+
+    let offset = |s: &str| partition_gen::parse_size(s).map_err(&invalid);
+    let mut parts = Vec::new();
+    for (at, file) in &args.flash_files {
+        parts.push(prebuilt::load(dir, file, offset(at)?, checksums)?);
+    }
+    let bootloader_at = match &args.bootloader {
+        Some(entry) => offset(&entry.offset)?,
+        None => prebuilt::bootloader_offset(chip),
+    };
+    let table_at = match &args.partition_table {
+        Some(entry) => offset(&entry.offset)?,
+        None => partition_gen::DEFAULT_TABLE_OFFSET,
+    };
+
+    prebuilt::serve(
+        dir,
+        parts,
+        bootloader_at,
+        table_at,
+        chip,
+        flash_size,
+        flash_size_bytes,
+    )
+}
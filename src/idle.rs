@@ -0,0 +1,101 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::time,
+    Data, Orbit, Request, Rocket,
+};
+
+/// Remaining idle seconds at which the shutdown is announced
+const WARN_AT: &[u64] = &[60, 10];
+
+/// `--idle-timeout`: shuts the server down once no request arrived for
+/// `timeout`. Clones share the time of the last request, so a request to
+/// any listener resets the countdown.
+#[derive(Clone)]
+pub struct IdleTimeout {
+    timeout: Duration,
+    last_request: Arc<Mutex<Instant>>,
+    /// whether this listener runs the countdown; shutting it down stops
+    /// the others as well
+    countdown: bool,
+}
+
+impl IdleTimeout {
+    pub fn new(secs: u64) -> Self {
+        IdleTimeout {
+            timeout: Duration::from_secs(secs),
+            last_request: Arc::new(Mutex::new(Instant::now())),
+            countdown: false,
+        }
+    }
+
+    /// The fairing for the listener that runs the countdown
+    pub fn with_countdown(mut self) -> Self {
+        self.countdown = true;
+        self
+    }
+
+    fn remaining(&self) -> Duration {
+        self.timeout
+            .saturating_sub(self.last_request.lock().unwrap().elapsed())
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for IdleTimeout {
+    fn info(&self) -> Info {
+        Info {
+            name: "Idle timeout",
+            kind: Kind::Request | Kind::Liftoff,
+        }
+    }
+
+    async fn on_request(&self, _req: &mut Request<'_>, _data: &mut Data<'_>) {
+        *self.last_request.lock().unwrap() = Instant::now();
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        if !self.countdown {
+            return;
+        }
+        let idle = self.clone();
+        let shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            // the lowest threshold announced in this countdown
+            let mut warned: Option<u64> = None;
+            loop {
+                let remaining = idle.remaining();
+                if remaining.is_zero() {
+                    status!(
+                        "\nNo request for {}s, shutting down (--idle-timeout)",
+                        idle.timeout.as_secs()
+                    );
+                    shutdown.notify();
+                    return;
+                }
+
+                // a request came in, start over
+                if warned.map_or(false, |at| remaining > Duration::from_secs(at)) {
+                    warned = None;
+                }
+                let due = WARN_AT.iter().copied().find(|&at| {
+                    at < idle.timeout.as_secs()
+                        && remaining <= Duration::from_secs(at)
+                        && warned.map_or(true, |warned| warned > at)
+                });
+                if let Some(at) = due {
+                    status!(
+                        "No request for a while, shutting down in {}s",
+                        remaining.as_secs_f32().round()
+                    );
+                    warned = Some(at);
+                }
+                time::sleep(remaining.min(Duration::from_secs(1))).await;
+            }
+        });
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use arc_swap::ArcSwap;
+
+/// Placeholders a `--index` page may use, substituted when it's served
+pub const PLACEHOLDERS: &[&str] = &["name", "chip", "flash_size", "token_query", "esp_web_tools"];
+
+/// The `--index` page, replaced as a whole when reloaded in watch mode
+#[derive(Clone)]
+pub struct IndexTemplate {
+    path: PathBuf,
+    html: Arc<ArcSwap<String>>,
+}
+
+/// Reads the template, refusing it if it isn't UTF-8 or uses a placeholder
+/// that would never be replaced
+fn read(path: &Path) -> Result<String> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let html = String::from_utf8(bytes)
+        .with_context(|| format!("{} is not UTF-8 text", path.display()))?;
+
+    let mut rest = html.as_str();
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .with_context(|| format!("{}: '{{{{' without a closing '}}}}'", path.display()))?;
+        let name = &after[..end];
+        if !PLACEHOLDERS.contains(&name) {
+            bail!(
+                "{}: unknown placeholder {{{{{}}}}} (available: {})",
+                path.display(),
+                name,
+                PLACEHOLDERS
+                    .iter()
+                    .map(|name| format!("{{{{{}}}}}", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        rest = &after[end + 2..];
+    }
+    Ok(html)
+}
+
+impl IndexTemplate {
+    pub fn load(path: &Path) -> Result<Self> {
+        let html = read(path)?;
+        status!("Page template: {}", path.display());
+        Ok(IndexTemplate {
+            path: path.to_path_buf(),
+            html: Arc::new(ArcSwap::from_pointee(html)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the template again; a broken edit keeps the last good one
+    pub fn reload(&self) {
+        match read(&self.path) {
+            Ok(html) => {
+                self.html.store(Arc::new(html));
+                status!("Reloaded the page template {}", self.path.display());
+            }
+            Err(e) => warning!("keeping the last page template: {:#}", e),
+        }
+    }
+
+    /// The page with every placeholder replaced; `vars` are HTML-safe
+    pub fn render(&self, vars: &[(&str, &str)]) -> String {
+        crate::render_template(&self.html.load(), vars)
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use dialoguer::{theme::ColorfulTheme, Input, Select};
+use espflash::Chip;
+
+use crate::{boards, presets};
+
+/// Values accepted by --chip, offered in this order
+const CHIPS: &[&str] = &["esp32", "esp32s2", "esp32s3", "esp32c3", "esp8266"];
+const FLASH_SIZES: &[&str] = &["2MB", "4MB", "8MB", "16MB"];
+/// How deep below the current directory ELF files are looked for
+const SEARCH_DEPTH: usize = 4;
+
+/// Whether missing arguments may be asked for: only with a user at the
+/// terminal, and never with --non-interactive
+pub fn available(args: &[OsString]) -> bool {
+    !args.iter().any(|arg| arg == "--non-interactive")
+        && atty::is(atty::Stream::Stdin)
+        && atty::is(atty::Stream::Stdout)
+}
+
+/// Asks for the chip, appending `--chip` to `args`
+pub fn ask_chip(args: &mut Vec<OsString>) -> Result<()> {
+    let theme = ColorfulTheme::default();
+    let i = Select::with_theme(&theme)
+        .with_prompt("Chip")
+        .items(CHIPS)
+        .default(0)
+        .interact()?;
+    // the same parser as --chip, so both paths accept the same values
+    if let Err(e) = CHIPS[i].parse::<Chip>() {
+        bail!("{}", e);
+    }
+    push(args, "--chip", CHIPS[i]);
+    Ok(())
+}
+
+/// Asks for the ELF, then for the flash size and partition table unless
+/// given, appending them to `args`
+pub fn ask_image(args: &mut Vec<OsString>) -> Result<()> {
+    let theme = ColorfulTheme::default();
+
+    let mut candidates = Vec::new();
+    find_elfs(Path::new("."), SEARCH_DEPTH, &mut candidates);
+    candidates.sort();
+    let mut items: Vec<String> = candidates
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    items.push(String::from("Enter a path..."));
+
+    let i = Select::with_theme(&theme)
+        .with_prompt("ELF file")
+        .items(&items)
+        .default(0)
+        .interact()?;
+    let elf = match candidates.get(i) {
+        Some(path) => path.display().to_string(),
+        None => Input::<String>::with_theme(&theme)
+            .with_prompt("Path to the ELF")
+            .validate_with(|input: &String| check_elf(Path::new(input)))
+            .interact_text()?,
+    };
+
+    if !boards::given(args, "--flash-size", Some('f')) {
+        let i = Select::with_theme(&theme)
+            .with_prompt("Flash size")
+            .items(FLASH_SIZES)
+            .default(1)
+            .interact()?;
+        push(args, "--flash-size", FLASH_SIZES[i]);
+    }
+
+    let layout_given = boards::given(args, "--partition-table", Some('p'))
+        || boards::given(args, "--gen-partition-table", None);
+    if !layout_given {
+        let mut items = vec![String::from("default (espflash's built-in table)")];
+        items.extend(
+            presets::PRESETS
+                .iter()
+                .map(|preset| format!("{} - {}", preset.name, preset.description)),
+        );
+        let i = Select::with_theme(&theme)
+            .with_prompt("Partition table")
+            .items(&items)
+            .default(0)
+            .interact()?;
+        if let Some(preset) = i.checked_sub(1).map(|i| &presets::PRESETS[i]) {
+            let value = format!("preset:{}", preset.name);
+            if let Err(e) = value.parse::<presets::PartitionTableArg>() {
+                bail!("{}", e);
+            }
+            push(args, "--partition-table", &value);
+        }
+    }
+
+    args.push(OsString::from(elf));
+    Ok(())
+}
+
+/// Prints `args` as a command line to reuse next time
+pub fn echo(args: &[OsString]) {
+    let program = args
+        .first()
+        .map(PathBuf::from)
+        .and_then(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| String::from("web-flash"));
+    let line: Vec<String> = std::iter::once(program)
+        .chain(args.iter().skip(1).map(|arg| quote(&arg.to_string_lossy())))
+        .collect();
+    println!("\nNext time, run:\n  {}\n", line.join(" "));
+}
+
+fn quote(arg: &str) -> String {
+    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || "'\"$\\".contains(c)) {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    } else {
+        arg.to_string()
+    }
+}
+
+fn push(args: &mut Vec<OsString>, flag: &str, value: &str) {
+    args.push(OsString::from(flag));
+    args.push(OsString::from(value));
+}
+
+fn check_elf(path: &Path) -> Result<(), String> {
+    let mut magic = [0; 4];
+    std::fs::File::open(path)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut magic))
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if magic != *b"\x7fELF" {
+        return Err(format!("{} is not an ELF file", path.display()));
+    }
+    Ok(())
+}
+
+/// ELF files below `dir`, skipping hidden directories and build
+/// intermediates (`deps`, `build`, `incremental`)
+fn find_elfs(dir: &Path, depth: usize, found: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        match entry.file_type() {
+            Ok(ty) if ty.is_dir() => {
+                if depth > 0
+                    && !name.starts_with('.')
+                    && !["deps", "build", "incremental"].contains(&name.as_str())
+                {
+                    find_elfs(&path, depth - 1, found);
+                }
+            }
+            Ok(ty) if ty.is_file() => {
+                if check_elf(&path).is_ok() {
+                    found.push(path.strip_prefix(".").unwrap_or(&path).to_path_buf());
+                }
+            }
+            _ => {}
+        }
+    }
+}
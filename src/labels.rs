@@ -0,0 +1,64 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+/// Keys the server sets itself wherever labels are reported
+const RESERVED: &[&str] = &["chip", "hash", "build", "version"];
+
+/// `--label key=value`
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for Label {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, e.g. pipeline=1234, got '{}'", s))?;
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+        {
+            return Err(format!(
+                "label key '{}' may only contain letters, digits, '_', '-' and '.'",
+                key
+            ));
+        }
+        if RESERVED.contains(&key) {
+            return Err(format!(
+                "label key '{}' is reserved (reserved keys: {})",
+                key,
+                RESERVED.join(", ")
+            ));
+        }
+
+        Ok(Label {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Labels describing this invocation, e.g. the CI pipeline that started it.
+/// They're kept apart from the build so rebuilds don't drop them.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct Labels(pub BTreeMap<String, String>);
+
+impl Labels {
+    pub fn new(labels: &[Label]) -> Result<Self> {
+        let mut map = BTreeMap::new();
+        for label in labels {
+            if map.insert(label.key.clone(), label.value.clone()).is_some() {
+                bail!("--label {} is given more than once", label.key);
+            }
+        }
+        Ok(Labels(map))
+    }
+}
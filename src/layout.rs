@@ -0,0 +1,83 @@
+use serde::Serialize;
+
+use crate::partition_table::PartitionEntry;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionKind {
+    /// written by this flash
+    Written,
+    /// defined by the partition table but not written
+    Defined,
+    /// free space
+    Unused,
+    /// written by more than one part
+    Overlap,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Region {
+    pub start: u32,
+    pub end: u32,
+    pub kind: RegionKind,
+    pub name: String,
+    pub size: u32,
+}
+
+/// A flash range written by one of the served parts
+pub struct WrittenPart<'a> {
+    pub name: &'a str,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// Splits the whole flash into consecutive regions, classifying each one
+/// by what covers it: written parts first, then partition table entries.
+pub fn compute(parts: &[WrittenPart], table: &[PartitionEntry], flash_size: u32) -> Vec<Region> {
+    let mut bounds = vec![0, flash_size];
+    for part in parts {
+        bounds.push(part.offset);
+        bounds.push(part.offset.saturating_add(part.size));
+    }
+    for entry in table {
+        bounds.push(entry.offset);
+        bounds.push(entry.end());
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut regions: Vec<Region> = Vec::new();
+    for window in bounds.windows(2) {
+        let (start, end) = (window[0], window[1]);
+
+        let written: Vec<&str> = parts
+            .iter()
+            .filter(|p| p.offset <= start && end <= p.offset.saturating_add(p.size))
+            .map(|p| p.name)
+            .collect();
+        let defined = table.iter().find(|e| e.offset <= start && end <= e.end());
+
+        let (kind, name) = match (written.len(), defined) {
+            (0, Some(entry)) => (RegionKind::Defined, entry.name.clone()),
+            (0, None) => (RegionKind::Unused, String::from("free")),
+            (1, _) => (RegionKind::Written, written[0].to_string()),
+            _ => (RegionKind::Overlap, written.join(" + ")),
+        };
+
+        match regions.last_mut() {
+            Some(last) if last.kind == kind && last.name == name && last.end == start => {
+                last.end = end;
+                last.size = end - last.start;
+            }
+            _ => regions.push(Region {
+                start,
+                end,
+                kind,
+                name,
+                size: end - start,
+            }),
+        }
+    }
+
+    regions
+}
@@ -0,0 +1,142 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+use rocket::figment::Figment;
+
+/// `--listen <address:port>[,tls][,cert=<path>][,key=<path>]`
+#[derive(Debug, Clone)]
+pub struct Listener {
+    pub addr: SocketAddr,
+    pub tls: bool,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+impl FromStr for Listener {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut options = s.split(',');
+        let addr = options.next().unwrap_or_default();
+        let mut listener = Listener {
+            addr: addr.parse().map_err(|_| {
+                format!(
+                    "'{}' is not an address like 127.0.0.1:8000 or [::]:8443",
+                    addr
+                )
+            })?,
+            tls: false,
+            cert: None,
+            key: None,
+        };
+
+        for option in options {
+            match option.split_once('=') {
+                None if option == "tls" => listener.tls = true,
+                Some(("cert", path)) => listener.cert = Some(PathBuf::from(path)),
+                Some(("key", path)) => listener.key = Some(PathBuf::from(path)),
+                _ => {
+                    return Err(format!(
+                        "unknown listener option '{}' (expected tls, cert=<path> or key=<path>)",
+                        option
+                    ))
+                }
+            }
+        }
+        if listener.cert.is_some() || listener.key.is_some() {
+            listener.tls = true;
+        }
+
+        Ok(listener)
+    }
+}
+
+impl Listener {
+    /// Rocket configuration for this listener, using `default_cert` and
+    /// `default_key` when TLS is enabled without its own certificate
+    pub fn figment(
+        &self,
+        default_cert: Option<&PathBuf>,
+        default_key: Option<&PathBuf>,
+    ) -> Result<Figment> {
+        let mut figment = rocket::Config::figment()
+            .merge(("address", self.addr.ip()))
+            .merge(("port", self.addr.port()));
+
+        if self.tls {
+            let cert = self.cert.as_ref().or(default_cert).with_context(|| {
+                format!(
+                    "--listen {} uses TLS but has no certificate (cert=<path> or --tls-cert)",
+                    self.addr
+                )
+            })?;
+            let key = self.key.as_ref().or(default_key).with_context(|| {
+                format!(
+                    "--listen {} uses TLS but has no key (key=<path> or --tls-key)",
+                    self.addr
+                )
+            })?;
+            figment = figment.merge(("tls.certs", cert)).merge(("tls.key", key));
+        }
+
+        Ok(figment)
+    }
+}
+
+/// The non-loopback addresses of this host's interfaces, with their names,
+/// at which other machines reach a listener bound to all interfaces at
+/// `addr`. IPv6 ones are only listed for an IPv6 bind, and link-local ones
+/// never, as their URLs would need a zone.
+pub fn interfaces(addr: IpAddr) -> Vec<(String, IpAddr)> {
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(_) => return Vec::new(),
+    };
+    interfaces
+        .into_iter()
+        .filter(|interface| !interface.is_loopback())
+        .filter(|interface| match interface.ip() {
+            IpAddr::V4(_) => true,
+            IpAddr::V6(ip) => addr.is_ipv6() && ip.segments()[0] & 0xffc0 != 0xfe80,
+        })
+        .map(|interface| {
+            let ip = interface.ip();
+            (interface.name, ip)
+        })
+        .collect()
+}
+
+/// The address other machines reach a listener bound to `addr` at. Bound to
+/// all interfaces, that's the one outgoing traffic would leave from; no
+/// packet is sent to find it.
+pub fn reachable(addr: IpAddr) -> IpAddr {
+    if !addr.is_unspecified() {
+        return addr;
+    }
+    let (bind, probe, loopback): (SocketAddr, SocketAddr, IpAddr) = if addr.is_ipv4() {
+        (
+            (Ipv4Addr::UNSPECIFIED, 0).into(),
+            (Ipv4Addr::new(192, 0, 2, 1), 80).into(),
+            Ipv4Addr::LOCALHOST.into(),
+        )
+    } else {
+        (
+            (Ipv6Addr::UNSPECIFIED, 0).into(),
+            (Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 80).into(),
+            Ipv6Addr::LOCALHOST.into(),
+        )
+    };
+    UdpSocket::bind(bind)
+        .and_then(|socket| {
+            socket.connect(probe)?;
+            socket.local_addr()
+        })
+        .map(|local| local.ip())
+        .ok()
+        .filter(|ip| !ip.is_unspecified())
+        .unwrap_or(loopback)
+}
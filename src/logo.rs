@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rocket::http::ContentType;
+
+/// `--logo`, shown above the page heading
+#[derive(Clone)]
+pub enum Logo {
+    /// an http(s) URL the browser loads it from
+    Url(String),
+    /// a file read at startup and served at /assets/logo
+    File {
+        content_type: ContentType,
+        data: Vec<u8>,
+    },
+}
+
+impl Logo {
+    /// Reads a logo file, or takes a URL as is
+    pub fn load(arg: &str) -> Result<Self> {
+        if arg.starts_with("http://") || arg.starts_with("https://") {
+            return Ok(Logo::Url(arg.to_string()));
+        }
+
+        let path = Path::new(arg);
+        let content_type = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(ContentType::from_extension)
+            .filter(|ty| ty.top() == "image")
+            .with_context(|| {
+                format!(
+                    "--logo {} is not an image file (.svg, .png, .jpg, .gif, .webp or .ico)",
+                    path.display()
+                )
+            })?;
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Logo::File { content_type, data })
+    }
+
+    /// Where the page loads it from, relative to the page
+    pub fn src(&self, token_query: &str) -> String {
+        match self {
+            Logo::Url(url) => url.clone(),
+            Logo::File { .. } => format!("assets/logo{}", token_query),
+        }
+    }
+}
@@ -1,604 +1,4288 @@
 use ::rocket::async_main;
-use anyhow::Result;
-use std::{path::PathBuf, time::Duration};
+use anyhow::{bail, Context, Result};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    ffi::OsString,
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, UNIX_EPOCH},
+};
 
-use clap::Parser;
-use espflash::{elf::FirmwareImageBuilder, Chip, FlashSize, PartitionTable};
-use rocket::{response::content, State, serde::json::Json};
+use admin::{Admin, AdminSettings, Operator};
+use auth::{Access, AuthSettings};
+use checksums::Checksums;
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use delta_ota::DeltaOta;
+use espflash::{elf::FirmwareImageBuilder, Chip};
+use labels::Labels;
+use monitor::Monitor;
+use prepare_error::PrepareError;
+use prepared_build::{BuildStatus, Builds, Current, PreparedBuild};
+use presets::PartitionTableArg;
+use rocket::{
+    data::{Data, ToByteUnit},
+    fairing::AdHoc,
+    figment::Figment,
+    http::{ContentType, Status},
+    response::{
+        content,
+        stream::{Event, EventStream, TextStream},
+        Redirect,
+    },
+    serde::json::{self, Json, Value},
+    tokio::{
+        select,
+        sync::{broadcast::error::RecvError, mpsc},
+    },
+    Build, Orbit, Request, Rocket, Shutdown, State,
+};
 use serde::Serialize;
+use theme::Theme;
+use web_manifest::ErasePolicy;
+
+/// Set by subcommands printing data on stdout, moving progress reports to
+/// stderr
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+/// `--quiet`: progress reports only go to the log file
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// `--log-format json`: progress reports are only logged, as JSON lines
+static JSON_LOGS: AtomicBool = AtomicBool::new(false);
+
+/// `println!` for progress reports, see `STATUS_TO_STDERR`, `QUIET` and
+/// `JSON_LOGS`; also logged, so `--log-file` has them
+macro_rules! status {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        tracing::info!(target: "status", "{}", message.trim());
+        if !crate::QUIET.load(std::sync::atomic::Ordering::Relaxed)
+            && !crate::JSON_LOGS.load(std::sync::atomic::Ordering::Relaxed)
+        {
+            if crate::STATUS_TO_STDERR.load(std::sync::atomic::Ordering::Relaxed) {
+                eprintln!("{}", message)
+            } else {
+                println!("{}", message)
+            }
+        }
+    }};
+}
+
+/// `eprintln!` for warnings, prefixed with "Warning: "; also logged, and
+/// only logged with `--log-format json`
+macro_rules! warning {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        tracing::warn!(target: "status", "{}", message.trim());
+        if !crate::JSON_LOGS.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!("Warning: {}", message)
+        }
+    }};
+}
+
+mod admin;
+mod app_image;
+mod artifacts;
+mod auth;
+mod boards;
+mod bootloader_image;
+mod cargo_elf;
+mod checksums;
+mod chip_detect;
+mod chip_rev;
+mod chips;
+mod client_logs;
+mod compare;
+mod config_file;
+mod delta_ota;
+mod device;
+mod elf_arg;
+mod elf_info;
+mod elf_url;
+mod estimate;
+mod filesystem;
+mod flash_all;
+mod flash_size;
+mod fota;
+mod health;
+mod i18n;
+mod idf_build;
+mod idle;
+mod index_template;
+mod interactive;
+mod labels;
+mod layout;
+mod listen;
+mod logo;
+mod mdns;
+mod merged_bin;
+mod merged_image;
+mod monitor;
+mod nvs_gen;
+mod offsets;
+mod partition_gen;
+mod partition_table;
+mod patch_symbol;
+mod pio;
+mod prebuilt;
+mod prepare_error;
+mod prepared_build;
+mod presets;
+mod qr;
+mod record;
+mod rules;
+mod sbom;
+mod second_app;
+mod selftest;
+mod stats;
+mod summary;
+mod symbols;
+mod telemetry;
+mod theme;
+mod tls;
+mod upload;
+mod vendor;
+mod verify;
+mod watch;
+mod web_manifest;
 
 #[macro_use]
 extern crate rocket;
 
-#[derive(Parser, Debug, Clone)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// chip name
-    #[arg(short, long)]
-    chip: Chip,
+const INDEX_HTML: &str = include_str!("index.html");
+const MONITOR_HTML: &str = include_str!("monitor.html");
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// never ask for missing arguments, even at a terminal
+    #[arg(long, global = true)]
+    non_interactive: bool,
+
+    /// TOML file with option values, overridden by the command line
+    /// (default: esp-web-flash.toml in the current directory, if present)
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    config: Option<PathBuf>,
+
+    /// log more: -v adds Rocket's launch and request logging, -vv debug, -vvv trace
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// print only the server's URL and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// format of the log on stderr; json writes everything, progress reports and the server's
+    /// URLs included, as one JSON object per line
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = telemetry::LogFormat::Text,
+        env = "ESP_WEB_FLASH_LOG_FORMAT"
+    )]
+    log_format: telemetry::LogFormat,
+
+    /// also append the log, progress reports included, to this file as JSON lines
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    log_file: Option<PathBuf>,
+}
+
+impl Cli {
+    fn log_settings(&self) -> telemetry::LogSettings {
+        telemetry::LogSettings {
+            verbosity: if self.quiet {
+                -1
+            } else {
+                self.verbose.min(i8::MAX as u8) as i8
+            },
+            format: self.log_format,
+            file: self.log_file.clone(),
+        }
+    }
+
+    /// Whether the subcommand needs an ELF but got none
+    fn missing_elf(&self) -> bool {
+        match &self.command {
+            Command::Serve(opts) => {
+                !opts.monitor_only && opts.image.missing_elf() && opts.named_builds.is_empty()
+            }
+            Command::Verify(opts) => opts.image.missing_elf(),
+            Command::FlashAll(opts) => opts.image.missing_elf(),
+            Command::Export(opts) => opts.image.missing_elf(),
+            Command::Manifest(opts) => opts.image.missing_elf(),
+            Command::Check(opts) => opts.image.missing_elf(),
+            Command::Erase(_)
+            | Command::RecordSummary(_)
+            | Command::Compare(_)
+            | Command::Completions(_) => false,
+        }
+    }
+
+    /// Fills in or checks `--chip` for subcommands preparing an image
+    fn resolve_chip(&mut self) -> Result<(), String> {
+        match &mut self.command {
+            Command::Serve(opts) => {
+                let fallback = opts.named_builds.first().map(|build| build.elf.clone());
+                opts.image.resolve_chip(fallback.as_deref())
+            }
+            Command::Verify(opts) => opts.image.resolve_chip(None),
+            Command::FlashAll(opts) => opts.image.resolve_chip(None),
+            Command::Export(opts) => opts.image.resolve_chip(None),
+            Command::Manifest(opts) => opts.image.resolve_chip(None),
+            Command::Check(opts) => opts.image.resolve_chip(None),
+            Command::Erase(_)
+            | Command::RecordSummary(_)
+            | Command::Compare(_)
+            | Command::Completions(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// build the image and serve the flashing page (default)
+    Serve(Args),
+    /// read back an attached device and compare it to the prepared image
+    Verify(VerifyArgs),
+    /// erase the entire flash of an attached device
+    Erase(EraseArgs),
+    /// flash the prepared image to every attached device
+    FlashAll(FlashAllArgs),
+    /// write the prepared parts and their manifest.json into a directory
+    Export(ExportArgs),
+    /// print the manifest.json of the prepared parts
+    Manifest(ManifestArgs),
+    /// check that the app, partition table and flash size fit together, one line per rule
+    Check(CheckArgs),
+    /// print the timeline of a recording made with --record
+    RecordSummary(RecordSummaryArgs),
+    /// report what changed in size, symbols and app descriptor between two builds
+    Compare(CompareArgs),
+    /// print a shell completion script to stdout
+    Completions(CompletionsArgs),
+}
+
+/// Options describing the image to prepare, shared by all subcommands
+#[derive(clap::Args, Debug, Clone)]
+struct ImageArgs {
+    /// chip name (default: detected from the ELF)
+    #[arg(short, long, value_parser = chips::arg_parser(), env = "ESP_WEB_FLASH_CHIP")]
+    chip: Option<Chip>,
+
+    /// path to bootloader (.bin image or ELF, which is converted for the chip)
+    #[arg(
+        short,
+        long,
+        value_hint = ValueHint::FilePath,
+        env = "ESP_WEB_FLASH_BOOTLOADER"
+    )]
+    bootloader: Option<PathBuf>,
+
+    /// path to partition table (CSV or binary), or preset:<name> (see --list-presets)
+    #[arg(short, long, env = "ESP_WEB_FLASH_PARTITION_TABLE")]
+    partition_table: Option<PartitionTableArg>,
+
+    /// list the built-in partition table presets and exit
+    #[arg(long)]
+    list_presets: bool,
+
+    /// dev board whose chip, flash size and partition table are the defaults (see --list-boards)
+    #[arg(long, env = "ESP_WEB_FLASH_BOARD")]
+    board: Option<String>,
+
+    /// list the known boards, including ~/.config/esp-web-flash/boards.toml, and exit
+    #[arg(long)]
+    list_boards: bool,
+
+    /// flash size: 1MB, 2MB, 4MB, 8MB, 16MB, 32MB, 64MB or 128MB
+    #[arg(
+        short,
+        long,
+        default_value = "4MB",
+        value_parser = flash_size::parse,
+        env = "ESP_WEB_FLASH_FLASH_SIZE"
+    )]
+    flash_size: flash_size::FlashSizeArg,
+
+    /// flash mode for the app and bootloader headers (default: keep the bootloader's)
+    #[arg(long, value_enum)]
+    flash_mode: Option<bootloader_image::FlashMode>,
+
+    /// flash frequency for the app and bootloader headers (default: keep the bootloader's)
+    #[arg(long, value_enum)]
+    flash_freq: Option<bootloader_image::FlashFreq>,
+
+    /// serve the bootloader as is, without matching its header to the flash settings
+    #[arg(long)]
+    no_bootloader_patch: bool,
+
+    /// oldest chip revision the app boots on, e.g. v3.0 or 0.3, written into its image header
+    #[arg(
+        long,
+        value_name = "REV",
+        value_parser = chip_rev::parse,
+        conflicts_with_all = ["serve_dir", "idf_build_dir", "pio", "merged_bin"]
+    )]
+    min_chip_rev: Option<chip_rev::ChipRev>,
+
+    /// keep the ELF, symbols included, and serve it at /firmware.elf for decoding backtraces
+    #[arg(long, conflicts_with_all = ["serve_dir", "idf_build_dir", "pio", "merged_bin"])]
+    serve_elf: bool,
+
+    /// flash only the app, for devices that already have a bootloader and partition table
+    /// (--bootloader and --partition-table would never be flashed)
+    #[arg(
+        long,
+        conflicts_with_all = ["bootloader", "partition_table", "gen_partition_table", "merged_bin"]
+    )]
+    app_only: bool,
+
+    /// flash offset of the bootloader (default: where the chip's ROM loads it from)
+    #[arg(long, value_parser = partition_gen::parse_size, help_heading = "Offsets")]
+    bootloader_offset: Option<u32>,
+
+    /// flash offset of the partition table (CONFIG_PARTITION_TABLE_OFFSET, default
+    /// 0x8000); needs a --bootloader built for it
+    #[arg(
+        long,
+        value_parser = partition_gen::parse_size,
+        requires = "bootloader",
+        help_heading = "Offsets"
+    )]
+    partition_table_offset: Option<u32>,
+
+    /// flash offset of the app, which must start an app partition (default: the first one)
+    #[arg(long, value_parser = partition_gen::parse_size, help_heading = "Offsets")]
+    app_offset: Option<u32>,
+
+    /// generate the partition table from --app-size, --ota, --nvs-size and --spiffs-size
+    #[arg(
+        long,
+        conflicts_with = "partition_table",
+        help_heading = "Partition table generation"
+    )]
+    gen_partition_table: bool,
+
+    /// size of each app partition
+    #[arg(
+        long,
+        default_value = "1M",
+        value_parser = partition_gen::parse_size,
+        help_heading = "Partition table generation"
+    )]
+    app_size: u32,
+
+    /// use two OTA app slots plus otadata instead of a single factory app
+    #[arg(long, help_heading = "Partition table generation")]
+    ota: bool,
+
+    /// size of the nvs partition
+    #[arg(
+        long,
+        default_value = "24K",
+        value_parser = partition_gen::parse_size,
+        help_heading = "Partition table generation"
+    )]
+    nvs_size: u32,
+
+    /// add a spiffs partition of this size after the apps
+    #[arg(
+        long,
+        value_parser = partition_gen::parse_size,
+        help_heading = "Partition table generation"
+    )]
+    spiffs_size: Option<u32>,
+
+    /// second app image (ELF or .bin) and the partition to write it to, e.g. shipping.elf@ota_0
+    #[arg(long)]
+    second_app: Option<second_app::SecondApp>,
+
+    /// also flash an erased (0xFF) image over the nvs partition, resetting stored
+    /// settings without a full chip erase
+    #[arg(long)]
+    erase_nvs: bool,
+
+    /// generate the nvs partition from a CSV in the format of ESP-IDF's nvs_partition_gen.py
+    /// (key,type,encoding,value) and flash it as nvs.bin
+    #[arg(long, conflicts_with = "erase_nvs")]
+    nvs_csv: Option<PathBuf>,
+
+    /// pack this directory into a filesystem image and flash it to the spiffs/littlefs partition
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+
+    /// filesystem of the --data-dir image, built with mklittlefs or mkspiffs
+    #[arg(long = "fs", value_enum, default_value = "littlefs")]
+    filesystem: filesystem::Filesystem,
+
+    /// another binary and its flash offset, e.g. ota_data_initial.bin@0xd000, served at
+    /// /extra/<file name> (repeatable)
+    #[arg(long = "extra-part")]
+    extra_part_files: Vec<prebuilt::PartSpec>,
+
+    /// write a value into a symbol of the ELF before building, e.g.
+    /// FIRMWARE_BUILD_INFO=abc1234 (NUL-padded to the symbol's size; repeatable)
+    #[arg(long = "patch-symbol")]
+    patch_symbols: Vec<patch_symbol::SymbolPatch>,
+
+    /// expected SHA-256 of an input file, e.g. app.elf=<hex> (repeatable)
+    #[arg(long = "expect-sha256")]
+    expect_sha256: Vec<checksums::ExpectSha256>,
+
+    /// file in sha256sum format with the expected digests of the inputs
+    #[arg(long)]
+    checksums_file: Option<PathBuf>,
+
+    /// serve the app even if it is empty or has no code in flash (e.g. RAM-only test images)
+    #[arg(long)]
+    allow_empty_app: bool,
+
+    /// serve prebuilt .bin files from this directory as-is instead of building from an ELF
+    #[arg(
+        long,
+        value_hint = ValueHint::DirPath,
+        conflicts_with_all = [
+            "elf",
+            "bootloader",
+            "partition_table",
+            "gen_partition_table",
+            "second_app",
+            "erase_nvs",
+            "nvs_csv",
+            "data_dir",
+            "extra_part_files",
+            "patch_symbols",
+            "bootloader_offset",
+            "partition_table_offset",
+            "app_offset"
+        ],
+        help_heading = "Prebuilt binaries"
+    )]
+    serve_dir: Option<PathBuf>,
+
+    /// a file in --serve-dir and its flash offset, e.g. app.bin@0x10000 (default: bootloader.bin, partitions.bin and firmware.bin or app.bin)
+    #[arg(
+        long = "part",
+        requires = "serve_dir",
+        help_heading = "Prebuilt binaries"
+    )]
+    prebuilt_parts: Vec<prebuilt::PartSpec>,
+
+    /// serve an ESP-IDF build directory: every binary its flasher_args.json lists, at its offset
+    #[arg(
+        long,
+        value_hint = ValueHint::DirPath,
+        conflicts_with_all = [
+            "elf",
+            "bootloader",
+            "partition_table",
+            "gen_partition_table",
+            "second_app",
+            "erase_nvs",
+            "nvs_csv",
+            "data_dir",
+            "extra_part_files",
+            "patch_symbols",
+            "serve_dir",
+            "bootloader_offset",
+            "partition_table_offset",
+            "app_offset"
+        ],
+        help_heading = "Prebuilt binaries"
+    )]
+    idf_build_dir: Option<PathBuf>,
+
+    /// serve what `pio run` built for a PlatformIO project in this directory; the chip and flash
+    /// size default to its board's
+    #[arg(
+        long,
+        value_hint = ValueHint::DirPath,
+        value_name = "PROJECT_DIR",
+        conflicts_with_all = [
+            "elf",
+            "bootloader",
+            "partition_table",
+            "gen_partition_table",
+            "second_app",
+            "erase_nvs",
+            "nvs_csv",
+            "data_dir",
+            "extra_part_files",
+            "patch_symbols",
+            "serve_dir",
+            "idf_build_dir",
+            "bootloader_offset",
+            "partition_table_offset",
+            "app_offset"
+        ],
+        help_heading = "Prebuilt binaries"
+    )]
+    pio: Option<PathBuf>,
+
+    /// the platformio.ini environment --pio serves (default: the first of default_envs, or the only one)
+    #[arg(long, requires = "pio", help_heading = "Prebuilt binaries")]
+    pio_env: Option<String>,
+
+    /// serve one image meant for `write_flash 0x0` (e.g. a vendor's factory.bin) as a single part
+    #[arg(
+        long,
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = [
+            "elf",
+            "bootloader",
+            "partition_table",
+            "gen_partition_table",
+            "second_app",
+            "erase_nvs",
+            "nvs_csv",
+            "data_dir",
+            "extra_part_files",
+            "patch_symbols",
+            "serve_dir",
+            "idf_build_dir",
+            "pio",
+            "bootloader_offset",
+            "partition_table_offset",
+            "app_offset"
+        ],
+        help_heading = "Prebuilt binaries"
+    )]
+    merged_bin: Option<PathBuf>,
+
+    /// find the ELF with cargo metadata: the newest build of the package's binary below target/
+    #[arg(
+        long,
+        conflicts_with_all = ["elf", "serve_dir", "idf_build_dir", "pio", "merged_bin"]
+    )]
+    cargo: bool,
+
+    /// the package whose binary --cargo looks for, in a workspace with several
+    #[arg(long, requires = "cargo")]
+    package: Option<String>,
+
+    /// header sent when the ELF is an http(s) URL, e.g. 'Authorization: Bearer <token>'
+    /// (repeatable)
+    #[arg(long = "http-header")]
+    http_headers: Vec<elf_url::HttpHeader>,
+
+    /// path to the ELF file (optional with --monitor-only, where it's used to decode backtraces);
+    /// a glob or a directory picks the newest file matching it, an http(s) URL is downloaded
+    #[arg(value_hint = ValueHint::AnyPath, env = "ESP_WEB_FLASH_ELF")]
+    elf: Option<PathBuf>,
+}
+
+impl ImageArgs {
+    /// No ELF and nothing else to serve instead
+    fn missing_elf(&self) -> bool {
+        self.elf.is_none()
+            && !self.cargo
+            && self.serve_dir.is_none()
+            && self.idf_build_dir.is_none()
+            && self.pio.is_none()
+            && self.merged_bin.is_none()
+    }
+
+    /// The ELF file to use, which clap can't require since `--monitor-only`,
+    /// `--serve-dir`, `--idf-build-dir`, `--pio` and `--merged-bin` work
+    /// without one. Resolved anew on every call, so a rebuild picks up a newer
+    /// artifact matching the same glob or directory.
+    fn elf(&self) -> Result<PathBuf, PrepareError> {
+        if self.cargo {
+            return cargo_elf::discover(self.package.as_deref());
+        }
+        elf_arg::resolve(self.elf.as_ref().ok_or(PrepareError::MissingElf)?)
+    }
+
+    /// The chip, which `resolve_chip` fills in while parsing the command line
+    fn chip(&self) -> Chip {
+        self.chip.expect("--chip is resolved while parsing")
+    }
+
+    /// Downloads an ELF given as URL, then detects the chip from the ELF
+    /// (or `fallback`, the default named build's) when `--chip` isn't
+    /// given; that a given one matches is a rule `prepare` checks
+    fn resolve_chip(&mut self, fallback: Option<&Path>) -> Result<(), String> {
+        if let Some(url) = self.elf.as_deref().filter(|elf| elf_url::is_url(elf)) {
+            let url = url.to_string_lossy().into_owned();
+            self.elf = Some(elf_url::fetch(&url, &self.http_headers).map_err(|e| e.to_string())?);
+        }
+        let path = if self.chip.is_some() {
+            None
+        } else if self.missing_elf() {
+            fallback.map(Path::to_path_buf)
+        } else if self.elf.is_some() || self.cargo {
+            Some(self.elf().map_err(|e| e.to_string())?)
+        } else {
+            None
+        };
+        let data = match &path {
+            Some(path) => Some(
+                std::fs::read(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+            ),
+            None => None,
+        };
+
+        match (self.chip, data) {
+            (Some(_), _) => {}
+            (None, Some(data)) => {
+                let chip = chip_detect::detect(&data)?;
+                status!(
+                    "Chip: {} (detected from {})",
+                    chip_name(chip),
+                    path.unwrap_or_default().display()
+                );
+                self.chip = Some(chip);
+            }
+            (None, None) => {
+                return Err(String::from(
+                    "--chip is required when there's no ELF to detect it from",
+                ))
+            }
+        }
+        if self.chip == Some(Chip::Esp8266) {
+            self.check_esp8266()?;
+        }
+        if let Some(rev) = self.min_chip_rev {
+            chip_rev::check(rev, self.chip())?;
+        }
+        Ok(())
+    }
+
+    /// The ESP8266's ROM boots the app itself, so there's no second stage
+    /// bootloader and no partition table for these options to act on
+    fn check_esp8266(&self) -> Result<(), String> {
+        let given: Vec<&str> = [
+            ("--bootloader", self.bootloader.is_some()),
+            ("--partition-table", self.partition_table.is_some()),
+            ("--gen-partition-table", self.gen_partition_table),
+            ("--second-app", self.second_app.is_some()),
+            ("--erase-nvs", self.erase_nvs),
+            ("--nvs-csv", self.nvs_csv.is_some()),
+            ("--data-dir", self.data_dir.is_some()),
+        ]
+        .into_iter()
+        .filter(|(_, given)| *given)
+        .map(|(name, _)| name)
+        .collect();
+        if given.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "the ESP8266 has no bootloader or partition table of its own, drop {}",
+                given.join(", ")
+            ))
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct Args {
+    #[command(flatten)]
+    image: ImageArgs,
+
+    /// another build to serve, picked on the page or with `?build=<name>`, e.g.
+    /// staging=target/staging/app.elf (repeatable); without an ELF argument the
+    /// first one is the default
+    #[arg(
+        long = "build",
+        value_name = "NAME=ELF",
+        conflicts_with_all = [
+            "monitor_only",
+            "serve_dir",
+            "idf_build_dir",
+            "pio",
+            "merged_bin"
+        ]
+    )]
+    named_builds: Vec<prepared_build::NamedBuild>,
+
+    /// language of the web page (en, de, ja, fr, es); `?lang=` overrides it per request
+    #[arg(short, long, default_value = "en", env = "ESP_WEB_FLASH_LANG")]
+    lang: String,
+
+    /// number of builds kept available at /builds, including the current one
+    #[arg(long, default_value_t = 5)]
+    keep_builds: usize,
+
+    /// total size of the kept builds, in MiB; older builds are dropped beyond it
+    #[arg(long, default_value_t = 256)]
+    builds_budget: usize,
+
+    /// whether installs erase the device first; the page lets the user switch
+    #[arg(long, value_enum, default_value_t = ErasePolicy::Prompt, env = "ESP_WEB_FLASH_ERASE_POLICY")]
+    erase_policy: ErasePolicy,
+
+    /// never offer a full erase: installs keep NVS and other data, and the
+    /// page hides the erase choice
+    #[arg(long, conflicts_with = "erase_policy")]
+    no_erase_prompt: bool,
+
+    /// shut down once the page reports a finished install, for setup scripts; a failed one
+    /// keeps the server up for a retry
+    #[arg(long, conflicts_with = "monitor_only")]
+    once: bool,
+
+    /// shut down once no request arrived for this many seconds (0: never)
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    idle_timeout: u64,
+
+    /// application name shown in the install dialog and as the page heading
+    #[arg(long, env = "ESP_WEB_FLASH_NAME")]
+    name: Option<String>,
+
+    /// page title in the browser tab and heading (default: --name, or "ESP Web Flasher")
+    #[arg(long, env = "ESP_WEB_FLASH_TITLE")]
+    title: Option<String>,
+
+    /// logo shown above the heading: an image file, served at /assets/logo, or an http(s) URL
+    #[arg(long)]
+    logo: Option<String>,
+
+    /// color theme of the web page
+    #[arg(long, value_enum, default_value_t = Theme::Light, env = "ESP_WEB_FLASH_THEME")]
+    theme: Theme,
+
+    /// accent color for buttons and the heading (examples: #3498db, #e67e22)
+    #[arg(long, value_parser = theme::parse_color)]
+    accent_color: Option<String>,
+
+    /// load esp-web-tools from unpkg.com instead of the copy bundled into the binary
+    #[arg(long)]
+    cdn: bool,
+
+    /// HTML file served as the page instead of the built-in one, with {{name}}, {{chip}},
+    /// {{flash_size}} and {{token_query}} filled in; reloaded on change with --watch
+    #[arg(long, conflicts_with = "monitor_only")]
+    index: Option<PathBuf>,
+
+    /// allow operating on the board attached to this machine via the admin API
+    #[arg(long, requires_all = ["local_port", "admin_token"])]
+    enable_local_flash: bool,
+
+    /// serial port of the board attached to this machine
+    #[arg(long, env = "ESP_WEB_FLASH_LOCAL_PORT")]
+    local_port: Option<String>,
+
+    /// bearer token required by the admin API
+    #[arg(long, env = "ESP_WEB_FLASH_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+
+    /// disable POST /shutdown and the admin API
+    #[arg(long, conflicts_with_all = ["admin_token", "enable_local_flash"])]
+    no_admin: bool,
+
+    /// token required by every route, as `Authorization: Bearer` or `?token=`; without a value
+    /// one is generated. The printed and opened URLs carry it.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        value_name = "TOKEN",
+        value_parser = auth::token_arg,
+        env = "ESP_WEB_FLASH_AUTH_TOKEN",
+        hide_env_values = true
+    )]
+    auth_token: Option<Option<String>>,
+
+    /// don't prepare an image, only show the serial output of the board on --local-port
+    #[arg(long, requires = "local_port")]
+    monitor_only: bool,
+
+    /// rebuild and serve the new image whenever the ELF, bootloader or partition table changes
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "monitor_only",
+            "serve_dir",
+            "idf_build_dir",
+            "pio",
+            "merged_bin"
+        ]
+    )]
+    watch: bool,
+
+    /// extra seconds to wait after the server is listening before opening the browser
+    #[arg(long, default_value_t = 0.0)]
+    open_delay: f32,
+
+    /// don't open the page in a browser
+    #[arg(long, conflicts_with = "browser")]
+    no_open: bool,
+
+    /// write the parts and manifest.json into this directory and exit instead
+    /// of serving them, like `export`
+    #[arg(long, conflicts_with = "monitor_only")]
+    output_dir: Option<PathBuf>,
+
+    /// with --output-dir, replace files already in the directory
+    #[arg(long, requires = "output_dir")]
+    force: bool,
+
+    /// browser executable to open the page with, e.g. chromium (default: the system's)
+    #[arg(long)]
+    browser: Option<String>,
+
+    /// baud rate of the serial monitor
+    #[arg(long, default_value_t = 115_200)]
+    monitor_baud: u32,
+
+    /// write every HTTP exchange to this file as JSON lines, see `record-summary`
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// keep the console logs pages send after each flash in this directory, one file per flash
+    #[arg(long, value_hint = ValueHint::DirPath, conflicts_with = "monitor_only")]
+    log_dir: Option<PathBuf>,
+
+    /// export flash session traces to this OpenTelemetry collector (OTLP/gRPC)
+    #[arg(long, value_name = "URL", env = "ESP_WEB_FLASH_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+
+    /// baud rate the flash time estimate in /info assumes
+    #[arg(long, default_value_t = estimate::DEFAULT_BAUD)]
+    assumed_baud: u32,
+
+    /// whether /merged.bin ends after the last part or fills the whole flash
+    #[arg(long, value_enum, default_value_t = merged_bin::MergedSize::End)]
+    merged_size: merged_bin::MergedSize,
+
+    /// request every route once the server is listening, report and exit non-zero on failure
+    #[arg(long)]
+    selftest: bool,
+
+    /// once listening, print the URL, chip, flash size and parts as one JSON line on stdout,
+    /// with the progress reports moved to stderr
+    #[arg(long, conflicts_with = "monitor_only")]
+    summary_json: bool,
+
+    /// prepare every build and exit without starting the server, for checking in CI that the
+    /// image is flashable; exits non-zero with the error if it isn't
+    #[arg(long, conflicts_with_all = ["monitor_only", "output_dir"])]
+    dry_run: bool,
+
+    /// format of reports such as --selftest
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// base URL devices reach this server at, for absolute links (e.g. https://flash.lab:8443)
+    #[arg(long, env = "ESP_WEB_FLASH_PUBLIC_URL")]
+    public_url: Option<String>,
+
+    /// firmware version shown on the page and in the install dialog, and offered by /fota.json
+    /// (default: from the app descriptor)
+    #[arg(long, visible_alias = "fw-version", value_parser = fota::version_arg)]
+    firmware_version: Option<String>,
+
+    /// device type in /fota.json, matched by esp32FOTA against the device's own
+    #[arg(long, default_value = "esp32-fota-http", help_heading = "esp32FOTA")]
+    fota_type: String,
+
+    /// app image (.bin) the devices run now; serves a patch from it at /ota/patch.bin
+    #[arg(long, conflicts_with_all = ["monitor_only", "merged_bin"], help_heading = "Delta OTA")]
+    ota_base: Option<PathBuf>,
+
+    /// serve on every interface and print the URL of each, for flashing from other machines
+    #[arg(long, conflicts_with_all = ["address", "listen"])]
+    lan: bool,
+
+    /// advertise the server via mDNS/Bonjour as <--mdns-name>.local (needs multicast on the network)
+    #[arg(long)]
+    mdns: bool,
+
+    /// print the URL as a QR code even when only serving this machine
+    #[arg(long)]
+    qr: bool,
+
+    /// don't print the QR code shown when serving other machines from a terminal
+    #[arg(long, conflicts_with = "qr")]
+    no_qr: bool,
+
+    /// host name advertised with --mdns, without .local
+    #[arg(long, value_parser = mdns::name_arg, default_value = mdns::DEFAULT_NAME)]
+    mdns_name: String,
+
+    /// address to bind to, e.g. 0.0.0.0 for every interface (default: Rocket's, 127.0.0.1)
+    #[arg(long, conflicts_with = "listen", env = "ESP_WEB_FLASH_ADDRESS")]
+    address: Option<IpAddr>,
+
+    /// port to listen on (default: Rocket's, 8000)
+    #[arg(long, conflicts_with = "listen", env = "ESP_WEB_FLASH_PORT")]
+    port: Option<u16>,
+
+    /// address to listen on, optionally with TLS: 0.0.0.0:8443,tls[,cert=<path>,key=<path>]
+    /// (repeatable; default: Rocket's configuration, 127.0.0.1:8000)
+    #[arg(long)]
+    listen: Vec<listen::Listener>,
+
+    /// TLS certificate chain (PEM) for listeners without their own cert=
+    #[arg(long, env = "ESP_WEB_FLASH_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+
+    /// TLS private key (PEM) for listeners without their own key=
+    #[arg(long, env = "ESP_WEB_FLASH_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+
+    /// replace the self-signed certificate used without --tls-cert; devices pinning it must be updated
+    #[arg(long)]
+    tls_regenerate: bool,
+
+    /// serve HTTPS with a certificate generated for this run instead of --tls-cert/--tls-key
+    #[arg(long, value_enum, conflicts_with_all = ["tls_cert", "tls_key", "tls_regenerate"])]
+    tls: Option<tls::TlsMode>,
+
+    /// allow plain HTTP on every interface (0.0.0.0 or ::), where other machines can't flash
+    /// because Web Serial needs a secure context
+    #[arg(long)]
+    allow_plain_http: bool,
+
+    /// label reported with the served build, e.g. pipeline=1234 (repeatable)
+    #[arg(long = "label")]
+    labels: Vec<labels::Label>,
+
+    #[command(flatten)]
+    batch: BatchArgs,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct VerifyArgs {
+    #[command(flatten)]
+    image: ImageArgs,
+
+    /// serial port of the device to read back
+    #[arg(long)]
+    port: String,
+
+    /// only check these parts (examples: firmware, bootloader,partitions)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    parts: Vec<verify::Part>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct EraseArgs {
+    /// serial port of the device to erase
+    #[arg(long)]
+    port: String,
+
+    /// expected chip; erasing is refused if the device reports another one
+    #[arg(short, long, value_parser = chips::arg_parser())]
+    chip: Option<Chip>,
+
+    /// don't ask for confirmation
+    #[arg(short, long)]
+    yes: bool,
+}
+
+/// Options of batch flashing, for `flash-all` and `POST /flash-all`
+#[derive(clap::Args, Debug, Clone)]
+struct BatchArgs {
+    /// serial ports to flash (default: every port with a known ESP USB ID)
+    #[arg(long, value_delimiter = ',', help_heading = "Batch flashing")]
+    ports: Vec<String>,
+
+    /// number of devices flashed at the same time
+    #[arg(long, default_value_t = 4, help_heading = "Batch flashing")]
+    parallel: usize,
+
+    /// append every flash result to this file as JSON lines
+    #[arg(long, help_heading = "Batch flashing")]
+    history: Option<PathBuf>,
+}
+
+impl BatchArgs {
+    fn settings(&self) -> flash_all::BatchSettings {
+        flash_all::BatchSettings {
+            ports: self.ports.clone(),
+            parallel: self.parallel,
+            history: self.history.clone(),
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct FlashAllArgs {
+    #[command(flatten)]
+    image: ImageArgs,
+
+    #[command(flatten)]
+    batch: BatchArgs,
+
+    /// format of the report
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct ExportArgs {
+    #[command(flatten)]
+    image: ImageArgs,
+
+    /// directory to write the parts and manifest.json into, created if missing
+    #[arg(short, long, alias = "output-dir", default_value = "web-flash-export")]
+    out_dir: PathBuf,
+
+    /// replace files already in the directory
+    #[arg(long)]
+    force: bool,
+
+    /// whether installs from the manifest erase the device first
+    #[arg(long, value_enum, default_value_t = ErasePolicy::Prompt)]
+    erase_policy: ErasePolicy,
+
+    /// application name shown in the install dialog
+    #[arg(long, default_value = web_manifest::DEFAULT_NAME)]
+    name: String,
+
+    /// whether merged.bin ends after the last part or fills the whole flash
+    #[arg(long, value_enum, default_value_t = merged_bin::MergedSize::End)]
+    merged_size: merged_bin::MergedSize,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct ManifestArgs {
+    #[command(flatten)]
+    image: ImageArgs,
+
+    /// whether installs from the manifest erase the device first
+    #[arg(long, value_enum, default_value_t = ErasePolicy::Prompt)]
+    erase_policy: ErasePolicy,
+
+    /// application name shown in the install dialog
+    #[arg(long, default_value = web_manifest::DEFAULT_NAME)]
+    name: String,
+
+    /// print exactly what /manifest.json serves for these options, part paths pinned to the build
+    #[arg(long)]
+    as_served: bool,
+
+    /// make the part paths absolute URLs below this one, e.g. https://flash.lab:8443/
+    #[arg(long, value_parser = web_manifest::base_url_arg)]
+    base_url: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct RecordSummaryArgs {
+    /// recording written with --record
+    file: PathBuf,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct CompareArgs {
+    /// the earlier build, an ELF or an app image (.bin)
+    old: PathBuf,
+
+    /// the later build, an ELF or an app image (.bin)
+    new: PathBuf,
+
+    /// number of symbols listed for each kind of change
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+
+    /// format of the report
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct CheckArgs {
+    #[command(flatten)]
+    image: ImageArgs,
+
+    /// report failed rules but exit 0
+    #[arg(long)]
+    warn_only: bool,
+
+    /// format of the report
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct CompletionsArgs {
+    /// the shell to complete in
+    #[arg(value_enum)]
+    shell: clap_complete::Shell,
+}
+
+/// Parses the command line, treating an invocation without a subcommand as `serve`
+fn parse_cli() -> Cli {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    // before anything is reported while resolving the arguments
+    if args.iter().any(|arg| arg == "-q" || arg == "--quiet") {
+        QUIET.store(true, Ordering::Relaxed);
+    }
+
+    // informational flags that must work without the otherwise required arguments
+    if args.iter().any(|arg| arg == "--list-presets") {
+        presets::print_list();
+        std::process::exit(0);
+    }
+    if args.iter().any(|arg| arg == "--list-boards") {
+        if let Err(e) = boards::print_list() {
+            Cli::command().error(ErrorKind::Io, e).exit();
+        }
+        std::process::exit(0);
+    }
+
+    let explicit = args.get(1).map_or(false, |arg| {
+        let arg = arg.to_string_lossy();
+        let arg: &str = &arg;
+        Cli::command().find_subcommand(arg).is_some()
+            || ["-h", "--help", "-V", "--version"].contains(&arg)
+    });
+    if !explicit {
+        args.insert(1, OsString::from("serve"));
+    }
+    // stdout is the manifest, the check report or the startup summary
+    if args[1] == "manifest" || args[1] == "check" || args.iter().any(|arg| arg == "--summary-json")
+    {
+        STATUS_TO_STDERR.store(true, Ordering::Relaxed);
+    }
+
+    if args.get(1).map_or(false, |arg| {
+        config_file::SUBCOMMANDS.iter().any(|name| arg == name)
+    }) {
+        if let Err(e) = config_file::apply(&Cli::command(), &mut args) {
+            Cli::command().error(ErrorKind::InvalidValue, e).exit();
+        }
+        if let Err(e) = boards::apply(&mut args) {
+            Cli::command().error(ErrorKind::InvalidValue, e).exit();
+        }
+        if let Err(e) = pio::apply(&mut args) {
+            Cli::command().error(ErrorKind::InvalidValue, e).exit();
+        }
+        config_file::report_env(&Cli::command(), &args);
+    }
+
+    if !interactive::available(&args) {
+        let mut cli = Cli::parse_from(args);
+        if let Err(e) = cli.resolve_chip() {
+            Cli::command().error(ErrorKind::ValueValidation, e).exit();
+        }
+        return cli;
+    }
+
+    // ask for what's missing, then parse again so the answers go through
+    // exactly the same validation as arguments
+    let mut asked = false;
+    let mut parsed = Cli::try_parse_from(&args);
+    if matches!(&parsed, Ok(cli) if cli.missing_elf()) {
+        exit_on_error(interactive::ask_image(&mut args));
+        asked = true;
+        parsed = Cli::try_parse_from(&args);
+    }
+    let mut cli = parsed.unwrap_or_else(|e| e.exit());
+    if let Err(e) = cli.resolve_chip() {
+        if boards::given(&args, "--chip", Some('c')) {
+            Cli::command().error(ErrorKind::ValueValidation, e).exit();
+        }
+        eprintln!("{}", e);
+        exit_on_error(interactive::ask_chip(&mut args));
+        asked = true;
+        cli = Cli::try_parse_from(&args).unwrap_or_else(|e| e.exit());
+        if let Err(e) = cli.resolve_chip() {
+            Cli::command().error(ErrorKind::ValueValidation, e).exit();
+        }
+    }
+
+    if asked {
+        interactive::echo(&args);
+    }
+    cli
+}
+
+fn exit_on_error(result: Result<()>) {
+    if let Err(e) = result {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+#[get("/bootloader.bin")]
+fn bootloader(_access: Access, build: Current) -> Option<Vec<u8>> {
+    build.part("bootloader.bin").map(<[u8]>::to_vec)
+}
+
+#[get("/partitions.bin")]
+fn partitions(_access: Access, build: Current) -> Option<Vec<u8>> {
+    build.part("partitions.bin").map(<[u8]>::to_vec)
+}
+
+#[get("/partitions.csv")]
+fn partitions_csv(_access: Access, build: Current) -> Result<String, (Status, String)> {
+    if build.parts.partitions.is_empty() {
+        return Err((Status::NotFound, String::from("no partition table")));
+    }
+    partition_table::to_csv(&build.parts.partitions).map_err(|e| (Status::InternalServerError, e))
+}
+
+/// The partition table's entries, empty when no table is flashed
+#[get("/partitions.json")]
+fn partitions_json(_access: Access, build: Current) -> Json<partition_table::TableInfo> {
+    Json(partition_table::TableInfo::new(
+        &build.parts.partitions,
+        &build.parts.partition_table_source,
+    ))
+}
+
+#[get("/firmware.bin")]
+fn firmware(_access: Access, build: Current) -> Vec<u8> {
+    build.parts.firmware.clone()
+}
+
+/// Every part in one image for `esptool write_flash 0x0`, built per request
+/// so it only takes memory while being sent
+#[get("/merged.bin")]
+fn merged(_access: Access, build: Current, settings: &State<merged_bin::MergeSettings>) -> Vec<u8> {
+    merged_bin::build(&build.parts, settings.size)
+}
+
+/// Digests of the parts, then of `merged.bin`, which is hashed per request
+/// as its size depends on `--merged-size`
+fn served_checksums(
+    build: &PreparedBuild,
+    merged_size: merged_bin::MergedSize,
+) -> Vec<checksums::FileDigest> {
+    let mut digests = build.digests.clone();
+    digests.push(checksums::FileDigest {
+        file: String::from("merged.bin"),
+        sha256: merged_bin::sha256_hex(&build.parts, merged_size),
+    });
+    digests
+}
+
+/// `sha256sum -c` input for everything served
+#[get("/checksums.txt")]
+fn checksums_txt(
+    _access: Access,
+    build: Current,
+    merge: &State<merged_bin::MergeSettings>,
+) -> String {
+    served_checksums(&build, merge.size)
+        .iter()
+        .map(checksums::FileDigest::line)
+        .collect()
+}
+
+/// `/<file>.sha256` for a part or `merged.bin`, as sha256sum prints it
+#[get("/<file>", rank = 9)]
+fn file_sha256(
+    _access: Access,
+    file: checksums::Sha256File<'_>,
+    build: Current,
+    merge: &State<merged_bin::MergeSettings>,
+) -> Option<String> {
+    let sha256 = match file.0 {
+        "merged.bin" => merged_bin::sha256_hex(&build.parts, merge.size),
+        name => build.sha256(name)?.to_string(),
+    };
+    Some(
+        checksums::FileDigest {
+            file: file.0.to_string(),
+            sha256,
+        }
+        .line(),
+    )
+}
+
+/// The parts with a manifest and their checksums, named after the app
+#[get("/artifacts.zip")]
+fn artifacts_zip(
+    _access: Access,
+    build: Current,
+    settings: &State<PageSettings>,
+    fota: &State<fota::FotaSettings>,
+) -> Result<artifacts::ArtifactsZip, (Status, String)> {
+    let base = artifacts::base_name(
+        settings.manifest_name(),
+        fota.version(build.parts.app_version.as_deref()),
+    );
+    let zip = artifacts::build(
+        &build,
+        settings.manifest_name(),
+        &base,
+        settings.erase(None),
+    )
+    .map_err(|e| (Status::InternalServerError, e.to_string()))?;
+    Ok(artifacts::ArtifactsZip::new(zip, &base))
+}
+
+/// Parts as referenced by the manifest, refused once their build is no
+/// longer retained
+#[get("/b/<hash>/<file..>?<session>")]
+fn pinned_part(
+    _access: Access,
+    hash: &str,
+    file: PathBuf,
+    session: Option<&str>,
+    builds: &State<Builds>,
+    sessions: &State<telemetry::Sessions>,
+) -> Result<Vec<u8>, (Status, String)> {
+    let file = file.to_string_lossy();
+    let file = file.as_ref();
+    let span = sessions.artifact(session, file);
+    let _entered = span.enter();
+    let build = builds.find(hash).ok_or_else(|| {
+        tracing::warn!(hash, "build no longer retained");
+        (
+            Status::Conflict,
+            format!(
+                "Build {} is no longer served (current build is {}), re-fetch manifest.json",
+                hash,
+                builds.current().hash
+            ),
+        )
+    })?;
+
+    let part = build.part(file).ok_or_else(|| {
+        tracing::warn!(hash, "no such part");
+        (
+            Status::NotFound,
+            format!("Build {} has no part {}", hash, file),
+        )
+    })?;
+    tracing::info!(hash, size = part.len(), "served");
+    Ok(part.to_vec())
+}
+
+#[derive(Serialize)]
+struct FirmwareInfo {
+    chip: String,
+    total_size: usize,
+    bootloader_size: usize,
+    partitions_size: usize,
+    firmware_size: usize,
+    flash_size: String,
+    /// where the partition table came from: a path, `preset:<name>`, `generated` or `default`
+    partition_table_source: String,
+    extra_parts: Vec<PartInfo>,
+    /// every part in flash order, bootloader, partition table and app included
+    parts: Vec<PartInfo>,
+    labels: Labels,
+    /// rough duration of a flash at `--assumed-baud`
+    estimated_flash_seconds: u64,
+    /// a merged image is served as the only part
+    single_part: bool,
+    /// inputs whose SHA-256 was checked against the expected digest
+    verified_sha256: Vec<String>,
+    /// `--board` profile the chip, flash size and table defaults came from
+    board: Option<String>,
+    /// whether /sbom.json and /sbom.cdx.json are available
+    sbom_available: bool,
+    /// whether /firmware.elf is available (`--serve-elf`) and its size
+    elf_available: bool,
+    elf_size: Option<usize>,
+    /// fingerprint of the certificate devices should pin, see /ota/cert.pem
+    tls_cert_sha256: Option<String>,
+    /// the ELF file the image was built from, after resolving globs
+    elf_path: Option<String>,
+    /// erase flavor the page starts with (`prompt`, `keep` or `full`)
+    erase_policy: &'static str,
+    /// `--no-erase-prompt`: installs never erase, so the NVS survives
+    erase_locked: bool,
+    /// flash mode and frequency the bootloader header configures
+    flash_mode: Option<String>,
+    flash_freq: Option<String>,
+    /// header of the app image given instead of an ELF
+    app_header: Option<app_image::AppHeader>,
+    /// `--firmware-version`, or the app descriptor's version
+    firmware_version: Option<String>,
+    /// oldest chip revision the app header allows, e.g. `v3.0`
+    min_chip_rev: Option<String>,
+    /// builds given with `--build`, selected with `?build=<name>`
+    named_builds: Vec<String>,
+    /// URL other machines open the page at: `--public-url`, or this
+    /// server's LAN address when it isn't bound to loopback
+    external_url: Option<String>,
+    /// size and SHA-256 of /merged.bin
+    merged_size: usize,
+    merged_sha256: String,
+    /// SHA-256 of every part and /merged.bin, as in /checksums.txt
+    checksums: Vec<checksums::FileDigest>,
+}
+
+#[derive(Serialize)]
+struct PartInfo {
+    name: String,
+    label: String,
+    offset: u32,
+    size: usize,
+}
+
+#[get("/info")]
+fn info(
+    access: Access,
+    build: Current,
+    labels: &State<Labels>,
+    estimate: &State<estimate::EstimateSettings>,
+    tls: &State<tls::Pinning>,
+    settings: &State<PageSettings>,
+    builds: &State<Builds>,
+    fota: &State<fota::FotaSettings>,
+    merge: &State<merged_bin::MergeSettings>,
+    config: &rocket::Config,
+) -> Json<FirmwareInfo> {
+    Json(firmware_info(
+        &access, &build, labels, estimate, tls, settings, builds, fota, merge, config,
+    ))
+}
+
+/// What `/info` reports about `build`
+#[allow(clippy::too_many_arguments)]
+fn firmware_info(
+    access: &Access,
+    build: &PreparedBuild,
+    labels: &Labels,
+    estimate: &estimate::EstimateSettings,
+    tls: &tls::Pinning,
+    settings: &PageSettings,
+    builds: &Builds,
+    fota: &fota::FotaSettings,
+    merge: &merged_bin::MergeSettings,
+    config: &rocket::Config,
+) -> FirmwareInfo {
+    let data = &build.parts;
+    let checksums = served_checksums(build, merge.size);
+    let external_url = fota.public_url.clone().or_else(|| {
+        (!config.address.is_loopback()).then(|| {
+            let scheme = if config.tls_enabled() {
+                "https"
+            } else {
+                "http"
+            };
+            let query = access
+                .query()
+                .map(|query| format!("?{}", query))
+                .unwrap_or_default();
+            format!(
+                "{}://{}/{}",
+                scheme,
+                SocketAddr::new(listen::reachable(config.address), config.port),
+                query
+            )
+        })
+    });
+    FirmwareInfo {
+        chip: data.chip.clone(),
+        total_size: data.total_size,
+        bootloader_size: data.bootloader_size,
+        partitions_size: data.partitions_size,
+        firmware_size: data.firmware_size,
+        flash_size: data.flash_size.clone(),
+        partition_table_source: data.partition_table_source.clone(),
+        extra_parts: data
+            .extra_parts
+            .iter()
+            .map(|part| PartInfo {
+                name: part.file.clone(),
+                label: part.label.clone(),
+                offset: part.offset,
+                size: part.data.len(),
+            })
+            .collect(),
+        parts: data.parts(),
+        labels: labels.clone(),
+        estimated_flash_seconds: estimate::flash_seconds(data.total_size, estimate.assumed_baud),
+        single_part: data.single_part,
+        verified_sha256: data.verified_inputs.clone(),
+        board: data.board.clone(),
+        sbom_available: data.sbom.is_some(),
+        elf_available: data.elf.is_some(),
+        elf_size: data.elf.as_ref().map(Vec::len),
+        tls_cert_sha256: tls.cert.as_ref().map(|cert| cert.sha256.clone()),
+        elf_path: data.elf_path.clone(),
+        erase_policy: settings.erase_policy.as_str(),
+        erase_locked: settings.erase_locked,
+        flash_mode: data.flash_mode.clone(),
+        flash_freq: data.flash_freq.clone(),
+        app_header: data.app_header.clone(),
+        firmware_version: fota
+            .version(data.app_version.as_deref())
+            .map(str::to_string),
+        min_chip_rev: data.min_chip_rev.map(|rev| rev.to_string()),
+        named_builds: builds.names(),
+        external_url,
+        merged_size: merged_bin::size(data, merge.size),
+        merged_sha256: checksums
+            .last()
+            .map(|digest| digest.sha256.clone())
+            .unwrap_or_default(),
+        checksums,
+    }
+}
+
+/// Serves the additional parts (second app, otadata) by their file name
+#[get("/<file>", rank = 10)]
+fn extra_part(_access: Access, file: &str, build: Current) -> Option<Vec<u8>> {
+    build
+        .parts
+        .extra_parts
+        .iter()
+        .find(|part| part.file == file)
+        .map(|part| part.data.clone())
+}
+
+/// Serves the `--extra-part` binaries
+#[get("/extra/<file>", rank = 9)]
+fn extra_file(_access: Access, file: &str, build: Current) -> Option<Vec<u8>> {
+    build.part(&format!("extra/{}", file)).map(<[u8]>::to_vec)
+}
+
+/// Descriptor polled by devices using the esp32FOTA library; with
+/// `?current=<version>` only a newer firmware is offered
+#[get("/fota.json?<current>")]
+fn fota_json(
+    _access: Access,
+    current: Option<&str>,
+    build: Current,
+    settings: &State<fota::FotaSettings>,
+    base: fota::BaseUrl,
+) -> Result<Json<fota::Descriptor>, (Status, String)> {
+    let version = settings
+        .version(build.parts.app_version.as_deref())
+        .ok_or_else(|| {
+            (
+                Status::NotFound,
+                String::from("The firmware has no app descriptor version, pass --firmware-version"),
+            )
+        })?;
+    let offered = fota::parse_version(version).ok_or_else(|| {
+        (
+            Status::InternalServerError,
+            format!("Firmware version '{}' is not a version like 1.2.3", version),
+        )
+    })?;
+
+    if let Some(current) = current {
+        let current = fota::parse_version(current).ok_or_else(|| {
+            (
+                Status::BadRequest,
+                format!("'{}' is not a version like 1.2.3", current),
+            )
+        })?;
+        if !fota::is_newer(offered, current) {
+            return Err((Status::NoContent, String::new()));
+        }
+    }
+
+    Ok(Json(fota::Descriptor::new(settings, offered, &base.0)))
+}
+
+/// Patch for devices running the `--ota-base` image; others, and all
+/// devices once the served build changed, are sent to the full image
+#[get("/ota/patch.bin?<base>")]
+fn ota_patch(
+    _access: Access,
+    base: Option<&str>,
+    build: Current,
+    delta: &State<DeltaOta>,
+) -> Result<delta_ota::PatchResponse, Redirect> {
+    let current = checksums::sha256_hex(&build.parts.firmware) == delta.info.target_sha256;
+    let from_base = base.map_or(true, |base| {
+        base.eq_ignore_ascii_case(&delta.info.base_sha256)
+    });
+    if !current || !from_base {
+        return Err(Redirect::to(uri!(firmware)));
+    }
+    Ok(delta_ota::PatchResponse::new(delta))
+}
+
+#[get("/ota/patch.json")]
+fn ota_patch_json(
+    _access: Access,
+    build: Current,
+    delta: &State<DeltaOta>,
+) -> Result<Json<delta_ota::PatchInfo>, (Status, String)> {
+    if checksums::sha256_hex(&build.parts.firmware) != delta.info.target_sha256 {
+        return Err((
+            Status::Gone,
+            String::from(
+                "the served build changed since the patch was generated; use /firmware.bin",
+            ),
+        ));
+    }
+    Ok(Json(delta.info.clone()))
+}
+
+const NO_SBOM: &str = "The served firmware has no dependency list: build it with \
+                       cargo-auditable (cargo install cargo-auditable, then cargo auditable build)";
+
+const NO_ELF: &str = "The ELF is not served, start the server with --serve-elf";
+
+/// The ELF the served app was built from, for decoding backtraces
+#[get("/firmware.elf")]
+fn firmware_elf(
+    _access: Access,
+    build: Current,
+) -> Result<elf_info::ElfDownload, (Status, &'static str)> {
+    let elf = build.parts.elf.as_ref().ok_or((Status::NotFound, NO_ELF))?;
+    Ok(elf_info::ElfDownload::new(
+        elf.clone(),
+        build.parts.elf_path.as_deref(),
+    ))
+}
+
+#[get("/sbom.json")]
+fn sbom_json(_access: Access, build: Current) -> Result<Json<sbom::Sbom>, (Status, &'static str)> {
+    build
+        .parts
+        .sbom
+        .clone()
+        .map(Json)
+        .ok_or((Status::NotFound, NO_SBOM))
+}
+
+#[get("/sbom.cdx.json")]
+fn sbom_cdx_json(_access: Access, build: Current) -> Result<Json<Value>, (Status, &'static str)> {
+    build
+        .parts
+        .sbom
+        .as_ref()
+        .map(|sbom| Json(sbom::cyclonedx(sbom)))
+        .ok_or((Status::NotFound, NO_SBOM))
+}
+
+/// SHA-256 fingerprint of the certificate devices should pin
+#[get("/ota/cert-fingerprint")]
+fn cert_fingerprint(_access: Access, tls: &State<tls::Pinning>) -> Option<String> {
+    tls.cert.as_ref().map(|cert| cert.sha256.clone())
+}
+
+/// The certificate as PEM, for firmware builds to embed
+#[get("/ota/cert.pem")]
+fn cert_pem(_access: Access, tls: &State<tls::Pinning>) -> Option<(ContentType, String)> {
+    let cert = tls.cert.as_ref()?;
+    Some((
+        ContentType::new("application", "x-pem-file"),
+        cert.pem.clone(),
+    ))
+}
+
+/// Readiness probe: 503 while a rebuild runs or after one failed
+#[get("/health")]
+fn health(
+    _access: Access,
+    rocket: &Rocket<Orbit>,
+    stats: &State<stats::ServeStats>,
+) -> (Status, Json<health::Health>) {
+    // absent with --monitor-only
+    let (status, health) = health::check(rocket.state::<Builds>(), stats);
+    (status, Json(health))
+}
+
+#[get("/status")]
+fn build_status(_access: Access, builds: &State<Builds>) -> Json<BuildStatus> {
+    Json(builds.status())
+}
+
+/// The app descriptor ESP-IDF embeds, with nulls for an image without one
+#[get("/app-info")]
+fn app_info(_access: Access, build: Current) -> Json<fota::AppInfo> {
+    Json(build.parts.app_desc.as_ref().into())
+}
+
+#[get("/layout.json")]
+fn layout_json(_access: Access, build: Current) -> Json<Vec<layout::Region>> {
+    Json(build.parts.layout.clone())
+}
+
+#[get("/elf-info")]
+fn elf_info_json(_access: Access, build: Current) -> Json<elf_info::ElfInfo> {
+    Json(build.parts.elf_info.clone())
+}
+
+#[post("/erase-local")]
+fn erase_local(
+    _access: Access,
+    _admin: Admin,
+    local: &State<LocalDevice>,
+) -> Result<TextStream![String], (Status, String)> {
+    let claim = device::claim(&local.port, "web-flash server (erase request)")
+        .map_err(|e| (Status::Conflict, e.to_string()))?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (port, chip) = (local.port.clone(), local.chip);
+    std::thread::spawn(move || {
+        let _claim = claim;
+        let result = device::erase(&port, Some(chip), |msg| {
+            tx.send(format!("{}\n", msg)).ok();
+        });
+        if let Err(e) = result {
+            tx.send(format!("Error: {:#}\n", e)).ok();
+        }
+    });
+
+    Ok(TextStream! {
+        while let Some(line) = rx.recv().await {
+            yield line;
+        }
+    })
+}
+
+/// Flashes the current build to the attached devices, see `flash-all`
+#[post("/flash-all")]
+async fn flash_all_devices(
+    _access: Access,
+    _admin: Admin,
+    build: Current,
+    local: &State<LocalDevice>,
+    batch: &State<flash_all::BatchSettings>,
+) -> Result<Json<Vec<flash_all::PortResult>>, (Status, String)> {
+    let (build, chip, batch) = (build.0.clone(), local.chip, batch.inner().clone());
+    let results = rocket::tokio::task::spawn_blocking(move || flash_all::run(&batch, &build, chip))
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+        .map_err(|e| (Status::Conflict, format!("{:#}", e)))?;
+
+    Ok(Json(results))
+}
+
+#[post("/upload/partition-table?<session>", data = "<data>")]
+async fn upload_partition_table(
+    _access: Access,
+    _admin: Admin,
+    session: Option<&str>,
+    data: Data<'_>,
+    builds: &State<Builds>,
+    rebuilder: &State<upload::Rebuilder>,
+    sessions: &State<telemetry::Sessions>,
+) -> Result<Json<BuildStatus>, (Status, Json<upload::UploadError>)> {
+    let span = sessions.rebuild(session, "partition-table");
+    replace_input(upload::Input::PartitionTable, data, builds, rebuilder, span).await
+}
+
+#[post("/upload/bootloader?<session>", data = "<data>")]
+async fn upload_bootloader(
+    _access: Access,
+    _admin: Admin,
+    session: Option<&str>,
+    data: Data<'_>,
+    builds: &State<Builds>,
+    rebuilder: &State<upload::Rebuilder>,
+    sessions: &State<telemetry::Sessions>,
+) -> Result<Json<BuildStatus>, (Status, Json<upload::UploadError>)> {
+    let span = sessions.rebuild(session, "bootloader");
+    replace_input(upload::Input::Bootloader, data, builds, rebuilder, span).await
+}
+
+/// Replaces the ELF, and optionally the partition table, with the files of
+/// a `multipart/form-data` upload, e.g. `curl -F elf=@app.elf`. A build that
+/// fails leaves the served one as it was.
+#[post("/upload?<session>", data = "<data>")]
+#[allow(clippy::too_many_arguments)]
+async fn upload_elf(
+    access: Access,
+    _admin: Admin,
+    session: Option<&str>,
+    content_type: &ContentType,
+    data: Data<'_>,
+    builds: &State<Builds>,
+    rebuilder: &State<upload::Rebuilder>,
+    sessions: &State<telemetry::Sessions>,
+    labels: &State<Labels>,
+    estimate: &State<estimate::EstimateSettings>,
+    tls: &State<tls::Pinning>,
+    settings: &State<PageSettings>,
+    fota: &State<fota::FotaSettings>,
+    merge: &State<merged_bin::MergeSettings>,
+    config: &rocket::Config,
+) -> Result<Json<FirmwareInfo>, (Status, Json<upload::UploadError>)> {
+    let failure = |status: Status, error: String, exit_code: i32| {
+        (status, Json(upload::UploadError { error, exit_code }))
+    };
+    let span = sessions.rebuild(session, "elf");
+    let upload::ElfUpload {
+        elf,
+        partition_table,
+    } = upload::ElfUpload::read(content_type, data)
+        .await
+        .map_err(|(status, error)| failure(status, error, 3))?;
+
+    let (served, rebuilder) = (builds.inner().clone(), rebuilder.inner().clone());
+    let build = rocket::tokio::task::spawn_blocking(move || {
+        span.in_scope(|| {
+            let mut inputs = vec![(upload::Input::Elf, elf.as_slice())];
+            if let Some(table) = &partition_table {
+                inputs.push((upload::Input::PartitionTable, table.as_slice()));
+            }
+            let result = rebuilder.rebuild_with(&served, &inputs);
+            match &result {
+                Ok(build) => tracing::info!(hash = %build.hash, "rebuilt"),
+                Err(e) => tracing::warn!(error = %e.error, "rebuild failed"),
+            }
+            result
+        })
+    })
+    .await
+    .map_err(|e| failure(Status::InternalServerError, e.to_string(), 1))?
+    .map_err(|e| (Status::UnprocessableEntity, Json(e)))?;
+
+    status!(
+        "\nServing the uploaded ELF as {}: total {} bytes",
+        build.hash,
+        build.parts.total_size
+    );
+    Ok(Json(firmware_info(
+        &access, &build, labels, estimate, tls, settings, builds, fota, merge, config,
+    )))
+}
+
+/// Prepares the image again from the same paths, e.g. after copying a new
+/// ELF over the old one; a failed build leaves the served one as it was
+#[post("/reload?<session>")]
+async fn reload(
+    _access: Access,
+    _admin: Admin,
+    session: Option<&str>,
+    builds: &State<Builds>,
+    rebuilder: &State<upload::Rebuilder>,
+    sessions: &State<telemetry::Sessions>,
+) -> Result<Json<upload::Reloaded>, (Status, Json<upload::UploadError>)> {
+    let span = sessions.rebuild(session, "reload");
+    let (builds, rebuilder) = (builds.inner().clone(), rebuilder.inner().clone());
+    rocket::tokio::task::spawn_blocking(move || {
+        span.in_scope(|| {
+            builds.start_rebuild();
+            let result = rebuilder.rebuild_current();
+            let answer = match &result {
+                Ok(build) => {
+                    status!(
+                        "\nReloaded {}: total {} bytes",
+                        build.hash,
+                        build.parts.total_size
+                    );
+                    tracing::info!(hash = %build.hash, "reloaded");
+                    Ok(Json(upload::Reloaded::new(build)))
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "reload failed");
+                    Err((
+                        Status::UnprocessableEntity,
+                        Json(upload::UploadError {
+                            error: e.to_string(),
+                            exit_code: e.exit_code(),
+                        }),
+                    ))
+                }
+            };
+            builds.finish(result);
+            answer
+        })
+    })
+    .await
+    .map_err(|e| {
+        (
+            Status::InternalServerError,
+            Json(upload::UploadError {
+                error: e.to_string(),
+                exit_code: 1,
+            }),
+        )
+    })?
+}
+
+/// Rebuilds with an uploaded input and serves the result; a rejected
+/// upload leaves the served build as it was
+async fn replace_input(
+    input: upload::Input,
+    data: Data<'_>,
+    builds: &Builds,
+    rebuilder: &upload::Rebuilder,
+    span: tracing::Span,
+) -> Result<Json<BuildStatus>, (Status, Json<upload::UploadError>)> {
+    let failure = |status: Status, error: String, exit_code: i32| {
+        (status, Json(upload::UploadError { error, exit_code }))
+    };
+
+    let data = data
+        .open(upload::MAX_UPLOAD_SIZE.bytes())
+        .into_bytes()
+        .await
+        .map_err(|e| failure(Status::BadRequest, e.to_string(), 3))?;
+    if !data.is_complete() {
+        return Err(failure(
+            Status::PayloadTooLarge,
+            format!("uploads are limited to {} bytes", upload::MAX_UPLOAD_SIZE),
+            3,
+        ));
+    }
+
+    let (builds, rebuilder) = (builds.clone(), rebuilder.clone());
+    rocket::tokio::task::spawn_blocking(move || {
+        span.in_scope(|| {
+            let result = rebuilder.rebuild(&builds, input, &data.into_inner());
+            match &result {
+                Ok(build) => tracing::info!(hash = %build.hash, "rebuilt"),
+                Err(e) => tracing::warn!(error = %e.error, "rebuild failed"),
+            }
+            result.map(|_| Json(builds.status()))
+        })
+    })
+    .await
+    .map_err(|e| failure(Status::InternalServerError, e.to_string(), 1))?
+    .map_err(|e| (Status::UnprocessableEntity, Json(e)))
+}
+
+/// A `firmware-updated` event whenever a new build is served, so open pages
+/// can refresh; Rocket sends a keepalive comment every 30 seconds
+#[get("/events")]
+fn firmware_events(_access: Access, builds: &State<Builds>, mut end: Shutdown) -> EventStream![] {
+    let mut rx = builds.subscribe();
+    EventStream! {
+        loop {
+            let update = select! {
+                update = rx.recv() => match update {
+                    Ok(update) => update,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut end => break,
+            };
+
+            yield Event::json(&update).event("firmware-updated");
+        }
+    }
+}
+
+#[get("/monitor")]
+fn monitor_events(_access: Access, monitor: &State<Monitor>, mut end: Shutdown) -> EventStream![] {
+    let mut rx = monitor.subscribe();
+    EventStream! {
+        loop {
+            let line = select! {
+                line = rx.recv() => match line {
+                    Ok(line) => line,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut end => break,
+            };
+
+            yield Event::data(line);
+        }
+    }
+}
+
+/// Stands in for the image routes when running with `--monitor-only`
+#[get("/<name>", rank = 20)]
+fn no_image(_access: Access, name: &str) -> (Status, String) {
+    (
+        Status::NotFound,
+        format!(
+            "/{} is not available: the server runs with --monitor-only, so no firmware image was prepared",
+            name
+        ),
+    )
+}
+
+#[get("/?<lang>")]
+fn monitor_page(
+    access: Access,
+    lang: Option<&str>,
+    settings: &State<PageSettings>,
+) -> content::RawHtml<String> {
+    content::RawHtml(render_page(MONITOR_HTML, lang, settings, &access))
+}
+
+#[get("/?<lang>")]
+fn index(
+    access: Access,
+    lang: Option<&str>,
+    build: Current,
+    settings: &State<PageSettings>,
+) -> content::RawHtml<String> {
+    match &settings.index {
+        Some(template) => {
+            let name = escape_html(settings.manifest_name());
+            let token_query = access
+                .query()
+                .map(|query| format!("?{}", query))
+                .unwrap_or_default();
+            content::RawHtml(template.render(&[
+                ("name", name.as_str()),
+                ("chip", build.parts.chip.as_str()),
+                ("flash_size", build.parts.flash_size.as_str()),
+                ("token_query", token_query.as_str()),
+                ("esp_web_tools", settings.esp_web_tools.as_str()),
+            ]))
+        }
+        None => content::RawHtml(render_page(INDEX_HTML, lang, settings, &access)),
+    }
+}
+
+/// Renders one of the built-in pages in the requested (or configured)
+/// language, passing on the `--auth-token` it was requested with
+fn render_page(
+    template: &str,
+    lang: Option<&str>,
+    settings: &PageSettings,
+    access: &Access,
+) -> String {
+    let requested = lang.unwrap_or(&settings.lang);
+    let (translation, missing_lang) = match i18n::find(requested) {
+        Some(translation) => (translation, ""),
+        // only echo plain language codes back into the page script
+        None if requested
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+        {
+            (&i18n::ENGLISH, requested)
+        }
+        None => (&i18n::ENGLISH, "?"),
+    };
+
+    let title = match &settings.title {
+        Some(title) => escape_html(title),
+        None => translation.title.to_string(),
+    };
+    let app_name = match (&settings.title, &settings.name) {
+        (None, Some(name)) => escape_html(name),
+        _ => title.clone(),
+    };
+
+    let mut vars: Vec<(&str, &str)> = translation.vars();
+    vars.retain(|(name, _)| *name != "title");
+    vars.push(("title", &title));
+    vars.push(("app_name", &app_name));
+    vars.push(("missing_lang", missing_lang));
+    vars.push(("erase_policy", settings.erase_policy.as_str()));
+    vars.push((
+        "erase_locked",
+        if settings.erase_locked {
+            "true"
+        } else {
+            "false"
+        },
+    ));
+    vars.push(("once", if settings.once { "true" } else { "false" }));
+    vars.push(("admin", if settings.admin { "true" } else { "false" }));
+    vars.push((
+        "client_logs",
+        if settings.client_logs {
+            "true"
+        } else {
+            "false"
+        },
+    ));
+    vars.push(("esp_web_tools", &settings.esp_web_tools));
+    // the token is limited to URL-safe characters, see auth::token_arg
+    let token_query = access
+        .query()
+        .map(|query| format!("?{}", query))
+        .unwrap_or_default();
+    let auth_token = access
+        .token()
+        .map_or_else(|| String::from("null"), |token| format!("'{}'", token));
+    vars.push(("token_query", &token_query));
+    vars.push(("auth_token", &auth_token));
+    let logo = settings
+        .logo
+        .as_ref()
+        .map(|logo| {
+            format!(
+                "<img class=\"logo\" src=\"{}\" alt=\"\">",
+                escape_html(&logo.src(&token_query))
+            )
+        })
+        .unwrap_or_default();
+    vars.push(("logo", &logo));
+
+    render_template(template, &vars)
+}
+
+/// The bundled esp-web-tools module and the chunks it imports. Public
+/// library code, so served without `--auth-token`: module imports can't
+/// carry the token.
+#[get("/vendor/esp-web-tools/<file..>")]
+fn esp_web_tools(file: PathBuf) -> Option<(ContentType, Cow<'static, [u8]>)> {
+    vendor::esp_web_tools(&file).map(|data| (ContentType::JavaScript, data))
+}
+
+#[get("/assets/logo")]
+fn logo(_access: Access, settings: &State<PageSettings>) -> Option<(ContentType, Vec<u8>)> {
+    match &settings.logo {
+        Some(logo::Logo::File { content_type, data }) => Some((content_type.clone(), data.clone())),
+        _ => None,
+    }
+}
+
+#[get("/theme.css")]
+fn theme_css(_access: Access, settings: &State<PageSettings>) -> content::RawCss<String> {
+    content::RawCss(settings.theme_css.clone())
+}
+
+/// Makes text safe to insert into HTML elements and attributes
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Replaces every `{{name}}` placeholder in `template` with its value
+fn render_template(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut page = template.to_string();
+    for (name, value) in vars {
+        page = page.replace(&format!("{{{{{}}}}}", name), value);
+    }
+    page
+}
+
+#[derive(Serialize)]
+struct UnknownBuild {
+    error: String,
+    builds: Vec<String>,
+}
+
+/// A `?build=` naming no build gets the valid names, other 404s stay plain
+#[catch(404)]
+fn unknown_build(req: &Request) -> (Status, Result<Json<UnknownBuild>, &'static str>) {
+    let builds = req.rocket().state::<Builds>();
+    let name = req.query_value::<&str>("build").and_then(Result::ok);
+    let body = match (builds, name) {
+        (Some(builds), Some(name)) if builds.named(name).is_none() => Ok(Json(UnknownBuild {
+            error: format!("No build named '{}'", name),
+            builds: builds.names(),
+        })),
+        _ => Err("Not Found"),
+    };
+    (Status::NotFound, body)
+}
+
+#[get("/builds")]
+fn build_list(_access: Access, builds: &State<Builds>) -> Json<Vec<prepared_build::BuildSummary>> {
+    Json(builds.list())
+}
+
+/// Manifest of a retained build, whose parts stay at `/b/<hash>/` while it's kept
+#[get("/builds/<hash>/manifest.json?<erase>&<session>")]
+fn build_manifest(
+    access: Access,
+    hash: &str,
+    erase: Option<ErasePolicy>,
+    session: Option<&str>,
+    builds: &State<Builds>,
+    settings: &State<PageSettings>,
+    fota: &State<fota::FotaSettings>,
+    sessions: &State<telemetry::Sessions>,
+) -> Option<Json<web_manifest::Manifest>> {
+    let session = telemetry::session_id(session);
+    let span = sessions.artifact(session, "manifest.json");
+    let _entered = span.enter();
+    let build = builds.find(hash)?;
+    let erase = settings.erase(erase);
+    tracing::info!(hash = %build.hash, erase = erase.as_str(), "served");
+    Some(Json(web_manifest::generate(
+        &build.parts,
+        &settings.manifest_title(fota.version(build.parts.app_version.as_deref())),
+        &build.hash,
+        "../../",
+        erase,
+        session,
+        access.query().as_deref(),
+    )))
+}
+
+/// `?erase=full|keep|prompt` picks the flavor, by default the `--erase-policy` one.
+/// `?session=<id>` opens the flash session's trace, which the parts join.
+#[get("/manifest.json?<erase>&<session>")]
+fn manifest(
+    access: Access,
+    erase: Option<ErasePolicy>,
+    session: Option<&str>,
+    build: Current,
+    settings: &State<PageSettings>,
+    fota: &State<fota::FotaSettings>,
+    sessions: &State<telemetry::Sessions>,
+) -> Json<web_manifest::Manifest> {
+    let session = telemetry::session_id(session);
+    let span = sessions.artifact(session, "manifest.json");
+    let _entered = span.enter();
+    let erase = settings.erase(erase);
+    tracing::info!(hash = %build.hash, erase = erase.as_str(), "served");
+    Json(web_manifest::generate(
+        &build.parts,
+        &settings.manifest_title(fota.version(build.parts.app_version.as_deref())),
+        &build.hash,
+        "",
+        erase,
+        session,
+        access.query().as_deref(),
+    ))
+}
+
+/// Closes a flash session's trace, reported by the page when the install ends
+#[post("/session/<id>/end?<outcome>&<message>")]
+fn end_session(
+    _access: Access,
+    id: &str,
+    outcome: telemetry::Outcome,
+    message: Option<&str>,
+    sessions: &State<telemetry::Sessions>,
+) -> Status {
+    if sessions.end(id, outcome, message) {
+        Status::NoContent
+    } else {
+        Status::NotFound
+    }
+}
+
+/// Stores the console log a page sends when a flash ends, see `--log-dir`
+#[post("/logs", data = "<data>")]
+async fn client_log(
+    _access: Access,
+    data: Data<'_>,
+    logs: &State<client_logs::ClientLogs>,
+) -> Result<Json<client_logs::Saved>, (Status, String)> {
+    let body = data
+        .open(client_logs::MAX_LOG_SIZE.bytes())
+        .into_string()
+        .await
+        .map_err(|e| (Status::BadRequest, e.to_string()))?;
+    if !body.is_complete() {
+        return Err((
+            Status::PayloadTooLarge,
+            format!("logs are limited to {} bytes", client_logs::MAX_LOG_SIZE),
+        ));
+    }
+    let log: client_logs::ClientLog =
+        json::from_str(&body).map_err(|e| (Status::UnprocessableEntity, e.to_string()))?;
+    let file = logs
+        .save(&log)
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?;
+    tracing::info!(file = %file, entries = log.entries.len(), "client log saved");
+    Ok(Json(client_logs::Saved { file }))
+}
+
+/// Shuts the server down after a finished install, `--once`
+#[post("/finished")]
+fn install_finished(_access: Access, shutdown: Shutdown) -> Status {
+    status!("\nInstall finished, shutting down (--once)");
+    shutdown.notify();
+    Status::NoContent
+}
+
+#[derive(Serialize)]
+struct ShutdownAccepted {
+    status: &'static str,
+}
+
+/// Stops every listener gracefully; `serve` then prints its summary and returns
+#[post("/shutdown")]
+fn remote_shutdown(
+    _access: Access,
+    _operator: Operator,
+    shutdown: Shutdown,
+) -> (Status, Json<ShutdownAccepted>) {
+    status!("\nShutdown requested over HTTP");
+    shutdown.notify();
+    (
+        Status::Accepted,
+        Json(ShutdownAccepted {
+            status: "shutting down",
+        }),
+    )
+}
+
+/// The board attached to the server machine, see `--enable-local-flash`
+#[derive(Clone)]
+struct LocalDevice {
+    port: String,
+    chip: Chip,
+}
+
+#[derive(Clone)]
+struct PageSettings {
+    lang: String,
+    theme_css: String,
+    erase_policy: ErasePolicy,
+    /// `--name`, replacing the generic page heading and manifest name
+    name: Option<String>,
+    /// `--title`, replacing the page title and heading
+    title: Option<String>,
+    logo: Option<logo::Logo>,
+    /// `--no-erase-prompt`: `erase_policy` is `keep` and can't be changed
+    erase_locked: bool,
+    /// `--once`: the page reports a finished install to /finished
+    once: bool,
+    /// `--admin-token` is set, so the page offers to reload the firmware
+    admin: bool,
+    /// `--log-dir`: the page sends its console log after each flash
+    client_logs: bool,
+    /// `--index`, served instead of the built-in page
+    index: Option<index_template::IndexTemplate>,
+    /// where the page loads esp-web-tools' install button from
+    esp_web_tools: String,
+}
+
+impl PageSettings {
+    /// The erase flavor a manifest request gets
+    fn erase(&self, requested: Option<ErasePolicy>) -> ErasePolicy {
+        match requested {
+            Some(erase) if !self.erase_locked => erase,
+            _ => self.erase_policy,
+        }
+    }
+
+    fn manifest_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(web_manifest::DEFAULT_NAME)
+    }
+
+    fn manifest_title(&self, version: Option<&str>) -> String {
+        web_manifest::title(self.manifest_name(), version)
+    }
+}
+
+#[derive(Default)]
+struct PartsData {
+    chip: String,
+    bootloader: Vec<u8>,
+    partitions: Vec<u8>,
+    firmware: Vec<u8>,
+    total_size: usize,
+    bootloader_size: usize,
+    partitions_size: usize,
+    firmware_size: usize,
+    flash_size: String,
+    partition_table_source: String,
+    bootloader_offset: u32,
+    partitions_offset: u32,
+    firmware_offset: u32,
+    extra_parts: Vec<ExtraPart>,
+    layout: Vec<layout::Region>,
+    elf_info: elf_info::ElfInfo,
+    /// version from the app descriptor, if the image has one
+    app_version: Option<String>,
+    /// the whole app descriptor, read once the image is prepared
+    app_desc: Option<fota::AppDesc>,
+    /// `--merged-bin`: `firmware` is the whole flash image at 0x0 and
+    /// there's no separate bootloader
+    single_part: bool,
+    /// `--app-only`: bootloader and partition table are kept for the
+    /// checks and the layout, but neither served nor flashed
+    app_only: bool,
+    /// inputs whose SHA-256 matched `--expect-sha256`/`--checksums-file`
+    verified_inputs: Vec<String>,
+    /// `--board` profile the defaults came from
+    board: Option<String>,
+    /// dependency list embedded by cargo-auditable
+    sbom: Option<sbom::Sbom>,
+    /// the ELF file the image was built from, after resolving globs
+    elf_path: Option<String>,
+    /// when the ELF was last modified as it was read, in seconds since the Unix epoch
+    elf_mtime: Option<u64>,
+    /// the ELF as the app was built from it, kept with `--serve-elf`
+    elf: Option<Vec<u8>>,
+    /// flash mode and frequency from the bootloader header, as `DIO`, `40m`
+    flash_mode: Option<String>,
+    flash_freq: Option<String>,
+    /// header of an app image served instead of one built from an ELF
+    app_header: Option<app_image::AppHeader>,
+    /// minimum chip revision in the app header
+    min_chip_rev: Option<chip_rev::ChipRev>,
+}
+
+/// A part flashed in addition to bootloader, partition table and app
+struct ExtraPart {
+    /// file name it's served under, also used in the manifest
+    file: String,
+    /// human readable description for the page and console
+    label: String,
+    offset: u32,
+    data: Vec<u8>,
+}
+
+impl PartsData {
+    /// Every part the manifest lists, in flash order
+    fn parts(&self) -> Vec<PartInfo> {
+        let info = |name: &str, label: &str, offset: u32, size: usize| PartInfo {
+            name: name.to_string(),
+            label: label.to_string(),
+            offset,
+            size,
+        };
+        let mut parts = if self.single_part {
+            vec![info(
+                "firmware.bin",
+                "merged image",
+                self.firmware_offset,
+                self.firmware_size,
+            )]
+        } else if self.app_only {
+            vec![info(
+                "firmware.bin",
+                "app",
+                self.firmware_offset,
+                self.firmware_size,
+            )]
+        } else {
+            vec![
+                info(
+                    "bootloader.bin",
+                    "bootloader",
+                    self.bootloader_offset,
+                    self.bootloader_size,
+                ),
+                info(
+                    "partitions.bin",
+                    "partition table",
+                    self.partitions_offset,
+                    self.partitions_size,
+                ),
+                info(
+                    "firmware.bin",
+                    "app",
+                    self.firmware_offset,
+                    self.firmware_size,
+                ),
+            ]
+        };
+        parts.extend(
+            self.extra_parts
+                .iter()
+                .map(|part| info(&part.file, &part.label, part.offset, part.data.len())),
+        );
+        // the ESP8266 has neither bootloader nor partition table
+        parts.retain(|part| {
+            part.size > 0 || !matches!(part.name.as_str(), "bootloader.bin" | "partitions.bin")
+        });
+        parts.sort_by_key(|part| part.offset);
+        parts
+    }
+}
+
+fn chip_name(chip: Chip) -> &'static str {
+    chips::info(chip).name
+}
+
+/// Names the `--tls self-signed` certificate is valid for: localhost, this
+/// host and every address served on, including the LAN address of one
+/// bound to all interfaces
+fn tls_hosts(opts: &Args) -> Vec<String> {
+    let mut addresses: Vec<IpAddr> = vec![IpAddr::from([127, 0, 0, 1])];
+    let bound = opts
+        .listen
+        .iter()
+        .map(|listener| listener.addr.ip())
+        .chain(opts.address)
+        .chain(opts.lan.then(|| IpAddr::from([0, 0, 0, 0])));
+    for addr in bound {
+        addresses.push(addr);
+        addresses.push(listen::reachable(addr));
+        if addr.is_unspecified() {
+            addresses.extend(listen::interfaces(addr).into_iter().map(|(_, ip)| ip));
+        }
+    }
+    let mut hosts = vec![String::from("localhost")];
+    hosts.extend(std::env::var("HOSTNAME").ok());
+    for addr in addresses.iter().filter(|addr| !addr.is_unspecified()) {
+        let addr = addr.to_string();
+        if !hosts.contains(&addr) {
+            hosts.push(addr);
+        }
+    }
+    hosts
+}
+
+/// Per-user settings such as boards.toml and the self-signed certificate:
+/// `$XDG_CONFIG_HOME/esp-web-flash`, or `~/.config/esp-web-flash`
+fn config_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("esp-web-flash"))
+}
+
+/// Peak resident set size of the process, where the OS reports it
+fn peak_memory_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+fn prepare(opts: &ImageArgs) -> Result<PartsData, PrepareError> {
+    prepare_reporting(opts, &mut rules::Report::strict())
+}
+
+/// `prepare`, sending the outcome of each compatibility rule to `report`
+fn prepare_reporting(
+    opts: &ImageArgs,
+    report: &mut rules::Report,
+) -> Result<PartsData, PrepareError> {
+    let checksums = Checksums::new(&opts.expect_sha256, opts.checksums_file.as_deref())?;
+    let mut data = prepare_parts(opts, &checksums, report)?;
+    if opts.app_only {
+        data.app_only = true;
+        data.total_size -= data.bootloader_size + data.partitions_size;
+        status!(
+            "App only: flashing {} bytes at 0x{:x}, keeping the device's bootloader and partition table",
+            data.firmware_size,
+            data.firmware_offset
+        );
+    }
+    let header = if data.single_part {
+        data.firmware.get(data.bootloader_offset as usize..)
+    } else {
+        Some(&data.bootloader[..])
+    };
+    if let Some((mode, freq)) = header.and_then(bootloader_image::flash_params) {
+        status!("Flash mode {}, frequency {}", mode, freq);
+        data.flash_mode = Some(mode);
+        data.flash_freq = Some(freq);
+    }
+    if !data.single_part {
+        data.app_desc = fota::app_desc(&data.firmware);
+        data.min_chip_rev = chip_rev::read(&data.firmware, opts.chip());
+        if let Some(rev) = data.min_chip_rev {
+            status!("Minimum chip revision {}", rev);
+        }
+    }
+
+    data.verified_inputs = checksums.verified();
+    data.board = opts.board.clone();
+    if let Some(kib) = peak_memory_kib() {
+        tracing::debug!(peak_kib = kib, "peak memory while preparing");
+    }
+    for path in &data.verified_inputs {
+        status!("SHA-256 verified: {}", path);
+    }
+    for file in checksums.unused() {
+        warning!(
+            "--expect-sha256/--checksums-file entry '{}' matches no input",
+            file
+        );
+    }
+    Ok(data)
+}
+
+fn prepare_parts(
+    opts: &ImageArgs,
+    checksums: &Checksums,
+    report: &mut rules::Report,
+) -> Result<PartsData, PrepareError> {
+    let (flash_size, flash_size_bytes) = (opts.flash_size.espflash(), opts.flash_size.bytes());
+    let flash_size_name = opts.flash_size.to_string();
+    if let Some(dir) = &opts.serve_dir {
+        return prebuilt::prepare(
+            dir,
+            &opts.prebuilt_parts,
+            opts.chip(),
+            &flash_size_name,
+            flash_size_bytes,
+            checksums,
+        );
+    }
+    if let Some(dir) = &opts.idf_build_dir {
+        return idf_build::prepare(
+            dir,
+            opts.chip(),
+            &flash_size_name,
+            flash_size_bytes,
+            checksums,
+        );
+    }
+    if let Some(dir) = &opts.pio {
+        return pio::prepare(
+            dir,
+            opts.pio_env.as_deref(),
+            opts.chip(),
+            &flash_size_name,
+            flash_size_bytes,
+            checksums,
+        );
+    }
+    if let Some(path) = &opts.merged_bin {
+        return merged_image::prepare(
+            path,
+            opts.chip(),
+            &flash_size_name,
+            flash_size_bytes,
+            checksums,
+        );
+    }
+
+    // Display file information
+    let elf_path = &opts.elf()?;
+    let mut elf = checksums.map(elf_path)?;
+    let is_app_image = app_image::is_app_image(&elf);
+    if is_app_image {
+        status!("App image: {}", elf_path.display());
+    } else {
+        status!("ELF file: {}", elf_path.display());
+    }
+    status!("  Size: {} bytes", elf.len());
+    if is_app_image && opts.serve_elf {
+        warning!("--serve-elf: an app image has no ELF to serve");
+    }
+    if is_app_image && !opts.patch_symbols.is_empty() {
+        return Err(PrepareError::PatchSymbol {
+            path: elf_path.clone(),
+            reason: String::from("an app image has no symbols, patch the ELF instead"),
+        });
+    }
+    let patched_symbols = patch_symbol::apply(&mut elf, &opts.patch_symbols).map_err(|reason| {
+        PrepareError::PatchSymbol {
+            path: elf_path.clone(),
+            reason,
+        }
+    })?;
+    for patch in &patched_symbols {
+        status!(
+            "  Patched {} at 0x{:08x} ({} bytes): {:?}",
+            patch.symbol,
+            patch.addr,
+            patch.size,
+            patch.value
+        );
+    }
+    let elf: &[u8] = &elf;
+    report.rule("elf-arch", rules::elf_arch(elf, elf_path, opts.chip()))?;
+
+    let partition_table_input = match &opts.partition_table {
+        Some(PartitionTableArg::File(path)) => {
+            Some((checksums.read(path)?, path.display().to_string()))
+        }
+        Some(PartitionTableArg::Preset(preset)) => {
+            status!("Partition table: preset '{}'", preset.name);
+            Some((
+                preset.csv.as_bytes().to_vec(),
+                format!("preset:{}", preset.name),
+            ))
+        }
+        None if opts.gen_partition_table => {
+            let csv = partition_gen::generate(
+                &partition_gen::Options {
+                    app_size: opts.app_size,
+                    ota: opts.ota,
+                    nvs_size: opts.nvs_size,
+                    spiffs_size: opts.spiffs_size,
+                    table_offset: opts
+                        .partition_table_offset
+                        .unwrap_or(partition_gen::DEFAULT_TABLE_OFFSET),
+                },
+                flash_size_bytes,
+            )
+            .map_err(|e| PrepareError::PartitionTableGeneration(e.to_string()))?;
+            status!("Generated partition table:\n{}", csv);
+            Some((csv.into_bytes(), String::from("generated")))
+        }
+        None => None,
+    };
+    let (table_bin, mut partition_table_source) = match partition_table_input {
+        Some((data, source)) => {
+            let bin = partition_table::load_file(&source, &data)
+                .and_then(|table| partition_table::to_bin(&table))
+                .map_err(|reason| PrepareError::InvalidPartitionTable {
+                    table: source.clone(),
+                    reason,
+                })?;
+            (Some(bin), source)
+        }
+        None => (None, String::from("default")),
+    };
+
+    bootloader_image::check_flash_params(opts.chip(), opts.flash_mode, opts.flash_freq)
+        .map_err(PrepareError::FlashParams)?;
+
+    let b = if let Some(p) = &opts.bootloader {
+        Some(bootloader_image::load(
+            p,
+            opts.chip(),
+            flash_size,
+            checksums,
+        )?)
+    } else {
+        None
+    };
+
+    let chip = opts.chip();
+    let chip_name = chip_name(chip);
+    let mut app_header = None;
+    // bootloader, partition table and app, each with its offset, and any
+    // further segments of the app
+    let (parts, app_segments, elf_info, sbom) = if is_app_image {
+        let invalid_image = |reason: String| PrepareError::InvalidAppImage {
+            path: elf_path.clone(),
+            reason,
+        };
+        app_header = Some(app_image::inspect(elf, chip).map_err(invalid_image)?);
+        let parts = if chip == Chip::Esp8266 {
+            // booted from 0x0 by the ROM
+            [
+                (prebuilt::bootloader_offset(chip), Vec::new()),
+                (prebuilt::PARTITION_TABLE_OFFSET, Vec::new()),
+                (0, elf.to_vec()),
+            ]
+        } else if opts.app_only {
+            // only the app is flashed, the device keeps its bootloader and table
+            [
+                (prebuilt::bootloader_offset(chip), Vec::new()),
+                (prebuilt::PARTITION_TABLE_OFFSET, Vec::new()),
+                (
+                    opts.app_offset.unwrap_or(prebuilt::DEFAULT_APP_OFFSET),
+                    elf.to_vec(),
+                ),
+            ]
+        } else {
+            let bootloader = b.ok_or_else(|| {
+                invalid_image(String::from(
+                    "espflash only provides its bootloader when building from an ELF, pass --bootloader",
+                ))
+            })?;
+            let table_bin = match table_bin {
+                Some(bin) => bin,
+                None => {
+                    // espflash's default table is only available when building from an ELF
+                    let preset = &presets::PRESETS[0];
+                    partition_table_source = format!("preset:{}", preset.name);
+                    status!("Partition table: preset '{}'", preset.name);
+                    partition_table::load(preset.csv.as_bytes())
+                        .and_then(|table| partition_table::to_bin(&table))
+                        .map_err(|reason| PrepareError::InvalidPartitionTable {
+                            table: partition_table_source.clone(),
+                            reason,
+                        })?
+                }
+            };
+            let app_offset = partition_table::parse(&table_bin)
+                .iter()
+                .filter(|e| e.ty == 0x00)
+                .map(|e| e.offset)
+                .min()
+                .unwrap_or(prebuilt::DEFAULT_APP_OFFSET);
+            [
+                (prebuilt::bootloader_offset(chip), bootloader),
+                (partition_gen::DEFAULT_TABLE_OFFSET, table_bin),
+                (app_offset, elf.to_vec()),
+            ]
+        };
+        (parts, Vec::new(), elf_info::ElfInfo::default(), None)
+    } else {
+        let p = table_bin
+            .map(|bin| partition_table::for_espflash(&bin))
+            .transpose()
+            .map_err(|reason| PrepareError::InvalidPartitionTable {
+                table: partition_table_source.clone(),
+                reason,
+            })?;
+        let invalid_elf = |e: &dyn std::fmt::Display| PrepareError::InvalidElf {
+            path: elf_path.clone(),
+            reason: e.to_string(),
+        };
+        let firmware = FirmwareImageBuilder::new(elf)
+            .flash_size(Some(flash_size))
+            .flash_mode(
+                opts.flash_mode
+                    .map(bootloader_image::FlashMode::to_espflash),
+            )
+            .flash_freq(
+                opts.flash_freq
+                    .map(bootloader_image::FlashFreq::to_espflash),
+            )
+            .build()
+            .map_err(|e| invalid_elf(&e))?;
+
+        let sbom = match sbom::from_elf(elf) {
+            Ok(Some(sbom)) => {
+                status!("  SBOM: {} packages (cargo-auditable)", sbom.packages.len());
+                Some(sbom)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warning!("ignoring the cargo-auditable data: {}", e);
+                None
+            }
+        };
 
-    /// path to bootloader
-    #[arg(short, long)]
-    bootloader: Option<PathBuf>,
+        let mut elf_info = elf_info::inspect(elf, &firmware, chip).map_err(|e| invalid_elf(&e))?;
+        elf_info.patched_symbols = patched_symbols;
 
-    /// path to partition table csv
-    #[arg(short, long)]
-    partition_table: Option<PathBuf>,
+        let image = chip
+            .get_flash_image(&firmware, b, p, None, None)
+            .map_err(|e| PrepareError::Image(e.to_string()))?;
+        let mut segments: Vec<_> = image
+            .flash_segments()
+            .map(|segment| (segment.addr, segment.data.to_vec()))
+            .collect();
+        // the ESP8266's ROM boots the app at 0x0, and its IROM follows in a
+        // segment of its own
+        let mut app_segments = Vec::new();
+        if chip == Chip::Esp8266 && !segments.is_empty() {
+            app_segments = segments.split_off(1);
+            segments.insert(0, (prebuilt::bootloader_offset(chip), Vec::new()));
+            segments.insert(1, (prebuilt::PARTITION_TABLE_OFFSET, Vec::new()));
+        }
+        let parts = match <[_; 3]>::try_from(segments) {
+            Ok(parts) => parts,
+            // only the app is used, wherever the other segments went
+            Err(mut segments) if opts.app_only && !segments.is_empty() => [
+                (prebuilt::bootloader_offset(chip), Vec::new()),
+                (prebuilt::PARTITION_TABLE_OFFSET, Vec::new()),
+                segments.pop().unwrap(),
+            ],
+            Err(segments) => {
+                return Err(PrepareError::Image(format!(
+                    "expected bootloader, partition table and app segments, got {}",
+                    segments.len()
+                )))
+            }
+        };
+        (parts, app_segments, elf_info, sbom)
+    };
+    let [(bootloader_addr, mut bootloader_data), (partitions_addr, partitions_data), (app_addr, mut firmware_data)] =
+        parts;
+    let offsets = offsets::Offsets {
+        bootloader: opts.bootloader_offset.unwrap_or(bootloader_addr),
+        partition_table: opts.partition_table_offset.unwrap_or(partitions_addr),
+        app: opts.app_offset.unwrap_or(app_addr),
+    };
 
-    /// flash size (examples: 2MB, 4MB, 8MB, 16MB)
-    #[arg(short, long, default_value = "4MB")]
-    flash_size: String,
+    if !opts.no_bootloader_patch && !opts.app_only && !bootloader_data.is_empty() {
+        let changes = bootloader_image::patch_flash_params(
+            &mut bootloader_data,
+            chip,
+            flash_size_bytes,
+            opts.flash_mode,
+            opts.flash_freq,
+        )
+        .map_err(|reason| PrepareError::InvalidBootloader {
+            path: opts
+                .bootloader
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("(espflash's bootloader)")),
+            reason,
+        })?;
+        for change in changes {
+            status!("Bootloader header patched: {}", change);
+        }
+    }
+    if is_app_image {
+        // espflash writes the flash settings into the headers it builds,
+        // an app image has whatever it was built with
+        match bootloader_image::patch_flash_params(
+            &mut firmware_data,
+            chip,
+            flash_size_bytes,
+            opts.flash_mode,
+            opts.flash_freq,
+        ) {
+            Ok(changes) => {
+                for change in changes {
+                    status!("App image header patched: {}", change);
+                }
+            }
+            Err(reason) => warning!("the app image header keeps its flash settings: {}", reason),
+        }
+    }
+    if let Some(rev) = opts.min_chip_rev {
+        chip_rev::patch(&mut firmware_data, chip, rev)
+            .map_err(|reason| PrepareError::Image(format!("--min-chip-rev: {}", reason)))?;
+    }
+
+    let in_flash = elf_info
+        .image_segments
+        .iter()
+        .any(|segment| segment.region == elf_info::Region::Flash && segment.size > 0);
+    if (firmware_data.is_empty() || !(in_flash || is_app_image)) && !opts.allow_empty_app {
+        return Err(PrepareError::NoFlashableCode {
+            path: elf_path.clone(),
+            app_size: firmware_data.len(),
+            segments: elf_info::describe_segments(&elf_info),
+        });
+    }
+
+    let bootloader_size = bootloader_data.len();
+    let partitions_size = partitions_data.len();
+    let firmware_size = firmware_data.len();
+
+    let table = partition_table::parse(&partitions_data);
+    report.rule(
+        "table-fits-flash",
+        rules::table_fits_flash(
+            &table,
+            &partition_table_source,
+            flash_size_bytes,
+            &flash_size_name,
+        ),
+    )?;
+    report.rule("partitions-disjoint", rules::partitions_disjoint(&table))?;
+    report.rule(
+        "app-fits-partitions",
+        rules::app_fits_partitions(firmware_size, &table, offsets.app),
+    )?;
+    let mut extra_parts: Vec<ExtraPart> = app_segments
+        .into_iter()
+        .map(|(offset, data)| ExtraPart {
+            file: format!("app_0x{:x}.bin", offset),
+            label: format!("App (0x{:x})", offset),
+            offset,
+            data,
+        })
+        .collect();
+    if let Some(second_app) = &opts.second_app {
+        extra_parts.extend(second_app::prepare(
+            second_app,
+            chip,
+            flash_size,
+            &table,
+            offsets.app,
+            checksums,
+        )?);
+    }
+    let nvs_partition = || {
+        table
+            .iter()
+            .find(|e| e.ty == 0x01 && e.subtype == 0x02)
+            .ok_or_else(|| PrepareError::PartitionNotFound {
+                name: String::from("nvs"),
+                available: table
+                    .iter()
+                    .map(|e| e.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            })
+    };
+    if opts.erase_nvs {
+        let nvs = nvs_partition()?;
+        status!(
+            "Erasing NVS: {} bytes of 0xFF at 0x{:x}",
+            nvs.size,
+            nvs.offset
+        );
+        extra_parts.push(ExtraPart {
+            file: String::from("nvs_blank.bin"),
+            label: format!("Blank NVS ({})", nvs.name),
+            offset: nvs.offset,
+            data: vec![0xff; nvs.size as usize],
+        });
+    }
+    if let Some(path) = &opts.nvs_csv {
+        let nvs = nvs_partition()?;
+        let csv = String::from_utf8(checksums.read(path)?).map_err(|_| PrepareError::NvsCsv {
+            path: path.clone(),
+            reason: String::from("not UTF-8 text"),
+        })?;
+        let data =
+            nvs_gen::generate(path, &csv, nvs.size).map_err(|reason| PrepareError::NvsCsv {
+                path: path.clone(),
+                reason,
+            })?;
+        status!(
+            "NVS: generated from {} for '{}' at 0x{:x}",
+            path.display(),
+            nvs.name,
+            nvs.offset
+        );
+        extra_parts.push(ExtraPart {
+            file: String::from("nvs.bin"),
+            label: format!("NVS ({})", nvs.name),
+            offset: nvs.offset,
+            data,
+        });
+    }
+    if let Some(dir) = &opts.data_dir {
+        extra_parts.push(filesystem::prepare(dir, opts.filesystem, &table)?);
+    }
+    for spec in &opts.extra_part_files {
+        let path = PathBuf::from(&spec.file);
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| spec.file.clone());
+        let file = format!("extra/{}", name);
+        if extra_parts.iter().any(|part| part.file == file) {
+            return Err(PrepareError::InvalidPart {
+                path,
+                reason: format!("another --extra-part is also named {}", name),
+            });
+        }
+        let data = checksums.read(&path)?;
+        extra_parts.push(ExtraPart {
+            file,
+            label: name,
+            offset: spec.offset,
+            data,
+        });
+    }
+
+    let total_size = bootloader_size
+        + partitions_size
+        + firmware_size
+        + extra_parts.iter().map(|p| p.data.len()).sum::<usize>();
+
+    status!("Firmware prepared:");
+    status!("  Chip: {}", chip_name);
+    status!("  Flash size: {}", opts.flash_size);
+    status!("  Bootloader: {} bytes", bootloader_size);
+    status!("  Partitions: {} bytes", partitions_size);
+    status!("  Firmware: {} bytes", firmware_size);
+    for part in &extra_parts {
+        status!("  {}: {} bytes", part.label, part.data.len());
+    }
+    status!("  Total: {} bytes", total_size);
+    tracing::info!(
+        chip = chip_name,
+        bootloader_size,
+        partitions_size,
+        firmware_size,
+        total_size,
+        "firmware prepared"
+    );
 
-    elf: PathBuf,
+    let mut written = vec![
+        layout::WrittenPart {
+            name: "bootloader",
+            offset: offsets.bootloader,
+            size: bootloader_size as u32,
+        },
+        layout::WrittenPart {
+            name: "partition table",
+            offset: offsets.partition_table,
+            size: partitions_size as u32,
+        },
+        layout::WrittenPart {
+            name: "firmware",
+            offset: offsets.app,
+            size: firmware_size as u32,
+        },
+    ];
+    written.extend(extra_parts.iter().map(|part| layout::WrittenPart {
+        name: &part.label,
+        offset: part.offset,
+        size: part.data.len() as u32,
+    }));
+    let overridden = opts.bootloader_offset.is_some()
+        || opts.partition_table_offset.is_some()
+        || opts.app_offset.is_some();
+    if overridden {
+        report.rule(
+            "offsets",
+            offsets::check(&offsets, &written, &table, flash_size_bytes)
+                .map(|_| String::from("custom offsets are aligned and inside their partitions")),
+        )?;
+    }
+    report.rule("parts-disjoint", rules::parts_disjoint(&written))?;
+    let layout = layout::compute(&written, &table, flash_size_bytes);
+
+    Ok(PartsData {
+        chip: chip_name.to_string(),
+        bootloader: bootloader_data,
+        partitions: partitions_data,
+        firmware: firmware_data,
+        total_size,
+        bootloader_size,
+        partitions_size,
+        firmware_size,
+        flash_size: flash_size_name,
+        partition_table_source,
+        bootloader_offset: offsets.bootloader,
+        partitions_offset: offsets.partition_table,
+        firmware_offset: offsets.app,
+        extra_parts,
+        layout,
+        elf_info,
+        app_version: fota::app_version(&firmware_data),
+        app_desc: None,
+        single_part: false,
+        app_only: false,
+        verified_inputs: Vec::new(),
+        board: None,
+        sbom,
+        elf_path: Some(elf_path.display().to_string()),
+        elf_mtime: std::fs::metadata(elf_path)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_secs()),
+        elf: (opts.serve_elf && !is_app_image).then(|| elf.to_vec()),
+        flash_mode: None,
+        flash_freq: None,
+        app_header,
+        min_chip_rev: None,
+    })
 }
 
-#[get("/bootloader.bin")]
-fn bootloader(data: &State<PartsData>) -> Vec<u8> {
-    data.bootloader.clone()
+/// Runs `prepare`, exiting with the error's exit code if it fails
+fn prepare_or_exit(opts: &ImageArgs) -> PartsData {
+    prepare(opts).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    })
 }
 
-#[get("/partitions.bin")]
-fn partitions(data: &State<PartsData>) -> Vec<u8> {
-    data.partitions.clone()
+fn main() -> Result<()> {
+    let cli = parse_cli();
+    QUIET.store(cli.quiet, Ordering::Relaxed);
+    JSON_LOGS.store(
+        cli.log_format == telemetry::LogFormat::Json,
+        Ordering::Relaxed,
+    );
+    telemetry::init(&cli.log_settings())?;
+    match cli.command {
+        Command::Serve(opts) => serve(opts),
+        Command::Verify(opts) => {
+            let data = prepare_or_exit(&opts.image);
+            if !verify::run(&opts, &data)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Erase(opts) => erase(opts),
+        Command::FlashAll(opts) => {
+            let build = PreparedBuild::new(prepare_or_exit(&opts.image));
+            let results = flash_all::run(&opts.batch.settings(), &build, opts.image.chip())?;
+            flash_all::print_report(&results, opts.output);
+            if !results.iter().all(|result| result.ok()) {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Export(opts) => export(opts),
+        Command::Manifest(opts) => print_manifest(&opts),
+        Command::Check(opts) => check(&opts),
+        Command::RecordSummary(opts) => record::summary(&opts.file),
+        Command::Compare(opts) => {
+            let comparison = compare::compare(&opts.old, &opts.new, opts.top)?;
+            compare::print_report(&comparison, opts.output);
+            Ok(())
+        }
+        Command::Completions(opts) => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(opts.shell, &mut command, name, &mut std::io::stdout());
+            Ok(())
+        }
+    }
 }
 
-#[get("/firmware.bin")]
-fn firmware(data: &State<PartsData>) -> Vec<u8> {
-    data.firmware.clone()
+/// The `check` subcommand: prepares the image collecting every rule's
+/// outcome instead of stopping at the first failure
+fn check(opts: &CheckArgs) -> Result<()> {
+    let mut report = rules::Report::collecting();
+    if let Err(e) = prepare_reporting(&opts.image, &mut report) {
+        // the image couldn't be built far enough for the remaining rules
+        report.results.push(rules::RuleResult {
+            rule: "prepare",
+            ok: false,
+            detail: e.to_string(),
+        });
+    }
+    rules::print(&report.results, opts.output);
+
+    let failed = report.results.iter().filter(|result| !result.ok).count();
+    if failed > 0 {
+        eprintln!("{} of {} rules failed", failed, report.results.len());
+        if !opts.warn_only {
+            std::process::exit(1);
+        }
+    }
+    Ok(())
 }
 
-#[derive(Serialize)]
-struct FirmwareInfo {
-    chip: String,
-    total_size: usize,
-    bootloader_size: usize,
-    partitions_size: usize,
-    firmware_size: usize,
-    flash_size: String,
+/// The `manifest` subcommand. `--as-served` prints the body of
+/// /manifest.json byte for byte, which `generate` and Rocket's compact
+/// serialization make; otherwise the `export` layout is printed readably.
+fn print_manifest(opts: &ManifestArgs) -> Result<()> {
+    print!("{}", manifest_output(opts, prepare_or_exit(&opts.image))?);
+    Ok(())
 }
 
-#[get("/info")]
-fn info(data: &State<PartsData>) -> Json<FirmwareInfo> {
-    Json(FirmwareInfo {
-        chip: data.chip.clone(),
-        total_size: data.total_size,
-        bootloader_size: data.bootloader_size,
-        partitions_size: data.partitions_size,
-        firmware_size: data.firmware_size,
-        flash_size: data.flash_size.clone(),
-    })
+/// What the `manifest` subcommand prints for the prepared `data`
+fn manifest_output(opts: &ManifestArgs, data: PartsData) -> Result<String> {
+    let base = opts.base_url.as_deref().unwrap_or_default();
+    if opts.as_served {
+        let build = PreparedBuild::new(data);
+        let manifest = web_manifest::generate(
+            &build.parts,
+            &web_manifest::title(&opts.name, build.parts.app_version.as_deref()),
+            &build.hash,
+            base,
+            opts.erase_policy,
+            None,
+            None,
+        );
+        Ok(json::to_string(&manifest)?)
+    } else {
+        let mut manifest = web_manifest::flat(&data, &opts.name, opts.erase_policy);
+        for part in manifest
+            .builds
+            .iter_mut()
+            .flat_map(|build| &mut build.parts)
+        {
+            part.path = format!("{}{}", base, part.path);
+        }
+        Ok(format!("{}\n", json::to_pretty_string(&manifest)?))
+    }
 }
 
-#[get("/")]
-fn index() -> content::RawHtml<&'static str> {
-    content::RawHtml(
-        r#"
-        <html>
-        <head>
-            <title>ESP Web Flasher</title>
-            <style>
-                body {
-                    font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;
-                    max-width: 900px;
-                    margin: 0 auto;
-                    padding: 20px;
-                    background-color: #f5f5f5;
-                    color: #333;
-                }
-                h1 {
-                    color: #2c3e50;
-                    margin-bottom: 30px;
-                    font-weight: 300;
-                    font-size: 2.5em;
-                }
-                h3 {
-                    color: #34495e;
-                    margin-bottom: 15px;
-                    font-weight: 400;
-                }
-                .main-container {
-                    background-color: white;
-                    border-radius: 10px;
-                    padding: 30px;
-                    box-shadow: 0 2px 10px rgba(0,0,0,0.1);
-                }
-                .info-box {
-                    background-color: #f8f9fa;
-                    border: 1px solid #e9ecef;
-                    border-radius: 8px;
-                    padding: 20px;
-                    margin: 20px 0;
-                }
-                .info-grid {
-                    display: grid;
-                    grid-template-columns: repeat(2, 1fr);
-                    gap: 15px;
-                }
-                .info-item {
-                    padding: 8px 0;
-                    border-bottom: 1px solid #eee;
-                }
-                .info-item:last-child {
-                    border-bottom: none;
-                }
-                .size-label {
-                    font-weight: 600;
-                    color: #666;
-                    display: inline-block;
-                    width: 140px;
-                }
-                .size-value {
-                    color: #2c3e50;
-                    font-weight: 400;
-                }
-                .total-row {
-                    margin-top: 15px;
-                    padding-top: 15px;
-                    border-top: 2px solid #dee2e6;
-                    font-size: 1.1em;
-                }
-                #console {
-                    background-color: #1e1e1e;
-                    color: #d4d4d4;
-                    font-family: 'Consolas', 'Monaco', 'Courier New', monospace;
-                    font-size: 13px;
-                    padding: 15px;
-                    border-radius: 8px;
-                    height: 250px;
-                    overflow-y: auto;
-                    margin-top: 20px;
-                    white-space: pre-wrap;
-                    line-height: 1.5;
-                    box-shadow: inset 0 2px 4px rgba(0,0,0,0.2);
-                }
-                .progress-info {
-                    background-color: #e3f2fd;
-                    border: 1px solid #90caf9;
-                    border-radius: 8px;
-                    padding: 15px;
-                    margin: 20px 0;
-                    font-family: monospace;
-                }
-                .progress-info div {
-                    margin: 5px 0;
-                }
-                esp-web-install-button {
-                    margin: 20px 0;
-                }
-                button {
-                    background-color: #3498db;
-                    color: white;
-                    border: none;
-                    padding: 10px 20px;
-                    border-radius: 5px;
-                    font-size: 14px;
-                    cursor: pointer;
-                    transition: background-color 0.3s;
-                }
-                button:hover {
-                    background-color: #2980b9;
-                }
-                button:active {
-                    transform: translateY(1px);
-                }
-                .button-group {
-                    margin-top: 20px;
-                    display: flex;
-                    gap: 10px;
+/// How the page is opened once the first listener is up
+#[derive(Clone)]
+struct OpenBrowser {
+    delay: Duration,
+    /// `--browser`, instead of the system's default
+    command: Option<String>,
+}
+
+impl OpenBrowser {
+    fn open(&self, url: &str) {
+        let result = match &self.command {
+            Some(command) => std::process::Command::new(command)
+                .arg(url)
+                .spawn()
+                .map(drop)
+                .map_err(|e| format!("Failed to run {}: {}", command, e)),
+            None => opener::open_browser(url).map_err(|e| e.to_string()),
+        };
+        if let Err(e) = result {
+            warning!("Failed to open {} in a browser: {}", url, e);
+        }
+    }
+}
+
+/// Prints where the server is listening once it is, and opens the browser
+/// there if asked to. Opening waits for liftoff, so the browser never races
+/// the bind and nothing opens if launching fails.
+fn announce_on_liftoff(open: Option<OpenBrowser>, qr: qr::QrMode) -> AdHoc {
+    AdHoc::on_liftoff("Announce listener", move |rocket| {
+        Box::pin(async move {
+            let config = rocket.config();
+            let scheme = if config.tls_enabled() {
+                "https"
+            } else {
+                "http"
+            };
+            let token = rocket
+                .state::<AuthSettings>()
+                .and_then(|auth| auth.token.as_deref())
+                .map(|token| format!("?token={}", token))
+                .unwrap_or_default();
+            let url = |host: IpAddr| {
+                format!(
+                    "{}://{}/{}",
+                    scheme,
+                    SocketAddr::new(host, config.port),
+                    token
+                )
+            };
+
+            let reachable = url(listen::reachable(config.address));
+            let mdns = rocket
+                .state::<mdns::Advertised>()
+                .filter(|advertised| advertised.port == config.port)
+                .map(|advertised| {
+                    format!(
+                        "{}://{}:{}/{}",
+                        scheme, advertised.host, advertised.port, token
+                    )
+                });
+            // Web Serial needs a secure context, which plain HTTP only is on
+            // localhost, so the local browser gets a loopback URL
+            let (local, interfaces) = if config.address.is_unspecified() {
+                let loopback = if config.address.is_ipv4() {
+                    IpAddr::from([127, 0, 0, 1])
+                } else {
+                    IpAddr::from(std::net::Ipv6Addr::LOCALHOST)
+                };
+                let interfaces: Vec<(String, String)> = listen::interfaces(config.address)
+                    .into_iter()
+                    .map(|(name, ip)| (name, url(ip)))
+                    .collect();
+                (url(loopback), interfaces)
+            } else {
+                (reachable.clone(), Vec::new())
+            };
+
+            if JSON_LOGS.load(Ordering::Relaxed) {
+                let interfaces: Vec<String> = interfaces
+                    .iter()
+                    .map(|(name, url)| format!("{}={}", name, url))
+                    .collect();
+                tracing::info!(
+                    url = %reachable,
+                    local = %local,
+                    mdns = mdns.as_deref(),
+                    interfaces = %interfaces.join(" "),
+                    "listening"
+                );
+            } else {
+                // even with --quiet, but off stdout when that's for data
+                let say = |line: String| {
+                    if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+                        eprintln!("{}", line)
+                    } else {
+                        println!("{}", line)
+                    }
+                };
+                say(format!("Server is available at: {}", reachable));
+                if qr.shows(config.address) {
+                    if let Some(code) = qr::render(&reachable) {
+                        say(code);
+                    }
                 }
-                .note {
-                    background-color: #fff3cd;
-                    border: 1px solid #ffeaa7;
-                    color: #856404;
-                    padding: 12px;
-                    border-radius: 5px;
-                    margin: 15px 0;
-                    font-size: 0.9em;
+                if let Some(mdns) = &mdns {
+                    say(format!("  via mDNS: {}", mdns));
                 }
-                .error-message {
-                    background-color: #f8d7da;
-                    border: 1px solid #f5c6cb;
-                    color: #721c24;
-                    padding: 20px;
-                    border-radius: 8px;
-                    text-align: center;
+                if local != reachable {
+                    say(format!("  on this machine: {}", local));
                 }
-            </style>
-        </head>
-        <body>
-            <h1>ESP Web Flasher</h1>
-
-            <div id="main" class="main-container" style="display: none;">
-                <div id="firmwareInfo" class="info-box" style="display: none;">
-                    <h3>Firmware Information</h3>
-                    <div class="info-grid">
-                        <div>
-                            <div class="info-item">
-                                <span class="size-label">Chip:</span>
-                                <span id="chipType" class="size-value"></span>
-                            </div>
-                            <div class="info-item">
-                                <span class="size-label">Flash Size:</span>
-                                <span id="flashSize" class="size-value"></span>
-                            </div>
-                        </div>
-                        <div>
-                            <div class="info-item">
-                                <span class="size-label">Bootloader:</span>
-                                <span id="bootloaderSize" class="size-value"></span>
-                            </div>
-                            <div class="info-item">
-                                <span class="size-label">Partitions:</span>
-                                <span id="partitionsSize" class="size-value"></span>
-                            </div>
-                            <div class="info-item">
-                                <span class="size-label">Firmware:</span>
-                                <span id="firmwareSize" class="size-value"></span>
-                            </div>
-                        </div>
-                    </div>
-                    <div class="total-row">
-                        <span class="size-label">Total Size:</span>
-                        <span id="totalSize" class="size-value"></span>
-                    </div>
-                </div>
-
-                <script type="module" src="https://unpkg.com/esp-web-tools@9.4.3/dist/web/install-button.js?module">
-                </script>
-                <esp-web-install-button id="installButton" manifest="manifest.json"></esp-web-install-button>
-                
-                <div class="note">
-                    <strong>Note:</strong> Make sure to close any applications using your device's COM port (e.g., Serial Monitor)
-                </div>
-                
-                <div class="progress-info" id="progressInfo" style="display: none;">
-                    <div><strong>Progress:</strong> <span id="progressPercent">0%</span></div>
-                    <div><strong>Uploaded:</strong> <span id="uploadedBytes">0</span> / <span id="totalBytes">0</span> bytes</div>
-                </div>
-
-                <h3>Console Output</h3>
-                <div id="console"></div>
-                
-                <div class="button-group">
-                    <button onclick="downloadLogs()">Download Logs</button>
-                    <button onclick="clearLogs()">Clear Logs</button>
-                </div>
-            </div>
-            
-            <div id="notSupported" class="main-container error-message" style="display: none;">
-                <h2>Browser Not Supported</h2>
-                <p>Your browser does not support the Web Serial API.</p>
-                <p>Please use Chrome or Microsoft Edge to flash your ESP device.</p>
-            </div>
-
-            <script>
-                function formatBytes(bytes) {
-                    if (bytes === 0) return '0 Bytes';
-                    const k = 1024;
-                    const sizes = ['Bytes', 'KB', 'MB', 'GB'];
-                    const i = Math.floor(Math.log(bytes) / Math.log(k));
-                    return parseFloat((bytes / Math.pow(k, i)).toFixed(2)) + ' ' + sizes[i];
+                for (name, url) in &interfaces {
+                    say(format!("  on {}: {}", name, url));
                 }
+            }
+            if config.address.is_unspecified() && !config.tls_enabled() {
+                warning!(
+                    "browsers only allow Web Serial over HTTPS away from localhost, \
+                     so other machines can open these URLs but not flash. Serve HTTPS with \
+                     --tls self-signed or --tls-cert/--tls-key."
+                );
+            }
 
-                function log(message, type = 'info') {
-                    const console = document.getElementById('console');
-                    const timestamp = new Date().toLocaleTimeString();
-                    const logEntry = document.createElement('div');
-                    
-                    let color = '#d4d4d4';
-                    if (type === 'error') color = '#f48771';
-                    else if (type === 'success') color = '#98c379';
-                    else if (type === 'warning') color = '#e5c07b';
-                    else if (type === 'progress') color = '#61afef';
-                    
-                    logEntry.style.color = color;
-                    logEntry.textContent = `[${timestamp}] ${message}`;
-                    console.appendChild(logEntry);
-                    console.scrollTop = console.scrollHeight;
+            if let Some(open) = open {
+                if !open.delay.is_zero() {
+                    rocket::tokio::time::sleep(open.delay).await;
                 }
+                open.open(&local);
+            }
+        })
+    })
+}
 
-                function downloadLogs() {
-                    const logs = document.getElementById('console').textContent;
-                    const blob = new Blob([logs], { type: 'text/plain' });
-                    const url = window.URL.createObjectURL(blob);
-                    const a = document.createElement('a');
-                    a.href = url;
-                    a.download = `esp-flash-logs-${new Date().toISOString().slice(0, 19).replace(/:/g, '-')}.txt`;
-                    a.click();
-                    window.URL.revokeObjectURL(url);
-                }
+/// Everything the routes need, shared by the Rockets of all listeners
+#[derive(Clone)]
+struct ServerState {
+    page: PageSettings,
+    admin: AdminSettings,
+    auth: AuthSettings,
+    labels: Labels,
+    fota: fota::FotaSettings,
+    estimate: estimate::EstimateSettings,
+    merge: merged_bin::MergeSettings,
+    tls: tls::Pinning,
+    recorder: Option<record::Recorder>,
+    /// `--log-dir`
+    client_logs: Option<client_logs::ClientLogs>,
+    /// `None` in monitor-only mode
+    builds: Option<Builds>,
+    monitor: Option<Monitor>,
+    local: Option<LocalDevice>,
+    batch: flash_all::BatchSettings,
+    /// `None` in monitor-only mode
+    rebuilder: Option<upload::Rebuilder>,
+    delta: Option<DeltaOta>,
+    sessions: telemetry::Sessions,
+    stats: stats::ServeStats,
+    idle: Option<idle::IdleTimeout>,
+    mdns: Option<mdns::Advertised>,
+}
 
-                function clearLogs() {
-                    document.getElementById('console').innerHTML = '';
-                    log('Logs cleared', 'info');
-                }
+fn build_rocket(
+    figment: Figment,
+    state: &ServerState,
+    open: Option<OpenBrowser>,
+    qr: qr::QrMode,
+) -> Rocket<Build> {
+    let mut rocket = rocket::custom(figment)
+        .attach(announce_on_liftoff(open, qr))
+        .attach(state.stats.clone())
+        .mount("/", routes![theme_css, esp_web_tools, logo, health])
+        .manage(state.page.clone())
+        .manage(state.admin.clone())
+        .manage(state.auth.clone())
+        .register("/", catchers![auth::unauthorized])
+        .manage(state.labels.clone())
+        .manage(state.fota.clone())
+        .manage(state.estimate.clone())
+        .manage(state.merge.clone())
+        .manage(state.tls.clone())
+        .manage(state.sessions.clone())
+        .manage(state.stats.clone());
 
-                async function fetchFirmwareInfo() {
-                    try {
-                        const response = await fetch('/info');
-                        const info = await response.json();
-                        
-                        document.getElementById('chipType').textContent = info.chip;
-                        document.getElementById('flashSize').textContent = info.flash_size;
-                        document.getElementById('bootloaderSize').textContent = formatBytes(info.bootloader_size);
-                        document.getElementById('partitionsSize').textContent = formatBytes(info.partitions_size);
-                        document.getElementById('firmwareSize').textContent = formatBytes(info.firmware_size);
-                        document.getElementById('totalSize').textContent = formatBytes(info.total_size);
-                        document.getElementById('firmwareInfo').style.display = 'block';
-                        
-                        log('Firmware information loaded', 'success');
-                        log(`Total size to flash: ${formatBytes(info.total_size)}`, 'info');
-                    } catch (error) {
-                        log('Failed to fetch firmware information: ' + error, 'error');
-                    }
-                }
+    if state.tls.cert.is_some() {
+        rocket = rocket.mount("/", routes![cert_fingerprint, cert_pem]);
+    }
 
-                if (navigator.serial) {
-                    document.getElementById("notSupported").style.display = 'none';
-                    document.getElementById("main").style.display = 'block';
-                    
-                    // Fetch firmware info when page loads
-                    fetchFirmwareInfo();
-                    
-                    // Listen for esp-web-tools events
-                    const installButton = document.getElementById('installButton');
-                    
-                    installButton.addEventListener('state-changed', (e) => {
-                        const state = e.detail;
-                        log(`State changed: ${state.state}`);
-                        
-                        if (state.state === 'initializing') {
-                            log('Initializing connection...');
-                            if (state.details) {
-                                log(`Port: ${state.details.port || 'Auto-detecting'}`);
-                            }
-                        } else if (state.state === 'manifest') {
-                            log('Loading manifest...');
-                        } else if (state.state === 'preparing') {
-                            log('Preparing installation...');
-                            if (state.chipFamily) {
-                                log(`Detected chip family: ${state.chipFamily}`);
-                            }
-                        } else if (state.state === 'erasing') {
-                            log('Erasing device...', 'warning');
-                        } else if (state.state === 'writing') {
-                            log('Writing firmware...', 'progress');
-                            document.getElementById('progressInfo').style.display = 'block';
-                            
-                            // Update progress with byte information if available
-                            if (state.details) {
-                                const { bytesWritten, bytesTotal, percentage } = state.details;
-                                document.getElementById('progressPercent').textContent = Math.round(percentage) + '%';
-                                document.getElementById('uploadedBytes').textContent = formatBytes(bytesWritten);
-                                document.getElementById('totalBytes').textContent = formatBytes(bytesTotal);
-                                
-                                // Log progress every 10%
-                                if (percentage % 10 === 0) {
-                                    log(`Progress: ${Math.round(percentage)}% - ${formatBytes(bytesWritten)} / ${formatBytes(bytesTotal)}`, 'progress');
-                                }
-                            }
-                        } else if (state.state === 'finished') {
-                            log('Installation complete!', 'success');
-                            log('Device will restart with new firmware.', 'success');
-                        } else if (state.state === 'error') {
-                            log(`Error: ${state.message}`, 'error');
-                            if (state.details) {
-                                log(`Error details: ${JSON.stringify(state.details)}`, 'error');
-                            }
-                        }
-                    });
-                    
-                } else {
-                    document.getElementById("notSupported").style.display = 'block';
-                    document.getElementById("main").style.display = 'none';
-                }
-            </script>
+    if let Some(recorder) = &state.recorder {
+        rocket = rocket.attach(recorder.clone());
+    }
 
-        </body>
-        </html>
-        "#,
-    )
+    rocket = match &state.builds {
+        Some(builds) => rocket
+            .mount(
+                "/",
+                routes![
+                    index,
+                    manifest,
+                    end_session,
+                    bootloader,
+                    partitions,
+                    partitions_csv,
+                    partitions_json,
+                    firmware,
+                    merged,
+                    checksums_txt,
+                    file_sha256,
+                    artifacts_zip,
+                    pinned_part,
+                    build_list,
+                    build_manifest,
+                    extra_part,
+                    extra_file,
+                    info,
+                    fota_json,
+                    build_status,
+                    layout_json,
+                    app_info,
+                    elf_info_json,
+                    sbom_json,
+                    firmware_elf,
+                    firmware_events,
+                    sbom_cdx_json
+                ],
+            )
+            .manage(builds.clone()),
+        None => rocket.mount("/", routes![monitor_page, no_image]),
+    };
+
+    if state
+        .builds
+        .as_ref()
+        .map_or(false, |b| !b.names().is_empty())
+    {
+        rocket = rocket.register("/", catchers![unknown_build]);
+    }
+
+    if let Some(logs) = &state.client_logs {
+        rocket = rocket.mount("/", routes![client_log]).manage(logs.clone());
+    }
+
+    if state.admin.shutdown {
+        rocket = rocket.mount("/", routes![remote_shutdown]);
+    }
+
+    if state.page.once && state.builds.is_some() {
+        rocket = rocket.mount("/", routes![install_finished]);
+    }
+
+    if let (Some(delta), Some(_)) = (&state.delta, &state.builds) {
+        rocket = rocket
+            .mount("/", routes![ota_patch, ota_patch_json])
+            .manage(delta.clone());
+    }
+
+    rocket = match &state.rebuilder {
+        Some(rebuilder) => rocket
+            .mount(
+                "/",
+                routes![
+                    upload_partition_table,
+                    upload_bootloader,
+                    upload_elf,
+                    reload
+                ],
+            )
+            .manage(rebuilder.clone()),
+        None => rocket,
+    };
+
+    if let Some(monitor) = &state.monitor {
+        rocket = rocket
+            .mount("/", routes![monitor_events])
+            .manage(monitor.clone());
+    }
+
+    if let Some(advertised) = &state.mdns {
+        rocket = rocket.manage(advertised.clone());
+    }
+
+    if let Some(local) = &state.local {
+        rocket = rocket
+            .mount("/", routes![erase_local])
+            .manage(local.clone());
+        if state.builds.is_some() {
+            rocket = rocket
+                .mount("/", routes![flash_all_devices])
+                .manage(state.batch.clone());
+        }
+    }
+
+    rocket
 }
 
-#[get("/manifest.json")]
-fn manifest() -> content::RawJson<&'static str> {
-    content::RawJson(
-        r#"
-        {
-            "name": "ESP Application",
-            "new_install_prompt_erase": true,
-            "builds": [
-                {
-                "chipFamily": "ESP32",
-                "parts": [
-                    {
-                    "path": "bootloader.bin",
-                    "offset": 4096
-                    },
-                    {
-                    "path": "partitions.bin",
-                    "offset": 32768
-                    },
-                    {
-                    "path": "firmware.bin",
-                    "offset": 65536
-                    }
-                ]
-                },
-                {
-                "chipFamily": "ESP32-C3",
-                "parts": [
-                    {
-                    "path": "bootloader.bin",
-                    "offset": 0
-                    },
-                    {
-                    "path": "partitions.bin",
-                    "offset": 32768
-                    },
-                    {
-                    "path": "firmware.bin",
-                    "offset": 65536
-                    }
-                ]
-                },
-                {
-                "chipFamily": "ESP32-S2",
-                "parts": [
-                    {
-                    "path": "bootloader.bin",
-                    "offset": 4096
-                    },
-                    {
-                    "path": "partitions.bin",
-                    "offset": 32768
-                    },
-                    {
-                    "path": "firmware.bin",
-                    "offset": 65536
-                    }
-                ]
-                },
-                {
-                "chipFamily": "ESP32-S3",
-                "parts": [
-                    {
-                    "path": "bootloader.bin",
-                    "offset": 0
-                    },
-                    {
-                    "path": "partitions.bin",
-                    "offset": 32768
-                    },
-                    {
-                    "path": "firmware.bin",
-                    "offset": 65536
-                    }
-                ]
-                }
-            ]
+fn erase(opts: EraseArgs) -> Result<()> {
+    let _claim = device::claim(&opts.port, "web-flash erase")?;
+
+    if !opts.yes {
+        print!(
+            "This erases the entire flash of the device on {}. Continue? [y/N] ",
+            opts.port
+        );
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Aborted");
+            return Ok(());
         }
-        "#,
+    }
+
+    device::erase(&opts.port, opts.chip, |msg| println!("{}", msg))
+}
+
+fn export(opts: ExportArgs) -> Result<()> {
+    let data = prepare_or_exit(&opts.image);
+    write_export(
+        data,
+        &opts.out_dir,
+        &opts.name,
+        opts.erase_policy,
+        opts.merged_size,
+        opts.force,
     )
 }
 
-struct PartsData {
-    chip: String,
-    bootloader: Vec<u8>,
-    partitions: Vec<u8>,
-    firmware: Vec<u8>,
-    total_size: usize,
-    bootloader_size: usize,
-    partitions_size: usize,
-    firmware_size: usize,
-    flash_size: String,
+/// Writes the prepared parts, a manifest referring to them and merged.bin
+/// into `dir`, ready for any static file server. Existing files are only
+/// replaced with `force`.
+fn write_export(
+    data: PartsData,
+    dir: &Path,
+    name: &str,
+    erase: ErasePolicy,
+    merged_size: merged_bin::MergedSize,
+    force: bool,
+) -> Result<()> {
+    let build = PreparedBuild::new(data);
+    let manifest = web_manifest::flat(&build.parts, name, erase);
+    let parts: Vec<&web_manifest::Part> = manifest
+        .builds
+        .iter()
+        .flat_map(|build| &build.parts)
+        .collect();
+
+    let files: Vec<PathBuf> = parts
+        .iter()
+        .map(|part| part.path.as_str())
+        .chain(["manifest.json", "merged.bin"])
+        .map(|file| dir.join(file))
+        .collect();
+    let existing: Vec<String> = files
+        .iter()
+        .filter(|path| path.exists())
+        .map(|path| path.display().to_string())
+        .collect();
+    if !existing.is_empty() && !force {
+        bail!(
+            "Refusing to overwrite {} (pass --force to replace them)",
+            existing.join(", ")
+        );
+    }
+
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let mut total = 0;
+    for (part, path) in parts.iter().zip(&files) {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let data = build
+            .part(&part.path)
+            .with_context(|| format!("No data prepared for {}", part.path))?;
+        std::fs::write(path, data)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        println!(
+            "  {:<20} {:>9} bytes at 0x{:x}",
+            part.path,
+            data.len(),
+            part.offset
+        );
+        total += data.len();
+    }
+
+    let path = dir.join("manifest.json");
+    std::fs::write(&path, json::to_pretty_string(&manifest)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("  {:<20} {:>9} bytes to flash", "total", total);
+
+    let merged = merged_bin::build(&build.parts, merged_size);
+    let path = dir.join("merged.bin");
+    std::fs::write(&path, &merged)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("  {:<20} {:>9} bytes", "merged.bin", merged.len());
+    println!(
+        "Exported {} parts, manifest.json and merged.bin to {}",
+        parts.len(),
+        dir.display()
+    );
+    Ok(())
 }
 
-fn prepare() -> Result<PartsData> {
-    let opts = Args::parse();
+fn serve(opts: Args) -> Result<()> {
+    let erase_policy = if opts.no_erase_prompt {
+        ErasePolicy::Keep
+    } else {
+        opts.erase_policy
+    };
+    let default_image = match opts.named_builds.first() {
+        Some(first) if opts.image.missing_elf() => ImageArgs {
+            elf: Some(first.elf.clone()),
+            ..opts.image.clone()
+        },
+        _ => opts.image.clone(),
+    };
+    let (data, monitor) = if opts.monitor_only {
+        let symbols = if opts.image.elf.is_some() || opts.image.cargo {
+            Some(symbols::Symbols::from_path(&opts.image.elf()?)?)
+        } else {
+            None
+        };
+        let port = opts.local_port.as_deref().unwrap_or_default();
+        status!("Monitoring {} at {} baud", port, opts.monitor_baud);
 
-    // Display file information
-    let elf_metadata = std::fs::metadata(&opts.elf)?;
-    println!("ELF file: {}", opts.elf.display());
-    println!("  Size: {} bytes", elf_metadata.len());
+        (
+            None,
+            Some(Monitor::start(port, opts.monitor_baud, symbols)?),
+        )
+    } else {
+        (Some(prepare_or_exit(&default_image)), None)
+    };
+    let app_version = data.as_ref().and_then(|data| data.app_version.as_deref());
+    if let (Some(given), Some(embedded)) = (&opts.firmware_version, app_version) {
+        if given != embedded {
+            warning!(
+                "--firmware-version {} differs from the app descriptor's version {}",
+                given,
+                embedded
+            );
+        }
+    }
+
+    let mut named = BTreeMap::new();
+    for build in &opts.named_builds {
+        status!("\nBuild '{}':", build.name);
+        let image = ImageArgs {
+            elf: Some(build.elf.clone()),
+            ..opts.image.clone()
+        };
+        if named
+            .insert(
+                build.name.clone(),
+                PreparedBuild::new(prepare_or_exit(&image)),
+            )
+            .is_some()
+        {
+            bail!("--build {} is given more than once", build.name);
+        }
+    }
+
+    if opts.dry_run {
+        // conflicts with --monitor-only, so there's always an image
+        let data = data.context("Nothing to check without an image")?;
+        if opts.summary_json {
+            summary::Summary::new(None, &PreparedBuild::new(data)).print();
+        }
+        status!("\nDry run: the image is flashable, not starting the server");
+        return Ok(());
+    }
 
-    let elf = std::fs::read(opts.elf)?;
+    if let Some(dir) = &opts.output_dir {
+        // conflicts with --monitor-only, so there's always an image
+        let data = data.context("Nothing to export without an image")?;
+        let name = opts.name.as_deref().unwrap_or(web_manifest::DEFAULT_NAME);
+        return write_export(data, dir, name, erase_policy, opts.merged_size, opts.force);
+    }
 
-    let p = if let Some(p) = &opts.partition_table {
-        Some(PartitionTable::try_from_bytes(std::fs::read(p)?)?)
+    let lang = if i18n::find(&opts.lang).is_some() {
+        opts.lang.clone()
     } else {
-        None
+        warning!(
+            "Unknown language '{}', falling back to English (available: {})",
+            opts.lang,
+            i18n::available().join(", ")
+        );
+        i18n::ENGLISH.code.to_string()
     };
 
-    let b = if let Some(p) = &opts.bootloader {
-        Some(std::fs::read(p)?)
+    let delta = match (&opts.ota_base, &data) {
+        (Some(base), Some(data)) => Some(DeltaOta::generate(base, &data.firmware)?),
+        _ => None,
+    };
+
+    let retention = prepared_build::Retention {
+        count: opts.keep_builds.max(1),
+        budget: opts.builds_budget * 1024 * 1024,
+    };
+
+    let labels = Labels::new(&opts.labels)?;
+    for (key, value) in &labels.0 {
+        status!("Label: {}={}", key, value);
+    }
+
+    let recorder = match &opts.record {
+        Some(path) => Some(record::Recorder::create(path)?),
+        None => None,
+    };
+    let client_logs = opts
+        .log_dir
+        .as_deref()
+        .map(client_logs::ClientLogs::create)
+        .transpose()?;
+
+    // TLS listeners without any certificate share a persistent self-signed one
+    let needs_self_signed =
+        opts.tls_cert.is_none() && opts.listen.iter().any(|l| l.tls && l.cert.is_none());
+    let (tls_cert, tls_key) = if opts.tls == Some(tls::TlsMode::SelfSigned) {
+        let (cert, key) = tls::throwaway(&tls_hosts(&opts))?;
+        (Some(cert), Some(key))
+    } else if needs_self_signed {
+        let dir = config_dir().context(
+            "Neither HOME nor XDG_CONFIG_HOME is set to keep a self-signed certificate in",
+        )?;
+        let (cert, key) = tls::self_signed(&dir, opts.tls_regenerate)?;
+        (Some(cert), Some(key))
     } else {
-        None
+        (opts.tls_cert.clone(), opts.tls_key.clone())
+    };
+
+    let figments = if opts.listen.is_empty() {
+        let mut figment = rocket::Config::figment();
+        if let Some(address) = opts.address {
+            figment = figment.merge(("address", address));
+        }
+        if opts.lan {
+            figment = figment.merge(("address", IpAddr::from([0, 0, 0, 0])));
+        }
+        if let Some(port) = opts.port {
+            figment = figment.merge(("port", port));
+        }
+        match (&tls_cert, &tls_key) {
+            (Some(cert), Some(key)) => {
+                figment = figment.merge(("tls.certs", cert)).merge(("tls.key", key));
+            }
+            (None, None) => {}
+            _ => bail!("--tls-cert and --tls-key are only used together"),
+        }
+        vec![figment]
+    } else {
+        opts.listen
+            .iter()
+            .map(|listener| listener.figment(tls_cert.as_ref(), tls_key.as_ref()))
+            .collect::<Result<Vec<_>>>()?
     };
 
-    let flash_size = match opts.flash_size.to_uppercase().as_str() {
-        "2MB" => FlashSize::Flash2Mb,
-        "4MB" => FlashSize::Flash4Mb,
-        "8MB" => FlashSize::Flash8Mb,
-        "16MB" => FlashSize::Flash16Mb,
-        _ => {
-            eprintln!("Warning: Unknown flash size '{}', defaulting to 4MB", opts.flash_size);
-            FlashSize::Flash4Mb
+    for figment in &figments {
+        let config: rocket::Config = figment.extract().context("Invalid Rocket configuration")?;
+        // --lan asks for every interface, and warns once listening instead
+        if config.address.is_unspecified()
+            && !config.tls_enabled()
+            && !opts.allow_plain_http
+            && !opts.lan
+        {
+            bail!(
+                "Refusing to serve plain HTTP on {}: browsers only allow Web Serial in a secure \
+                 context, so flashing wouldn't work from other machines. Use --tls self-signed, \
+                 --tls-cert/--tls-key or a ,tls listener, or pass --allow-plain-http",
+                SocketAddr::new(config.address, config.port)
+            );
+        }
+    }
+
+    // the first listener is advertised, like it's the one opened
+    let mut advertisement = None;
+    if opts.mdns {
+        let config: rocket::Config = figments[0].extract()?;
+        if config.address.is_loopback() {
+            warning!(
+                "not advertising via mDNS, the server only listens on {}; \
+                 use --lan or --address to serve other machines",
+                config.address
+            );
+        } else {
+            match mdns::advertise(
+                &opts.mdns_name,
+                config.address,
+                config.port,
+                config.tls_enabled(),
+            ) {
+                Ok(registered) => advertisement = Some(registered),
+                Err(e) => warning!("Failed to advertise via mDNS: {}", e),
+            }
         }
+    }
+    let (_advertisement, advertised) = advertisement.unzip();
+
+    // devices pin the certificate of the first TLS listener
+    let mut pinned = None;
+    if let (true, Some(path)) = (opts.listen.is_empty(), &tls_cert) {
+        let cert = tls::CertInfo::load(path)?;
+        status!("TLS certificate: SHA-256 {}", cert.sha256);
+        pinned = Some(cert);
+    }
+    for listener in opts.listen.iter().filter(|l| l.tls) {
+        if let Some(path) = listener.cert.as_ref().or(tls_cert.as_ref()) {
+            let cert = tls::CertInfo::load(path)?;
+            status!(
+                "TLS certificate of {}: SHA-256 {}",
+                listener.addr,
+                cert.sha256
+            );
+            pinned.get_or_insert(cert);
+        }
+    }
+
+    let cdn = opts.cdn || !vendor::bundled();
+    if !opts.cdn && cdn {
+        warning!(
+            "this binary was built without esp-web-tools \
+             (scripts/vendor-esp-web-tools.sh), the page loads it from unpkg.com"
+        );
+    }
+
+    let state = ServerState {
+        page: PageSettings {
+            lang,
+            theme_css: theme::stylesheet(opts.theme, opts.accent_color.as_deref()),
+            erase_policy,
+            name: opts.name.clone(),
+            title: opts.title.clone(),
+            logo: opts.logo.as_deref().map(logo::Logo::load).transpose()?,
+            erase_locked: opts.no_erase_prompt,
+            once: opts.once,
+            admin: opts.admin_token.is_some() && !opts.monitor_only,
+            client_logs: opts.log_dir.is_some(),
+            index: opts
+                .index
+                .as_deref()
+                .map(index_template::IndexTemplate::load)
+                .transpose()?,
+            esp_web_tools: vendor::install_button_url(cdn),
+        },
+        admin: AdminSettings {
+            token: opts.admin_token.clone(),
+            shutdown: !opts.no_admin,
+        },
+        auth: AuthSettings {
+            token: match &opts.auth_token {
+                Some(Some(token)) => Some(token.clone()),
+                Some(None) => Some(
+                    auth::generate()
+                        .map_err(|e| anyhow::anyhow!("Failed to generate a token: {}", e))?,
+                ),
+                None => None,
+            },
+        },
+        labels,
+        fota: fota::FotaSettings {
+            ty: opts.fota_type.clone(),
+            version: opts.firmware_version.clone(),
+            public_url: opts.public_url.clone(),
+        },
+        estimate: estimate::EstimateSettings {
+            assumed_baud: opts.assumed_baud,
+        },
+        merge: merged_bin::MergeSettings {
+            size: opts.merged_size,
+        },
+        tls: tls::Pinning { cert: pinned },
+        recorder,
+        client_logs,
+        builds: data.map(|data| Builds::new(PreparedBuild::new(data), retention).with_named(named)),
+        monitor,
+        local: match (opts.enable_local_flash, &opts.local_port) {
+            (true, Some(port)) => Some(LocalDevice {
+                port: port.clone(),
+                chip: opts.image.chip(),
+            }),
+            _ => None,
+        },
+        batch: opts.batch.settings(),
+        rebuilder: if opts.monitor_only {
+            None
+        } else {
+            Some(upload::Rebuilder::new(&default_image)?)
+        },
+        delta,
+        sessions: telemetry::Sessions::default(),
+        stats: stats::ServeStats::default(),
+        idle: (opts.idle_timeout > 0).then(|| idle::IdleTimeout::new(opts.idle_timeout)),
+        mdns: advertised,
     };
 
-    let firmware = FirmwareImageBuilder::new(&elf)
-        .flash_size(Some(flash_size))
-        .build()?;
-
-    let chip = opts.chip;
-    let chip_name = match chip {
-        Chip::Esp32 => "ESP32",
-        Chip::Esp32c3 => "ESP32-C3",
-        Chip::Esp32s2 => "ESP32-S2",
-        Chip::Esp32s3 => "ESP32-S3",
-        Chip::Esp8266 => "ESP8266",
+    // kept alive while the server runs
+    let _watcher = match (&state.rebuilder, &state.builds) {
+        (Some(rebuilder), Some(builds)) if opts.watch => {
+            Some(watch::spawn(rebuilder.clone(), builds.clone())?)
+        }
+        _ => None,
+    };
+    let _template_watcher = match &state.page.index {
+        Some(template) if opts.watch => Some(watch::template(template.clone())?),
+        _ => None,
     };
 
-    let image = chip.get_flash_image(&firmware, b, p, None, None)?;
-    let parts: Vec<_> = image.flash_segments().collect();
-    let bootloader = &parts[0];
-    let partitions = &parts[1];
-    let app = &parts[2];
+    status!("\nStarting web server...");
 
-    let bootloader_data = bootloader.data.to_vec();
-    let partitions_data = partitions.data.to_vec();
-    let firmware_data = app.data.to_vec();
-    
-    let bootloader_size = bootloader_data.len();
-    let partitions_size = partitions_data.len();
-    let firmware_size = firmware_data.len();
-    let total_size = bootloader_size + partitions_size + firmware_size;
+    let open = (!opts.no_open).then(|| OpenBrowser {
+        delay: Duration::from_secs_f32(opts.open_delay.max(0.0)),
+        command: opts.browser.clone(),
+    });
+    async_main(async move {
+        // the OTLP exporter runs on Rocket's runtime
+        if let Some(endpoint) = &opts.otlp_endpoint {
+            telemetry::export(endpoint)?;
+        }
 
-    println!("Firmware prepared:");
-    println!("  Chip: {}", chip_name);
-    println!("  Flash size: {}", opts.flash_size);
-    println!("  Bootloader: {} bytes", bootloader_size);
-    println!("  Partitions: {} bytes", partitions_size);
-    println!("  Firmware: {} bytes", firmware_size);
-    println!("  Total: {} bytes", total_size);
+        // one Rocket per listener, all sharing the same state
+        let mut ignited = Vec::new();
+        for (i, figment) in figments.into_iter().enumerate() {
+            let open = open.clone().filter(|_| i == 0);
+            // one QR code, of the first listener
+            let qr = if opts.no_qr || opts.summary_json || i > 0 {
+                qr::QrMode::Never
+            } else if opts.qr {
+                qr::QrMode::Always
+            } else {
+                qr::QrMode::Auto
+            };
+            let mut rocket = build_rocket(figment, &state, open, qr);
+            if i == 0 && opts.selftest {
+                rocket = rocket.attach(selftest::on_liftoff(state.clone(), opts.output));
+            }
+            if i == 0 && opts.summary_json {
+                rocket = rocket.attach(summary::on_liftoff(state.clone()));
+            }
+            if let Some(idle) = &state.idle {
+                // one countdown, on the first listener
+                let idle = idle.clone();
+                rocket = rocket.attach(if i == 0 { idle.with_countdown() } else { idle });
+            }
+            ignited.push(rocket.ignite().await.expect("Problem launching server"));
+        }
 
-    Ok(PartsData {
-        chip: chip_name.to_string(),
-        bootloader: bootloader_data,
-        partitions: partitions_data,
-        firmware: firmware_data,
-        total_size,
-        bootloader_size,
-        partitions_size,
-        firmware_size,
-        flash_size: opts.flash_size.clone(),
+        let shutdowns: Vec<Shutdown> = ignited.iter().map(|rocket| rocket.shutdown()).collect();
+        let launches = ignited.into_iter().map(|rocket| {
+            let shutdowns = shutdowns.clone();
+            async move {
+                let result = rocket.launch().await;
+                // a listener stopping (Ctrl-C or a failure to bind) stops all of them
+                for shutdown in shutdowns {
+                    shutdown.notify();
+                }
+                result
+            }
+        });
+        for result in rocket::futures::future::join_all(launches).await {
+            result.expect("Problem launching server");
+        }
+        if JSON_LOGS.load(Ordering::Relaxed) {
+            state.stats.log();
+        } else {
+            status!("{}", state.stats.summary());
+        }
+        if let Some(rebuilder) = &state.rebuilder {
+            rebuilder.remove_uploads();
+        }
+        telemetry::shutdown();
+        Ok(())
     })
 }
 
-fn main() -> Result<()> {
-    let data = prepare()?;
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
 
-    println!("\nStarting web server...");
-    println!("Server will be available at: http://127.0.0.1:8000/");
-    println!("Opening browser automatically in 1 second...\n");
+    use rocket::local::asynchronous::Client;
 
-    std::thread::spawn(|| {
-        std::thread::sleep(Duration::from_millis(1000));
-        opener::open_browser("http://127.0.0.1:8000/").ok();
-    });
+    use super::*;
+    use prepared_build::Retention;
 
-    async_main(async move {
-        let _res = rocket::build()
+    /// A build whose parts are all filled with `n`
+    fn numbered_build(n: u8) -> PreparedBuild {
+        PreparedBuild::new(PartsData {
+            chip: String::from("esp32"),
+            bootloader: vec![n; 32],
+            partitions: vec![n; 32],
+            firmware: vec![n; 64],
+            total_size: 128,
+            bootloader_size: 32,
+            partitions_size: 32,
+            firmware_size: 64,
+            flash_size: String::from("4MB"),
+            bootloader_offset: 0x1000,
+            partitions_offset: 0x8000,
+            firmware_offset: 0x10000,
+            ..Default::default()
+        })
+    }
+
+    fn test_rocket(builds: Builds) -> Rocket<Build> {
+        rocket::build()
             .mount(
                 "/",
-                routes![index, manifest, bootloader, partitions, firmware, info],
+                routes![manifest, bootloader, firmware, pinned_part, app_info],
             )
-            .manage(data)
-            .launch()
+            .manage(builds)
+            .manage(PageSettings {
+                lang: String::from("en"),
+                theme_css: String::new(),
+                erase_policy: ErasePolicy::Prompt,
+                name: None,
+                title: None,
+                logo: None,
+                erase_locked: false,
+                once: false,
+                admin: false,
+                client_logs: false,
+                index: None,
+                esp_web_tools: String::new(),
+            })
+            .manage(fota::FotaSettings {
+                ty: String::from("esp32"),
+                version: None,
+                public_url: None,
+            })
+            .manage(telemetry::Sessions::default())
+    }
+
+    #[rocket::async_test]
+    async fn rebuilds_never_mix_parts_of_two_builds() {
+        let builds = Builds::new(
+            numbered_build(0),
+            Retention {
+                count: 2,
+                budget: usize::MAX,
+            },
+        );
+        let client = Client::untracked(test_rocket(builds.clone()))
             .await
-            .expect("Problem launching server");
-    });
+            .unwrap();
 
-    Ok(())
+        let done = Arc::new(AtomicBool::new(false));
+        let rebuilder = thread::spawn({
+            let builds = builds.clone();
+            let done = done.clone();
+            move || {
+                let mut n = 0u8;
+                while !done.load(Ordering::Relaxed) {
+                    n = n.wrapping_add(1);
+                    builds.start_rebuild();
+                    builds.finish(Ok(numbered_build(n)));
+                }
+            }
+        });
+
+        let (mut complete, mut conflicts) = (0, 0);
+        for _ in 0..200 {
+            let manifest: Value = client
+                .get("/manifest.json")
+                .dispatch()
+                .await
+                .into_json()
+                .await
+                .unwrap();
+            let paths: Vec<String> = manifest["builds"][0]["parts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|part| part["path"].as_str().unwrap().to_string())
+                .collect();
+            assert_eq!(paths.len(), 3);
+
+            let mut markers = Vec::new();
+            for path in &paths {
+                let response = client.get(format!("/{}", path)).dispatch().await;
+                match response.status() {
+                    Status::Ok => {
+                        let part = response.into_bytes().await.unwrap();
+                        assert!(part.iter().all(|&b| b == part[0]));
+                        markers.push(part[0]);
+                    }
+                    Status::Conflict => break,
+                    status => panic!("{} answered {}", path, status),
+                }
+            }
+            if markers.len() == paths.len() {
+                assert!(markers.iter().all(|&m| m == markers[0]), "{:?}", markers);
+                complete += 1;
+            } else {
+                conflicts += 1;
+            }
+
+            // the unpinned paths resolve the current build on their own
+            let firmware = client.get("/firmware.bin").dispatch().await;
+            let firmware = firmware.into_bytes().await.unwrap();
+            assert!(firmware.iter().all(|&b| b == firmware[0]));
+        }
+
+        done.store(true, Ordering::Relaxed);
+        rebuilder.join().unwrap();
+        assert_eq!(complete + conflicts, 200);
+        assert!(complete > 0);
+    }
+
+    #[rocket::async_test]
+    async fn evicted_builds_are_a_conflict() {
+        let builds = Builds::new(
+            numbered_build(0),
+            Retention {
+                count: 1,
+                budget: usize::MAX,
+            },
+        );
+        let old = builds.current().hash.clone();
+        builds.finish(Ok(numbered_build(1)));
+        let client = Client::untracked(test_rocket(builds.clone()))
+            .await
+            .unwrap();
+
+        let response = client
+            .get(format!("/b/{}/firmware.bin", old))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Conflict);
+        assert!(response
+            .into_string()
+            .await
+            .unwrap()
+            .contains("re-fetch manifest.json"));
+
+        let current = builds.current().hash.clone();
+        let response = client
+            .get(format!("/b/{}/firmware.bin", current))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_bytes().await.unwrap(), vec![1; 64]);
+    }
+
+    /// The image options of `manifest <args>`
+    fn image_args(args: &[&str]) -> ImageArgs {
+        let cli = Cli::try_parse_from(["esp-web-flash", "manifest"].iter().chain(args)).unwrap();
+        match cli.command {
+            Command::Manifest(opts) => opts.image,
+            command => panic!("parsed as {:?}", command),
+        }
+    }
+
+    #[test]
+    fn app_only_serves_a_prebuilt_app_image_alone() {
+        let opts = image_args(&[
+            "--chip",
+            "esp32",
+            "--app-only",
+            "tests/fixtures/esp32-app.bin",
+        ]);
+        let data = prepare(&opts).unwrap();
+        assert!(data.app_only);
+        let parts: Vec<_> = data
+            .parts()
+            .into_iter()
+            .map(|part| (part.name, part.offset))
+            .collect();
+        assert_eq!(parts, [(String::from("firmware.bin"), 0x10000)]);
+        assert_eq!(data.total_size, data.firmware_size);
+
+        let opts = image_args(&[
+            "--chip",
+            "esp32",
+            "--app-only",
+            "--app-offset",
+            "0x20000",
+            "tests/fixtures/esp32-app.bin",
+        ]);
+        assert_eq!(prepare(&opts).unwrap().firmware_offset, 0x20000);
+    }
+
+    #[rocket::async_test]
+    async fn app_info_reads_the_descriptor_of_a_prepared_image() {
+        // see tests/fixtures/gen_elf.py
+        let opts = image_args(&["--chip", "esp32", "tests/fixtures/esp32-app.bin"]);
+        let build = PreparedBuild::new(prepare(&opts).unwrap());
+        let retention = Retention {
+            count: 1,
+            budget: usize::MAX,
+        };
+        let client = Client::untracked(test_rocket(Builds::new(build, retention)))
+            .await
+            .unwrap();
+        let info: json::Value = client
+            .get("/app-info")
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(info["project_name"], "blink");
+        assert_eq!(info["version"], "v2.0.1");
+        assert_eq!(info["idf_version"], "v5.1");
+        assert_eq!(info["compile_date"], "Jan  1 2024");
+        assert_eq!(info["compile_time"], "12:00:00");
+        assert_eq!(info["secure_version"], 0);
+        assert_eq!(
+            info["app_elf_sha256"],
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn app_info_is_null_without_a_descriptor() {
+        let retention = Retention {
+            count: 1,
+            budget: usize::MAX,
+        };
+        let client = Client::untracked(test_rocket(Builds::new(numbered_build(0), retention)))
+            .await
+            .unwrap();
+        let info: json::Value = client
+            .get("/app-info")
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        let fields = info.as_object().unwrap();
+        assert_eq!(fields.len(), 7);
+        assert!(fields.values().all(json::Value::is_null));
+    }
+
+    #[test]
+    fn bash_completion_offers_the_flags() {
+        let mut command = Cli::command();
+        let mut script = Vec::new();
+        clap_complete::generate(
+            clap_complete::Shell::Bash,
+            &mut command,
+            "esp-web-flash-server",
+            &mut script,
+        );
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("--flash-size"));
+        assert!(script.contains("completions"));
+    }
 }
@@ -0,0 +1,93 @@
+use std::net::IpAddr;
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+use crate::listen;
+
+/// Instance name service browsers list the server under
+const INSTANCE: &str = "esp-web-flash";
+pub const DEFAULT_NAME: &str = "esp-flash";
+
+/// Checks an `--mdns-name` value: a single DNS label
+pub fn name_arg(s: &str) -> Result<String, String> {
+    let s = s.strip_suffix(".local").unwrap_or(s);
+    if !s.is_empty()
+        && s.len() <= 63
+        && !s.starts_with('-')
+        && !s.ends_with('-')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        Ok(s.to_ascii_lowercase())
+    } else {
+        Err(String::from(
+            "the name may only contain letters, digits and '-', e.g. esp-flash",
+        ))
+    }
+}
+
+/// `<name>.local` and the advertised port, managed so the startup banner
+/// can print its URL
+#[derive(Clone)]
+pub struct Advertised {
+    pub host: String,
+    pub port: u16,
+}
+
+/// The registered service, deregistered when dropped at shutdown
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        if let Ok(done) = self.daemon.unregister(&self.fullname) {
+            // wait for the goodbye packets, so browsers drop the entry now
+            let _ = done.recv_timeout(std::time::Duration::from_secs(1));
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Registers `_http._tcp` (or `_https._tcp` with TLS) as `<name>.local` on
+/// `port`, at the addresses of the interfaces `addr` is reachable on
+pub fn advertise(
+    name: &str,
+    addr: IpAddr,
+    port: u16,
+    tls: bool,
+) -> Result<(Advertisement, Advertised), mdns_sd::Error> {
+    let addresses: Vec<IpAddr> = if addr.is_unspecified() {
+        listen::interfaces(addr)
+            .into_iter()
+            .map(|(_, ip)| ip)
+            .collect()
+    } else {
+        vec![addr]
+    };
+    let ty = if tls {
+        "_https._tcp.local."
+    } else {
+        "_http._tcp.local."
+    };
+
+    let daemon = ServiceDaemon::new()?;
+    let service = ServiceInfo::new(
+        ty,
+        INSTANCE,
+        &format!("{}.local.", name),
+        &addresses[..],
+        port,
+        &[("path", "/")][..],
+    )?;
+    let fullname = service.get_fullname().to_string();
+    daemon.register(service)?;
+
+    Ok((
+        Advertisement { daemon, fullname },
+        Advertised {
+            host: format!("{}.local", name),
+            port,
+        },
+    ))
+}
@@ -0,0 +1,95 @@
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+
+use crate::{flash_size, PartsData};
+
+/// Erased flash, which the gaps between the parts are filled with
+const ERASED: u8 = 0xff;
+
+/// How far `merged.bin` extends, `--merged-size`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergedSize {
+    /// to the end of the last part
+    End,
+    /// to the end of the flash, `--flash-size`
+    Flash,
+}
+
+/// `--merged-size` for the `/merged.bin` route and `/info`
+#[derive(Clone)]
+pub struct MergeSettings {
+    pub size: MergedSize,
+}
+
+/// The parts with their offsets, in flash order; a merged image given as
+/// input has no bootloader or partition table of its own
+fn segments(data: &PartsData) -> Vec<(usize, &[u8])> {
+    let mut segments: Vec<(usize, &[u8])> = vec![(data.firmware_offset as usize, &data.firmware)];
+    if !data.app_only {
+        segments.push((data.bootloader_offset as usize, &data.bootloader));
+        segments.push((data.partitions_offset as usize, &data.partitions));
+    }
+    segments.extend(
+        data.extra_parts
+            .iter()
+            .map(|part| (part.offset as usize, part.data.as_slice())),
+    );
+    segments.retain(|(_, data)| !data.is_empty());
+    segments.sort_by_key(|(offset, _)| *offset);
+    segments
+}
+
+/// Size of the merged image
+pub fn size(data: &PartsData, size: MergedSize) -> usize {
+    let end = segments(data)
+        .iter()
+        .map(|(offset, data)| offset + data.len())
+        .max()
+        .unwrap_or_default();
+    match size {
+        MergedSize::End => end,
+        MergedSize::Flash => {
+            flash_size::parse(&data.flash_size).map_or(end, |flash| end.max(flash.bytes() as usize))
+        }
+    }
+}
+
+/// The image `esptool write_flash 0x0` takes, like `esptool merge_bin`
+/// makes: every part at its offset, erased flash in between. The parts are
+/// copied into a single buffer of the final size.
+pub fn build(data: &PartsData, size: MergedSize) -> Vec<u8> {
+    let mut image = vec![ERASED; self::size(data, size)];
+    for (offset, part) in segments(data) {
+        image[offset..offset + part.len()].copy_from_slice(part);
+    }
+    image
+}
+
+/// SHA-256 of the merged image, hashed part by part without building it
+pub fn sha256_hex(data: &PartsData, size: MergedSize) -> String {
+    let total = self::size(data, size);
+    let erased = [ERASED; 4096];
+    let mut hasher = Sha256::new();
+    let pad = |hasher: &mut Sha256, mut len: usize| {
+        while len > 0 {
+            let chunk = len.min(erased.len());
+            hasher.update(&erased[..chunk]);
+            len -= chunk;
+        }
+    };
+
+    let mut position = 0;
+    for (offset, part) in segments(data) {
+        // overlapping parts were rejected when preparing
+        pad(&mut hasher, offset.saturating_sub(position));
+        hasher.update(part);
+        position = offset + part.len();
+    }
+    pad(&mut hasher, total.saturating_sub(position));
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
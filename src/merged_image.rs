@@ -0,0 +1,101 @@
+use std::path::Path;
+
+use espflash::Chip;
+
+use crate::{
+    checksums::Checksums, layout, partition_table, prebuilt, prepare_error::PrepareError, PartsData,
+};
+
+const PARTITION_TABLE_OFFSET: usize = 0x8000;
+/// Size of the partition table region, including the MD5 row and padding
+const PARTITION_TABLE_SIZE: usize = 0xc00;
+const PARTITION_TABLE_MAGIC: &[u8] = &[0xaa, 0x50];
+const IMAGE_MAGIC: u8 = 0xe9;
+
+/// Serves an image meant for `write_flash 0x0` as a single part. The
+/// partition table embedded at 0x8000, if any, feeds /partitions.csv and
+/// the layout map.
+pub fn prepare(
+    path: &Path,
+    chip: Chip,
+    flash_size: &str,
+    flash_size_bytes: u32,
+    checksums: &Checksums,
+) -> Result<PartsData, PrepareError> {
+    let image = checksums.read(path)?;
+
+    if image.is_empty() || image.len() as u64 > flash_size_bytes as u64 {
+        return Err(PrepareError::InvalidPart {
+            path: path.to_path_buf(),
+            reason: format!("{} bytes don't fit the {} flash", image.len(), flash_size),
+        });
+    }
+    if image.get(prebuilt::bootloader_offset(chip) as usize) != Some(&IMAGE_MAGIC) {
+        warning!(
+            "{} has no bootloader image at 0x{:x}, is it really a merged image for {}?",
+            path.display(),
+            prebuilt::bootloader_offset(chip),
+            chip
+        );
+    }
+
+    let partitions = image
+        .get(PARTITION_TABLE_OFFSET..)
+        .filter(|rest| rest.starts_with(PARTITION_TABLE_MAGIC))
+        .map(|rest| rest[..rest.len().min(PARTITION_TABLE_SIZE)].to_vec())
+        .unwrap_or_default();
+    if partitions.is_empty() {
+        warning!(
+            "No partition table found at 0x{:x}, the layout map will only show the image",
+            PARTITION_TABLE_OFFSET
+        );
+    }
+    let table = partition_table::parse(&partitions);
+
+    status!("Serving merged image {}:", path.display());
+    status!("  Single part at 0x0: {} bytes", image.len());
+    status!("  Partition table entries found: {}", table.len());
+
+    let layout = layout::compute(
+        &[layout::WrittenPart {
+            name: "merged image",
+            offset: 0,
+            size: image.len() as u32,
+        }],
+        &table,
+        flash_size_bytes,
+    );
+
+    Ok(PartsData {
+        chip: crate::chip_name(chip).to_string(),
+        total_size: image.len(),
+        bootloader_size: 0,
+        partitions_size: partitions.len(),
+        firmware_size: image.len(),
+        flash_size: flash_size.to_string(),
+        partition_table_source: format!("embedded in {}", path.display()),
+        bootloader_offset: prebuilt::bootloader_offset(chip),
+        partitions_offset: PARTITION_TABLE_OFFSET as u32,
+        firmware_offset: 0,
+        bootloader: Vec::new(),
+        partitions,
+        app_version: None,
+        app_desc: None,
+        firmware: image,
+        extra_parts: Vec::new(),
+        layout,
+        elf_info: Default::default(),
+        single_part: true,
+        app_only: false,
+        verified_inputs: Vec::new(),
+        board: None,
+        sbom: None,
+        elf_path: None,
+        elf_mtime: None,
+        elf: None,
+        flash_mode: None,
+        flash_freq: None,
+        app_header: None,
+        min_chip_rev: None,
+    })
+}
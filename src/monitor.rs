@@ -0,0 +1,68 @@
+use std::{
+    io::{ErrorKind, Read},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use rocket::tokio::sync::broadcast;
+
+use crate::{device, symbols::Symbols};
+
+/// Serial output of the board attached to the server, one line per message
+#[derive(Clone)]
+pub struct Monitor {
+    tx: broadcast::Sender<String>,
+}
+
+impl Monitor {
+    /// Claims `port` and starts forwarding its output to subscribers,
+    /// decoding backtrace addresses when `symbols` are available
+    pub fn start(port: &str, baud: u32, symbols: Option<Symbols>) -> Result<Self> {
+        let claim = device::claim(port, "web-flash monitor")?;
+        let mut serial = serialport::new(port, baud)
+            .timeout(Duration::from_millis(100))
+            .open()
+            .with_context(|| format!("Failed to open serial port {}", port))?;
+
+        let (tx, _) = broadcast::channel(1024);
+        let sender = tx.clone();
+        std::thread::spawn(move || {
+            let _claim = claim;
+            let mut line = Vec::new();
+            let mut buf = [0u8; 1024];
+
+            loop {
+                let n = match serial.read(&mut buf) {
+                    Ok(n) => n,
+                    Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+                    Err(e) => {
+                        sender.send(format!("Serial port error: {}", e)).ok();
+                        break;
+                    }
+                };
+
+                for &byte in &buf[..n] {
+                    match byte {
+                        b'\n' => {
+                            let text = String::from_utf8_lossy(&line).trim_end().to_string();
+                            let text = match &symbols {
+                                Some(symbols) => symbols.annotate(&text),
+                                None => text,
+                            };
+                            // no subscribers is fine, the line is simply dropped
+                            sender.send(text).ok();
+                            line.clear();
+                        }
+                        byte => line.push(byte),
+                    }
+                }
+            }
+        });
+
+        Ok(Monitor { tx })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.tx.subscribe()
+    }
+}
@@ -0,0 +1,433 @@
+use std::path::Path;
+
+const PAGE_SIZE: usize = 4096;
+const ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_PAGE: usize = 126;
+/// Entries start after the 32 byte header and the 32 byte state bitmap
+const FIRST_ENTRY: usize = 64;
+const PAGE_ACTIVE: u32 = 0xffff_fffe;
+const PAGE_FULL: u32 = 0xffff_fffc;
+/// Format version 2, with blobs split into chunks that may span pages
+const PAGE_VERSION: u8 = 0xfe;
+/// Pages the NVS library needs to find erased for itself
+const MIN_PAGES: usize = 3;
+
+const TYPE_STRING: u8 = 0x21;
+const TYPE_BLOB_DATA: u8 = 0x42;
+const TYPE_BLOB_INDEX: u8 = 0x48;
+const CHUNK_ANY: u8 = 0xff;
+
+const MAX_KEY: usize = 15;
+const MAX_STRING: usize = 4000;
+
+/// CRC-32 as the NVS library computes it (zlib's with 0xFFFFFFFF as the
+/// previous value)
+fn crc(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new_with_initial(0xffff_ffff);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// The value of a `data` or `file` row
+enum Value {
+    /// type code and little-endian bytes of an integer
+    Integer(u8, Vec<u8>),
+    String(Vec<u8>),
+    Blob(Vec<u8>),
+}
+
+fn parse_integer(value: &str) -> Option<i128> {
+    let value = value.trim();
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    let magnitude = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i128>().ok()?,
+    };
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+fn hex2bin(value: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = value.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return Err(String::from("hex2bin needs an even number of hex digits"));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("'{}' is not hex", String::from_utf8_lossy(pair)))
+        })
+        .collect()
+}
+
+fn base64(value: &str) -> Result<Vec<u8>, String> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = Vec::new();
+    let (mut bits, mut count) = (0u32, 0);
+    for byte in value
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b'=')
+    {
+        let digit = ALPHABET
+            .iter()
+            .position(|&c| c == byte)
+            .ok_or_else(|| format!("'{}' is not base64", byte as char))?;
+        bits = bits << 6 | digit as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Encodes a row's value the way `nvs_partition_gen.py` does
+fn encode(encoding: &str, text: &str, raw: Option<Vec<u8>>) -> Result<Value, String> {
+    let integer = |code: u8, min: i128, max: i128, width: usize| {
+        let value = parse_integer(text).ok_or_else(|| format!("'{}' is not a number", text))?;
+        if value < min || value > max {
+            return Err(format!("{} is out of range for {}", value, encoding));
+        }
+        Ok(Value::Integer(code, value.to_le_bytes()[..width].to_vec()))
+    };
+    match encoding {
+        "u8" => integer(0x01, 0, u8::MAX.into(), 1),
+        "i8" => integer(0x11, i8::MIN.into(), i8::MAX.into(), 1),
+        "u16" => integer(0x02, 0, u16::MAX.into(), 2),
+        "i16" => integer(0x12, i16::MIN.into(), i16::MAX.into(), 2),
+        "u32" => integer(0x04, 0, u32::MAX.into(), 4),
+        "i32" => integer(0x14, i32::MIN.into(), i32::MAX.into(), 4),
+        "u64" => integer(0x08, 0, u64::MAX.into(), 8),
+        "i64" => integer(0x18, i64::MIN.into(), i64::MAX.into(), 8),
+        "string" => {
+            let mut bytes = text.as_bytes().to_vec();
+            bytes.push(0);
+            if bytes.len() > MAX_STRING {
+                return Err(format!(
+                    "the string has {} bytes, at most {} fit an entry",
+                    bytes.len() - 1,
+                    MAX_STRING - 1
+                ));
+            }
+            Ok(Value::String(bytes))
+        }
+        "hex2bin" => hex2bin(text).map(Value::Blob),
+        "base64" => base64(text).map(Value::Blob),
+        "binary" => raw
+            .map(Value::Blob)
+            .ok_or_else(|| String::from("binary is only for file rows")),
+        other => Err(format!("unknown encoding '{}'", other)),
+    }
+}
+
+/// Fills pages entry by entry; an item other than a blob never spans pages
+struct Writer {
+    pages: Vec<Vec<u8>>,
+    /// next free entry of the last page
+    entry: usize,
+}
+
+impl Writer {
+    fn free(&self) -> usize {
+        if self.pages.is_empty() {
+            0
+        } else {
+            ENTRIES_PER_PAGE - self.entry
+        }
+    }
+
+    fn new_page(&mut self) {
+        if let Some(page) = self.pages.last_mut() {
+            page[0..4].copy_from_slice(&PAGE_FULL.to_le_bytes());
+        }
+        let mut page = vec![0xff; PAGE_SIZE];
+        page[0..4].copy_from_slice(&PAGE_ACTIVE.to_le_bytes());
+        page[4..8].copy_from_slice(&(self.pages.len() as u32).to_le_bytes());
+        page[8] = PAGE_VERSION;
+        let header_crc = crc(&page[4..28]);
+        page[28..32].copy_from_slice(&header_crc.to_le_bytes());
+        self.pages.push(page);
+        self.entry = 0;
+    }
+
+    fn reserve(&mut self, span: usize) {
+        if self.free() < span {
+            self.new_page();
+        }
+    }
+
+    /// Writes one 32 byte entry and marks it written in the state bitmap
+    fn write(&mut self, entry: &[u8]) {
+        let index = self.entry;
+        let page = self.pages.last_mut().expect("reserve adds a page");
+        let at = FIRST_ENTRY + index * ENTRY_SIZE;
+        page[at..at + entry.len()].copy_from_slice(entry);
+        page[32 + index / 4] &= !(1 << (index % 4 * 2));
+        self.entry += 1;
+    }
+
+    fn item(&mut self, ns: u8, ty: u8, chunk: u8, key: &str, data: [u8; 8], payload: &[u8]) {
+        let span = 1 + (payload.len() + ENTRY_SIZE - 1) / ENTRY_SIZE;
+        self.reserve(span);
+        let mut entry = [0xff; ENTRY_SIZE];
+        entry[0] = ns;
+        entry[1] = ty;
+        entry[2] = span as u8;
+        entry[3] = chunk;
+        entry[8..24].fill(0);
+        entry[8..8 + key.len()].copy_from_slice(key.as_bytes());
+        entry[24..32].copy_from_slice(&data);
+        let entry_crc = crc(&[&entry[0..4], &entry[8..32]].concat());
+        entry[4..8].copy_from_slice(&entry_crc.to_le_bytes());
+        self.write(&entry);
+        for chunk in payload.chunks(ENTRY_SIZE) {
+            self.write(chunk);
+        }
+    }
+
+    /// Size, reserved and data CRC, the header of a string or blob chunk
+    fn sized(payload: &[u8]) -> [u8; 8] {
+        let mut data = [0xff; 8];
+        data[0..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        data[4..8].copy_from_slice(&crc(payload).to_le_bytes());
+        data
+    }
+
+    fn value(&mut self, ns: u8, key: &str, value: &Value) {
+        match value {
+            Value::Integer(ty, bytes) => {
+                let mut data = [0xff; 8];
+                data[..bytes.len()].copy_from_slice(bytes);
+                self.item(ns, *ty, CHUNK_ANY, key, data, &[]);
+            }
+            Value::String(bytes) => {
+                self.item(ns, TYPE_STRING, CHUNK_ANY, key, Self::sized(bytes), bytes)
+            }
+            Value::Blob(bytes) => {
+                let mut chunks = 0u8;
+                let mut rest = bytes.as_slice();
+                while !rest.is_empty() {
+                    if self.free() < 2 {
+                        self.new_page();
+                    }
+                    let (chunk, tail) =
+                        rest.split_at(rest.len().min((self.free() - 1) * ENTRY_SIZE));
+                    self.item(ns, TYPE_BLOB_DATA, chunks, key, Self::sized(chunk), chunk);
+                    chunks += 1;
+                    rest = tail;
+                }
+                let mut data = [0xff; 8];
+                data[0..4].copy_from_slice(&(bytes.len() as u32).to_le_bytes());
+                data[4] = chunks;
+                data[5] = 0;
+                self.item(ns, TYPE_BLOB_INDEX, CHUNK_ANY, key, data, &[]);
+            }
+        }
+    }
+}
+
+/// Builds the image of an NVS partition of `size` bytes from a CSV in the
+/// format of ESP-IDF's `nvs_partition_gen.py` (`key,type,encoding,value`).
+/// Files of `file` rows are relative to the CSV.
+pub fn generate(csv_path: &Path, csv: &str, size: u32) -> Result<Vec<u8>, String> {
+    let size = size as usize;
+    if size % PAGE_SIZE != 0 || size < MIN_PAGES * PAGE_SIZE {
+        return Err(format!(
+            "the nvs partition has {} bytes, it needs a multiple of 4096 and at least {}",
+            size,
+            MIN_PAGES * PAGE_SIZE
+        ));
+    }
+    let base = csv_path.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut writer = Writer {
+        pages: Vec::new(),
+        entry: 0,
+    };
+    let mut namespace: Option<u8> = None;
+    let mut namespaces = 0u8;
+    for (index, line) in csv.lines().enumerate() {
+        let row = line.trim();
+        if row.is_empty() || row.starts_with('#') || (index == 0 && row.starts_with("key")) {
+            continue;
+        }
+        let at = |reason: String| format!("line {}: {}", index + 1, reason);
+        let fields: Vec<&str> = row.splitn(4, ',').map(str::trim).collect();
+        let key = fields[0];
+        if key.is_empty() || key.len() > MAX_KEY {
+            return Err(at(format!(
+                "key '{}' must have 1 to {} characters",
+                key, MAX_KEY
+            )));
+        }
+        match fields.get(1).copied() {
+            Some("namespace") => {
+                namespaces = namespaces
+                    .checked_add(1)
+                    .filter(|&n| n < 255)
+                    .ok_or_else(|| at(String::from("too many namespaces")))?;
+                let mut data = [0xff; 8];
+                data[0] = namespaces;
+                writer.item(0, 0x01, CHUNK_ANY, key, data, &[]);
+                namespace = Some(namespaces);
+            }
+            Some(ty @ ("data" | "file")) => {
+                let ns = namespace
+                    .ok_or_else(|| at(String::from("data before the first namespace row")))?;
+                let (encoding, value) = match (fields.get(2), fields.get(3)) {
+                    (Some(encoding), Some(value)) => (*encoding, value.trim_matches('"')),
+                    _ => return Err(at(String::from("needs an encoding and a value"))),
+                };
+                let (text, raw) = if ty == "file" {
+                    let path = base.join(value);
+                    let raw = std::fs::read(&path)
+                        .map_err(|e| at(format!("failed to read {}: {}", path.display(), e)))?;
+                    (String::from_utf8_lossy(&raw).trim().to_string(), Some(raw))
+                } else {
+                    (value.to_string(), None)
+                };
+                let value = encode(encoding, &text, raw)
+                    .map_err(|reason| at(format!("{} '{}': {}", ty, key, reason)))?;
+                writer.value(ns, key, &value);
+            }
+            other => {
+                return Err(at(format!(
+                    "type '{}' is none of namespace, data and file",
+                    other.unwrap_or_default()
+                )))
+            }
+        }
+    }
+
+    // the library keeps one page erased to move entries into
+    let pages = size / PAGE_SIZE;
+    if writer.pages.len() > pages - 1 {
+        return Err(format!(
+            "the values need {} pages of 4096 bytes, the nvs partition has {} of which one stays erased",
+            writer.pages.len(),
+            pages
+        ));
+    }
+    let mut image = writer.pages.concat();
+    image.resize(size, 0xff);
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 0x6000;
+
+    fn generated(rows: &str) -> Result<Vec<u8>, String> {
+        generate(
+            Path::new("nvs.csv"),
+            &format!("key,type,encoding,value\n{}", rows),
+            SIZE,
+        )
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn entry(image: &[u8], page: usize, index: usize) -> &[u8] {
+        let at = page * PAGE_SIZE + FIRST_ENTRY + index * ENTRY_SIZE;
+        &image[at..at + ENTRY_SIZE]
+    }
+
+    #[test]
+    fn header_and_entry_crcs_match_nvs_partition_gen() {
+        // the bytes nvs_partition_gen.py writes for these rows: zlib's
+        // CRC-32 seeded with 0xFFFFFFFF over header bytes 4..28, and over
+        // every entry byte but the CRC at 4..8
+        let image = generated("storage,namespace,,\nboot_count,data,u8,7\n").unwrap();
+        assert_eq!(image.len(), SIZE as usize);
+        assert_eq!(
+            hex(&image[..32]),
+            "feffffff00000000feffffffffffffffffffffffffffffffffffffff842dbab9"
+        );
+        assert_eq!(
+            hex(entry(&image, 0, 0)),
+            "000101ff09a9500773746f7261676500000000000000000001ffffffffffffff"
+        );
+        assert_eq!(
+            hex(entry(&image, 0, 1)),
+            "010101ff202001a2626f6f745f636f756e7400000000000007ffffffffffffff"
+        );
+        // two entries written (0b10 each), the others still empty
+        assert_eq!(image[32], 0xfa);
+        assert!(image[33..64].iter().all(|&b| b == 0xff));
+        assert!(image[PAGE_SIZE..].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn blobs_are_split_across_pages() {
+        let blob: Vec<u8> = (0..5000u32).map(|i| (i * 7) as u8).collect();
+        let image = generated(&format!(
+            "storage,namespace,,\nblob,data,hex2bin,{}\n",
+            hex(&blob)
+        ))
+        .unwrap();
+
+        // the first page is full: the namespace and a chunk of 124 entries
+        // of data behind its header
+        assert_eq!(&image[0..4], &PAGE_FULL.to_le_bytes());
+        assert!(image[32..63].iter().all(|&b| b == 0xaa));
+        assert_eq!(image[63], 0xfa);
+        let first = entry(&image, 0, 1);
+        assert_eq!(first[..4], [1, TYPE_BLOB_DATA, 125, 0]);
+        assert_eq!(u16::from_le_bytes([first[24], first[25]]), 124 * 32);
+        assert_eq!(first[28..32], crc(&blob[..124 * 32]).to_le_bytes());
+
+        let second_page = &image[PAGE_SIZE..2 * PAGE_SIZE];
+        assert_eq!(&second_page[0..4], &PAGE_ACTIVE.to_le_bytes());
+        assert_eq!(&second_page[4..8], &1u32.to_le_bytes());
+        let second = entry(&image, 1, 0);
+        let rest = blob.len() - 124 * 32;
+        assert_eq!(second[..4], [1, TYPE_BLOB_DATA, 1 + 33, 1]);
+        assert_eq!(u16::from_le_bytes([second[24], second[25]]) as usize, rest);
+        assert_eq!(second[28..32], crc(&blob[124 * 32..]).to_le_bytes());
+
+        // the chunks hold the blob in order
+        let payload = |page: usize, len: usize| {
+            let at = page * PAGE_SIZE + FIRST_ENTRY + ENTRY_SIZE;
+            &image[at..at + len]
+        };
+        assert_eq!([payload(0, 124 * 32), payload(1, rest)].concat(), blob);
+
+        // followed by the index: total size, 2 chunks from chunk 0
+        let index = entry(&image, 1, 34);
+        assert_eq!(index[..4], [1, TYPE_BLOB_INDEX, 1, CHUNK_ANY]);
+        assert_eq!(index[24..30], [0x88, 0x13, 0, 0, 2, 0]);
+        assert_eq!(&index[8..13], b"blob\0");
+    }
+
+    #[test]
+    fn one_page_stays_erased() {
+        // fills a bit more than two pages of the three available
+        let blob = vec![0x5a; 8200];
+        let e = generate(
+            Path::new("nvs.csv"),
+            &format!("storage,namespace,,\nblob,data,hex2bin,{}\n", hex(&blob)),
+            0x3000,
+        )
+        .unwrap_err();
+        assert_eq!(
+            e,
+            "the values need 3 pages of 4096 bytes, the nvs partition has 3 of which one stays erased"
+        );
+
+        let e = generate(Path::new("nvs.csv"), "", 0x2000).unwrap_err();
+        assert!(e.contains("at least 12288"), "{}", e);
+    }
+}
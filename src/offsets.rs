@@ -0,0 +1,114 @@
+use crate::{
+    layout::{self, RegionKind, WrittenPart},
+    partition_table::PartitionEntry,
+    prepare_error::PrepareError,
+};
+
+/// Flash the partition table occupies, its MD5 entry included
+pub const PARTITION_TABLE_LEN: u32 = 0x1000;
+const APP_ALIGN: u32 = 0x10000;
+const APP_TYPE: u8 = 0x00;
+
+/// Where the bootloader, partition table and app are written
+#[derive(Debug, Clone, Copy)]
+pub struct Offsets {
+    pub bootloader: u32,
+    pub partition_table: u32,
+    pub app: u32,
+}
+
+/// Checks offsets given with `--bootloader-offset`, `--partition-table-offset`
+/// or `--app-offset`: the app must start an app partition, no partition may
+/// overlap the table, and no two written parts may overlap. The error shows
+/// the layout they would have produced.
+pub fn check(
+    offsets: &Offsets,
+    parts: &[WrittenPart],
+    table: &[PartitionEntry],
+    flash_size: u32,
+) -> Result<(), PrepareError> {
+    let invalid = |reason: String| PrepareError::Offsets {
+        reason,
+        layout: describe(&layout::compute(parts, table, flash_size)),
+    };
+
+    if offsets.partition_table % PARTITION_TABLE_LEN != 0 {
+        return Err(invalid(format!(
+            "the partition table offset 0x{:x} is not a multiple of 0x{:x}",
+            offsets.partition_table, PARTITION_TABLE_LEN
+        )));
+    }
+    if offsets.app % APP_ALIGN != 0 {
+        return Err(invalid(format!(
+            "the app offset 0x{:x} is not a multiple of 0x{:x}",
+            offsets.app, APP_ALIGN
+        )));
+    }
+    if !table
+        .iter()
+        .any(|e| e.ty == APP_TYPE && e.offset == offsets.app)
+    {
+        let apps: Vec<String> = table
+            .iter()
+            .filter(|e| e.ty == APP_TYPE)
+            .map(|e| format!("{} at 0x{:x}", e.name, e.offset))
+            .collect();
+        return Err(invalid(format!(
+            "no app partition starts at 0x{:x} (app partitions: {})",
+            offsets.app,
+            apps.join(", ")
+        )));
+    }
+
+    let table_end = offsets.partition_table + PARTITION_TABLE_LEN;
+    if let Some(entry) = table
+        .iter()
+        .find(|e| e.offset < table_end && e.end() > offsets.partition_table)
+    {
+        return Err(invalid(format!(
+            "partition '{}' (0x{:x}-0x{:x}) overlaps the partition table (0x{:x}-0x{:x})",
+            entry.name,
+            entry.offset,
+            entry.end(),
+            offsets.partition_table,
+            table_end
+        )));
+    }
+
+    let mut sorted: Vec<&WrittenPart> = parts.iter().collect();
+    sorted.sort_by_key(|part| part.offset);
+    for pair in sorted.windows(2) {
+        if pair[0].offset.saturating_add(pair[0].size) > pair[1].offset {
+            return Err(invalid(format!(
+                "the {} (0x{:x}-0x{:x}) overlaps the {} at 0x{:x}",
+                pair[0].name,
+                pair[0].offset,
+                pair[0].offset.saturating_add(pair[0].size),
+                pair[1].name,
+                pair[1].offset
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// One line per region, as shown under an offset error
+fn describe(regions: &[layout::Region]) -> String {
+    regions
+        .iter()
+        .map(|region| {
+            let kind = match region.kind {
+                RegionKind::Written => "written",
+                RegionKind::Defined => "partition",
+                RegionKind::Unused => "free",
+                RegionKind::Overlap => "OVERLAP",
+            };
+            format!(
+                "  0x{:06x}-0x{:06x}  {:<9}  {}",
+                region.start, region.end, kind, region.name
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,187 @@
+use anyhow::{bail, Result};
+
+use crate::offsets;
+
+/// Where the partition table lives unless `--partition-table-offset` moves it
+pub const DEFAULT_TABLE_OFFSET: u32 = 0x8000;
+const DATA_ALIGN: u32 = 0x1000;
+const APP_ALIGN: u32 = 0x10000;
+
+/// High level description of the partition table to generate
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub app_size: u32,
+    pub ota: bool,
+    pub nvs_size: u32,
+    pub spiffs_size: Option<u32>,
+    /// partitions start right after the table
+    pub table_offset: u32,
+}
+
+/// Parses sizes like `24K`, `2M`, `0x6000` or `4096`
+pub fn parse_size(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1024),
+        Some((i, 'm' | 'M')) => (&s[..i], 1024 * 1024),
+        _ => (s, 1),
+    };
+
+    let value = match number
+        .strip_prefix("0x")
+        .or_else(|| number.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => number.parse(),
+    }
+    .map_err(|_| format!("'{}' is not a size like 24K, 1M or 0x6000", s))?;
+
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("'{}' is too large", s))
+}
+
+fn align(offset: u32, alignment: u32) -> u32 {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+/// Lays the partitions out the way IDF's gen_esp32part.py assigns offsets
+/// (data partitions 4K aligned, apps 64K aligned) and renders them as CSV
+pub fn generate(opts: &Options, flash_size: u32) -> Result<String> {
+    if opts.app_size % APP_ALIGN != 0 {
+        bail!(
+            "--app-size must be a multiple of 64K (0x{:x}), got 0x{:x}",
+            APP_ALIGN,
+            opts.app_size
+        );
+    }
+
+    let mut entries: Vec<(&str, &str, &str, u32)> = vec![("nvs", "data", "nvs", opts.nvs_size)];
+    if opts.ota {
+        entries.push(("otadata", "data", "ota", 0x2000));
+    }
+    entries.push(("phy_init", "data", "phy", 0x1000));
+    if opts.ota {
+        entries.push(("ota_0", "app", "ota_0", opts.app_size));
+        entries.push(("ota_1", "app", "ota_1", opts.app_size));
+    } else {
+        entries.push(("factory", "app", "factory", opts.app_size));
+    }
+    if let Some(size) = opts.spiffs_size {
+        entries.push(("spiffs", "data", "spiffs", size));
+    }
+
+    let mut csv = String::from("# Name,   Type, SubType, Offset,  Size, Flags\n");
+    let mut offset = opts.table_offset + offsets::PARTITION_TABLE_LEN;
+    for (name, ty, subtype, size) in entries {
+        let alignment = if ty == "app" { APP_ALIGN } else { DATA_ALIGN };
+        if size % DATA_ALIGN != 0 {
+            bail!(
+                "The size of {} (0x{:x}) must be a multiple of 4K",
+                name,
+                size
+            );
+        }
+
+        offset = align(offset, alignment);
+        csv.push_str(&format!(
+            "{},{},{},0x{:x},0x{:x},\n",
+            name, ty, subtype, offset, size
+        ));
+        offset += size;
+    }
+
+    if offset > flash_size {
+        bail!(
+            "The generated partition table ends at 0x{:x}, beyond the 0x{:x} bytes of flash",
+            offset,
+            flash_size
+        );
+    }
+
+    Ok(csv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u32 = 1024 * 1024;
+
+    fn options(app_size: u32, ota: bool, nvs_size: u32, spiffs_size: Option<u32>) -> Options {
+        Options {
+            app_size,
+            ota,
+            nvs_size,
+            spiffs_size,
+            table_offset: DEFAULT_TABLE_OFFSET,
+        }
+    }
+
+    /// The rows of a generated table, without the header comment
+    fn rows(csv: &str) -> Vec<&str> {
+        csv.lines().skip(1).collect()
+    }
+
+    #[test]
+    fn matches_idf_singleapp() {
+        // partitions_singleapp.csv, as gen_esp32part.py lays it out
+        let csv = generate(&options(MB, false, 0x6000, None), 4 * MB).unwrap();
+        assert_eq!(
+            rows(&csv),
+            [
+                "nvs,data,nvs,0x9000,0x6000,",
+                "phy_init,data,phy,0xf000,0x1000,",
+                "factory,app,factory,0x10000,0x100000,",
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_idf_two_ota_without_factory() {
+        // partitions_two_ota.csv's data partitions, with the OTA slots
+        // starting where its factory app does
+        let csv = generate(&options(MB, true, 0x4000, None), 4 * MB).unwrap();
+        assert_eq!(
+            rows(&csv),
+            [
+                "nvs,data,nvs,0x9000,0x4000,",
+                "otadata,data,ota,0xd000,0x2000,",
+                "phy_init,data,phy,0xf000,0x1000,",
+                "ota_0,app,ota_0,0x10000,0x100000,",
+                "ota_1,app,ota_1,0x110000,0x100000,",
+            ]
+        );
+    }
+
+    #[test]
+    fn aligns_apps_after_larger_data_partitions() {
+        let csv = generate(&options(2 * MB, false, 24 * 1024, Some(MB)), 4 * MB).unwrap();
+        assert_eq!(
+            rows(&csv),
+            [
+                "nvs,data,nvs,0x9000,0x6000,",
+                "phy_init,data,phy,0xf000,0x1000,",
+                "factory,app,factory,0x10000,0x200000,",
+                "spiffs,data,spiffs,0x210000,0x100000,",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_layouts() {
+        assert!(generate(&options(MB + 0x1000, false, 0x6000, None), 4 * MB).is_err());
+        assert!(generate(&options(MB, false, 0x6800, None), 4 * MB).is_err());
+        assert!(generate(&options(2 * MB, true, 0x6000, None), 4 * MB).is_err());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("24K"), Ok(24 * 1024));
+        assert_eq!(parse_size("2m"), Ok(2 * MB));
+        assert_eq!(parse_size("0x6000"), Ok(0x6000));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert!(parse_size("4G").is_err());
+        assert!(parse_size("8192M").is_err());
+    }
+}
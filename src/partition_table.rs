@@ -0,0 +1,307 @@
+use espflash::PartitionTable;
+use serde::Serialize;
+
+const ENTRY_SIZE: usize = 32;
+const ENTRY_MAGIC: [u8; 2] = [0xAA, 0x50];
+
+/// One entry of a binary partition table as flashed to the device
+#[derive(Serialize, Debug, Clone)]
+pub struct PartitionEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: u8,
+    pub subtype: u8,
+    pub offset: u32,
+    pub size: u32,
+    pub flags: u32,
+}
+
+impl PartitionEntry {
+    pub fn end(&self) -> u32 {
+        self.offset.saturating_add(self.size)
+    }
+}
+
+/// The served table as `/partitions.json` reports it
+#[derive(Serialize, Debug, Clone)]
+pub struct TableInfo {
+    /// a path, `preset:<name>`, `generated` or `default`; `None` when no
+    /// table is flashed, as on the ESP8266
+    pub source: Option<String>,
+    pub entries: Vec<PartitionEntry>,
+    /// sum of the partition sizes
+    pub total_size: u64,
+    /// end of the last partition, 0 without any
+    pub end: u32,
+}
+
+impl TableInfo {
+    pub fn new(bin: &[u8], source: &str) -> Self {
+        let entries = parse(bin);
+        TableInfo {
+            source: (!bin.is_empty()).then(|| source.to_string()),
+            total_size: entries.iter().map(|e| e.size as u64).sum(),
+            end: entries.iter().map(PartitionEntry::end).max().unwrap_or(0),
+            entries,
+        }
+    }
+}
+
+/// Reads the entries of a binary partition table, stopping at the first
+/// entry without the partition magic (the MD5 row or the 0xFF padding)
+pub fn parse(bin: &[u8]) -> Vec<PartitionEntry> {
+    bin.chunks_exact(ENTRY_SIZE)
+        .take_while(|entry| entry[0..2] == ENTRY_MAGIC)
+        .map(|entry| {
+            let word =
+                |i: usize| u32::from_le_bytes([entry[i], entry[i + 1], entry[i + 2], entry[i + 3]]);
+            let name = &entry[12..28];
+            let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+
+            PartitionEntry {
+                name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+                ty: entry[2],
+                subtype: entry[3],
+                offset: word(4),
+                size: word(8),
+                flags: word(28),
+            }
+        })
+        .collect()
+}
+
+/// Reads a partition table in either the CSV or the binary format,
+/// accepting everything ESP-IDF's `gen_esp32part.py` does (size suffixes,
+/// custom types and subtypes, `encrypted` and `readonly` flags)
+pub fn load(data: &[u8]) -> Result<esp_idf_part::PartitionTable, String> {
+    if data.starts_with(&ENTRY_MAGIC) {
+        return esp_idf_part::PartitionTable::try_from_bytes(data.to_vec())
+            .map_err(|e| e.to_string());
+    }
+    let csv = std::str::from_utf8(data)
+        .map_err(|_| String::from("neither a binary partition table nor UTF-8 CSV"))?;
+    esp_idf_part::PartitionTable::try_from_str(csv)
+        .map_err(|e| csv_error(csv).unwrap_or_else(|| e.to_string()))
+}
+
+/// `load` for a file: a `.bin` one must be binary, anything else may be
+/// either format
+pub fn load_file(name: &str, data: &[u8]) -> Result<esp_idf_part::PartitionTable, String> {
+    if name.to_lowercase().ends_with(".bin") && !data.starts_with(&ENTRY_MAGIC) {
+        return Err(String::from(
+            "not a binary partition table (no 0xAA50 magic); CSV tables need a .csv name",
+        ));
+    }
+    load(data)
+}
+
+/// A number as `gen_esp32part.py` reads it: decimal or 0x hex, optionally
+/// with a K or M suffix
+fn csv_number(value: &str) -> bool {
+    let digits = value.strip_suffix(['K', 'k', 'M', 'm']).unwrap_or(value);
+    match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).is_ok(),
+        None => digits.parse::<u32>().is_ok(),
+    }
+}
+
+/// Finds the row and field of a CSV table that can't be parsed, for an
+/// error pointing at it rather than the parser's
+fn csv_error(csv: &str) -> Option<String> {
+    const FIELDS: [&str; 6] = ["name", "type", "subtype", "offset", "size", "flags"];
+    for (index, line) in csv.lines().enumerate() {
+        let row = line.trim();
+        if row.is_empty() || row.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = row.split(',').map(str::trim).collect();
+        let at = |field: usize, problem: String| {
+            Some(format!(
+                "line {}, field '{}': {}",
+                index + 1,
+                FIELDS[field],
+                problem
+            ))
+        };
+        if fields.len() < 5 {
+            return at(
+                fields.len(),
+                format!("missing, the row has {} fields of at least 5", fields.len()),
+            );
+        }
+        if fields.len() > 6 {
+            return Some(format!(
+                "line {}: {} fields, at most 6 are allowed",
+                index + 1,
+                fields.len()
+            ));
+        }
+        if fields[0].is_empty() || fields[0].len() > 16 {
+            return at(0, format!("'{}' must have 1 to 16 characters", fields[0]));
+        }
+        if !matches!(fields[1], "app" | "data") && !csv_number(fields[1]) {
+            return at(
+                1,
+                format!("'{}' is neither app, data nor a number", fields[1]),
+            );
+        }
+        if fields[2].is_empty() {
+            return at(2, String::from("empty"));
+        }
+        if !fields[3].is_empty() && !csv_number(fields[3]) {
+            return at(3, format!("'{}' is not a number", fields[3]));
+        }
+        if !csv_number(fields[4]) {
+            return at(4, format!("'{}' is not a number", fields[4]));
+        }
+        if let Some(flag) = fields
+            .get(5)
+            .into_iter()
+            .flat_map(|flags| flags.split(':'))
+            .map(str::trim)
+            .find(|flag| !matches!(*flag, "" | "encrypted" | "readonly"))
+        {
+            return at(5, format!("unknown flag '{}'", flag));
+        }
+    }
+    None
+}
+
+/// The binary form of a table, as flashed
+pub fn to_bin(table: &esp_idf_part::PartitionTable) -> Result<Vec<u8>, String> {
+    table.to_bin().map_err(|e| e.to_string())
+}
+
+/// Hands a binary table to espflash
+pub fn for_espflash(bin: &[u8]) -> Result<PartitionTable, String> {
+    PartitionTable::try_from_bytes(bin.to_vec()).map_err(|e| e.to_string())
+}
+
+/// Renders a binary partition table in the CSV format of ESP-IDF
+pub fn to_csv(bin: &[u8]) -> Result<String, String> {
+    load(bin)?.to_csv().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tables from the ESP-IDF examples, with the entries `gen_esp32part.py`
+    /// writes for them: name, type, subtype, offset, size and flags
+    const CORPUS: [(&str, &[(&str, u8, u8, u32, u32, u32)]); 4] = [
+        (
+            include_str!("../tests/fixtures/partitions/singleapp.csv"),
+            &[
+                ("nvs", 0x01, 0x02, 0x9000, 0x6000, 0),
+                ("phy_init", 0x01, 0x01, 0xf000, 0x1000, 0),
+                ("factory", 0x00, 0x00, 0x10000, 0x100000, 0),
+            ],
+        ),
+        (
+            include_str!("../tests/fixtures/partitions/two_ota.csv"),
+            &[
+                ("nvs", 0x01, 0x02, 0x9000, 0x4000, 0),
+                ("otadata", 0x01, 0x00, 0xd000, 0x2000, 0),
+                ("phy_init", 0x01, 0x01, 0xf000, 0x1000, 0),
+                ("factory", 0x00, 0x00, 0x10000, 0x100000, 0),
+                ("ota_0", 0x00, 0x10, 0x110000, 0x100000, 0),
+                ("ota_1", 0x00, 0x11, 0x210000, 0x100000, 0),
+            ],
+        ),
+        (
+            include_str!("../tests/fixtures/partitions/singleapp_coredump.csv"),
+            &[
+                ("nvs", 0x01, 0x02, 0x9000, 0x6000, 0),
+                ("phy_init", 0x01, 0x01, 0xf000, 0x1000, 0),
+                ("factory", 0x00, 0x00, 0x10000, 0x100000, 0),
+                ("coredump", 0x01, 0x03, 0x110000, 0x10000, 0),
+            ],
+        ),
+        (
+            include_str!("../tests/fixtures/partitions/flash_encryption.csv"),
+            &[
+                ("nvs", 0x01, 0x02, 0x9000, 0x6000, 0),
+                ("storage", 0x01, 0xff, 0xf000, 0x1000, 1),
+                ("factory", 0x00, 0x00, 0x10000, 0x100000, 0),
+                ("nvs_key", 0x01, 0x04, 0x110000, 0x1000, 1),
+                ("custom_nvs", 0x01, 0x02, 0x111000, 0x6000, 0),
+            ],
+        ),
+    ];
+
+    fn rows(bin: &[u8]) -> Vec<(String, u8, u8, u32, u32, u32)> {
+        parse(bin)
+            .into_iter()
+            .map(|e| (e.name, e.ty, e.subtype, e.offset, e.size, e.flags))
+            .collect()
+    }
+
+    #[test]
+    fn corpus_matches_gen_esp32part() {
+        for (csv, expected) in CORPUS {
+            let bin = to_bin(&load(csv.as_bytes()).unwrap()).unwrap();
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|&(name, ty, subtype, offset, size, flags)| {
+                    (name.to_string(), ty, subtype, offset, size, flags)
+                })
+                .collect();
+            assert_eq!(rows(&bin), expected);
+            // the entries, the MD5 row and 0xFF padding fill the 0xC00 sector
+            assert_eq!(bin.len(), 0xc00);
+        }
+    }
+
+    #[test]
+    fn binary_tables_load_unchanged() {
+        for (csv, _) in CORPUS {
+            let bin = to_bin(&load(csv.as_bytes()).unwrap()).unwrap();
+            assert_eq!(to_bin(&load(&bin).unwrap()).unwrap(), bin);
+            assert_eq!(
+                to_bin(&load_file("partitions.bin", &bin).unwrap()).unwrap(),
+                bin
+            );
+        }
+    }
+
+    #[test]
+    fn csv_files_must_not_be_named_bin() {
+        let (csv, _) = CORPUS[0];
+        assert!(load_file("partitions.bin", csv.as_bytes()).is_err());
+        assert!(load_file("partitions.csv", csv.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn csv_round_trips_through_binary() {
+        for (csv, _) in CORPUS {
+            let bin = to_bin(&load(csv.as_bytes()).unwrap()).unwrap();
+            let rendered = to_csv(&bin).unwrap();
+            let reparsed = to_bin(&load(rendered.as_bytes()).unwrap()).unwrap();
+            assert_eq!(reparsed, bin, "{}", rendered);
+        }
+    }
+
+    #[test]
+    fn csv_errors_name_the_line_and_field() {
+        let error = |csv: &str| load(csv.as_bytes()).unwrap_err();
+        assert_eq!(
+            error("# Name, Type, SubType, Offset, Size\nnvs, data, nvs, , 0x6000\nfactory, app, factory, , 1Q\n"),
+            "line 3, field 'size': '1Q' is not a number"
+        );
+        assert_eq!(
+            error("nvs, data, nvs, 0x9000\n"),
+            "line 1, field 'size': missing, the row has 4 fields of at least 5"
+        );
+        assert_eq!(
+            error("nvs, blob, nvs, , 0x6000\n"),
+            "line 1, field 'type': 'blob' is neither app, data nor a number"
+        );
+        assert_eq!(
+            error("nvs, data, nvs, , 0x6000, secret\n"),
+            "line 1, field 'flags': unknown flag 'secret'"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+use xmas_elf::{
+    sections::{SectionData, ShType},
+    symbol_table::Entry,
+    ElfFile,
+};
+
+/// `--patch-symbol NAME=value`
+#[derive(Debug, Clone)]
+pub struct SymbolPatch {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for SymbolPatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=value, got '{}'", s))?;
+        if name.is_empty() {
+            return Err(format!("missing symbol name in '{}'", s));
+        }
+        Ok(SymbolPatch {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// A patch as applied, reported in `/elf-info`
+#[derive(Serialize, Debug, Clone)]
+pub struct AppliedPatch {
+    pub symbol: String,
+    pub value: String,
+    pub addr: u32,
+    pub size: u32,
+}
+
+/// Finds `name` in the symbol table and returns its address, size and the
+/// offset of its initial data in the file
+fn locate(file: &ElfFile, name: &str) -> Result<(u32, u32, usize), String> {
+    let (addr, size) = file
+        .section_iter()
+        .filter_map(|section| match section.get_data(file) {
+            Ok(SectionData::SymbolTable32(entries)) => Some(entries),
+            _ => None,
+        })
+        .flatten()
+        .find(|entry| entry.get_name(file) == Ok(name))
+        .map(|entry| (entry.value() as u32, entry.size() as u32))
+        .ok_or_else(|| format!("symbol {} not found in the symbol table", name))?;
+
+    let section = file
+        .section_iter()
+        .find(|section| {
+            let start = section.address() as u32;
+            section.size() > 0 && start <= addr && addr < start + section.size() as u32
+        })
+        .ok_or_else(|| format!("symbol {} at 0x{:08x} is in no section", name, addr))?;
+    if section.get_type() == Ok(ShType::NoBits) {
+        return Err(format!(
+            "symbol {} is in {}, which has no initial data to patch",
+            name,
+            section.get_name(file).unwrap_or("a NOBITS section")
+        ));
+    }
+
+    let offset = section.offset() as usize + (addr - section.address() as u32) as usize;
+    Ok((addr, size, offset))
+}
+
+/// Writes each value over its symbol's initial data, NUL-padded to the
+/// symbol's size, so the stamp ends up in the app image built afterwards
+pub fn apply(elf: &mut [u8], patches: &[SymbolPatch]) -> Result<Vec<AppliedPatch>, String> {
+    let mut applied = Vec::new();
+    for patch in patches {
+        let file = ElfFile::new(elf)?;
+        let (addr, size, offset) = locate(&file, &patch.name)?;
+
+        let value = patch.value.as_bytes();
+        if value.len() > size as usize {
+            return Err(format!(
+                "value for {} is {} bytes, but the symbol only has {}",
+                patch.name,
+                value.len(),
+                size
+            ));
+        }
+
+        let target = elf
+            .get_mut(offset..offset + size as usize)
+            .ok_or_else(|| format!("symbol {} lies beyond the end of the file", patch.name))?;
+        target.fill(0);
+        target[..value.len()].copy_from_slice(value);
+
+        applied.push(AppliedPatch {
+            symbol: patch.name.clone(),
+            value: patch.value.clone(),
+            addr,
+            size,
+        });
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use espflash::{elf::FirmwareImageBuilder, Chip};
+
+    use super::*;
+
+    /// See tests/fixtures/gen_elf.py
+    const ESP32_ELF: &[u8] = include_bytes!("../tests/fixtures/esp32-app.elf");
+    const BUILD_INFO_ADDR: u32 = 0x3f40_0120;
+    /// The image header and its extended header
+    const IMAGE_HEADER_SIZE: usize = 24;
+
+    fn patch(s: &str) -> SymbolPatch {
+        s.parse().unwrap()
+    }
+
+    /// The app image built from `elf` and the flash offset it's written at
+    fn app_image(elf: &[u8]) -> (u32, Vec<u8>) {
+        let firmware = FirmwareImageBuilder::new(elf).build().unwrap();
+        let image = Chip::Esp32
+            .get_flash_image(&firmware, None, None, None, None)
+            .unwrap();
+        let app = image.flash_segments().last().unwrap();
+        (app.addr, app.data.to_vec())
+    }
+
+    /// The `len` bytes the app image maps at `addr`, and their flash address
+    fn mapped(image: &[u8], app_offset: u32, addr: u32, len: usize) -> (u32, &[u8]) {
+        let word = |i: usize| u32::from_le_bytes(image[i..i + 4].try_into().unwrap());
+        let mut pos = IMAGE_HEADER_SIZE;
+        for _ in 0..image[1] {
+            let (load, size) = (word(pos), word(pos + 4) as usize);
+            pos += 8;
+            if load <= addr && (addr - load) as usize + len <= size {
+                let start = pos + (addr - load) as usize;
+                return (app_offset + start as u32, &image[start..start + len]);
+            }
+            pos += size;
+        }
+        panic!("no segment of the image maps 0x{:08x}", addr);
+    }
+
+    #[test]
+    fn stamp_ends_up_at_the_symbols_flash_address() {
+        let mut elf = ESP32_ELF.to_vec();
+        let applied = apply(&mut elf, &[patch("FIRMWARE_BUILD_INFO=abc1234")]).unwrap();
+        assert_eq!(applied.len(), 1);
+        assert_eq!((applied[0].addr, applied[0].size), (BUILD_INFO_ADDR, 32));
+
+        let (app_offset, image) = app_image(&elf);
+        let (flash_addr, stamp) = mapped(&image, app_offset, BUILD_INFO_ADDR, 32);
+        let mut expected = b"abc1234".to_vec();
+        expected.resize(32, 0);
+        assert_eq!(stamp, expected);
+        // the MMU maps flash in 64 KiB pages, so the offsets within a page agree
+        assert_eq!(flash_addr % 0x10000, BUILD_INFO_ADDR % 0x10000);
+
+        let (app_offset, image) = app_image(ESP32_ELF);
+        let (_, placeholder) = mapped(&image, app_offset, BUILD_INFO_ADDR, 32);
+        assert!(placeholder.starts_with(b"unset\0"));
+    }
+
+    #[test]
+    fn rejects_values_larger_than_the_symbol() {
+        let mut elf = ESP32_ELF.to_vec();
+        let value = "x".repeat(33);
+        let e = apply(
+            &mut elf,
+            &[patch(&format!("FIRMWARE_BUILD_INFO={}", value))],
+        )
+        .unwrap_err();
+        assert!(e.contains("33 bytes"), "{}", e);
+        assert_eq!(elf, ESP32_ELF);
+    }
+
+    #[test]
+    fn rejects_missing_and_uninitialized_symbols() {
+        let mut elf = ESP32_ELF.to_vec();
+        assert!(apply(&mut elf, &[patch("NO_SUCH_SYMBOL=1")])
+            .unwrap_err()
+            .contains("not found"));
+        assert!(apply(&mut elf, &[patch("BSS_BUFFER=1")])
+            .unwrap_err()
+            .contains(".dram0.bss"));
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let parsed = patch("FIRMWARE_BUILD_INFO=a=b");
+        assert_eq!(
+            (parsed.name.as_str(), parsed.value.as_str()),
+            ("FIRMWARE_BUILD_INFO", "a=b")
+        );
+        assert!("FIRMWARE_BUILD_INFO".parse::<SymbolPatch>().is_err());
+        assert!("=value".parse::<SymbolPatch>().is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use espflash::Chip;
+
+use crate::{
+    boards, checksums::Checksums, merged_image, prebuilt, prepare_error::PrepareError, PartsData,
+};
+
+/// What `pio run` leaves in `.pio/build/<env>/` for the ESP32 family
+const ARTIFACTS: &[&str] = &["bootloader.bin", "partitions.bin", "firmware.bin"];
+
+/// Boards whose name doesn't tell the chip, (board, chip)
+const KNOWN_BOARDS: &[(&str, &str)] = &[
+    ("esp32dev", "esp32"),
+    ("esp32doit-devkit-v1", "esp32"),
+    ("nodemcu-32s", "esp32"),
+    ("lolin32", "esp32"),
+    ("lolin_d32", "esp32"),
+    ("m5stack-core-esp32", "esp32"),
+    ("ttgo-t1", "esp32"),
+    ("nodemcuv2", "esp8266"),
+    ("d1_mini", "esp8266"),
+    ("esp01_1m", "esp8266"),
+    ("esp12e", "esp8266"),
+];
+
+/// Sections of `platformio.ini`, with continuation lines joined by newlines
+type Ini = BTreeMap<String, BTreeMap<String, String>>;
+
+fn parse_ini(text: &str) -> Ini {
+    let mut ini = Ini::new();
+    let mut section = String::new();
+    let mut last_key: Option<String> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with(';') || trimmed.starts_with('#') {
+            continue;
+        }
+        let value = trimmed
+            .find(" ;")
+            .map_or(trimmed, |at| trimmed[..at].trim_end());
+
+        if line.starts_with(char::is_whitespace) {
+            if let Some(key) = &last_key {
+                let entry = ini
+                    .entry(section.clone())
+                    .or_default()
+                    .entry(key.clone())
+                    .or_default();
+                entry.push('\n');
+                entry.push_str(value);
+            }
+        } else if let Some(name) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            section = name.trim().to_string();
+            last_key = None;
+        } else if let Some((key, value)) = value.split_once('=') {
+            let key = key.trim().to_string();
+            ini.entry(section.clone())
+                .or_default()
+                .insert(key.clone(), value.trim().to_string());
+            last_key = Some(key);
+        }
+    }
+    ini
+}
+
+/// The environment of a PlatformIO project to serve
+#[derive(Debug)]
+pub struct Project {
+    pub env: String,
+    pub board: Option<String>,
+    /// value for --chip, if the board or `board_build.mcu` tells it
+    pub chip: Option<String>,
+    /// value for --flash-size from `board_upload.flash_size`
+    pub flash_size: Option<String>,
+    pub build_dir: PathBuf,
+}
+
+/// A key of `[env:<name>]`, falling back to what it `extends` and to `[env]`
+fn lookup<'a>(ini: &'a Ini, section: &str, key: &str) -> Option<&'a str> {
+    let mut seen = Vec::new();
+    let mut current = Some(section.to_string());
+    while let Some(name) = current.take() {
+        if seen.contains(&name) {
+            break;
+        }
+        let table = ini.get(&name);
+        if let Some(value) = table.and_then(|table| table.get(key)) {
+            return Some(value);
+        }
+        current = table
+            .and_then(|table| table.get("extends"))
+            .and_then(|extends| extends.split([',', '\n']).next())
+            .map(|name| name.trim().to_string());
+        seen.push(name);
+    }
+    ini.get("env")?.get(key).map(String::as_str)
+}
+
+fn infer_chip(board: Option<&str>, mcu: Option<&str>, platform: Option<&str>) -> Option<String> {
+    if let Some(mcu) = mcu {
+        return Some(mcu.to_lowercase());
+    }
+    if let Some(board) = board.map(str::to_lowercase) {
+        if let Some((_, chip)) = KNOWN_BOARDS.iter().find(|(name, _)| *name == board) {
+            return Some(chip.to_string());
+        }
+        let by_name = [
+            ("c3", "esp32c3"),
+            ("s3", "esp32s3"),
+            ("s2", "esp32s2"),
+            ("8266", "esp8266"),
+        ];
+        if let Some((_, chip)) = by_name.iter().find(|(part, _)| board.contains(part)) {
+            return Some(chip.to_string());
+        }
+    }
+    match platform? {
+        platform if platform.contains("espressif8266") => Some(String::from("esp8266")),
+        platform if platform.contains("espressif32") => Some(String::from("esp32")),
+        _ => None,
+    }
+}
+
+/// Reads `platformio.ini` in `dir` and picks `env`, the first of
+/// `default_envs`, or the only environment
+pub fn load(dir: &Path, env: Option<&str>) -> Result<Project, PrepareError> {
+    let path = dir.join("platformio.ini");
+    let text = std::fs::read_to_string(&path).map_err(|e| {
+        PrepareError::PlatformIo(format!("failed to read {}: {}", path.display(), e))
+    })?;
+    let ini = parse_ini(&text);
+
+    let envs: Vec<&str> = ini
+        .keys()
+        .filter_map(|name| name.strip_prefix("env:"))
+        .map(str::trim)
+        .collect();
+    let default_env = ini
+        .get("platformio")
+        .and_then(|table| table.get("default_envs"))
+        .and_then(|envs| {
+            envs.split([',', '\n'])
+                .map(str::trim)
+                .find(|e| !e.is_empty())
+        });
+    let env = match (env, default_env, envs.as_slice()) {
+        (Some(env), _, _) | (None, Some(env), _) | (None, None, [env]) => env.to_string(),
+        (None, None, []) => {
+            return Err(PrepareError::PlatformIo(format!(
+                "{} defines no [env:<name>] section",
+                path.display()
+            )))
+        }
+        (None, None, _) => {
+            return Err(PrepareError::PlatformIo(format!(
+                "several environments, pick one with --pio-env: {}",
+                envs.join(", ")
+            )))
+        }
+    };
+    if !envs.contains(&env.as_str()) {
+        return Err(PrepareError::PlatformIo(format!(
+            "no environment '{}' in {} (environments: {})",
+            env,
+            path.display(),
+            envs.join(", ")
+        )));
+    }
+
+    let section = format!("env:{}", env);
+    let board = lookup(&ini, &section, "board").map(str::to_string);
+    let chip = infer_chip(
+        board.as_deref(),
+        lookup(&ini, &section, "board_build.mcu"),
+        lookup(&ini, &section, "platform"),
+    );
+    let build_dir = ini
+        .get("platformio")
+        .and_then(|table| table.get("build_dir"))
+        .map_or_else(|| dir.join(".pio").join("build"), |build| dir.join(build))
+        .join(&env);
+
+    Ok(Project {
+        board,
+        chip,
+        flash_size: lookup(&ini, &section, "board_upload.flash_size").map(str::to_string),
+        env,
+        build_dir,
+    })
+}
+
+/// Inserts the chip and flash size of the `--pio` project into `args`
+/// (after the subcommand at index 1) unless they're given explicitly
+pub fn apply(args: &mut Vec<OsString>) -> Result<(), String> {
+    let dir = match boards::value_of(args, "--pio") {
+        Some(dir) => PathBuf::from(dir),
+        None => return Ok(()),
+    };
+    let env = boards::value_of(args, "--pio-env");
+    let project = load(&dir, env.as_deref()).map_err(|e| e.to_string())?;
+
+    status!(
+        "PlatformIO: environment {} (board {})",
+        project.env,
+        project.board.as_deref().unwrap_or("unknown")
+    );
+    let candidates = [
+        ("--chip", Some('c'), project.chip.clone()),
+        ("--flash-size", Some('f'), project.flash_size.clone()),
+    ];
+    let mut defaults = Vec::new();
+    for (flag, short, value) in candidates {
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        if boards::given(args, flag, short) {
+            status!("  {} {} (overridden)", flag, value);
+        } else {
+            status!("  {} {}", flag, value);
+            defaults.push(OsString::from(flag));
+            defaults.push(OsString::from(value));
+        }
+    }
+
+    let at = 2.min(args.len());
+    args.splice(at..at, defaults);
+    Ok(())
+}
+
+/// Serves the binaries `pio run` built for the project's environment,
+/// `--pio`. ESP8266 builds are a single image written at 0x0.
+pub fn prepare(
+    dir: &Path,
+    env: Option<&str>,
+    chip: Chip,
+    flash_size: &str,
+    flash_size_bytes: u32,
+    checksums: &Checksums,
+) -> Result<PartsData, PrepareError> {
+    let project = load(dir, env)?;
+    let artifacts: &[&str] = match chip {
+        Chip::Esp8266 => &["firmware.bin"],
+        _ => ARTIFACTS,
+    };
+    let missing: Vec<&str> = artifacts
+        .iter()
+        .copied()
+        .filter(|file| !project.build_dir.join(file).is_file())
+        .collect();
+    if !missing.is_empty() {
+        return Err(PrepareError::PlatformIo(format!(
+            "{} not found in {}, run `pio run -e {}` first",
+            missing.join(", "),
+            project.build_dir.display(),
+            project.env
+        )));
+    }
+
+    if chip == Chip::Esp8266 {
+        return merged_image::prepare(
+            &project.build_dir.join("firmware.bin"),
+            chip,
+            flash_size,
+            flash_size_bytes,
+            checksums,
+        );
+    }
+    // the conventional names prebuilt picks up without --part
+    prebuilt::prepare(
+        &project.build_dir,
+        &[],
+        chip,
+        flash_size,
+        flash_size_bytes,
+        checksums,
+    )
+}
@@ -0,0 +1,292 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use espflash::Chip;
+
+use crate::{
+    bootloader_image, checksums::Checksums, fota, layout, partition_gen, partition_table,
+    prepare_error::PrepareError, ExtraPart, PartsData,
+};
+
+pub const PARTITION_TABLE_OFFSET: u32 = 0x8000;
+const PARTITION_TABLE_MAGIC: &[u8] = &[0xaa, 0x50];
+/// Where the app goes when the partition table has no app partition
+pub const DEFAULT_APP_OFFSET: u32 = 0x10000;
+const TYPE_APP: u8 = 0x00;
+
+/// `--part <file>@<offset>`, also used by `--extra-part`
+#[derive(Debug, Clone)]
+pub struct PartSpec {
+    pub file: String,
+    pub offset: u32,
+}
+
+impl FromStr for PartSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once('@') {
+            Some((file, offset)) if !file.is_empty() => Ok(PartSpec {
+                file: file.to_string(),
+                offset: partition_gen::parse_size(offset)?,
+            }),
+            _ => Err(format!(
+                "expected <file>@<offset>, e.g. app.bin@0x10000, got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+/// Where the ROM loads the second stage bootloader from
+pub fn bootloader_offset(chip: Chip) -> u32 {
+    crate::chips::info(chip).bootloader_offset
+}
+
+/// A binary read from disk and the offset it is written to
+pub struct Loaded {
+    pub file: String,
+    pub path: PathBuf,
+    pub offset: u32,
+    pub data: Vec<u8>,
+}
+
+pub fn load(
+    dir: &Path,
+    file: &str,
+    offset: u32,
+    checksums: &Checksums,
+) -> Result<Loaded, PrepareError> {
+    let path = dir.join(file);
+    let data = checksums.read(&path)?;
+    Ok(Loaded {
+        file: file.to_string(),
+        path,
+        offset,
+        data,
+    })
+}
+
+/// Finds the first of `names` that exists in `dir`
+fn detect(dir: &Path, names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find(|name| dir.join(name).is_file())
+        .map(|name| name.to_string())
+}
+
+/// Loads the parts given with `--part`, or the conventionally named files
+/// in `dir`, placing the app at the first app partition
+fn load_parts(
+    dir: &Path,
+    specs: &[PartSpec],
+    chip: Chip,
+    checksums: &Checksums,
+) -> Result<Vec<Loaded>, PrepareError> {
+    if !specs.is_empty() {
+        return specs
+            .iter()
+            .map(|spec| load(dir, &spec.file, spec.offset, checksums))
+            .collect();
+    }
+
+    let mut parts = Vec::new();
+    if let Some(file) = detect(dir, &["bootloader.bin"]) {
+        parts.push(load(dir, &file, bootloader_offset(chip), checksums)?);
+    }
+    if let Some(file) = detect(dir, &["partitions.bin", "partition-table.bin"]) {
+        let table = load(dir, &file, PARTITION_TABLE_OFFSET, checksums)?;
+        let app_offset = partition_table::parse(&table.data)
+            .iter()
+            .filter(|e| e.ty == TYPE_APP)
+            .map(|e| e.offset)
+            .min()
+            .unwrap_or(DEFAULT_APP_OFFSET);
+        parts.push(table);
+
+        if let Some(file) = detect(dir, &["firmware.bin", "app.bin"]) {
+            parts.push(load(dir, &file, app_offset, checksums)?);
+        }
+    }
+    Ok(parts)
+}
+
+fn take(parts: &mut Vec<Loaded>, part: &'static str, offset: u32) -> Result<Loaded, PrepareError> {
+    match parts.iter().position(|p| p.offset == offset) {
+        Some(i) => Ok(parts.remove(i)),
+        None => Err(PrepareError::MissingPart { part, offset }),
+    }
+}
+
+/// Serves prebuilt binaries as-is, bypassing the espflash image pipeline.
+/// Only existence, magic bytes, overlaps and the flash size are checked.
+pub fn prepare(
+    dir: &Path,
+    specs: &[PartSpec],
+    chip: Chip,
+    flash_size: &str,
+    flash_size_bytes: u32,
+    checksums: &Checksums,
+) -> Result<PartsData, PrepareError> {
+    let parts = load_parts(dir, specs, chip, checksums)?;
+    serve(
+        dir,
+        parts,
+        bootloader_offset(chip),
+        PARTITION_TABLE_OFFSET,
+        chip,
+        flash_size,
+        flash_size_bytes,
+    )
+}
+
+/// Checks loaded parts and turns them into the served build, taking the
+/// bootloader and partition table from the given offsets and the app from
+/// the first app partition. Any other part is served by its file name.
+pub fn serve(
+    dir: &Path,
+    mut parts: Vec<Loaded>,
+    bootloader_at: u32,
+    table_at: u32,
+    chip: Chip,
+    flash_size: &str,
+    flash_size_bytes: u32,
+) -> Result<PartsData, PrepareError> {
+    let mut sorted: Vec<&Loaded> = parts.iter().collect();
+    sorted.sort_by_key(|p| p.offset);
+    for pair in sorted.windows(2) {
+        if pair[0].offset + pair[0].data.len() as u32 > pair[1].offset {
+            return Err(PrepareError::PartsOverlap {
+                first: pair[0].file.clone(),
+                second: pair[1].file.clone(),
+                offset: pair[1].offset,
+            });
+        }
+    }
+    if let Some(last) = sorted.last() {
+        let end = last.offset + last.data.len() as u32;
+        if end > flash_size_bytes {
+            return Err(PrepareError::InvalidPart {
+                path: last.path.clone(),
+                reason: format!("ends at 0x{:x}, beyond the {} flash", end, flash_size),
+            });
+        }
+    }
+
+    let bootloader = take(&mut parts, "bootloader", bootloader_at)?;
+    let partitions = take(&mut parts, "partition table", table_at)?;
+    let table = partition_table::parse(&partitions.data);
+    let app_offset = table
+        .iter()
+        .filter(|e| e.ty == TYPE_APP)
+        .map(|e| e.offset)
+        .min()
+        .unwrap_or(DEFAULT_APP_OFFSET);
+    let app = take(&mut parts, "app", app_offset)?;
+
+    for image in [&bootloader, &app] {
+        bootloader_image::validate(&image.data, chip).map_err(|reason| {
+            PrepareError::InvalidPart {
+                path: image.path.clone(),
+                reason,
+            }
+        })?;
+    }
+    if !partitions.data.starts_with(PARTITION_TABLE_MAGIC) {
+        return Err(PrepareError::InvalidPart {
+            path: partitions.path.clone(),
+            reason: String::from("not a binary partition table (missing the 0xAA50 magic)"),
+        });
+    }
+
+    let extra_parts: Vec<ExtraPart> = parts
+        .into_iter()
+        .map(|part| ExtraPart {
+            file: Path::new(&part.file).file_name().map_or_else(
+                || part.file.clone(),
+                |name| name.to_string_lossy().into_owned(),
+            ),
+            label: part.file,
+            offset: part.offset,
+            data: part.data,
+        })
+        .collect();
+
+    let total_size = bootloader.data.len()
+        + partitions.data.len()
+        + app.data.len()
+        + extra_parts.iter().map(|p| p.data.len()).sum::<usize>();
+
+    status!("Serving prebuilt binaries from {}:", dir.display());
+    let summary = [&bootloader, &partitions, &app]
+        .into_iter()
+        .map(|p| (&p.file, p.offset, p.data.len()))
+        .chain(
+            extra_parts
+                .iter()
+                .map(|p| (&p.label, p.offset, p.data.len())),
+        );
+    for (file, offset, size) in summary {
+        status!("  {} at 0x{:x}: {} bytes", file, offset, size);
+    }
+    status!("  Total: {} bytes", total_size);
+
+    let mut written = vec![
+        layout::WrittenPart {
+            name: "bootloader",
+            offset: bootloader.offset,
+            size: bootloader.data.len() as u32,
+        },
+        layout::WrittenPart {
+            name: "partition table",
+            offset: partitions.offset,
+            size: partitions.data.len() as u32,
+        },
+        layout::WrittenPart {
+            name: "firmware",
+            offset: app.offset,
+            size: app.data.len() as u32,
+        },
+    ];
+    written.extend(extra_parts.iter().map(|part| layout::WrittenPart {
+        name: &part.label,
+        offset: part.offset,
+        size: part.data.len() as u32,
+    }));
+    let layout = layout::compute(&written, &table, flash_size_bytes);
+
+    Ok(PartsData {
+        chip: crate::chip_name(chip).to_string(),
+        total_size,
+        bootloader_size: bootloader.data.len(),
+        partitions_size: partitions.data.len(),
+        firmware_size: app.data.len(),
+        flash_size: flash_size.to_string(),
+        partition_table_source: partitions.path.display().to_string(),
+        bootloader_offset: bootloader.offset,
+        partitions_offset: partitions.offset,
+        firmware_offset: app.offset,
+        bootloader: bootloader.data,
+        partitions: partitions.data,
+        firmware: app.data,
+        extra_parts,
+        layout,
+        elf_info: Default::default(),
+        app_version: fota::app_version(&app.data),
+        app_desc: None,
+        single_part: false,
+        app_only: false,
+        verified_inputs: Vec::new(),
+        board: None,
+        sbom: None,
+        elf_path: None,
+        elf_mtime: None,
+        elf: None,
+        flash_mode: None,
+        flash_freq: None,
+        app_header: None,
+        min_chip_rev: None,
+    })
+}
@@ -0,0 +1,272 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+/// Why an image couldn't be prepared, with enough context to act on it
+#[derive(Debug, Error)]
+pub enum PrepareError {
+    #[error("the following required argument was not provided: <ELF>")]
+    MissingElf,
+
+    #[error("No ELF file for '{pattern}': {reason}")]
+    ElfPattern { pattern: String, reason: String },
+
+    #[error("Failed to find the ELF with --cargo: {0}")]
+    CargoDiscovery(String),
+
+    #[error("Failed to download {url}: {reason}")]
+    Download { url: String, reason: String },
+
+    #[error("Failed to read {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Failed to build an image from {}: {reason}", path.display())]
+    InvalidElf { path: PathBuf, reason: String },
+
+    #[error("Failed to patch {}: {reason}", path.display())]
+    PatchSymbol { path: PathBuf, reason: String },
+
+    #[error(
+        "No flashable code found in {}: the app image is {app_size} bytes and none of its \
+         segments is in flash, check the linker script (or pass --allow-empty-app)\n\
+         ELF segments:\n{segments}",
+        path.display()
+    )]
+    NoFlashableCode {
+        path: PathBuf,
+        app_size: usize,
+        /// one line per program header with its address range and region
+        segments: String,
+    },
+
+    #[error("Cannot serve the PlatformIO project: {0}")]
+    PlatformIo(String),
+
+    #[error("Invalid app image {}: {reason}", path.display())]
+    InvalidAppImage { path: PathBuf, reason: String },
+
+    #[error("Invalid partition table ({table}): {reason}")]
+    InvalidPartitionTable { table: String, reason: String },
+
+    #[error("Cannot generate a partition table: {0}")]
+    PartitionTableGeneration(String),
+
+    #[error(
+        "Partition '{partition}' of the {table} partition table ends at 0x{end:x}, beyond the {flash_size} flash"
+    )]
+    PartitionBeyondFlash {
+        table: String,
+        partition: String,
+        end: u32,
+        flash_size: String,
+    },
+
+    #[error("Partitions '{first}' and '{second}' overlap at 0x{offset:x}")]
+    PartitionsOverlap {
+        first: String,
+        second: String,
+        offset: u32,
+    },
+
+    #[error("Partition '{name}' not found in the partition table (available: {available})")]
+    PartitionNotFound { name: String, available: String },
+
+    #[error("Partition '{0}' is not an app partition")]
+    NotAnAppPartition(String),
+
+    #[error("The {app} ({size} bytes) does not fit partition '{partition}' ({capacity} bytes)")]
+    AppTooLarge {
+        app: &'static str,
+        size: usize,
+        partition: String,
+        capacity: u32,
+    },
+
+    #[error("Invalid bootloader {}: {reason}", path.display())]
+    InvalidBootloader { path: PathBuf, reason: String },
+
+    #[error("Unsupported flash settings: {0}")]
+    FlashParams(String),
+
+    #[error("Invalid offsets: {reason}\nLayout:\n{layout}")]
+    Offsets { reason: String, layout: String },
+
+    #[error("Invalid --second-app: {0}")]
+    SecondApp(String),
+
+    #[error("Failed to assemble the flash image: {0}")]
+    Image(String),
+
+    #[error(
+        "Checksum mismatch for {}:\n  expected sha256 {expected}\n  actual   sha256 {actual}",
+        path.display()
+    )]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Invalid checksums file {}: {reason}", path.display())]
+    InvalidChecksums { path: PathBuf, reason: String },
+
+    #[error("Cannot generate the nvs partition from {}: {reason}", path.display())]
+    NvsCsv { path: PathBuf, reason: String },
+
+    #[error("Cannot build the filesystem image of {}: {reason}", path.display())]
+    Filesystem { path: PathBuf, reason: String },
+
+    #[error("Invalid part {}: {reason}", path.display())]
+    InvalidPart { path: PathBuf, reason: String },
+
+    #[error("No {part} found at 0x{offset:x}; pass it with --part <file>@0x{offset:x}")]
+    MissingPart { part: &'static str, offset: u32 },
+
+    #[error("Parts {first} and {second} overlap at 0x{offset:x}")]
+    PartsOverlap {
+        first: String,
+        second: String,
+        offset: u32,
+    },
+}
+
+impl PrepareError {
+    /// Process exit code for this error, so scripts can tell failures apart
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PrepareError::MissingElf => 2,
+            PrepareError::Read { .. }
+            | PrepareError::ElfPattern { .. }
+            | PrepareError::CargoDiscovery(_)
+            | PrepareError::Download { .. }
+            | PrepareError::PlatformIo(_) => 3,
+            PrepareError::InvalidElf { .. }
+            | PrepareError::InvalidAppImage { .. }
+            | PrepareError::PatchSymbol { .. }
+            | PrepareError::NoFlashableCode { .. }
+            | PrepareError::Image(_) => 4,
+            PrepareError::InvalidPartitionTable { .. }
+            | PrepareError::PartitionTableGeneration(_)
+            | PrepareError::PartitionBeyondFlash { .. }
+            | PrepareError::PartitionsOverlap { .. }
+            | PrepareError::PartitionNotFound { .. }
+            | PrepareError::Offsets { .. }
+            | PrepareError::NotAnAppPartition(_) => 5,
+            PrepareError::AppTooLarge { .. } => 6,
+            PrepareError::InvalidBootloader { .. } | PrepareError::FlashParams(_) => 7,
+            PrepareError::SecondApp(_) => 8,
+            PrepareError::InvalidPart { .. }
+            | PrepareError::MissingPart { .. }
+            | PrepareError::PartsOverlap { .. } => 9,
+            PrepareError::ChecksumMismatch { .. } | PrepareError::InvalidChecksums { .. } => 10,
+            PrepareError::NvsCsv { .. } | PrepareError::Filesystem { .. } => 11,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use espflash::Chip;
+
+    use super::*;
+    use crate::{
+        checksums::{Checksums, ExpectSha256},
+        elf_arg,
+        partition_table::PartitionEntry,
+        rules,
+    };
+
+    fn entry(name: &str, subtype: u8, offset: u32, size: u32) -> PartitionEntry {
+        PartitionEntry {
+            name: name.to_string(),
+            ty: 0x00,
+            subtype,
+            offset,
+            size,
+            flags: 0,
+        }
+    }
+
+    #[test]
+    fn unreadable_elf() {
+        let path = Path::new("/nonexistent/app.elf");
+        let e = Checksums::new(&[], None).unwrap().read(path).unwrap_err();
+        assert!(matches!(&e, PrepareError::Read { path: p, .. } if p == path));
+        assert_eq!(e.exit_code(), 3);
+    }
+
+    #[test]
+    fn unmatched_elf_pattern() {
+        let e = elf_arg::resolve(Path::new("/nonexistent/*.elf")).unwrap_err();
+        assert!(
+            matches!(&e, PrepareError::ElfPattern { pattern, .. } if pattern == "/nonexistent/*.elf")
+        );
+        assert_eq!(e.exit_code(), 3);
+    }
+
+    #[test]
+    fn invalid_elf() {
+        let e = rules::elf_arch(b"not an elf", Path::new("app.elf"), Chip::Esp32).unwrap_err();
+        assert!(
+            matches!(&e, PrepareError::InvalidElf { path, .. } if path == Path::new("app.elf"))
+        );
+        assert_eq!(e.exit_code(), 4);
+    }
+
+    #[test]
+    fn invalid_partition_table() {
+        let table = [
+            entry("factory", 0x00, 0x10000, 0x100000),
+            entry("ota_0", 0x10, 0x100000, 0x100000),
+        ];
+        let e = rules::partitions_disjoint(&table).unwrap_err();
+        assert!(matches!(
+            &e,
+            PrepareError::PartitionsOverlap { first, second, offset: 0x100000 }
+                if first == "factory" && second == "ota_0"
+        ));
+        assert_eq!(e.exit_code(), 5);
+
+        let e = rules::table_fits_flash(&table, "csv", 0x100000, "1MB").unwrap_err();
+        assert!(matches!(
+            &e,
+            PrepareError::PartitionBeyondFlash { partition, end: 0x200000, .. } if partition == "ota_0"
+        ));
+        assert_eq!(e.exit_code(), 5);
+    }
+
+    #[test]
+    fn firmware_too_large() {
+        let table = [
+            entry("factory", 0x00, 0x10000, 0x100000),
+            entry("ota_0", 0x10, 0x110000, 0x80000),
+        ];
+        let e = rules::app_fits_partitions(0x90000, &table, 0x10000).unwrap_err();
+        assert!(matches!(
+            &e,
+            PrepareError::AppTooLarge { size: 0x90000, partition, capacity: 0x80000, .. }
+                if partition == "ota_0"
+        ));
+        assert_eq!(e.exit_code(), 6);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"));
+        let expect: ExpectSha256 = format!("Cargo.toml={}", "0".repeat(64)).parse().unwrap();
+        let e = Checksums::new(&[expect], None)
+            .unwrap()
+            .read(path)
+            .unwrap_err();
+        assert!(
+            matches!(&e, PrepareError::ChecksumMismatch { expected, .. } if expected == &"0".repeat(64))
+        );
+        assert_eq!(e.exit_code(), 10);
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Deref,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use arc_swap::ArcSwap;
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    tokio::sync::broadcast,
+};
+use serde::Serialize;
+
+use crate::{
+    checksums::{self, FileDigest},
+    prepare_error::PrepareError,
+    summary, PartsData,
+};
+
+/// Longest build error reported by `/status`, in characters
+const MAX_ERROR_LEN: usize = 2000;
+/// Updates queued for a slow `/events` subscriber before it misses some
+const EVENT_BACKLOG: usize = 16;
+/// A retained build fetched from this recently is kept even over the
+/// limits, so a flash of it in progress can fetch its remaining parts
+const IN_USE_SECS: u64 = 120;
+
+/// Everything served for one build: the parts and the hash identifying them.
+///
+/// Never mutated after creation; a rebuild produces a new `PreparedBuild` that
+/// replaces the old one as a whole, so a client can't get parts of two builds.
+pub struct PreparedBuild {
+    /// short content hash, embedded in the manifest's part URLs
+    pub hash: String,
+    pub parts: PartsData,
+    /// SHA-256 of every part a full install writes, in flash order
+    pub digests: Vec<FileDigest>,
+}
+
+impl PreparedBuild {
+    pub fn new(parts: PartsData) -> Self {
+        let hash = content_hash(&parts);
+        let mut build = PreparedBuild {
+            hash,
+            parts,
+            digests: Vec::new(),
+        };
+        build.digests = build
+            .parts
+            .parts()
+            .into_iter()
+            .map(|part| FileDigest {
+                sha256: checksums::sha256_hex(build.part(&part.name).unwrap_or_default()),
+                file: part.name,
+            })
+            .collect();
+        build
+    }
+
+    /// SHA-256 of a part by the file name it's served under
+    pub fn sha256(&self, file: &str) -> Option<&str> {
+        self.digests
+            .iter()
+            .find(|digest| digest.file == file)
+            .map(|digest| digest.sha256.as_str())
+    }
+
+    /// Looks up a part by the file name it's served under; a merged image
+    /// has no bootloader and possibly no partition table of its own
+    pub fn part(&self, file: &str) -> Option<&[u8]> {
+        match file {
+            "bootloader.bin" | "partitions.bin" if self.parts.app_only => None,
+            "bootloader.bin" => non_empty(&self.parts.bootloader),
+            "partitions.bin" => non_empty(&self.parts.partitions),
+            "firmware.bin" => Some(&self.parts.firmware),
+            _ => self
+                .parts
+                .extra_parts
+                .iter()
+                .find(|part| part.file == file)
+                .map(|part| part.data.as_slice()),
+        }
+    }
+}
+
+fn non_empty(data: &[u8]) -> Option<&[u8]> {
+    (!data.is_empty()).then_some(data)
+}
+
+/// CRC32 over every part's offset and contents
+fn content_hash(parts: &PartsData) -> String {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut add = |offset: u32, data: &[u8]| {
+        hasher.update(&offset.to_le_bytes());
+        hasher.update(&(data.len() as u32).to_le_bytes());
+        hasher.update(data);
+    };
+    add(parts.bootloader_offset, &parts.bootloader);
+    add(parts.partitions_offset, &parts.partitions);
+    add(parts.firmware_offset, &parts.firmware);
+    for part in &parts.extra_parts {
+        add(part.offset, &part.data);
+    }
+    format!("{:08x}", hasher.finalize())
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildState {
+    /// serving the latest build
+    Ready,
+    /// a rebuild is running, the previous build is still served
+    Building,
+    /// the last rebuild failed, the previous good build is still served
+    Error,
+}
+
+/// What `/status` reports about the rebuild machinery
+#[derive(Serialize, Debug, Clone)]
+pub struct BuildStatus {
+    pub state: BuildState,
+    /// number of builds served so far, starting at 1
+    pub build_counter: u64,
+    /// when the served build was prepared, in seconds since the Unix epoch
+    pub last_build_time: u64,
+    pub last_error: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// How many previous builds are kept, see `--keep-builds`
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    /// number of builds, including the current one
+    pub count: usize,
+    /// total size of the parts of all kept builds, in bytes
+    pub budget: usize,
+}
+
+/// A build that was served at some point, kept for `/builds`
+struct Retained {
+    build: Arc<PreparedBuild>,
+    number: u64,
+    time: u64,
+    /// when one of its parts was last requested, seconds since the Unix epoch
+    last_used: AtomicU64,
+}
+
+/// What `/builds` lists about a retained build
+#[derive(Serialize, Debug, Clone)]
+pub struct BuildSummary {
+    pub hash: String,
+    pub number: u64,
+    /// when it was prepared, in seconds since the Unix epoch
+    pub time: u64,
+    pub total_size: usize,
+    pub app_version: Option<String>,
+    pub current: bool,
+}
+
+/// The `firmware-updated` event of `/events`, sent when a new build is served
+#[derive(Serialize, Debug, Clone)]
+pub struct FirmwareUpdated {
+    pub hash: String,
+    pub build_counter: u64,
+    pub total_size: usize,
+    pub app_version: Option<String>,
+    pub parts: Vec<summary::Part>,
+}
+
+/// `--build <name>=<elf>`
+#[derive(Debug, Clone)]
+pub struct NamedBuild {
+    pub name: String,
+    pub elf: PathBuf,
+}
+
+impl FromStr for NamedBuild {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((name, elf)) if !name.is_empty() && !elf.is_empty() => {
+                // it ends up in query strings and the page
+                if !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    return Err(format!(
+                        "build name '{}' may only contain letters, digits, '-' and '_'",
+                        name
+                    ));
+                }
+                Ok(NamedBuild {
+                    name: name.to_string(),
+                    elf: PathBuf::from(elf),
+                })
+            }
+            _ => Err(format!(
+                "expected <name>=<elf>, e.g. staging=target/staging/app.elf, got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+/// The build currently being served, swapped atomically on rebuilds, and
+/// the previous builds still available by hash.
+/// Clones share the same builds, so every listener serves the same ones.
+#[derive(Clone)]
+pub struct Builds {
+    current: Arc<ArcSwap<PreparedBuild>>,
+    status: Arc<Mutex<BuildStatus>>,
+    /// oldest first; the last one is the current build
+    retained: Arc<Mutex<VecDeque<Retained>>>,
+    retention: Retention,
+    /// `--build <name>=<elf>`, selected with `?build=<name>`; never
+    /// replaced or evicted
+    named: Arc<BTreeMap<String, Arc<PreparedBuild>>>,
+    updates: broadcast::Sender<FirmwareUpdated>,
+}
+
+impl Builds {
+    pub fn new(build: PreparedBuild, retention: Retention) -> Self {
+        let build = Arc::new(build);
+        let builds = Builds {
+            current: Arc::new(ArcSwap::new(build.clone())),
+            status: Arc::new(Mutex::new(BuildStatus {
+                state: BuildState::Ready,
+                build_counter: 1,
+                last_build_time: now(),
+                last_error: None,
+            })),
+            retained: Arc::new(Mutex::new(VecDeque::new())),
+            retention,
+            named: Arc::default(),
+            updates: broadcast::channel(EVENT_BACKLOG).0,
+        };
+        builds.retain(build, 1);
+        builds
+    }
+
+    fn retain(&self, build: Arc<PreparedBuild>, number: u64) {
+        let mut retained = self.retained.lock().unwrap();
+        // a rebuild with the same result replaces its earlier entry
+        retained.retain(|r| r.build.hash != build.hash);
+        retained.push_back(Retained {
+            build,
+            number,
+            time: now(),
+            last_used: AtomicU64::new(0),
+        });
+
+        // evict the oldest builds not in use until within the limits; the
+        // current build is never evicted
+        let size = |r: &Retained| r.build.parts.total_size;
+        let mut total: usize = retained.iter().map(size).sum();
+        let mut i = 0;
+        while i + 1 < retained.len()
+            && (retained.len() > self.retention.count || total > self.retention.budget)
+        {
+            if now().saturating_sub(retained[i].last_used.load(Ordering::Relaxed)) < IN_USE_SECS {
+                i += 1;
+                continue;
+            }
+            if let Some(evicted) = retained.remove(i) {
+                total -= size(&evicted);
+            }
+        }
+    }
+
+    /// Adds the builds selected by name
+    pub fn with_named(mut self, named: BTreeMap<String, PreparedBuild>) -> Self {
+        self.named = Arc::new(
+            named
+                .into_iter()
+                .map(|(name, build)| (name, Arc::new(build)))
+                .collect(),
+        );
+        self
+    }
+
+    /// The current, a retained or a named build by its hash
+    pub fn find(&self, hash: &str) -> Option<Arc<PreparedBuild>> {
+        let retained = self.retained.lock().unwrap();
+        if let Some(found) = retained.iter().find(|r| r.build.hash == hash) {
+            found.last_used.store(now(), Ordering::Relaxed);
+            return Some(found.build.clone());
+        }
+        self.named
+            .values()
+            .find(|build| build.hash == hash)
+            .cloned()
+    }
+
+    pub fn named(&self, name: &str) -> Option<Arc<PreparedBuild>> {
+        self.named.get(name).cloned()
+    }
+
+    /// Names of the builds given with `--build`, sorted
+    pub fn names(&self) -> Vec<String> {
+        self.named.keys().cloned().collect()
+    }
+
+    /// The retained builds, newest first
+    pub fn list(&self) -> Vec<BuildSummary> {
+        let current = self.current();
+        self.retained
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .map(|r| BuildSummary {
+                hash: r.build.hash.clone(),
+                number: r.number,
+                time: r.time,
+                total_size: r.build.parts.total_size,
+                app_version: r.build.parts.app_version.clone(),
+                current: Arc::ptr_eq(&r.build, &current),
+            })
+            .collect()
+    }
+
+    pub fn current(&self) -> Arc<PreparedBuild> {
+        self.current.load_full()
+    }
+
+    /// Receives an event for every build served from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<FirmwareUpdated> {
+        self.updates.subscribe()
+    }
+
+    pub fn status(&self) -> BuildStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Marks a rebuild as running until [`Builds::finish`] is called
+    pub fn start_rebuild(&self) {
+        self.status.lock().unwrap().state = BuildState::Building;
+    }
+
+    /// Serves the result of a rebuild, or keeps the previous build and
+    /// records the error if it failed
+    pub fn finish(&self, result: Result<PreparedBuild, PrepareError>) {
+        let mut status = self.status.lock().unwrap();
+        match result {
+            Ok(build) => {
+                let build = Arc::new(build);
+                self.current.store(build.clone());
+                status.state = BuildState::Ready;
+                status.build_counter += 1;
+                self.retain(build.clone(), status.build_counter);
+                status.last_build_time = now();
+                status.last_error = None;
+                // no open page is not an error
+                self.updates
+                    .send(FirmwareUpdated {
+                        hash: build.hash.clone(),
+                        build_counter: status.build_counter,
+                        total_size: build.parts.total_size,
+                        app_version: build.parts.app_version.clone(),
+                        parts: summary::parts(&build),
+                    })
+                    .ok();
+            }
+            Err(e) => {
+                let mut message = e.to_string();
+                if let Some((i, _)) = message.char_indices().nth(MAX_ERROR_LEN) {
+                    message.truncate(i);
+                    message.push('…');
+                }
+                status.state = BuildState::Error;
+                status.last_error = Some(message);
+            }
+        }
+    }
+}
+
+/// Request guard resolving the current build once per request, so all
+/// guards and the handler of one request see the same build. `?build=<name>`
+/// picks a named build instead; an unknown name is a 404.
+pub struct Current(pub Arc<PreparedBuild>);
+
+impl Deref for Current {
+    type Target = PreparedBuild;
+
+    fn deref(&self) -> &PreparedBuild {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Current {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let builds = match req.rocket().state::<Builds>() {
+            Some(builds) => builds,
+            None => return Outcome::Failure((Status::NotFound, "no image is being served")),
+        };
+        if let Some(name) = req.query_value::<&str>("build").and_then(Result::ok) {
+            return match builds.named(name) {
+                Some(build) => Outcome::Success(Current(build)),
+                None => Outcome::Failure((Status::NotFound, "no build of that name")),
+            };
+        }
+        let build = req.local_cache(|| builds.current());
+        Outcome::Success(Current(build.clone()))
+    }
+}
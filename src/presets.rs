@@ -0,0 +1,91 @@
+use std::{path::PathBuf, str::FromStr};
+
+/// A well-known partition layout that can be used instead of a CSV file
+#[derive(Debug)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// smallest flash the layout fits in
+    pub min_flash_size: &'static str,
+    pub csv: &'static str,
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "singleapp",
+        description: "nvs, phy_init and a 1MB factory app (IDF default)",
+        min_flash_size: "2MB",
+        csv: include_str!("presets/singleapp.csv"),
+    },
+    Preset {
+        name: "singleapp_large",
+        description: "nvs, phy_init and a 1.5MB factory app",
+        min_flash_size: "2MB",
+        csv: include_str!("presets/singleapp_large.csv"),
+    },
+    Preset {
+        name: "two_ota",
+        description: "factory app plus two 1MB OTA slots",
+        min_flash_size: "4MB",
+        csv: include_str!("presets/two_ota.csv"),
+    },
+    Preset {
+        name: "two_ota_large",
+        description: "two 1.6MB OTA slots without a factory app",
+        min_flash_size: "4MB",
+        csv: include_str!("presets/two_ota_large.csv"),
+    },
+    Preset {
+        name: "minimal",
+        description: "1.25MB OTA app, spiffs and coredump for 2MB boards",
+        min_flash_size: "2MB",
+        csv: include_str!("presets/minimal.csv"),
+    },
+];
+
+fn names() -> String {
+    PRESETS
+        .iter()
+        .map(|p| p.name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Prints the presets for `--list-presets`
+pub fn print_list() {
+    println!("Available partition table presets (use --partition-table preset:<name>):");
+    for preset in PRESETS {
+        println!(
+            "  {:<16} {} (needs {})",
+            preset.name, preset.description, preset.min_flash_size
+        );
+    }
+}
+
+/// Value of `--partition-table`: a file or `preset:<name>`
+#[derive(Debug, Clone)]
+pub enum PartitionTableArg {
+    File(PathBuf),
+    Preset(&'static Preset),
+}
+
+impl FromStr for PartitionTableArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("preset:") {
+            Some(name) => PRESETS
+                .iter()
+                .find(|p| p.name == name)
+                .map(PartitionTableArg::Preset)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown partition table preset '{}' (available: {})",
+                        name,
+                        names()
+                    )
+                }),
+            None => Ok(PartitionTableArg::File(PathBuf::from(s))),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::{net::IpAddr, sync::atomic::Ordering};
+
+use qrcode::{render::unicode::Dense1x2, QrCode};
+
+/// When the URL is printed as a QR code once the server listens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrMode {
+    /// when bound to other machines and printing to a terminal
+    Auto,
+    /// `--qr`
+    Always,
+    /// `--no-qr`, and listeners but the first
+    Never,
+}
+
+impl QrMode {
+    pub fn shows(self, addr: IpAddr) -> bool {
+        match self {
+            QrMode::Always => true,
+            QrMode::Never => false,
+            QrMode::Auto => {
+                !addr.is_loopback()
+                    && !crate::QUIET.load(Ordering::Relaxed)
+                    && atty::is(atty::Stream::Stdout)
+            }
+        }
+    }
+}
+
+/// Whether the terminal can show the half-block characters: a UTF-8
+/// locale and not a dumb terminal, such as some serial consoles
+fn unicode_terminal() -> bool {
+    if std::env::var("TERM").map_or(false, |term| term == "dumb") {
+        return false;
+    }
+    if cfg!(windows) {
+        return true;
+    }
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+        .map_or(false, |locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
+/// `url` as a QR code for the terminal, two modules per character cell, or
+/// in `#` where the terminal can't show block characters. Either is drawn
+/// light on dark, as most terminals are.
+pub fn render(url: &str) -> Option<String> {
+    let code = QrCode::new(url.as_bytes()).ok()?;
+    Some(if unicode_terminal() {
+        code.render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build()
+    } else {
+        code.render::<char>()
+            .dark_color(' ')
+            .light_color('#')
+            .module_dimensions(2, 1)
+            .build()
+    })
+}
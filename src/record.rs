@@ -0,0 +1,204 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{ContentType, HeaderMap},
+    serde::json::{self, Value},
+    Data, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::auth;
+
+/// Headers whose values never end up in a recording
+const REDACTED: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<Header>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: Vec<Header>,
+    /// `None` for streamed bodies (event streams, erase progress)
+    pub body_size: Option<usize>,
+    pub body_sha256: Option<String>,
+    /// only kept for JSON responses
+    pub body: Option<Value>,
+}
+
+/// One HTTP exchange, written as a line of JSON
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Entry {
+    /// milliseconds since the Unix epoch
+    pub started: u64,
+    pub duration_ms: u64,
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// `value` with the `?token=` of every URL in it redacted
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::String(s) => *s = auth::redact(s),
+        Value::Array(values) => values.iter_mut().for_each(redact_json),
+        Value::Object(fields) => fields.values_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+/// When a request arrived, cached on the request by `on_request`
+struct Started(u64, Instant);
+
+fn headers(map: &HeaderMap) -> Vec<Header> {
+    map.iter()
+        .map(|h| Header {
+            name: h.name().to_string(),
+            value: if REDACTED.contains(&h.name().as_str().to_lowercase().as_str()) {
+                String::from("[redacted]")
+            } else {
+                auth::redact(h.value())
+            },
+        })
+        .collect()
+}
+
+/// Fairing appending every exchange to a file, one JSON object per line
+/// and flushed right away so a crash doesn't lose what was recorded
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        status!("Recording HTTP exchanges to {}", path.display());
+        Ok(Recorder {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Recorder {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request recorder",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        req.local_cache(|| Started(started, Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let started = req.local_cache(|| Started(0, Instant::now()));
+
+        // streamed bodies have no size up front and are left alone, reading
+        // them here would block until the stream ends
+        let (body_size, body_sha256, body) = if res.body().preset_size().is_some() {
+            let bytes = res.body_mut().to_bytes().await.unwrap_or_default();
+            let mut body: Option<Value> = (res.content_type() == Some(ContentType::JSON))
+                .then(|| json::from_slice(&bytes).ok())
+                .flatten();
+            body.iter_mut().for_each(redact_json);
+            let hash = Sha256::digest(&bytes)
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            let size = bytes.len();
+            res.set_sized_body(size, Cursor::new(bytes));
+            (Some(size), Some(hash), body)
+        } else {
+            (None, None, None)
+        };
+
+        let entry = Entry {
+            started: started.0,
+            duration_ms: started.1.elapsed().as_millis() as u64,
+            request: RecordedRequest {
+                method: req.method().to_string(),
+                uri: auth::redact(&req.uri().to_string()),
+                headers: headers(req.headers()),
+            },
+            response: RecordedResponse {
+                status: res.status().code,
+                headers: headers(res.headers()),
+                body_size,
+                body_sha256,
+                body,
+            },
+        };
+
+        if let Ok(line) = json::to_string(&entry) {
+            let mut file = self.file.lock().unwrap();
+            if writeln!(file, "{}", line)
+                .and_then(|_| file.flush())
+                .is_err()
+            {
+                warning!("Failed to write to the recording");
+            }
+        }
+    }
+}
+
+/// Prints the timeline of a recording made with `--record`
+pub fn summary(path: &Path) -> Result<()> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open recording {}", path.display()))?;
+
+    let mut first = None;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = json::from_str(&line)
+            .with_context(|| format!("Line {} is not a recorded exchange", i + 1))?;
+        let start = *first.get_or_insert(entry.started);
+
+        println!(
+            "+{:>9.3}s  {:<6} {:<40} {}  {:>9}  {:>5} ms",
+            entry.started.saturating_sub(start) as f64 / 1000.0,
+            entry.request.method,
+            entry.request.uri,
+            entry.response.status,
+            entry
+                .response
+                .body_size
+                .map_or(String::from("stream"), |size| format!("{} B", size)),
+            entry.duration_ms
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use espflash::Chip;
+use rocket::serde::json;
+use serde::Serialize;
+
+use crate::{
+    chip_detect, layout::WrittenPart, partition_table::PartitionEntry, prepare_error::PrepareError,
+    OutputFormat,
+};
+
+const APP_TYPE: u8 = 0x00;
+const FACTORY: u8 = 0x00;
+const OTA_FIRST: u8 = 0x10;
+const OTA_LAST: u8 = 0x1f;
+
+/// Outcome of one rule, as `check` reports it
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleResult {
+    pub rule: &'static str,
+    pub ok: bool,
+    /// the numbers the rule compared
+    pub detail: String,
+}
+
+/// Where `prepare` sends the outcome of each rule: the first failure is
+/// returned as its error, unless the `check` subcommand collects them all
+pub struct Report {
+    collect: bool,
+    pub results: Vec<RuleResult>,
+}
+
+impl Report {
+    pub fn strict() -> Self {
+        Report {
+            collect: false,
+            results: Vec::new(),
+        }
+    }
+
+    pub fn collecting() -> Self {
+        Report {
+            collect: true,
+            results: Vec::new(),
+        }
+    }
+
+    /// Records a rule's outcome, `Ok` with what passed
+    pub fn rule(
+        &mut self,
+        rule: &'static str,
+        outcome: Result<String, PrepareError>,
+    ) -> Result<(), PrepareError> {
+        match outcome {
+            Ok(detail) => {
+                self.results.push(RuleResult {
+                    rule,
+                    ok: true,
+                    detail,
+                });
+                Ok(())
+            }
+            Err(e) if self.collect => {
+                self.results.push(RuleResult {
+                    rule,
+                    ok: false,
+                    detail: e.to_string(),
+                });
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// `1.4MB`, `24.0KB` or `512B`, as the report shows sizes
+fn size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{}B", bytes),
+        1024..=0xf_ffff => format!("{:.1}KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1}MB", bytes as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+/// `elf-arch`: the ELF is built for the chip's architecture
+pub fn elf_arch(elf: &[u8], path: &Path, chip: Chip) -> Result<String, PrepareError> {
+    chip_detect::check(elf, chip)
+        .map(|_| format!("built for {}", crate::chip_name(chip)))
+        .map_err(|reason| PrepareError::InvalidElf {
+            path: path.to_path_buf(),
+            reason,
+        })
+}
+
+/// `table-fits-flash`: no partition ends beyond the flash
+pub fn table_fits_flash(
+    table: &[PartitionEntry],
+    source: &str,
+    flash_size: u32,
+    flash_size_name: &str,
+) -> Result<String, PrepareError> {
+    match table.iter().max_by_key(|e| e.end()) {
+        Some(last) if last.end() > flash_size => Err(PrepareError::PartitionBeyondFlash {
+            table: source.to_string(),
+            partition: last.name.clone(),
+            end: last.end(),
+            flash_size: flash_size_name.to_string(),
+        }),
+        Some(last) => Ok(format!(
+            "'{}' ends at 0x{:x} <= {} flash",
+            last.name,
+            last.end(),
+            flash_size_name
+        )),
+        None => Ok(String::from("no partition table")),
+    }
+}
+
+/// `partitions-disjoint`: no two partitions share flash
+pub fn partitions_disjoint(table: &[PartitionEntry]) -> Result<String, PrepareError> {
+    let mut sorted: Vec<&PartitionEntry> = table.iter().collect();
+    sorted.sort_by_key(|e| e.offset);
+    for pair in sorted.windows(2) {
+        if pair[0].end() > pair[1].offset {
+            return Err(PrepareError::PartitionsOverlap {
+                first: pair[0].name.clone(),
+                second: pair[1].name.clone(),
+                offset: pair[1].offset,
+            });
+        }
+    }
+    Ok(format!("{} partitions", table.len()))
+}
+
+/// `app-fits-partitions`: the app fits the partition it's written to and
+/// every factory and OTA partition it could be updated into
+pub fn app_fits_partitions(
+    app_size: usize,
+    table: &[PartitionEntry],
+    app_offset: u32,
+) -> Result<String, PrepareError> {
+    let targets: Vec<&PartitionEntry> = table
+        .iter()
+        .filter(|e| {
+            e.offset == app_offset
+                || (e.ty == APP_TYPE
+                    && (e.subtype == FACTORY || (OTA_FIRST..=OTA_LAST).contains(&e.subtype)))
+        })
+        .collect();
+    if let Some(small) = targets.iter().find(|e| app_size as u64 > e.size as u64) {
+        return Err(PrepareError::AppTooLarge {
+            app: "app",
+            size: app_size,
+            partition: small.name.clone(),
+            capacity: small.size,
+        });
+    }
+    Ok(match targets.iter().min_by_key(|e| e.size) {
+        Some(smallest) => format!(
+            "app {} <= {} {}",
+            size(app_size as u64),
+            smallest.name,
+            size(smallest.size as u64)
+        ),
+        None => String::from("no app partition to check against"),
+    })
+}
+
+/// `parts-disjoint`: no two written parts overlap
+pub fn parts_disjoint(parts: &[WrittenPart]) -> Result<String, PrepareError> {
+    let mut sorted: Vec<&WrittenPart> = parts.iter().filter(|part| part.size > 0).collect();
+    sorted.sort_by_key(|part| part.offset);
+    for pair in sorted.windows(2) {
+        if pair[0].offset.saturating_add(pair[0].size) > pair[1].offset {
+            return Err(PrepareError::PartsOverlap {
+                first: pair[0].name.to_string(),
+                second: pair[1].name.to_string(),
+                offset: pair[1].offset,
+            });
+        }
+    }
+    Ok(format!("{} parts", sorted.len()))
+}
+
+/// The report of the `check` subcommand
+pub fn print(results: &[RuleResult], output: OutputFormat) {
+    match output {
+        OutputFormat::Json => match json::to_pretty_string(results) {
+            Ok(report) => println!("{}", report),
+            Err(e) => eprintln!("Failed to serialize the check report: {}", e),
+        },
+        OutputFormat::Text => {
+            println!("Checks:");
+            for result in results {
+                println!(
+                    "  {} {:<20} {}",
+                    if result.ok { "✓" } else { "✗" },
+                    result.rule,
+                    result.detail
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+use rocket::serde::json::{self, json, Value};
+use serde::{Deserialize, Serialize};
+use xmas_elf::ElfFile;
+
+/// Section cargo-auditable embeds the zlib-compressed dependency list in
+const SECTION: &str = ".dep-v0";
+/// Decompressed size refused as implausible for a dependency list
+const MAX_SIZE: u64 = 8 * 1024 * 1024;
+
+/// One crate as recorded by cargo-auditable
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    /// `crates.io`, `git`, `local` or `registry`
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// indices into the package list
+    #[serde(default)]
+    pub dependencies: Vec<usize>,
+    #[serde(default)]
+    pub root: bool,
+}
+
+/// The dependency list of the served firmware, as served by `/sbom.json`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sbom {
+    pub packages: Vec<Package>,
+}
+
+/// Reads the cargo-auditable data from an ELF; `Ok(None)` if it was built
+/// without cargo-auditable
+pub fn from_elf(elf: &[u8]) -> Result<Option<Sbom>, String> {
+    let file = ElfFile::new(elf)?;
+    let section = match file.find_section_by_name(SECTION) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let mut decoded = Vec::new();
+    ZlibDecoder::new(section.raw_data(&file))
+        .take(MAX_SIZE)
+        .read_to_end(&mut decoded)
+        .map_err(|e| format!("failed to decompress {}: {}", SECTION, e))?;
+    let sbom: Sbom =
+        json::from_slice(&decoded).map_err(|e| format!("invalid {} data: {}", SECTION, e))?;
+
+    if let Some(i) = sbom
+        .packages
+        .iter()
+        .flat_map(|package| package.dependencies.iter())
+        .find(|&&i| i >= sbom.packages.len())
+    {
+        return Err(format!("{} refers to missing package #{}", SECTION, i));
+    }
+    Ok(Some(sbom))
+}
+
+fn purl(package: &Package) -> String {
+    format!("pkg:cargo/{}@{}", package.name, package.version)
+}
+
+/// Renders the list as a CycloneDX 1.4 BOM, with the root crate as the
+/// described component
+pub fn cyclonedx(sbom: &Sbom) -> Value {
+    let component = |package: &Package| {
+        json!({
+            "type": if package.root { "application" } else { "library" },
+            "bom-ref": purl(package),
+            "name": package.name,
+            "version": package.version,
+            "purl": purl(package),
+        })
+    };
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.4",
+        "version": 1,
+        "metadata": {
+            "component": sbom.packages.iter().find(|package| package.root).map(component),
+        },
+        "components": sbom
+            .packages
+            .iter()
+            .filter(|package| !package.root)
+            .map(component)
+            .collect::<Vec<_>>(),
+        "dependencies": sbom
+            .packages
+            .iter()
+            .map(|package| json!({
+                "ref": purl(package),
+                "dependsOn": package
+                    .dependencies
+                    .iter()
+                    .map(|&i| purl(&sbom.packages[i]))
+                    .collect::<Vec<_>>(),
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// See tests/fixtures/gen_elf.py
+    const AUDITABLE_ELF: &[u8] = include_bytes!("../tests/fixtures/esp32-auditable.elf");
+    const PLAIN_ELF: &[u8] = include_bytes!("../tests/fixtures/esp32-app.elf");
+
+    #[test]
+    fn reads_cargo_auditable_data() {
+        let sbom = from_elf(AUDITABLE_ELF).unwrap().unwrap();
+        let packages: Vec<_> = sbom
+            .packages
+            .iter()
+            .map(|p| {
+                (
+                    p.name.as_str(),
+                    p.version.as_str(),
+                    p.source.as_str(),
+                    p.root,
+                )
+            })
+            .collect();
+        assert_eq!(
+            packages,
+            [
+                ("blink", "0.1.0", "local", true),
+                ("esp-hal", "0.16.1", "crates.io", false),
+                ("critical-section", "1.1.2", "crates.io", false),
+            ]
+        );
+        assert_eq!(sbom.packages[0].dependencies, [1, 2]);
+        assert!(sbom.packages[2].dependencies.is_empty());
+    }
+
+    #[test]
+    fn elf_without_the_section_has_no_sbom() {
+        assert!(from_elf(PLAIN_ELF).unwrap().is_none());
+    }
+
+    #[test]
+    fn renders_cyclonedx() {
+        let bom = cyclonedx(&from_elf(AUDITABLE_ELF).unwrap().unwrap());
+        assert_eq!(bom["bomFormat"], "CycloneDX");
+        assert_eq!(
+            bom["metadata"]["component"]["purl"],
+            "pkg:cargo/blink@0.1.0"
+        );
+        assert_eq!(bom["metadata"]["component"]["type"], "application");
+        assert_eq!(bom["components"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            bom["dependencies"][0]["dependsOn"],
+            json!([
+                "pkg:cargo/esp-hal@0.16.1",
+                "pkg:cargo/critical-section@1.1.2"
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_files_that_are_no_elf() {
+        assert!(from_elf(b"not an elf").is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::{path::PathBuf, str::FromStr};
+
+use espflash::{elf::FirmwareImageBuilder, Chip, FlashSize};
+
+use crate::{
+    checksums::Checksums, partition_table::PartitionEntry, prepare_error::PrepareError, ExtraPart,
+};
+
+const TYPE_APP: u8 = 0x00;
+const TYPE_DATA: u8 = 0x01;
+const SUBTYPE_OTA_DATA: u8 = 0x00;
+const SUBTYPE_OTA_0: u8 = 0x10;
+const SUBTYPE_OTA_15: u8 = 0x1f;
+
+/// `--second-app <elf-or-bin>@<partition-name>`
+#[derive(Debug, Clone)]
+pub struct SecondApp {
+    pub path: PathBuf,
+    pub partition: String,
+}
+
+impl FromStr for SecondApp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once('@') {
+            Some((path, partition)) if !path.is_empty() && !partition.is_empty() => Ok(SecondApp {
+                path: PathBuf::from(path),
+                partition: partition.to_string(),
+            }),
+            _ => Err(format!(
+                "expected <elf-or-bin>@<partition-name>, e.g. recovery.elf@factory, got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+fn ota_slot(entry: &PartitionEntry) -> Option<u8> {
+    (entry.ty == TYPE_APP && (SUBTYPE_OTA_0..=SUBTYPE_OTA_15).contains(&entry.subtype))
+        .then(|| entry.subtype - SUBTYPE_OTA_0)
+}
+
+/// Builds the second app image plus the otadata selecting the OTA slot
+/// among the two apps, validating it against the partition table
+pub fn prepare(
+    spec: &SecondApp,
+    chip: Chip,
+    flash_size: FlashSize,
+    table: &[PartitionEntry],
+    first_offset: u32,
+    checksums: &Checksums,
+) -> Result<Vec<ExtraPart>, PrepareError> {
+    let names = || {
+        table
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let target = match table.iter().find(|e| e.name == spec.partition) {
+        Some(target) if target.ty == TYPE_APP => target,
+        Some(_) => return Err(PrepareError::NotAnAppPartition(spec.partition.clone())),
+        None => {
+            return Err(PrepareError::PartitionNotFound {
+                name: spec.partition.clone(),
+                available: names(),
+            })
+        }
+    };
+    let first = table
+        .iter()
+        .find(|e| e.offset == first_offset)
+        .ok_or_else(|| {
+            PrepareError::SecondApp(String::from(
+                "the main app does not start at a partition in the partition table",
+            ))
+        })?;
+
+    if first.offset == target.offset {
+        return Err(PrepareError::SecondApp(format!(
+            "both apps would be written to partition '{}'; choose a different partition",
+            target.name
+        )));
+    }
+
+    let invalid_elf = |e: &dyn std::fmt::Display| PrepareError::InvalidElf {
+        path: spec.path.clone(),
+        reason: e.to_string(),
+    };
+    let raw = checksums.read(&spec.path)?;
+    let image = if raw.starts_with(b"\x7fELF") {
+        let firmware = FirmwareImageBuilder::new(&raw)
+            .flash_size(Some(flash_size))
+            .build()
+            .map_err(|e| invalid_elf(&e))?;
+        let flash_image = chip
+            .get_flash_image(&firmware, None, None, None, None)
+            .map_err(|e| invalid_elf(&e))?;
+        let app = flash_image
+            .flash_segments()
+            .last()
+            .ok_or_else(|| invalid_elf(&"espflash produced no app segment"))?;
+        app.data.to_vec()
+    } else {
+        raw
+    };
+
+    if image.len() as u32 > target.size {
+        return Err(PrepareError::AppTooLarge {
+            app: "second app",
+            size: image.len(),
+            partition: target.name.clone(),
+            capacity: target.size,
+        });
+    }
+
+    let otadata = table
+        .iter()
+        .find(|e| e.ty == TYPE_DATA && e.subtype == SUBTYPE_OTA_DATA)
+        .ok_or_else(|| {
+            PrepareError::SecondApp(String::from(
+                "an otadata partition is needed to select the boot slot",
+            ))
+        })?;
+    let slot = ota_slot(target)
+        .or_else(|| ota_slot(first))
+        .ok_or_else(|| {
+            PrepareError::SecondApp(String::from(
+                "neither app is in an OTA slot (ota_0..ota_15), so there is nothing to select",
+            ))
+        })?;
+
+    status!(
+        "Second app: {} bytes in '{}' at 0x{:x}, booting ota_{}",
+        image.len(),
+        target.name,
+        target.offset,
+        slot
+    );
+
+    Ok(vec![
+        ExtraPart {
+            file: String::from("firmware2.bin"),
+            label: format!("Second app ({})", target.name),
+            offset: target.offset,
+            data: image,
+        },
+        ExtraPart {
+            file: String::from("otadata.bin"),
+            label: String::from("OTA data"),
+            offset: otadata.offset,
+            data: otadata_selecting(slot, otadata.size),
+        },
+    ])
+}
+
+/// Builds an otadata partition whose first `esp_ota_select_entry_t` makes
+/// the bootloader start `ota_<slot>`
+fn otadata_selecting(slot: u8, size: u32) -> Vec<u8> {
+    let mut data = vec![0xff; size as usize];
+
+    // the bootloader boots ota_{(seq - 1) % number of OTA slots}
+    let seq = slot as u32 + 1;
+    let mut crc = crc32fast::Hasher::new_with_initial(u32::MAX);
+    crc.update(&seq.to_le_bytes());
+
+    data[0..4].copy_from_slice(&seq.to_le_bytes());
+    // seq_label (20 bytes) and ota_state stay 0xff
+    data[28..32].copy_from_slice(&crc.finalize().to_le_bytes());
+
+    data
+}
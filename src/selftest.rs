@@ -0,0 +1,241 @@
+use rocket::{
+    fairing::AdHoc,
+    http::{ContentType, Header, Status},
+    local::asynchronous::Client,
+    serde::json::{self, Value},
+};
+use serde::Serialize;
+
+use crate::{auth::AuthSettings, OutputFormat, ServerState};
+
+/// Outcome of one request made by the self test
+#[derive(Serialize, Debug)]
+pub struct Check {
+    pub method: &'static str,
+    pub path: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+struct Expect {
+    status: Status,
+    content_type: Option<ContentType>,
+    non_empty: bool,
+}
+
+impl Expect {
+    fn ok(content_type: ContentType) -> Self {
+        Expect {
+            status: Status::Ok,
+            content_type: Some(content_type),
+            non_empty: true,
+        }
+    }
+}
+
+async fn check(client: &Client, path: &str, expect: Expect) -> (Check, Vec<u8>) {
+    let mut request = client.get(path.to_string());
+    // with --auth-token, requests like the page's
+    if let Some(token) = client
+        .rocket()
+        .state::<AuthSettings>()
+        .and_then(|auth| auth.token.as_deref())
+    {
+        request.add_header(Header::new("Authorization", format!("Bearer {}", token)));
+    }
+    let response = request.dispatch().await;
+    let status = response.status();
+    let content_type = response.content_type();
+    let body = response.into_bytes().await.unwrap_or_default();
+
+    let problem = if status != expect.status {
+        Some(format!("status {}, expected {}", status, expect.status))
+    } else if expect.content_type.is_some() && content_type != expect.content_type {
+        Some(format!(
+            "content type {}, expected {}",
+            content_type.map_or(String::from("none"), |c| c.to_string()),
+            expect
+                .content_type
+                .map(|c| c.to_string())
+                .unwrap_or_default()
+        ))
+    } else if expect.non_empty && body.is_empty() {
+        Some(String::from("empty body"))
+    } else {
+        None
+    };
+
+    let check = Check {
+        method: "GET",
+        path: path.to_string(),
+        ok: problem.is_none(),
+        detail: problem.unwrap_or_else(|| format!("{} ({} bytes)", status, body.len())),
+    };
+    (check, body)
+}
+
+/// Requests every route the server mounted and checks the responses
+pub async fn run(state: &ServerState) -> Vec<Check> {
+    let rocket = crate::build_rocket(
+        rocket::Config::figment(),
+        state,
+        None,
+        crate::qr::QrMode::Never,
+    );
+    let client = match Client::untracked(rocket).await {
+        Ok(client) => client,
+        Err(e) => {
+            return vec![Check {
+                method: "-",
+                path: String::from("-"),
+                ok: false,
+                detail: format!("Failed to start the test client: {}", e),
+            }]
+        }
+    };
+
+    let mut checks = Vec::new();
+    checks.push(check(&client, "/", Expect::ok(ContentType::HTML)).await.0);
+    checks.push(
+        check(&client, "/theme.css", Expect::ok(ContentType::CSS))
+            .await
+            .0,
+    );
+    checks.push(
+        check(&client, "/health", Expect::ok(ContentType::JSON))
+            .await
+            .0,
+    );
+
+    if let Some(builds) = &state.builds {
+        for path in [
+            "/info",
+            "/layout.json",
+            "/partitions.json",
+            "/app-info",
+            "/elf-info",
+            "/status",
+        ] {
+            checks.push(check(&client, path, Expect::ok(ContentType::JSON)).await.0);
+        }
+        let build = builds.current();
+        if !build.parts.partitions.is_empty() {
+            checks.push(
+                check(&client, "/partitions.csv", Expect::ok(ContentType::Plain))
+                    .await
+                    .0,
+            );
+        }
+        for path in ["/bootloader.bin", "/partitions.bin", "/firmware.bin"] {
+            // a merged image has no separate bootloader
+            if build.part(&path[1..]).is_some() {
+                checks.push(
+                    check(&client, path, Expect::ok(ContentType::Binary))
+                        .await
+                        .0,
+                );
+            }
+        }
+        checks.push(
+            check(&client, "/merged.bin", Expect::ok(ContentType::Binary))
+                .await
+                .0,
+        );
+        for path in ["/checksums.txt", "/firmware.bin.sha256"] {
+            checks.push(check(&client, path, Expect::ok(ContentType::Plain)).await.0);
+        }
+        checks.push(
+            check(&client, "/artifacts.zip", Expect::ok(ContentType::ZIP))
+                .await
+                .0,
+        );
+
+        if state.fota.version.is_some() || build.parts.app_version.is_some() {
+            checks.push(
+                check(&client, "/fota.json", Expect::ok(ContentType::JSON))
+                    .await
+                    .0,
+            );
+        }
+
+        let (manifest_check, manifest) =
+            check(&client, "/manifest.json", Expect::ok(ContentType::JSON)).await;
+        checks.push(manifest_check);
+        let manifest: Value = json::from_slice(&manifest).unwrap_or_default();
+        let paths = manifest["builds"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|build| build["parts"].as_array().into_iter().flatten())
+            .filter_map(|part| part["path"].as_str().map(|path| format!("/{}", path)));
+        for path in paths.collect::<Vec<_>>() {
+            checks.push(
+                check(&client, &path, Expect::ok(ContentType::Binary))
+                    .await
+                    .0,
+            );
+        }
+    }
+
+    let mut admin_paths = Vec::new();
+    if state.local.is_some() {
+        admin_paths.push("/erase-local");
+        if state.builds.is_some() {
+            admin_paths.push("/flash-all");
+        }
+    }
+    if state.rebuilder.is_some() {
+        admin_paths.extend(["/upload/partition-table", "/upload/bootloader"]);
+    }
+    for path in admin_paths {
+        // without the admin token the guard has to refuse, before touching the device or build
+        let response = client.post(path).dispatch().await;
+        let status = response.status();
+        checks.push(Check {
+            method: "POST",
+            path: String::from(path),
+            ok: status == Status::Unauthorized || status == Status::Forbidden,
+            detail: format!("{} without the admin token", status),
+        });
+    }
+
+    checks
+}
+
+fn print(checks: &[Check], output: OutputFormat) {
+    match output {
+        OutputFormat::Json => match json::to_pretty_string(checks) {
+            Ok(report) => println!("{}", report),
+            Err(e) => eprintln!("Failed to serialize the self test report: {}", e),
+        },
+        OutputFormat::Text => {
+            println!("Self test:");
+            for check in checks {
+                println!(
+                    "  {} {:<5} {:<40} {}",
+                    if check.ok { "✓" } else { "✗" },
+                    check.method,
+                    check.path,
+                    check.detail
+                );
+            }
+        }
+    }
+}
+
+/// Runs the self test once the server is listening, exiting with a failure
+/// code if any check failed and continuing to serve otherwise
+pub fn on_liftoff(state: ServerState, output: OutputFormat) -> AdHoc {
+    AdHoc::on_liftoff("Self test", move |_| {
+        Box::pin(async move {
+            let checks = run(&state).await;
+            print(&checks, output);
+
+            let failed = checks.iter().filter(|check| !check.ok).count();
+            if failed > 0 {
+                eprintln!("Self test failed: {} of {} checks", failed, checks.len());
+                std::process::exit(1);
+            }
+        })
+    })
+}
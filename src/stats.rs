@@ -0,0 +1,101 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::ContentType,
+    Request, Response,
+};
+
+use crate::auth;
+
+/// How long the server ran and how much it sent, printed on exit.
+/// Clones share the counters, so every listener adds to the same ones.
+#[derive(Clone)]
+pub struct ServeStats {
+    started: Instant,
+    bytes: Arc<AtomicU64>,
+    responses: Arc<AtomicU64>,
+}
+
+impl Default for ServeStats {
+    fn default() -> Self {
+        ServeStats {
+            started: Instant::now(),
+            bytes: Arc::default(),
+            responses: Arc::default(),
+        }
+    }
+}
+
+impl ServeStats {
+    /// One line with the uptime and the bytes served
+    pub fn summary(&self) -> String {
+        let elapsed = Duration::from_secs(self.started.elapsed().as_secs());
+        format!(
+            "Served {} bytes in {} responses over {}",
+            self.bytes.load(Ordering::Relaxed),
+            self.responses.load(Ordering::Relaxed),
+            format_duration(elapsed)
+        )
+    }
+
+    pub fn uptime_seconds(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    /// The summary as one event with a field per number, for `--log-format json`
+    pub fn log(&self) {
+        tracing::info!(
+            bytes = self.bytes.load(Ordering::Relaxed),
+            responses = self.responses.load(Ordering::Relaxed),
+            uptime_seconds = self.uptime_seconds(),
+            "shutdown"
+        );
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, s) => format!("{}h {}m {}s", h, m, s),
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for ServeStats {
+    fn info(&self) -> Info {
+        Info {
+            name: "Serve statistics",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        self.responses.fetch_add(1, Ordering::Relaxed);
+        // streamed bodies (the monitor's event stream) have no size up front
+        let size = res.body().preset_size();
+        if let Some(size) = size {
+            self.bytes.fetch_add(size as u64, Ordering::Relaxed);
+        }
+
+        let status = res.status().code;
+        let client = req.client_ip().map(|ip| ip.to_string());
+        let client = client.as_deref().unwrap_or("unknown");
+        // binaries are what the browser flashes; pages and assets only at -vv
+        // without the `?token=` the browser adds with --auth-token
+        let uri = auth::redact(&req.uri().to_string());
+        if res.content_type() == Some(ContentType::Binary) {
+            tracing::info!(uri = %uri, status, size, client, "download");
+        } else {
+            tracing::debug!(method = %req.method(), uri = %uri, status, size, client, "response");
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::net::SocketAddr;
+
+use rocket::{fairing::AdHoc, serde::json};
+use serde::Serialize;
+
+use crate::{listen, prepared_build::PreparedBuild, ServerState};
+
+/// The document `--summary-json` prints on stdout, on a single line, once
+/// the server listens. Fields are only ever added, never renamed.
+#[derive(Serialize, Debug)]
+pub struct Summary {
+    /// the page, with `?token=` when the server requires one; null with
+    /// `--dry-run`, which doesn't listen
+    pub url: Option<String>,
+    /// as shown on the page, e.g. `ESP32-C3`
+    pub chip: String,
+    /// e.g. `4MB`
+    pub flash_size: String,
+    /// every part a full install writes, in flash order
+    pub parts: Vec<Part>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Part {
+    /// file name the part is served under, e.g. `bootloader.bin`
+    pub name: String,
+    pub offset: u32,
+    pub size: usize,
+    /// lowercase hex
+    pub sha256: String,
+}
+
+impl Summary {
+    pub fn new(url: Option<String>, build: &PreparedBuild) -> Self {
+        let data = &build.parts;
+        Summary {
+            url,
+            chip: data.chip.clone(),
+            flash_size: data.flash_size.clone(),
+            parts: parts(build),
+        }
+    }
+
+    /// Prints the summary on stdout as one line
+    pub fn print(&self) {
+        match json::to_string(self) {
+            Ok(summary) => println!("{}", summary),
+            Err(e) => eprintln!("Failed to serialize the startup summary: {}", e),
+        }
+    }
+}
+
+/// Every part a full install writes with its digest, in flash order
+pub fn parts(build: &PreparedBuild) -> Vec<Part> {
+    build
+        .parts
+        .parts()
+        .into_iter()
+        .map(|part| Part {
+            sha256: build.sha256(&part.name).unwrap_or_default().to_string(),
+            name: part.name,
+            offset: part.offset,
+            size: part.size,
+        })
+        .collect()
+}
+
+/// Prints the summary once the first listener is up
+pub fn on_liftoff(state: ServerState) -> AdHoc {
+    AdHoc::on_liftoff("Startup summary", move |rocket| {
+        Box::pin(async move {
+            let config = rocket.config();
+            let scheme = if config.tls_enabled() {
+                "https"
+            } else {
+                "http"
+            };
+            let query = state
+                .auth
+                .token
+                .as_deref()
+                .map(|token| format!("?token={}", token))
+                .unwrap_or_default();
+            let url = format!(
+                "{}://{}/{}",
+                scheme,
+                SocketAddr::new(listen::reachable(config.address), config.port),
+                query
+            );
+
+            if let Some(builds) = &state.builds {
+                Summary::new(Some(url), &builds.current()).print();
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{checksums, PartsData};
+
+    #[test]
+    fn serializes_the_documented_fields() {
+        let build = PreparedBuild::new(PartsData {
+            chip: String::from("ESP32-C3"),
+            bootloader: vec![1; 32],
+            partitions: vec![2; 16],
+            firmware: vec![3; 64],
+            total_size: 112,
+            bootloader_size: 32,
+            partitions_size: 16,
+            firmware_size: 64,
+            flash_size: String::from("4MB"),
+            bootloader_offset: 0x0,
+            partitions_offset: 0x8000,
+            firmware_offset: 0x10000,
+            ..Default::default()
+        });
+        let summary = Summary::new(Some(String::from("http://10.0.0.2:8000/")), &build);
+        let line = json::to_string(&summary).unwrap();
+        assert!(!line.contains('\n'));
+
+        let value: json::Value = json::from_str(&line).unwrap();
+        assert_eq!(value["url"], "http://10.0.0.2:8000/");
+        assert_eq!(value["chip"], "ESP32-C3");
+        assert_eq!(value["flash_size"], "4MB");
+        let parts = value["parts"].as_array().unwrap();
+        let names: Vec<_> = parts.iter().map(|part| part["name"].clone()).collect();
+        assert_eq!(names, ["bootloader.bin", "partitions.bin", "firmware.bin"]);
+        assert_eq!(parts[1]["offset"], 0x8000);
+        assert_eq!(parts[1]["size"], 16);
+        assert_eq!(parts[2]["sha256"], checksums::sha256_hex(&[3; 64]));
+    }
+
+    #[test]
+    fn dry_runs_have_a_null_url() {
+        let build = PreparedBuild::new(PartsData::default());
+        let line = json::to_string(&Summary::new(None, &build)).unwrap();
+        let value: json::Value = json::from_str(&line).unwrap();
+        assert!(value["url"].is_null());
+    }
+}
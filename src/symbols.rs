@@ -0,0 +1,95 @@
+use std::{fs::File, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use memmap2::Mmap;
+use xmas_elf::{
+    sections::SectionData,
+    symbol_table::{Entry, Type},
+    ElfFile,
+};
+
+/// Symbols of the given types with a size, as (address, size, demangled
+/// name); shared by backtrace decoding and `compare`
+pub fn sized(file: &ElfFile, types: &[Type]) -> Vec<(u32, u32, String)> {
+    let mut symbols = Vec::new();
+    for section in file.section_iter() {
+        if let Ok(SectionData::SymbolTable32(entries)) = section.get_data(file) {
+            for entry in entries {
+                let wanted = entry.get_type().map_or(false, |ty| types.contains(&ty));
+                if !wanted || entry.size() == 0 {
+                    continue;
+                }
+                if let Ok(name) = entry.get_name(file) {
+                    symbols.push((
+                        entry.value() as u32,
+                        entry.size() as u32,
+                        rustc_demangle::demangle(name).to_string(),
+                    ));
+                }
+            }
+        }
+    }
+    symbols
+}
+
+/// Function symbols of an ELF, used to decode addresses in backtraces
+pub struct Symbols {
+    /// (start, size, demangled name), sorted by start address
+    functions: Vec<(u32, u32, String)>,
+}
+
+impl Symbols {
+    /// Maps the ELF rather than reading it, as debug builds can be large
+    /// and only the symbol table is needed
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        // SAFETY: read-only mapping of a build output, dropped before returning
+        let elf = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Failed to map {}", path.display()))?;
+        Self::from_elf(&elf)
+    }
+
+    pub fn from_elf(elf: &[u8]) -> Result<Self> {
+        let file = ElfFile::new(elf).map_err(|e| anyhow!("Failed to parse ELF: {}", e))?;
+        let mut functions = sized(&file, &[Type::Func]);
+        functions.sort_by_key(|(start, _, _)| *start);
+
+        Ok(Symbols { functions })
+    }
+
+    /// Finds the function containing `addr` and the offset into it
+    pub fn lookup(&self, addr: u32) -> Option<(&str, u32)> {
+        let index = self
+            .functions
+            .partition_point(|(start, _, _)| *start <= addr)
+            .checked_sub(1)?;
+        let (start, size, name) = &self.functions[index];
+        (addr < start + size).then(|| (name.as_str(), addr - start))
+    }
+
+    /// Appends `[function+0xoffset]` after every hex address in `line` that
+    /// resolves to a function, as found in panic backtraces
+    pub fn annotate(&self, line: &str) -> String {
+        let mut annotated = String::with_capacity(line.len());
+        let mut rest = line;
+
+        while let Some(pos) = rest.find("0x") {
+            let digits = rest[pos + 2..]
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(rest.len() - pos - 2);
+            let (token, tail) = rest.split_at(pos + 2 + digits);
+            annotated.push_str(token);
+
+            if let Ok(addr) = u32::from_str_radix(&token[pos + 2..], 16) {
+                if let Some((name, offset)) = self.lookup(addr) {
+                    annotated.push_str(&format!(" [{}+0x{:x}]", name, offset));
+                }
+            }
+            rest = tail;
+        }
+        annotated.push_str(rest);
+
+        annotated
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use opentelemetry::{
+    sdk::{trace, Resource},
+    trace::TraceContextExt,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use rocket::FromFormField;
+use tracing::{field, info_span, Span};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Layer, Registry,
+};
+
+/// Sessions the page never reported the end of are closed after this long
+const SESSION_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const MAX_SESSION_ID_LEN: usize = 64;
+
+/// Target of the events `status!` emits; they're printed as they are, so
+/// stderr leaves them out
+pub const STATUS_TARGET: &str = "status";
+
+type OtelLayer = OpenTelemetryLayer<Registry, trace::Tracer>;
+
+/// Where [`export`] adds the OTLP layer once the Tokio runtime is up
+static OTEL: Mutex<Option<reload::Handle<Option<OtelLayer>, Registry>>> = Mutex::new(None);
+
+/// How log lines on stderr are written, `--log-format`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// for people
+    Text,
+    /// one JSON object per line, progress reports included, for log
+    /// aggregators
+    Json,
+}
+
+/// `-v`/`-q`, `--log-format` and `--log-file`
+#[derive(Debug, Clone)]
+pub struct LogSettings {
+    /// -1 with `--quiet`, otherwise the number of `-v`
+    pub verbosity: i8,
+    pub format: LogFormat,
+    pub file: Option<PathBuf>,
+}
+
+/// `RUST_LOG`, or what `verbosity` shows. Rocket's launch and request
+/// logging only shows from `-v` on.
+fn filter(verbosity: i8) -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(match verbosity {
+            i8::MIN..=-1 => "error",
+            0 => "info,rocket=warn,_=warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        })
+    })
+}
+
+/// Installs the subscriber: log lines on stderr, including Rocket's own
+/// logging, and with `--log-file` everything as JSON lines appended to
+/// the file, progress reports included. With `--log-format json` stderr
+/// gets JSON lines too, progress reports included, as they aren't
+/// printed then.
+pub fn init(settings: &LogSettings) -> Result<()> {
+    let (otel, handle) = reload::Layer::new(None);
+    let file = match &settings.file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open the log file {}", path.display()))?;
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_writer(Mutex::new(file))
+                    .with_filter(filter(settings.verbosity.max(0))),
+            )
+        }
+        None => None,
+    };
+    let (text, json) = match settings.format {
+        LogFormat::Text => {
+            let filter =
+                filter(settings.verbosity).add_directive(format!("{}=off", STATUS_TARGET).parse()?);
+            let layer = fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_filter(filter);
+            (Some(layer), None)
+        }
+        LogFormat::Json => {
+            let layer = fmt::layer()
+                .json()
+                .with_writer(std::io::stderr)
+                .with_filter(filter(settings.verbosity));
+            (None, Some(layer))
+        }
+    };
+
+    tracing_subscriber::registry()
+        .with(otel)
+        .with(text)
+        .with(json)
+        .with(file)
+        .try_init()
+        .context("Failed to install the tracing subscriber")?;
+    *OTEL.lock().unwrap() = Some(handle);
+    Ok(())
+}
+
+/// Starts exporting spans over OTLP. Must run inside the Tokio runtime,
+/// which the batch exporter is spawned on.
+pub fn export(endpoint: &str) -> Result<()> {
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                env!("CARGO_PKG_NAME"),
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)
+        .with_context(|| format!("Failed to set up OTLP export to {}", endpoint))?;
+    if let Some(handle) = OTEL.lock().unwrap().as_ref() {
+        handle
+            .reload(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+            .context("Failed to add the OTLP layer")?;
+    }
+    status!("Exporting traces to {}", endpoint);
+    Ok(())
+}
+
+/// Flushes the spans not exported yet
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Copies the OpenTelemetry ids into the span's `trace_id`/`span_id`
+/// fields, so log lines can be found in the trace. Without export there
+/// are no ids and the fields stay empty.
+fn record_ids(span: &Span) {
+    let context = span.context();
+    let span_context = context.span().span_context().clone();
+    if span_context.is_valid() {
+        span.record("trace_id", field::display(span_context.trace_id()));
+        span.record("span_id", field::display(span_context.span_id()));
+    }
+}
+
+/// How the page reported a session to have ended
+#[derive(FromFormField, Debug, Clone, Copy)]
+pub enum Outcome {
+    Finished,
+    Error,
+}
+
+/// The session id a request carried, if it's one the page could have
+/// generated; it ends up in part URLs, so nothing else is passed on
+pub fn session_id(id: Option<&str>) -> Option<&str> {
+    id.filter(|id| {
+        !id.is_empty()
+            && id.len() <= MAX_SESSION_ID_LEN
+            && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+struct Session {
+    span: Span,
+    started: Instant,
+}
+
+/// Root spans of the flash sessions in progress, keyed by the id the page
+/// sends with the manifest and part requests
+#[derive(Clone, Default)]
+pub struct Sessions(Arc<Mutex<HashMap<String, Session>>>);
+
+impl Sessions {
+    /// The root span of session `id`, opened by its first request; `None`
+    /// for ids the page couldn't have generated
+    fn root(&self, id: &str) -> Option<Span> {
+        let id = session_id(Some(id))?;
+        let mut sessions = self.0.lock().unwrap();
+        sessions.retain(|key, session| {
+            let live = session.started.elapsed() < SESSION_TIMEOUT;
+            if !live {
+                session.span.record("outcome", "timeout");
+                tracing::warn!(parent: &session.span, session = %key, "session never ended");
+            }
+            live
+        });
+        let session = sessions.entry(id.to_string()).or_insert_with(|| {
+            let span = info_span!(
+                parent: None,
+                "flash_session",
+                session = %id,
+                outcome = field::Empty,
+                trace_id = field::Empty,
+                span_id = field::Empty,
+            );
+            record_ids(&span);
+            Session {
+                span,
+                started: Instant::now(),
+            }
+        });
+        Some(session.span.clone())
+    }
+
+    /// Span for one artifact request, below the session's root span if
+    /// the request carried a session id
+    pub fn artifact(&self, session: Option<&str>, path: &str) -> Span {
+        let span = match session.and_then(|id| self.root(id)) {
+            Some(root) => info_span!(
+                parent: &root,
+                "artifact",
+                path,
+                trace_id = field::Empty,
+                span_id = field::Empty,
+            ),
+            None => info_span!(
+                "artifact",
+                path,
+                trace_id = field::Empty,
+                span_id = field::Empty,
+            ),
+        };
+        record_ids(&span);
+        span
+    }
+
+    /// Span for a server-side rebuild, below the session's root span if
+    /// the upload carried a session id
+    pub fn rebuild(&self, session: Option<&str>, input: &str) -> Span {
+        let span = match session.and_then(|id| self.root(id)) {
+            Some(root) => info_span!(
+                parent: &root,
+                "rebuild",
+                input,
+                trace_id = field::Empty,
+                span_id = field::Empty,
+            ),
+            None => info_span!(
+                "rebuild",
+                input,
+                trace_id = field::Empty,
+                span_id = field::Empty,
+            ),
+        };
+        record_ids(&span);
+        span
+    }
+
+    /// Closes the session's root span; false if it wasn't open
+    pub fn end(&self, id: &str, outcome: Outcome, message: Option<&str>) -> bool {
+        let session = match self.0.lock().unwrap().remove(id) {
+            Some(session) => session,
+            None => return false,
+        };
+        let elapsed = session.started.elapsed().as_secs_f32();
+        match outcome {
+            Outcome::Finished => {
+                session.span.record("outcome", "finished");
+                tracing::info!(parent: &session.span, elapsed, "flash session finished");
+            }
+            Outcome::Error => {
+                session.span.record("outcome", "error");
+                tracing::error!(
+                    parent: &session.span,
+                    elapsed,
+                    message = message.unwrap_or_default(),
+                    "flash session failed"
+                );
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use opentelemetry::{sdk::trace::TracerProvider, trace::TracerProvider as _};
+
+    use super::*;
+
+    /// Where the log lines of a test go
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The text log of `f`, with spans going to an OpenTelemetry tracer as
+    /// with `--otlp-endpoint` if `traced`
+    fn log(traced: bool, f: impl FnOnce()) -> String {
+        let captured = Captured::default();
+        let text = fmt::layer().with_ansi(false).with_writer({
+            let captured = captured.clone();
+            move || captured.clone()
+        });
+        // the tracer only hands out ids while its provider is alive
+        let provider = TracerProvider::builder().build();
+        let otel =
+            traced.then(|| tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(otel).with(text), f);
+        let log = captured.0.lock().unwrap().clone();
+        String::from_utf8(log).unwrap()
+    }
+
+    /// The values of `field` in a log line, in order
+    fn values<'a>(line: &'a str, field: &str) -> Vec<&'a str> {
+        line.match_indices(&format!("{}=", field))
+            .map(|(i, m)| {
+                let value = &line[i + m.len()..];
+                &value[..value.find(['}', ' ']).unwrap_or(value.len())]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn log_lines_carry_trace_and_span_ids() {
+        let log = log(true, || {
+            let span = Sessions::default().artifact(Some("abc-123"), "firmware.bin");
+            let _entered = span.enter();
+            tracing::info!("served");
+        });
+        let line = log.lines().find(|line| line.contains("served")).unwrap();
+
+        // the session's root span and the artifact's, in the same trace
+        let trace_ids = values(line, "trace_id");
+        let span_ids = values(line, "span_id");
+        assert_eq!(trace_ids.len(), 2, "{}", line);
+        assert_eq!(trace_ids[0], trace_ids[1]);
+        assert_eq!(trace_ids[0].len(), 32);
+        assert!(trace_ids[0].chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(span_ids.len(), 2, "{}", line);
+        assert_ne!(span_ids[0], span_ids[1]);
+        assert!(span_ids.iter().all(|id| id.len() == 16));
+    }
+
+    #[test]
+    fn without_export_there_are_no_ids() {
+        let log = log(false, || {
+            let span = Sessions::default().rebuild(None, "app.elf");
+            let _entered = span.enter();
+            tracing::info!("rebuilt");
+        });
+        assert!(log.contains("rebuilt"));
+        assert!(!log.contains("trace_id="));
+    }
+
+    #[test]
+    fn accepts_only_page_session_ids() {
+        assert_eq!(session_id(Some("3f2a-9b")), Some("3f2a-9b"));
+        assert_eq!(session_id(Some("a/b")), None);
+        assert_eq!(session_id(Some("")), None);
+        assert_eq!(session_id(Some(&"a".repeat(MAX_SESSION_ID_LEN + 1))), None);
+    }
+}
@@ -0,0 +1,165 @@
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Light,
+    Dark,
+    /// follow the browser's `prefers-color-scheme`
+    Auto,
+}
+
+const DEFAULT_ACCENT: &str = "#3498db";
+const DEFAULT_ACCENT_HOVER: &str = "#2980b9";
+
+const LIGHT: &[(&str, &str)] = &[
+    ("page-bg", "#f5f5f5"),
+    ("text", "#333"),
+    ("heading", "#2c3e50"),
+    ("subheading", "#34495e"),
+    ("surface", "white"),
+    ("surface-alt", "#f8f9fa"),
+    ("border", "#e9ecef"),
+    ("border-strong", "#dee2e6"),
+    ("muted", "#666"),
+    ("layout-defined", "#a9cce3"),
+    ("progress-bg", "#e3f2fd"),
+    ("progress-border", "#90caf9"),
+    ("note-bg", "#fff3cd"),
+    ("note-border", "#ffeaa7"),
+    ("note-text", "#856404"),
+    ("error-bg", "#f8d7da"),
+    ("error-border", "#f5c6cb"),
+    ("error-text", "#721c24"),
+];
+
+const DARK: &[(&str, &str)] = &[
+    ("page-bg", "#121417"),
+    ("text", "#d0d4d9"),
+    ("heading", "#e6e9ed"),
+    ("subheading", "#c3c9d0"),
+    ("surface", "#1c1f24"),
+    ("surface-alt", "#23272e"),
+    ("border", "#2f353d"),
+    ("border-strong", "#3a414b"),
+    ("muted", "#9aa3ad"),
+    ("layout-defined", "#2d4a63"),
+    ("progress-bg", "#15293d"),
+    ("progress-border", "#2d5a86"),
+    ("note-bg", "#3a3115"),
+    ("note-border", "#5c4d1a"),
+    ("note-text", "#f0d78c"),
+    ("error-bg", "#3d1a1e"),
+    ("error-border", "#6b2a31"),
+    ("error-text", "#f5b5bc"),
+];
+
+/// The console keeps its dark palette in every theme, so the log colors
+/// stay readable regardless of the page background.
+const CONSOLE: &[(&str, &str)] = &[
+    ("console-bg", "#1e1e1e"),
+    ("console-text", "#d4d4d4"),
+    ("console-error", "#f48771"),
+    ("console-success", "#98c379"),
+    ("console-warning", "#e5c07b"),
+    ("console-progress", "#61afef"),
+];
+
+/// Parses a `#rgb` or `#rrggbb` color, normalized to `#rrggbb`
+pub fn parse_color(s: &str) -> Result<String, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("'{}' is not a hex color like #3498db", s));
+    }
+
+    match hex.len() {
+        3 => {
+            Ok(format!("#{}", hex.chars().flat_map(|c| [c, c]).collect::<String>()).to_lowercase())
+        }
+        6 => Ok(format!("#{}", hex.to_lowercase())),
+        _ => Err(format!("'{}' is not a hex color like #3498db", s)),
+    }
+}
+
+/// Darkens a normalized `#rrggbb` color for the hover state
+fn darken(color: &str) -> String {
+    let channel = |i: usize| u8::from_str_radix(&color[i..i + 2], 16).unwrap_or(0);
+    let scale = |c: u8| (c as f32 * 0.85) as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        scale(channel(1)),
+        scale(channel(3)),
+        scale(channel(5))
+    )
+}
+
+fn block(selector: &str, vars: &[(&str, &str)], indent: &str) -> String {
+    let mut css = format!("{}{} {{\n", indent, selector);
+    for (name, value) in vars {
+        css.push_str(&format!("{}    --{}: {};\n", indent, name, value));
+    }
+    css.push_str(&format!("{}}}\n", indent));
+    css
+}
+
+/// Generates the custom properties used by the page's stylesheet
+pub fn stylesheet(theme: Theme, accent: Option<&str>) -> String {
+    // the heading keeps the theme's color unless an accent is given
+    let (accent, accent_hover, title) = match accent {
+        Some(accent) => (accent.to_string(), darken(accent), accent.to_string()),
+        None => (
+            DEFAULT_ACCENT.to_string(),
+            DEFAULT_ACCENT_HOVER.to_string(),
+            String::from("var(--heading)"),
+        ),
+    };
+    let accent_vars = [
+        ("accent", accent.as_str()),
+        ("accent-hover", accent_hover.as_str()),
+        ("accent-text", "white"),
+        ("title", title.as_str()),
+    ];
+
+    let mut css = String::new();
+    match theme {
+        Theme::Light => css.push_str(&block(":root", LIGHT, "")),
+        Theme::Dark => css.push_str(&block(":root", DARK, "")),
+        Theme::Auto => {
+            css.push_str(&block(":root", LIGHT, ""));
+            css.push_str("@media (prefers-color-scheme: dark) {\n");
+            css.push_str(&block(":root", DARK, "    "));
+            css.push_str("}\n");
+        }
+    }
+    css.push_str(&block(":root", CONSOLE, ""));
+    css.push_str(&block(":root", &accent_vars, ""));
+    css
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_theme_with_accent_matches_snapshot() {
+        let accent = parse_color("#F80").unwrap();
+        assert_eq!(
+            stylesheet(Theme::Auto, Some(&accent)),
+            include_str!("../tests/fixtures/theme-auto-ff8800.css")
+        );
+    }
+
+    #[test]
+    fn console_colors_are_the_same_in_every_theme() {
+        let console = block(":root", CONSOLE, "");
+        for theme in [Theme::Light, Theme::Dark, Theme::Auto] {
+            assert!(stylesheet(theme, None).contains(&console));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_colors() {
+        assert_eq!(parse_color("3498DB").as_deref(), Ok("#3498db"));
+        assert!(parse_color("#12345").is_err());
+        assert!(parse_color("#ggg").is_err());
+    }
+}
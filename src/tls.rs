@@ -0,0 +1,120 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// The certificate devices talking to this server over HTTPS should pin
+#[derive(Serialize, Debug, Clone)]
+pub struct CertInfo {
+    /// SHA-256 of the DER certificate, as `AB:CD:...` like openssl prints it
+    pub sha256: String,
+    #[serde(skip)]
+    pub pem: String,
+}
+
+impl CertInfo {
+    pub fn load(path: &Path) -> Result<Self> {
+        let pem = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let sha256 = fingerprint(&pem).with_context(|| format!("Invalid {}", path.display()))?;
+        Ok(CertInfo { sha256, pem })
+    }
+}
+
+/// Fingerprint of the first (leaf) certificate in a PEM chain
+fn fingerprint(pem: &str) -> Result<String> {
+    let leaf = pem::parse_many(pem)?
+        .into_iter()
+        .find(|block| block.tag == "CERTIFICATE")
+        .ok_or_else(|| anyhow!("no CERTIFICATE block"))?;
+    Ok(Sha256::digest(&leaf.contents)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
+
+/// `--tls`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsMode {
+    /// a certificate for this run only, for the host names and addresses served on
+    SelfSigned,
+}
+
+/// `/ota/cert.pem` and `/ota/cert-fingerprint`, empty without TLS
+#[derive(Clone)]
+pub struct Pinning {
+    pub cert: Option<CertInfo>,
+}
+
+/// Writes a file only the owner can read
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+/// The self-signed certificate used by TLS listeners without one, kept in
+/// the config directory because devices pin it: it is only replaced when
+/// asked to with `--tls-regenerate`
+pub fn self_signed(config_dir: &Path, regenerate: bool) -> Result<(PathBuf, PathBuf)> {
+    let dir = config_dir.join("tls");
+    let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+    if cert_path.exists() && key_path.exists() && !regenerate {
+        status!("Using the self-signed certificate {}", cert_path.display());
+        return Ok((cert_path, key_path));
+    }
+
+    let mut names = vec![String::from("localhost")];
+    if let Ok(host) = std::env::var("HOSTNAME") {
+        names.push(host);
+    }
+    let cert = rcgen::generate_simple_self_signed(names)
+        .context("Failed to generate a self-signed certificate")?;
+
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    std::fs::write(&cert_path, cert.serialize_pem()?)
+        .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+    write_private(&key_path, &cert.serialize_private_key_pem())
+        .with_context(|| format!("Failed to write {}", key_path.display()))?;
+
+    status!(
+        "Generated the self-signed certificate {}{}",
+        cert_path.display(),
+        if regenerate {
+            "; devices pinning the previous one must be updated"
+        } else {
+            ""
+        }
+    );
+    Ok((cert_path, key_path))
+}
+
+/// The certificate of `--tls self-signed`, valid for `hosts` and written to
+/// the temp directory: nothing pins it, so every run gets a new one
+pub fn throwaway(hosts: &[String]) -> Result<(PathBuf, PathBuf)> {
+    let cert = rcgen::generate_simple_self_signed(hosts.to_vec())
+        .context("Failed to generate a self-signed certificate")?;
+    let dir = std::env::temp_dir().join(format!("web-flash-tls-{}", std::process::id()));
+    let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    std::fs::write(&cert_path, cert.serialize_pem()?)
+        .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+    write_private(&key_path, &cert.serialize_private_key_pem())
+        .with_context(|| format!("Failed to write {}", key_path.display()))?;
+
+    status!(
+        "Generated a self-signed certificate for {}; the browser will ask to accept it",
+        hosts.join(", ")
+    );
+    Ok((cert_path, key_path))
+}
@@ -0,0 +1,297 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use multer::{Constraints, Multipart, SizeLimit};
+use rocket::{
+    data::{Data, ToByteUnit},
+    http::{ContentType, Status},
+};
+use serde::Serialize;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    prepare,
+    prepare_error::PrepareError,
+    prepared_build::{Builds, PreparedBuild},
+    presets::PartitionTableArg,
+    summary, ImageArgs,
+};
+
+/// Largest partition table or bootloader accepted by the upload endpoints
+pub const MAX_UPLOAD_SIZE: u64 = 256 * 1024;
+/// Largest `POST /upload` form, ELF and partition table together
+pub const MAX_ELF_UPLOAD_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Which input an upload replaces
+#[derive(Debug, Clone, Copy)]
+pub enum Input {
+    PartitionTable,
+    Bootloader,
+    Elf,
+}
+
+impl Input {
+    fn file_name(self) -> &'static str {
+        match self {
+            Input::PartitionTable => "partition-table",
+            Input::Bootloader => "bootloader",
+            Input::Elf => "elf",
+        }
+    }
+}
+
+/// The files of the `multipart/form-data` body of `POST /upload`
+pub struct ElfUpload {
+    pub elf: Vec<u8>,
+    pub partition_table: Option<Vec<u8>>,
+}
+
+impl ElfUpload {
+    /// Reads the form, up to [`MAX_ELF_UPLOAD_SIZE`] bytes. It's parsed
+    /// here rather than as a Rocket `Form`, whose limits apply to every
+    /// route.
+    pub async fn read(
+        content_type: &ContentType,
+        data: Data<'_>,
+    ) -> Result<Self, (Status, String)> {
+        let boundary = content_type
+            .param("boundary")
+            .filter(|_| content_type.is_form_data())
+            .ok_or((
+                Status::UnsupportedMediaType,
+                String::from("expected a multipart/form-data upload, e.g. curl -F elf=@app.elf"),
+            ))?;
+        let body = ReaderStream::new(data.open((MAX_ELF_UPLOAD_SIZE + 1).bytes()));
+        let constraints =
+            Constraints::new().size_limit(SizeLimit::new().whole_stream(MAX_ELF_UPLOAD_SIZE));
+        let mut form = Multipart::with_constraints(body, boundary, constraints);
+
+        let failure = |e: multer::Error| match e {
+            multer::Error::StreamSizeExceeded { .. } => (
+                Status::PayloadTooLarge,
+                format!("uploads are limited to {} bytes", MAX_ELF_UPLOAD_SIZE),
+            ),
+            e => (Status::BadRequest, e.to_string()),
+        };
+        let (mut elf, mut partition_table) = (None, None);
+        while let Some(field) = form.next_field().await.map_err(failure)? {
+            let file = match field.name() {
+                Some("elf") => &mut elf,
+                Some("partition_table") => &mut partition_table,
+                _ => continue,
+            };
+            *file = Some(field.bytes().await.map_err(failure)?.to_vec());
+        }
+        Ok(ElfUpload {
+            elf: elf.ok_or((
+                Status::BadRequest,
+                String::from("the form has no elf field"),
+            ))?,
+            partition_table,
+        })
+    }
+}
+
+/// A failed rebuild, with the message and exit code the CLI would give
+#[derive(Serialize, Debug)]
+pub struct UploadError {
+    pub error: String,
+    pub exit_code: i32,
+}
+
+impl From<&PrepareError> for UploadError {
+    fn from(e: &PrepareError) -> Self {
+        UploadError {
+            error: e.to_string(),
+            exit_code: e.exit_code(),
+        }
+    }
+}
+
+/// What `POST /reload` answers with once the new build is served
+#[derive(Serialize, Debug)]
+pub struct Reloaded {
+    pub hash: String,
+    pub total_size: usize,
+    pub parts: Vec<summary::Part>,
+}
+
+impl Reloaded {
+    pub fn new(build: &PreparedBuild) -> Self {
+        Reloaded {
+            hash: build.hash.clone(),
+            total_size: build.parts.total_size,
+            parts: summary::parts(build),
+        }
+    }
+}
+
+/// The image options the served build was prepared from, updated by every
+/// accepted upload so later uploads build on the earlier ones
+#[derive(Clone)]
+pub struct Rebuilder {
+    opts: Arc<Mutex<ImageArgs>>,
+    /// where uploaded files are kept, as rebuilds read their inputs by path
+    dir: PathBuf,
+}
+
+impl Rebuilder {
+    pub fn new(opts: &ImageArgs) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("web-flash-uploads-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Rebuilder {
+            opts: Arc::new(Mutex::new(opts.clone())),
+            dir,
+        })
+    }
+
+    /// Prepares a build with `data` replacing `input`, exactly as if it had
+    /// been given on the command line, and serves it from `builds`. The
+    /// served build stays as it was if that fails.
+    pub fn rebuild(
+        &self,
+        builds: &Builds,
+        input: Input,
+        data: &[u8],
+    ) -> Result<Arc<PreparedBuild>, UploadError> {
+        self.rebuild_with(builds, &[(input, data)])
+    }
+
+    /// [`Rebuilder::rebuild`] with several inputs replaced at once
+    pub fn rebuild_with(
+        &self,
+        builds: &Builds,
+        inputs: &[(Input, &[u8])],
+    ) -> Result<Arc<PreparedBuild>, UploadError> {
+        let mut opts = self.opts.lock().unwrap();
+        if opts.serve_dir.is_some()
+            || opts.idf_build_dir.is_some()
+            || opts.pio.is_some()
+            || opts.merged_bin.is_some()
+        {
+            return Err(UploadError {
+                error: String::from(
+                    "the server runs with --serve-dir, --idf-build-dir, --pio or --merged-bin, which have no separate \
+                     ELF, partition table or bootloader to replace",
+                ),
+                exit_code: 2,
+            });
+        }
+
+        let mut candidate = opts.clone();
+        for &(input, data) in inputs {
+            // a new name per upload, so a failed one can't clobber the file in use
+            let path = self.dir.join(format!(
+                "{}-{:08x}",
+                input.file_name(),
+                crc32fast::hash(data)
+            ));
+            std::fs::write(&path, data).map_err(|e| UploadError {
+                error: format!("Failed to store the upload in {}: {}", path.display(), e),
+                exit_code: 3,
+            })?;
+
+            match input {
+                Input::PartitionTable => {
+                    candidate.partition_table = Some(PartitionTableArg::File(path));
+                    candidate.gen_partition_table = false;
+                }
+                Input::Bootloader => candidate.bootloader = Some(path),
+                Input::Elf => {
+                    candidate.elf = Some(path);
+                    candidate.cargo = false;
+                }
+            }
+        }
+
+        builds.start_rebuild();
+        match prepare(&candidate) {
+            Ok(parts) => {
+                builds.finish(Ok(PreparedBuild::new(parts)));
+                // the uploads the new build replaced are never read again
+                let kept = self.uploads(&candidate);
+                for path in self.uploads(&opts) {
+                    if !kept.contains(&path) {
+                        std::fs::remove_file(path).ok();
+                    }
+                }
+                *opts = candidate;
+                Ok(builds.current())
+            }
+            Err(e) => {
+                let in_use = self.uploads(&opts);
+                for path in self.uploads(&candidate) {
+                    if !in_use.contains(&path) {
+                        std::fs::remove_file(path).ok();
+                    }
+                }
+                let error = UploadError::from(&e);
+                builds.finish(Err(e));
+                Err(error)
+            }
+        }
+    }
+
+    /// The inputs of `opts` that are uploads kept in the upload directory
+    fn uploads(&self, opts: &ImageArgs) -> Vec<PathBuf> {
+        let table = match &opts.partition_table {
+            Some(PartitionTableArg::File(path)) => Some(path),
+            _ => None,
+        };
+        [opts.elf.as_ref(), opts.bootloader.as_ref(), table]
+            .into_iter()
+            .flatten()
+            .filter(|path| path.starts_with(&self.dir))
+            .cloned()
+            .collect()
+    }
+
+    /// Deletes the upload directory, once the server has stopped
+    pub fn remove_uploads(&self) {
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+
+    /// Prepares a build from the current options again, picking up inputs
+    /// changed on disk
+    pub fn rebuild_current(&self) -> Result<PreparedBuild, PrepareError> {
+        let opts = self.opts.lock().unwrap();
+        Ok(PreparedBuild::new(prepare(&opts)?))
+    }
+
+    /// What the current options read, for `--watch`
+    pub fn inputs(&self) -> Inputs {
+        let opts = self.opts.lock().unwrap();
+        let mut inputs = Inputs::default();
+        if let Ok(elf) = opts.elf() {
+            // a glob, a directory or --cargo may resolve to another file
+            if opts.cargo || opts.elf.as_deref() != Some(elf.as_path()) {
+                inputs.dirs.extend(elf.parent().map(PathBuf::from));
+            }
+            inputs.files.push(elf);
+        }
+        inputs.files.extend(opts.bootloader.clone());
+        if let Some(PartitionTableArg::File(path)) = &opts.partition_table {
+            inputs.files.push(path.clone());
+        }
+        inputs.files.extend(opts.nvs_csv.clone());
+        inputs.dirs.extend(opts.data_dir.clone());
+        inputs.files.extend(
+            opts.extra_part_files
+                .iter()
+                .map(|spec| PathBuf::from(&spec.file)),
+        );
+        inputs
+    }
+}
+
+/// Files a build is prepared from, and directories in which any new file
+/// may be picked up as the ELF
+#[derive(Debug, Default)]
+pub struct Inputs {
+    pub files: Vec<PathBuf>,
+    pub dirs: Vec<PathBuf>,
+}
@@ -0,0 +1,37 @@
+use std::{borrow::Cow, path::Path};
+
+use rust_embed::RustEmbed;
+
+/// Version of esp-web-tools loaded from unpkg with `--cdn`, and the one
+/// scripts/vendor-esp-web-tools.sh fetches
+pub const ESP_WEB_TOOLS_VERSION: &str = "9.4.3";
+
+/// esp-web-tools' browser build, fetched by scripts/vendor-esp-web-tools.sh
+#[derive(RustEmbed)]
+#[folder = "vendor/esp-web-tools/"]
+#[include = "*.js"]
+struct EspWebTools;
+
+/// Whether the binary was built with the vendored files
+pub fn bundled() -> bool {
+    EspWebTools::get("install-button.js").is_some()
+}
+
+/// Where the page loads the install button from: the bundled copy, relative
+/// to the page, or unpkg with `--cdn`
+pub fn install_button_url(cdn: bool) -> String {
+    if cdn {
+        format!(
+            "https://unpkg.com/esp-web-tools@{}/dist/web/install-button.js?module",
+            ESP_WEB_TOOLS_VERSION
+        )
+    } else {
+        String::from("vendor/esp-web-tools/install-button.js")
+    }
+}
+
+/// A bundled module file by its path below dist/web
+pub fn esp_web_tools(file: &Path) -> Option<Cow<'static, [u8]>> {
+    let file = file.to_str()?.replace('\\', "/");
+    EspWebTools::get(&file).map(|asset| asset.data)
+}
@@ -0,0 +1,103 @@
+use std::io::Write;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use espflash::Flasher;
+
+use crate::{device, PartsData, VerifyArgs};
+
+/// Size of each read request, also the granularity of the progress output
+const READ_CHUNK: u32 = 0x4000;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    Bootloader,
+    Partitions,
+    Firmware,
+}
+
+/// Reads back every selected part and prints a match/mismatch report,
+/// returning whether all of them matched
+pub fn run(opts: &VerifyArgs, data: &PartsData) -> Result<bool> {
+    let _claim = device::claim(&opts.port, "web-flash verify")?;
+    let mut flasher = device::connect(&opts.port, Some(opts.image.chip()))?;
+
+    let parts = [
+        (
+            Part::Bootloader,
+            "bootloader.bin",
+            data.bootloader_offset,
+            &data.bootloader,
+        ),
+        (
+            Part::Partitions,
+            "partitions.bin",
+            data.partitions_offset,
+            &data.partitions,
+        ),
+        (
+            Part::Firmware,
+            "firmware.bin",
+            data.firmware_offset,
+            &data.firmware,
+        ),
+    ];
+
+    let mut results = Vec::new();
+    for (part, name, offset, expected) in parts {
+        if !opts.parts.is_empty() && !opts.parts.contains(&part) {
+            continue;
+        }
+
+        let actual = read_back(&mut flasher, name, offset, expected.len() as u32)?;
+        results.push((name, offset, first_difference(expected, &actual)));
+    }
+
+    println!("\nVerification report:");
+    for (name, offset, difference) in &results {
+        match difference {
+            None => println!("  {:<16} 0x{:06x}  match", name, offset),
+            Some(i) => println!(
+                "  {:<16} 0x{:06x}  MISMATCH at 0x{:06x} (+0x{:x} into the part)",
+                name,
+                offset,
+                *offset as usize + i,
+                i
+            ),
+        }
+    }
+
+    let all_match = results
+        .iter()
+        .all(|(_, _, difference)| difference.is_none());
+    if all_match {
+        println!("All parts match");
+    } else {
+        println!("Device contents differ from the prepared image");
+    }
+
+    Ok(all_match)
+}
+
+fn read_back(flasher: &mut Flasher, name: &str, offset: u32, size: u32) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size as usize);
+
+    while (data.len() as u32) < size {
+        let chunk = READ_CHUNK.min(size - data.len() as u32);
+        data.extend(flasher.read_flash(offset + data.len() as u32, chunk)?);
+
+        print!("\rReading {}: {} / {} bytes", name, data.len(), size);
+        std::io::stdout().flush().ok();
+    }
+    println!();
+
+    Ok(data)
+}
+
+fn first_difference(expected: &[u8], actual: &[u8]) -> Option<usize> {
+    expected
+        .iter()
+        .zip(actual)
+        .position(|(a, b)| a != b)
+        .or_else(|| (expected.len() != actual.len()).then(|| expected.len().min(actual.len())))
+}
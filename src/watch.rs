@@ -0,0 +1,234 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{index_template::IndexTemplate, prepared_build::Builds, upload::Rebuilder};
+
+/// Quiet time after the last change before rebuilding
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// How often the inputs' sizes are compared while they're being written
+const SETTLE_POLL: Duration = Duration::from_millis(100);
+/// Longest wait for the inputs' sizes to stop changing
+const SETTLE_LIMIT: Duration = Duration::from_secs(10);
+
+/// Sizes of `files`, `None` for one that's missing (e.g. while the linker
+/// replaces it)
+fn sizes(files: &[PathBuf]) -> Vec<Option<u64>> {
+    files
+        .iter()
+        .map(|file| std::fs::metadata(file).ok().map(|meta| meta.len()))
+        .collect()
+}
+
+/// Waits until every input exists and its size stayed the same between two
+/// polls, so a half-written ELF isn't read
+fn settle(files: &[PathBuf]) {
+    let started = Instant::now();
+    let mut last = sizes(files);
+    while started.elapsed() < SETTLE_LIMIT {
+        std::thread::sleep(SETTLE_POLL);
+        let current = sizes(files);
+        if current == last && current.iter().all(Option::is_some) {
+            return;
+        }
+        last = current;
+    }
+}
+
+/// Whether an event changes one of the inputs, or any file in the
+/// directory of a glob or directory ELF argument; reading them, as a
+/// rebuild does, doesn't count
+fn relevant(event: &Event, files: &[PathBuf], dirs: &BTreeSet<PathBuf>) -> bool {
+    let changed = matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    );
+    changed
+        && event.paths.iter().any(|path| {
+            files.contains(path) || path.parent().map_or(false, |dir| dirs.contains(dir))
+        })
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The inputs of the served build, and the directories watched for them
+struct Watched {
+    files: Vec<PathBuf>,
+    dirs: BTreeSet<PathBuf>,
+    /// the directories, as linkers and editors replace files rather than
+    /// writing them in place
+    watched: BTreeSet<PathBuf>,
+}
+
+impl Watched {
+    fn new(rebuilder: &Rebuilder) -> Self {
+        let inputs = rebuilder.inputs();
+        let files: Vec<PathBuf> = inputs.files.iter().map(|file| absolute(file)).collect();
+        let dirs: BTreeSet<PathBuf> = inputs.dirs.iter().map(|dir| absolute(dir)).collect();
+        let watched = files
+            .iter()
+            .filter_map(|file| file.parent().map(Path::to_path_buf))
+            .chain(dirs.iter().cloned())
+            .collect();
+        Watched {
+            files,
+            dirs,
+            watched,
+        }
+    }
+
+    /// Watches the directories `self` needs that `previous` didn't, and
+    /// stops watching those it no longer needs
+    fn register(
+        &self,
+        watcher: &mut RecommendedWatcher,
+        previous: &BTreeSet<PathBuf>,
+    ) -> Result<()> {
+        for dir in previous.difference(&self.watched) {
+            watcher.unwatch(dir).ok();
+        }
+        for dir in self.watched.difference(previous) {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("Failed to watch {}", dir.display()))?;
+        }
+        Ok(())
+    }
+
+    fn print(&self) {
+        status!("Watching for changes:");
+        for file in &self.files {
+            status!("  {}", file.display());
+        }
+        for dir in &self.dirs {
+            status!("  {}/", dir.display());
+        }
+    }
+}
+
+/// Watches the inputs of the served build, `--watch`, and serves a rebuild
+/// whenever they change. A failed rebuild keeps the last good build; a
+/// successful one may read other inputs (e.g. a newer ELF matching a glob),
+/// which are watched from then on. Watching stops when the returned watcher
+/// is dropped.
+pub fn spawn(rebuilder: Rebuilder, builds: Builds) -> Result<Arc<Mutex<RecommendedWatcher>>> {
+    let mut inputs = Watched::new(&rebuilder);
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })
+    .context("Failed to start the file watcher")?;
+    inputs.register(&mut watcher, &BTreeSet::new())?;
+    inputs.print();
+
+    let watcher = Arc::new(Mutex::new(watcher));
+    let handle = Arc::downgrade(&watcher);
+    std::thread::spawn(move || loop {
+        match rx.recv() {
+            Ok(Ok(event)) if relevant(&event, &inputs.files, &inputs.dirs) => {}
+            Ok(Ok(_)) => continue,
+            Ok(Err(e)) => {
+                warning!("file watcher: {}", e);
+                continue;
+            }
+            Err(_) => return,
+        }
+        // wait for the burst of events a build produces to end
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        settle(&inputs.files);
+
+        status!("\nInput changed, rebuilding...");
+        builds.start_rebuild();
+        let result = rebuilder.rebuild_current();
+        let rebuilt = result.is_ok();
+        match &result {
+            Ok(build) => {
+                let parts = &build.parts;
+                status!(
+                    "Rebuilt {}: bootloader {} bytes, partition table {} bytes, firmware {} bytes, total {} bytes",
+                    build.hash,
+                    parts.bootloader_size,
+                    parts.partitions_size,
+                    parts.firmware_size,
+                    parts.total_size
+                );
+                tracing::info!(hash = %build.hash, "rebuilt after a change");
+            }
+            Err(e) => {
+                if !crate::JSON_LOGS.load(Ordering::Relaxed) {
+                    eprintln!("Rebuild failed, still serving the last good build: {}", e);
+                }
+                tracing::warn!(error = %e, "rebuild failed");
+            }
+        }
+        builds.finish(result);
+
+        if rebuilt {
+            let Some(watcher) = handle.upgrade() else {
+                return;
+            };
+            let current = Watched::new(&rebuilder);
+            if current.files != inputs.files || current.dirs != inputs.dirs {
+                if let Err(e) = current.register(&mut watcher.lock().unwrap(), &inputs.watched) {
+                    warning!("{:#}", e);
+                }
+                current.print();
+                inputs = current;
+            }
+        }
+    });
+
+    Ok(watcher)
+}
+
+/// Reloads the `--index` page whenever it changes, with `--watch`
+pub fn template(template: IndexTemplate) -> Result<RecommendedWatcher> {
+    let files = vec![absolute(template.path())];
+    let dir = files[0].parent().map(Path::to_path_buf).unwrap_or_default();
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })
+    .context("Failed to start the file watcher")?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Failed to watch {}", dir.display()))?;
+
+    std::thread::spawn(move || loop {
+        match rx.recv() {
+            Ok(Ok(event)) if relevant(&event, &files, &BTreeSet::new()) => {}
+            Ok(_) => continue,
+            Err(_) => return,
+        }
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        settle(&files);
+        template.reload();
+    });
+
+    Ok(watcher)
+}
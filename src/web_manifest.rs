@@ -0,0 +1,357 @@
+use clap::ValueEnum;
+use rocket::FromFormField;
+use serde::Serialize;
+
+use crate::PartsData;
+
+/// Name esp-web-tools shows in the install dialog without `--name`
+pub const DEFAULT_NAME: &str = "ESP Application";
+
+/// Whether a new install erases the device first
+#[derive(ValueEnum, FromFormField, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErasePolicy {
+    /// let the user choose in the install dialog
+    Prompt,
+    /// erase everything first (factory install)
+    Full,
+    /// keep the device's settings (update)
+    Keep,
+}
+
+impl ErasePolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErasePolicy::Prompt => "prompt",
+            ErasePolicy::Full => "full",
+            ErasePolicy::Keep => "keep",
+        }
+    }
+}
+
+/// The esp-web-tools manifest describing what to flash where
+#[derive(Serialize, Debug, Clone)]
+pub struct Manifest {
+    pub name: String,
+    /// without the prompt, esp-web-tools erases on every new install...
+    pub new_install_prompt_erase: bool,
+    /// ...unless this is set
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub new_install_skip_erase: bool,
+    pub builds: Vec<Build>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Build {
+    pub chip_family: String,
+    pub parts: Vec<Part>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Part {
+    pub path: String,
+    pub offset: u32,
+}
+
+/// Builds the manifest for the prepared parts, using the offsets they were
+/// built for. Part paths are pinned to the build `hash`, so a client that
+/// mixes a stale manifest with a newer build gets a 409 instead of a bad flash.
+/// Generated per request, as the erase behavior depends on the flavor asked for.
+/// Part paths are relative to the manifest, which lives `base` below the root,
+/// and carry the flash `session` the manifest was fetched for and the
+/// `token=` query of `--auth-token`.
+pub fn generate(
+    data: &PartsData,
+    name: &str,
+    hash: &str,
+    base: &str,
+    erase: ErasePolicy,
+    session: Option<&str>,
+    token: Option<&str>,
+) -> Manifest {
+    let params: Vec<String> = session
+        .map(|id| format!("session={}", id))
+        .into_iter()
+        .chain(token.map(str::to_string))
+        .collect();
+    let query = if params.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", params.join("&"))
+    };
+    build(data, name, erase, |file| {
+        format!("{}b/{}/{}{}", base, hash, file, query)
+    })
+}
+
+/// The manifest for parts stored next to it under their file names, as
+/// written by `export`
+pub fn flat(data: &PartsData, name: &str, erase: ErasePolicy) -> Manifest {
+    build(data, name, erase, str::to_string)
+}
+
+/// The manifest's name with the firmware version, so the install dialog
+/// shows which release it flashes
+pub fn title(name: &str, version: Option<&str>) -> String {
+    match version {
+        Some(version) => format!("{} {}", name, version),
+        None => name.to_string(),
+    }
+}
+
+/// Checks a `--base-url` value, returning it with a trailing slash so part
+/// paths can be appended
+pub fn base_url_arg(s: &str) -> Result<String, String> {
+    if !(s.starts_with("http://") || s.starts_with("https://")) {
+        return Err(format!(
+            "'{}' is not an http(s) URL like https://flash.lab:8443/",
+            s
+        ));
+    }
+    Ok(if s.ends_with('/') {
+        s.to_string()
+    } else {
+        format!("{}/", s)
+    })
+}
+
+fn build(
+    data: &PartsData,
+    name: &str,
+    erase: ErasePolicy,
+    path: impl Fn(&str) -> String,
+) -> Manifest {
+    let mut parts = if data.single_part || data.app_only {
+        // the merged image already contains bootloader and partition table,
+        // with --app-only the device does
+        vec![Part {
+            path: path("firmware.bin"),
+            offset: data.firmware_offset,
+        }]
+    } else {
+        let mut parts = Vec::new();
+        // the ESP8266 boots its app without either
+        if !data.bootloader.is_empty() {
+            parts.push(Part {
+                path: path("bootloader.bin"),
+                offset: data.bootloader_offset,
+            });
+        }
+        if !data.partitions.is_empty() {
+            parts.push(Part {
+                path: path("partitions.bin"),
+                offset: data.partitions_offset,
+            });
+        }
+        parts.push(Part {
+            path: path("firmware.bin"),
+            offset: data.firmware_offset,
+        });
+        parts
+    };
+    parts.extend(data.extra_parts.iter().map(|part| Part {
+        path: path(&part.file),
+        offset: part.offset,
+    }));
+
+    Manifest {
+        name: name.to_string(),
+        new_install_prompt_erase: erase == ErasePolicy::Prompt,
+        new_install_skip_erase: erase == ErasePolicy::Keep,
+        builds: vec![Build {
+            chip_family: data.chip.clone(),
+            parts,
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use rocket::local::asynchronous::Client;
+
+    use super::*;
+    use crate::{
+        fota::FotaSettings,
+        prepared_build::{Builds, PreparedBuild, Retention},
+        telemetry::Sessions,
+        Cli, Command, ExtraPart, ManifestArgs, PageSettings,
+    };
+
+    fn parts() -> PartsData {
+        PartsData {
+            chip: String::from("ESP32"),
+            bootloader: vec![1; 32],
+            partitions: vec![2; 32],
+            firmware: vec![3; 64],
+            total_size: 160,
+            bootloader_size: 32,
+            partitions_size: 32,
+            firmware_size: 64,
+            flash_size: String::from("4MB"),
+            bootloader_offset: 0x1000,
+            partitions_offset: 0x8000,
+            firmware_offset: 0x10000,
+            extra_parts: vec![ExtraPart {
+                file: String::from("storage.bin"),
+                label: String::from("storage"),
+                offset: 0x310000,
+                data: vec![4; 32],
+            }],
+            app_version: Some(String::from("v1.4.2")),
+            ..Default::default()
+        }
+    }
+
+    fn manifest_args(args: &[&str]) -> ManifestArgs {
+        let cli = Cli::try_parse_from(
+            ["esp-web-flash", "manifest", "--chip", "esp32"]
+                .iter()
+                .chain(args)
+                .chain(&["app.elf"]),
+        )
+        .unwrap();
+        match cli.command {
+            Command::Manifest(opts) => opts,
+            command => panic!("parsed as {:?}", command),
+        }
+    }
+
+    /// The body of `/manifest.json` for `parts()`, as served with default options
+    async fn served(query: &str) -> String {
+        let rocket = rocket::build()
+            .mount("/", routes![crate::manifest])
+            .manage(Builds::new(
+                PreparedBuild::new(parts()),
+                Retention {
+                    count: 1,
+                    budget: usize::MAX,
+                },
+            ))
+            .manage(PageSettings {
+                lang: String::from("en"),
+                theme_css: String::new(),
+                erase_policy: ErasePolicy::Prompt,
+                name: None,
+                title: None,
+                logo: None,
+                erase_locked: false,
+                once: false,
+                admin: false,
+                client_logs: false,
+                index: None,
+                esp_web_tools: String::new(),
+            })
+            .manage(FotaSettings {
+                ty: String::from("esp32-fota-http"),
+                version: None,
+                public_url: None,
+            })
+            .manage(Sessions::default());
+        let client = Client::untracked(rocket).await.unwrap();
+        let response = client
+            .get(format!("/manifest.json{}", query))
+            .dispatch()
+            .await;
+        response.into_string().await.unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn as_served_matches_the_route_byte_for_byte() {
+        let printed = crate::manifest_output(&manifest_args(&["--as-served"]), parts()).unwrap();
+        assert_eq!(printed, served("").await);
+
+        let printed = crate::manifest_output(
+            &manifest_args(&["--as-served", "--erase-policy", "keep"]),
+            parts(),
+        )
+        .unwrap();
+        assert_eq!(printed, served("?erase=keep").await);
+    }
+
+    #[test]
+    fn paths_are_pinned_and_absolute_with_a_base_url() {
+        let build = PreparedBuild::new(parts());
+        let manifest = generate(
+            &build.parts,
+            "Blink",
+            &build.hash,
+            "https://flash.lab:8443/",
+            ErasePolicy::Full,
+            Some("abc"),
+            None,
+        );
+        let paths: Vec<&str> = manifest.builds[0]
+            .parts
+            .iter()
+            .map(|part| part.path.as_str())
+            .collect();
+        let pinned = |file: &str| {
+            format!(
+                "https://flash.lab:8443/b/{}/{}?session=abc",
+                build.hash, file
+            )
+        };
+        assert_eq!(
+            paths,
+            [
+                pinned("bootloader.bin"),
+                pinned("partitions.bin"),
+                pinned("firmware.bin"),
+                pinned("storage.bin"),
+            ]
+        );
+        assert!(!manifest.new_install_prompt_erase);
+        assert!(!manifest.new_install_skip_erase);
+    }
+
+    #[test]
+    fn flat_manifest_for_export() {
+        let manifest = flat(&parts(), "Blink", ErasePolicy::Keep);
+        let json = rocket::serde::json::to_string(&manifest).unwrap();
+        assert_eq!(
+            json,
+            r#"{"name":"Blink","new_install_prompt_erase":false,"new_install_skip_erase":true,"builds":[{"chipFamily":"ESP32","parts":[{"path":"bootloader.bin","offset":4096},{"path":"partitions.bin","offset":32768},{"path":"firmware.bin","offset":65536},{"path":"storage.bin","offset":3211264}]}]}"#
+        );
+    }
+
+    #[test]
+    fn bootloader_offset_follows_the_chip() {
+        // see tests/fixtures/gen_elf.py
+        for (chip, offset) in [
+            ("esp32", 0x1000),
+            ("esp32s2", 0x1000),
+            ("esp32c3", 0x0),
+            ("esp32s3", 0x0),
+        ] {
+            let elf = format!("tests/fixtures/{}-app.elf", chip);
+            let cli =
+                Cli::try_parse_from(["esp-web-flash", "manifest", "--chip", chip, &elf]).unwrap();
+            let opts = match cli.command {
+                Command::Manifest(opts) => opts,
+                command => panic!("parsed as {:?}", command),
+            };
+            let data = crate::prepare(&opts.image).unwrap();
+            assert_eq!(data.bootloader_offset, offset, "{}", chip);
+
+            let manifest = flat(&data, "Blink", ErasePolicy::Prompt);
+            let part = &manifest.builds[0].parts[0];
+            assert_eq!(
+                (part.path.as_str(), part.offset),
+                ("bootloader.bin", offset),
+                "{}",
+                chip
+            );
+        }
+    }
+
+    #[test]
+    fn base_urls_end_with_a_slash() {
+        assert_eq!(
+            base_url_arg("https://flash.lab:8443").unwrap(),
+            "https://flash.lab:8443/"
+        );
+        assert_eq!(base_url_arg("http://x/fw/").unwrap(), "http://x/fw/");
+        assert!(base_url_arg("flash.lab").is_err());
+    }
+}
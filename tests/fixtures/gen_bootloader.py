@@ -0,0 +1,92 @@
+#!/usr/bin/env python3
+"""Writes an ESP32 bootloader image and the images `esptool.py write_flash`
+flashes for it at 0x1000 with other flash settings.
+
+bootloader-esp32-4MB-qio-40m.bin has two segments, the checksum and an
+appended SHA-256 digest. The other files are what esptool's
+_update_image_flash_params (esptool 4.x) makes of it, transcribed below:
+it rewrites bytes 2 and 3 of the header and recomputes the digest over
+everything up to and including the checksum byte.
+
+    python3 tests/fixtures/gen_bootloader.py
+"""
+
+import hashlib
+import os
+import struct
+
+ESP_IMAGE_MAGIC = 0xE9
+ESP_CHECKSUM_MAGIC = 0xEF
+SHA256_DIGEST_LEN = 32
+
+# esptool's ESP32ROM tables
+FLASH_MODES = {"qio": 0, "qout": 1, "dio": 2, "dout": 3}
+FLASH_SIZES = {"1MB": 0x00, "2MB": 0x10, "4MB": 0x20, "8MB": 0x30, "16MB": 0x40}
+FLASH_FREQUENCY = {"80m": 0xF, "40m": 0x0, "26m": 0x1, "20m": 0x2}
+
+
+def bootloader():
+    segments = [
+        (0x3FFF0030, bytes((i * 7) & 0xFF for i in range(0x44))),
+        (0x40078000, bytes((i * 13 + 5) & 0xFF for i in range(0x68))),
+    ]
+    image = struct.pack(
+        "<BBBBI", ESP_IMAGE_MAGIC, len(segments), FLASH_MODES["qio"], FLASH_SIZES["4MB"] | FLASH_FREQUENCY["40m"], 0x40080400
+    )
+    # extended header: WP pin, drive settings, chip id, min revision, min and
+    # max full revision, reserved, hash appended
+    image += struct.pack("<B3sHBHH4sB", 0xEE, b"\0\0\0", 0, 0, 0, 0xFFFF, b"\0" * 4, 1)
+    checksum = ESP_CHECKSUM_MAGIC
+    for addr, data in segments:
+        image += struct.pack("<II", addr, len(data)) + data
+        for b in data:
+            checksum ^= b
+    image += b"\0" * (15 - len(image) % 16) + bytes([checksum])
+    return image + hashlib.sha256(image).digest()
+
+
+def data_length(image):
+    """Where the digest starts: after the segments and the checksum byte,
+    as ESP32FirmwareImage reads it"""
+    pos = 24
+    for _ in range(image[1]):
+        pos += 8 + struct.unpack("<I", image[pos + 4 : pos + 8])[0]
+    pos += 15 - pos % 16
+    return pos + 1
+
+
+def update_image_flash_params(image, flash_mode, flash_freq, flash_size):
+    magic, _, mode, size_freq = struct.unpack("BBBB", image[:4])
+    assert magic == ESP_IMAGE_MAGIC
+    sha_appended = image[23] == 1
+
+    if flash_mode != "keep":
+        mode = FLASH_MODES[flash_mode]
+    freq = size_freq & 0x0F
+    if flash_freq != "keep":
+        freq = FLASH_FREQUENCY[flash_freq]
+    size = size_freq & 0xF0
+    if flash_size != "keep":
+        size = FLASH_SIZES[flash_size]
+    flash_params = struct.pack("BB", mode, size + freq)
+    if flash_params != image[2:4]:
+        image = image[0:2] + flash_params + image[4:]
+
+    if sha_appended:
+        length = data_length(image)
+        before, after = image[:length], image[length + SHA256_DIGEST_LEN :]
+        image = before + hashlib.sha256(before).digest() + after
+    return image
+
+
+if __name__ == "__main__":
+    here = os.path.dirname(os.path.abspath(__file__))
+    base = bootloader()
+    outputs = {
+        "bootloader-esp32-4MB-qio-40m.bin": base,
+        "bootloader-esp32-8MB-dio-80m.bin": update_image_flash_params(base, "dio", "80m", "8MB"),
+        "bootloader-esp32-16MB-qio-40m.bin": update_image_flash_params(base, "keep", "keep", "16MB"),
+    }
+    for name, data in outputs.items():
+        with open(os.path.join(here, name), "wb") as f:
+            f.write(data)
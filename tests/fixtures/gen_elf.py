@@ -0,0 +1,159 @@
+#!/usr/bin/env python3
+"""Writes the minimal app ELFs the unit tests build images from, and an
+app image as given instead of an ELF.
+
+Each has the sections an ESP-IDF app maps from flash: the app descriptor
+and a 32 byte FIRMWARE_BUILD_INFO placeholder in .flash.rodata, a little
+code in .flash.text, and a buffer in .dram0.bss with no initial data.
+esp32-auditable.elf also has the .dep-v0 section cargo-auditable embeds.
+
+esp32-app.bin is laid out as esptool's elf2image writes an ESP32 app: the
+header, DIO at 40 MHz on 4 MB, the .flash.rodata segment at a flash offset
+matching its address, a small IRAM segment, the checksum and the SHA-256
+digest.
+
+    python3 tests/fixtures/gen_elf.py
+"""
+
+import hashlib
+import json
+import os
+import struct
+import zlib
+
+EM_XTENSA = 94
+EM_RISCV = 243
+
+SHT_PROGBITS, SHT_SYMTAB, SHT_STRTAB, SHT_NOBITS = 1, 2, 3, 8
+SHF_WRITE, SHF_ALLOC, SHF_EXECINSTR = 1, 2, 4
+PT_LOAD = 1
+STT_OBJECT, STT_FUNC, STB_GLOBAL = 1, 2, 1
+
+APP_DESC_MAGIC = 0xABCD5432
+BUILD_INFO_SIZE = 32
+
+
+def app_desc(version, project):
+    def string(s, size):
+        return s.encode().ljust(size, b"\0")
+
+    return (
+        struct.pack("<III", APP_DESC_MAGIC, 0, 0)
+        + b"\0" * 4
+        + string(version, 32)
+        + string(project, 32)
+        + string("12:00:00", 16)
+        + string("Jan  1 2024", 16)
+        + string("v5.1", 32)
+        + bytes(range(32))
+        + b"\0" * 80
+    )
+
+
+def elf(machine, drom, irom, dram, version, dep=None):
+    desc = app_desc(version, "blink")
+    rodata = desc + b"unset".ljust(BUILD_INFO_SIZE, b"\0") + b"hello\0".ljust(32, b"\0")
+    text = bytes((0x36, 0x41, 0x00) * 21) + b"\0"
+    build_info = drom + len(desc)
+
+    strtab = b"\0"
+    names = {}
+    for name in ("esp_app_desc", "FIRMWARE_BUILD_INFO", "app_main", "BSS_BUFFER"):
+        names[name] = len(strtab)
+        strtab += name.encode() + b"\0"
+    shstrtab = b"\0"
+    shnames = {}
+    sections = [".flash.rodata", ".flash.text", ".dram0.bss", ".symtab", ".strtab", ".shstrtab"]
+    for name in sections + ([".dep-v0"] if dep else []):
+        shnames[name] = len(shstrtab)
+        shstrtab += name.encode() + b"\0"
+
+    # section indices: 1 rodata, 2 text, 3 bss, 4 symtab, 5 strtab, 6 shstrtab, 7 .dep-v0
+    def symbol(name, value, size, kind, shndx):
+        return struct.pack("<IIIBBH", names[name], value, size, (STB_GLOBAL << 4) | kind, 0, shndx)
+
+    symtab = b"\0" * 16
+    symtab += symbol("esp_app_desc", drom, len(desc), STT_OBJECT, 1)
+    symtab += symbol("FIRMWARE_BUILD_INFO", build_info, BUILD_INFO_SIZE, STT_OBJECT, 1)
+    symtab += symbol("app_main", irom, len(text), STT_FUNC, 2)
+    symtab += symbol("BSS_BUFFER", dram, 0x100, STT_OBJECT, 3)
+
+    ehsize, phentsize, shentsize, phnum = 52, 32, 40, 3
+    offset = ehsize + phnum * phentsize
+    dep = zlib.compress(json.dumps(dep).encode()) if dep else b""
+    layout = []
+    for data in (rodata, text, symtab, strtab, shstrtab, dep):
+        offset = (offset + 3) & ~3
+        layout.append(offset)
+        offset += len(data)
+    shoff = (offset + 3) & ~3
+    rodata_off, text_off, symtab_off, strtab_off, shstrtab_off, dep_off = layout
+    shnum = 8 if dep else 7
+
+    header = b"\x7fELF" + bytes([1, 1, 1, 0]) + b"\0" * 8
+    header += struct.pack(
+        "<HHIIIIIHHHHHH", 2, machine, 1, irom, ehsize, shoff, 0, ehsize, phentsize, phnum, shentsize, shnum, 6
+    )
+    phdrs = struct.pack("<IIIIIIII", PT_LOAD, rodata_off, drom, drom, len(rodata), len(rodata), 4, 16)
+    phdrs += struct.pack("<IIIIIIII", PT_LOAD, text_off, irom, irom, len(text), len(text), 5, 4)
+    phdrs += struct.pack("<IIIIIIII", PT_LOAD, 0, dram, dram, 0, 0x100, 6, 4)
+
+    def section(name, ty, flags, addr, off, size, link=0, info=0, align=4, entsize=0):
+        return struct.pack("<IIIIIIIIII", shnames.get(name, 0), ty, flags, addr, off, size, link, info, align, entsize)
+
+    shdrs = b"\0" * shentsize
+    shdrs += section(".flash.rodata", SHT_PROGBITS, SHF_ALLOC, drom, rodata_off, len(rodata), align=16)
+    shdrs += section(".flash.text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, irom, text_off, len(text))
+    shdrs += section(".dram0.bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, dram, text_off + len(text), 0x100)
+    shdrs += section(".symtab", SHT_SYMTAB, 0, 0, symtab_off, len(symtab), link=5, info=1, entsize=16)
+    shdrs += section(".strtab", SHT_STRTAB, 0, 0, strtab_off, len(strtab), align=1)
+    shdrs += section(".shstrtab", SHT_STRTAB, 0, 0, shstrtab_off, len(shstrtab), align=1)
+    if dep:
+        shdrs += section(".dep-v0", SHT_PROGBITS, 0, 0, dep_off, len(dep), align=1)
+
+    out = bytearray(header + phdrs)
+    for off, data in zip(layout, (rodata, text, symtab, strtab, shstrtab, dep)):
+        out += b"\0" * (off - len(out))
+        out += data
+    out += b"\0" * (shoff - len(out))
+    out += shdrs
+    return bytes(out)
+
+
+def app_image(drom, iram, version):
+    desc = app_desc(version, "blink")
+    rodata = desc + b"unset".ljust(BUILD_INFO_SIZE, b"\0") + b"hello\0".ljust(32, b"\0")
+    segments = [(drom, rodata), (iram, bytes((0x36, 0x41, 0x00) * 5) + b"\0")]
+    image = struct.pack("<BBBBI", 0xE9, len(segments), 2, 0x20, iram + 4)
+    image += struct.pack("<B3sHBHH4sB", 0xEE, b"\0\0\0", 0, 0, 0, 0xFFFF, b"\0" * 4, 1)
+    checksum = 0xEF
+    for addr, data in segments:
+        image += struct.pack("<II", addr, len(data)) + data
+        for b in data:
+            checksum ^= b
+    image += b"\0" * (15 - len(image) % 16) + bytes([checksum])
+    return image + hashlib.sha256(image).digest()
+
+
+DEPENDENCIES = {
+    "packages": [
+        {"name": "blink", "version": "0.1.0", "source": "local", "dependencies": [1, 2], "root": True},
+        {"name": "esp-hal", "version": "0.16.1", "source": "crates.io", "dependencies": [2]},
+        {"name": "critical-section", "version": "1.1.2", "source": "crates.io"},
+    ]
+}
+
+FIXTURES = {
+    "esp32-app.elf": elf(EM_XTENSA, 0x3F400020, 0x400D0020, 0x3FFB0000, "v1.4.2"),
+    "esp32c3-app.elf": elf(EM_RISCV, 0x3C000020, 0x42000020, 0x3FC80000, "v1.4.2"),
+    "esp32s2-app.elf": elf(EM_XTENSA, 0x3F000020, 0x40080020, 0x3FFB0000, "v1.4.2"),
+    "esp32s3-app.elf": elf(EM_XTENSA, 0x3C000020, 0x42000020, 0x3FC88000, "v1.4.2"),
+    "esp32-auditable.elf": elf(EM_XTENSA, 0x3F400020, 0x400D0020, 0x3FFB0000, "v1.4.2", DEPENDENCIES),
+    "esp32-app.bin": app_image(0x3F400020, 0x40080400, "v2.0.1"),
+}
+
+if __name__ == "__main__":
+    here = os.path.dirname(os.path.abspath(__file__))
+    for name, data in FIXTURES.items():
+        with open(os.path.join(here, name), "wb") as f:
+            f.write(data)